@@ -0,0 +1,111 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use num_bigint::BigUint;
+use zkp_chaum_pedersen::{Backend, ParamSet, Statement, BETA_LABEL, ZKP};
+
+/// Proofs folded into one aggregate in the batch verify bench.
+const BATCH: usize = 16;
+
+/// RFC 3526 group 15. It is a safe prime group, so q = (p - 1) / 2 and the
+/// exponents are full length, unlike the short subgroups of RFC 5114.
+const MODP_3072: &str = r#"FFFFFFFF FFFFFFFF C90FDAA2 2168C234 C4C6628B 80DC1CD1
+    29024E08 8A67CC74 020BBEA6 3B139B22 514A0879 8E3404DD
+    EF9519B3 CD3A431B 302B0A6D F25F1437 4FE1356D 6D51C245
+    E485B576 625E7EC6 F44C42E9 A637ED6B 0BFF5CB6 F406B7ED
+    EE386BFB 5A899FA5 AE9F2411 7C4B1FE6 49286651 ECE45B3D
+    C2007CB8 A163BF05 98DA4836 1C55D39A 69163FA8 FD24CF5F
+    83655D23 DCA3AD96 1C62F356 208552BB 9ED52907 7096966D
+    670C354E 4ABC9804 F1746C08 CA18217C 32905E46 2E36CE3B
+    E39E772C 180E8603 9B2783A2 EC07A28F B5C55DF0 6F4C52C9
+    DE2BCBF6 95581718 3995497C EA956AE5 15D22618 98FA0510
+    15728E5A 8AAAC42D AD33170D 04507A33 A85521AB DF1CBA64
+    ECFB8504 58DBEF0A 8AEA7157 5D060C7D B3970F85 A6E1E4C7
+    ABF5AE8C DB0933D7 1E8C94E0 4A25619D CEE3D226 1AD2EE6B
+    F12FFA06 D98A0864 D8760273 3EC86A64 521F2B18 177B200C
+    BBE11757 7A615D6C 770988C0 BAD946E2 08E24FA0 74E5AB31
+    43DB5BFC E0FD108E 4B82D120 A93AD2CA FFFFFFFF FFFFFFFF"#;
+
+fn groups() -> Vec<(String, ZKP)> {
+    let mut groups = Vec::new();
+    for set in ParamSet::ALL {
+        for backend in [Backend::BigUint, Backend::Fixed2048] {
+            let zkp = ZKP::builder()
+                .param_set(set)
+                .backend(backend)
+                .build()
+                .unwrap();
+            groups.push((format!("{set}-{backend:?}"), zkp));
+        }
+    }
+
+    let p = BigUint::parse_bytes(
+        MODP_3072.split_whitespace().collect::<String>().as_bytes(),
+        16,
+    )
+    .unwrap();
+    let q = (&p - 1u32) / 2u32;
+    let zkp = ZKP::builder()
+        .p(p)
+        .q(q)
+        .alpha(BigUint::from(2u32))
+        .derive_beta(BETA_LABEL)
+        .build()
+        .unwrap();
+    groups.push(("rfc3526-3072-BigUint".to_string(), zkp));
+
+    groups
+}
+
+fn bench_core(criterion: &mut Criterion) {
+    for (name, zkp) in groups() {
+        let mut group = criterion.benchmark_group(name.as_str());
+        if zkp.p().bits() > 2048 {
+            group.sample_size(10);
+        }
+
+        let x = ZKP::generate_random_below(zkp.q());
+        let k = ZKP::generate_random_below(zkp.q());
+        let c = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        let (r1, r2) = zkp.compute_pair(&k);
+        let s = zkp.solve(&k, &c, &x);
+
+        group.bench_function("commitment", |b| b.iter(|| zkp.compute_pair(&k)));
+        group.bench_function("solve", |b| b.iter(|| zkp.solve(&k, &c, &x)));
+        group.bench_function("verify", |b| {
+            b.iter(|| zkp.verify(&r1, &r2, &y1, &y2, &c, &s).unwrap())
+        });
+
+        let (statements, proofs): (Vec<_>, Vec<_>) = (0..BATCH)
+            .map(|i| {
+                let x = ZKP::generate_random_below(zkp.q());
+                let (y1, y2) = zkp.compute_pair(&x);
+                let context = format!("bench {i}").into_bytes();
+                let proof = zkp.prove_non_interactive(&x, &context);
+                (Statement { y1, y2, context }, proof)
+            })
+            .unzip();
+        let aggregate = zkp.aggregate_proofs(&statements, &proofs).unwrap();
+
+        group.bench_function(BenchmarkId::new("verify_each", BATCH), |b| {
+            b.iter(|| {
+                for (statement, proof) in statements.iter().zip(&proofs) {
+                    zkp.verify_non_interactive(
+                        &statement.y1,
+                        &statement.y2,
+                        proof,
+                        &statement.context,
+                    )
+                    .unwrap();
+                }
+            })
+        });
+        group.bench_function(BenchmarkId::new("verify_aggregate", BATCH), |b| {
+            b.iter(|| zkp.verify_aggregate(&statements, &aggregate).unwrap())
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_core);
+criterion_main!(benches);
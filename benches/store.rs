@@ -0,0 +1,141 @@
+//! Throughput of the in-memory server state under concurrent logins, with
+//! the global `Mutex<HashMap>` the server used to have against the sharded
+//! `DashMap` of `MemoryStore`.
+//!
+//! Every login reads the user, stores a challenge under a fresh auth_id,
+//! consumes it and stores a session, the same map traffic as the handlers.
+
+use std::{collections::HashMap, sync::Arc, thread};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+
+const USERS: usize = 1024;
+const LOGINS_PER_THREAD: usize = 2000;
+
+trait Maps: Send + Sync + 'static {
+    fn new() -> Self;
+    fn get_user(&self, user: &str) -> Option<u64>;
+    fn put_user(&self, user: String, value: u64);
+    fn save_challenge(&self, auth_id: String, user: String);
+    fn consume(&self, auth_id: &str) -> Option<String>;
+    fn save_session(&self, session_id: String, user: String);
+}
+
+struct Locked {
+    users: Mutex<HashMap<String, u64>>,
+    challenges: Mutex<HashMap<String, String>>,
+    sessions: Mutex<HashMap<String, String>>,
+}
+
+impl Maps for Locked {
+    fn new() -> Self {
+        Self {
+            users: Default::default(),
+            challenges: Default::default(),
+            sessions: Default::default(),
+        }
+    }
+
+    fn get_user(&self, user: &str) -> Option<u64> {
+        self.users.lock().get(user).copied()
+    }
+
+    fn put_user(&self, user: String, value: u64) {
+        self.users.lock().insert(user, value);
+    }
+
+    fn save_challenge(&self, auth_id: String, user: String) {
+        self.challenges.lock().insert(auth_id, user);
+    }
+
+    fn consume(&self, auth_id: &str) -> Option<String> {
+        self.challenges.lock().remove(auth_id)
+    }
+
+    fn save_session(&self, session_id: String, user: String) {
+        self.sessions.lock().insert(session_id, user);
+    }
+}
+
+struct Sharded {
+    users: DashMap<String, u64>,
+    challenges: DashMap<String, String>,
+    sessions: DashMap<String, String>,
+}
+
+impl Maps for Sharded {
+    fn new() -> Self {
+        Self {
+            users: Default::default(),
+            challenges: Default::default(),
+            sessions: Default::default(),
+        }
+    }
+
+    fn get_user(&self, user: &str) -> Option<u64> {
+        self.users.get(user).map(|value| *value)
+    }
+
+    fn put_user(&self, user: String, value: u64) {
+        self.users.insert(user, value);
+    }
+
+    fn save_challenge(&self, auth_id: String, user: String) {
+        self.challenges.insert(auth_id, user);
+    }
+
+    fn consume(&self, auth_id: &str) -> Option<String> {
+        self.challenges.remove(auth_id).map(|(_, user)| user)
+    }
+
+    fn save_session(&self, session_id: String, user: String) {
+        self.sessions.insert(session_id, user);
+    }
+}
+
+fn logins<M: Maps>(threads: usize) {
+    let maps = Arc::new(M::new());
+    for user in 0..USERS {
+        maps.put_user(format!("user{user}"), user as u64);
+    }
+
+    thread::scope(|scope| {
+        for thread in 0..threads {
+            let maps = &maps;
+            scope.spawn(move || {
+                for login in 0..LOGINS_PER_THREAD {
+                    let user = format!("user{}", (thread * LOGINS_PER_THREAD + login) % USERS);
+                    let auth_id = format!("auth-{thread}-{login}");
+                    maps.get_user(&user).unwrap();
+                    maps.save_challenge(auth_id.clone(), user.clone());
+                    let user = maps.consume(&auth_id).unwrap();
+                    maps.save_session(format!("session-{thread}-{login}"), user);
+                }
+            });
+        }
+    });
+}
+
+fn bench_store(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("memory_store");
+    group.sample_size(20);
+    for threads in [1, 4, 8] {
+        group.throughput(Throughput::Elements((threads * LOGINS_PER_THREAD) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("mutex_hashmap", threads),
+            &threads,
+            |b, &threads| b.iter(|| logins::<Locked>(threads)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("dashmap", threads),
+            &threads,
+            |b, &threads| b.iter(|| logins::<Sharded>(threads)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_store);
+criterion_main!(benches);
@@ -1,7 +1,35 @@
 fn main() {
+    // sqlx::migrate! embeds these.
+    println!("cargo:rerun-if-changed=migrations");
     tonic_build::configure()
         .build_server(true)
+        // Channel::connect lives behind the client feature, callers pass
+        // their own channel to AuthClient::new.
+        .build_transport(false)
         .out_dir("src/")
-        .compile(&["proto/zkp_auth.proto"], &["proto/"])
+        // For the server's reflection service.
+        .file_descriptor_set_path("src/zkp_auth_descriptor.bin")
+        // Debug for these is implemented in src/redact.rs so secrets stay out of logs.
+        .skip_debug("zkp_auth.AuthenticationChallengeRequest")
+        .skip_debug("zkp_auth.OpenChallengeRequest")
+        .skip_debug("zkp_auth.AuthenticationAnswerRequest")
+        .skip_debug("zkp_auth.AuthenticationAnswerResponse")
+        .skip_debug("zkp_auth.RegisterResponse")
+        .skip_debug("zkp_auth.Proof")
+        .skip_debug("zkp_auth.UpdateCredentialsRequest")
+        .skip_debug("zkp_auth.RecoverAccountRequest")
+        .skip_debug("zkp_auth.DeleteAccountRequest")
+        .skip_debug("zkp_auth.RefreshSessionRequest")
+        .skip_debug("zkp_auth.RefreshSessionResponse")
+        .skip_debug("zkp_auth.LogoutRequest")
+        .skip_debug("zkp_auth.ValidateSessionRequest")
+        .skip_debug("zkp_auth.AddDeviceRequest")
+        .skip_debug("zkp_auth.ListDevicesRequest")
+        .skip_debug("zkp_auth.RevokeDeviceRequest")
+        .skip_debug("zkp_auth.ListOwnSessionsRequest")
+        .skip_debug("zkp_auth.RevokeOwnSessionRequest")
+        .skip_debug("zkp_auth.SessionInfo")
+        .skip_debug("zkp_auth.RevokeSessionRequest")
+        .compile_protos(&["proto/zkp_auth.proto"], &["proto/"])
         .unwrap();
 }
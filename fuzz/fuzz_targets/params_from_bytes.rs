@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zkp_chaum_pedersen::{ZkpConstants, ZKP};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ZkpConstants::from_bytes(data);
+    // Parameter validation runs on attacker supplied groups, it must reject
+    // rather than panic or hang.
+    let _ = ZKP::from_bytes(data);
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use num_bigint::BigUint;
+use zkp_chaum_pedersen::{encoding::parse_uint, ZKP};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(n) = parse_uint(data) {
+        assert_eq!(n.to_bytes_be(), data);
+    }
+
+    let zkp = ZKP::default();
+    if let Ok(n) = zkp.parse_element(data) {
+        assert!(n > BigUint::ZERO && n < *zkp.p());
+        assert_eq!(n.to_bytes_be(), data);
+    }
+    if let Ok(n) = zkp.parse_scalar(data) {
+        assert!(n < *zkp.q());
+        assert_eq!(n.to_bytes_be(), data);
+    }
+});
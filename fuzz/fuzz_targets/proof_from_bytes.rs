@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zkp_chaum_pedersen::{Proof, ZKP};
+
+fuzz_target!(|data: &[u8]| {
+    // Only canonical encodings are accepted, so whatever parses must
+    // re-encode to the same bytes.
+    if let Ok(proof) = Proof::from_bytes(data) {
+        assert_eq!(proof.to_bytes(), data);
+    }
+
+    let zkp = ZKP::default();
+    if let Ok(proof) = zkp.parse_proof(data) {
+        assert_eq!(proof.to_bytes(), data);
+        assert!(proof.r1 < *zkp.p() && proof.r2 < *zkp.p() && proof.s < *zkp.q());
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zkp_chaum_pedersen::{Proof, ZKP};
+
+fuzz_target!(|data: &[u8]| {
+    let zkp = ZKP::default();
+
+    // Proof bytes as sent by a client, checked against a fixed statement the
+    // way the server would. Forged proofs must not be accepted.
+    let (y1, y2) = zkp.compute_pair(&2u32.into());
+    if let Ok(proof) = Proof::from_bytes(data) {
+        assert!(zkp
+            .verify_non_interactive(&y1, &y2, &proof, b"fuzz")
+            .is_err());
+        let _ = zkp.verify(&proof.r1, &proof.r2, &y1, &y2, &proof.s, &proof.s);
+    }
+});
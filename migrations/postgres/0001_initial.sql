@@ -0,0 +1,44 @@
+-- Every table as it was before schema versions were kept, so databases
+-- from then take this as applied.
+CREATE TABLE IF NOT EXISTS users (
+    user_name TEXT PRIMARY KEY,
+    y1 BYTEA NOT NULL,
+    y2 BYTEA NOT NULL,
+    salt BYTEA NOT NULL,
+    m_cost BIGINT NOT NULL,
+    t_cost BIGINT NOT NULL,
+    p_cost BIGINT NOT NULL,
+    group_params BYTEA
+);
+-- When each user last registered or logged in. Users from before this was
+-- noted count as used now, the first time the store is opened since.
+CREATE TABLE IF NOT EXISTS user_activity (
+    user_name TEXT PRIMARY KEY,
+    last_used_at BIGINT NOT NULL
+);
+INSERT INTO user_activity (user_name, last_used_at)
+SELECT user_name, EXTRACT(EPOCH FROM now())::BIGINT FROM users
+ON CONFLICT (user_name) DO NOTHING;
+CREATE TABLE IF NOT EXISTS challenges (
+    auth_id TEXT PRIMARY KEY,
+    user_name TEXT NOT NULL,
+    state TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS challenges_user_name ON challenges (user_name);
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id TEXT PRIMARY KEY,
+    user_name TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sessions_user_name ON sessions (user_name);
+CREATE TABLE IF NOT EXISTS revoked_sessions (
+    session_id TEXT PRIMARY KEY,
+    expires_at BIGINT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS lockouts (
+    user_name TEXT PRIMARY KEY,
+    failures BIGINT NOT NULL,
+    locked_until BIGINT
+);
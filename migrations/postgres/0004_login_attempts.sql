@@ -0,0 +1,10 @@
+-- Answers to login challenges, the last LOGIN_HISTORY_SIZE of each user.
+CREATE TABLE login_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    user_name TEXT NOT NULL,
+    at BIGINT NOT NULL,
+    success BOOLEAN NOT NULL,
+    source_ip TEXT,
+    auth_id TEXT NOT NULL
+);
+CREATE INDEX login_attempts_user_name ON login_attempts (user_name, id);
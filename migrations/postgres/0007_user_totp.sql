@@ -0,0 +1,2 @@
+-- Secrets of users enrolled in TOTP, whose logins need a code too.
+ALTER TABLE users ADD COLUMN totp_secret BYTEA;
@@ -0,0 +1,41 @@
+-- Every table as it was before schema versions were kept, so databases
+-- from then take this as applied.
+CREATE TABLE IF NOT EXISTS users (
+    user_name TEXT PRIMARY KEY,
+    y1 BLOB NOT NULL,
+    y2 BLOB NOT NULL,
+    salt BLOB NOT NULL,
+    m_cost INTEGER NOT NULL,
+    t_cost INTEGER NOT NULL,
+    p_cost INTEGER NOT NULL,
+    group_params BLOB
+);
+-- When each user last registered or logged in. Users from before this was
+-- noted count as used now, the first time the store is opened since.
+CREATE TABLE IF NOT EXISTS user_activity (
+    user_name TEXT PRIMARY KEY,
+    last_used_at INTEGER NOT NULL
+);
+INSERT OR IGNORE INTO user_activity (user_name, last_used_at)
+SELECT user_name, CAST(strftime('%s', 'now') AS INTEGER) FROM users;
+CREATE TABLE IF NOT EXISTS challenges (
+    auth_id TEXT PRIMARY KEY,
+    user_name TEXT NOT NULL,
+    state TEXT NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id TEXT PRIMARY KEY,
+    user_name TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS revoked_sessions (
+    session_id TEXT PRIMARY KEY,
+    expires_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS lockouts (
+    user_name TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    locked_until INTEGER
+);
@@ -0,0 +1,5 @@
+-- Settings admins change at runtime, by name.
+CREATE TABLE settings (
+    name TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
@@ -0,0 +1,5 @@
+-- When users registered, 0 for those from before it was noted, when they
+-- last logged in, and the JSON object of metadata they registered with.
+ALTER TABLE users ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN last_login_at INTEGER;
+ALTER TABLE users ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,6 @@
+-- The IP denylist an admin set last, one address or CIDR block a row. The
+-- ip_denylist row of settings, holding its length, tells an empty list from
+-- one never set.
+CREATE TABLE ip_denylist (
+    cidr TEXT PRIMARY KEY
+);
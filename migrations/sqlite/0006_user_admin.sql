@@ -0,0 +1,2 @@
+-- Users whose sessions may call AuthAdmin, with ADMIN_SESSIONS set.
+ALTER TABLE users ADD COLUMN admin INTEGER NOT NULL DEFAULT 0;
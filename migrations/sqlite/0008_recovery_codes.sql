@@ -0,0 +1,6 @@
+-- Hashes of the recovery codes users haven't used yet, see hash_recovery_code.
+CREATE TABLE recovery_codes (
+    user_name TEXT NOT NULL,
+    code_hash BLOB NOT NULL,
+    PRIMARY KEY (user_name, code_hash)
+);
@@ -0,0 +1,2 @@
+-- The JSON array of keys users added for their devices, see Device.
+ALTER TABLE users ADD COLUMN devices TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,220 @@
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+use crate::{expand_hash, length_prefixed, Proof, ZkpError, ZKP};
+
+/// Public side of one non-interactive proof: who proved, and under which context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub y1: BigUint,
+    pub y2: BigUint,
+    pub context: Vec<u8>,
+}
+
+/// Half-aggregate of many proofs: every (r1, r2) is kept but all the
+/// responses collapse into a single s = sum(z_i * s_i) mod q, where the
+/// weights z_i are hashed from the whole batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateProof {
+    pub commitments: Vec<(BigUint, BigUint)>,
+    pub s: BigUint,
+}
+
+impl ZKP {
+    /// Compresses proofs of independent statements, each of which should
+    /// already verify on its own, otherwise the aggregate won't either.
+    /// The aggregate has no room for expiries, so neither may the proofs.
+    pub fn aggregate_proofs(
+        &self,
+        statements: &[Statement],
+        proofs: &[Proof],
+    ) -> Result<AggregateProof, ZkpError> {
+        if statements.len() != proofs.len() {
+            return Err(ZkpError::ProofCount {
+                statements: statements.len(),
+                proofs: proofs.len(),
+            });
+        }
+        if proofs.iter().any(|proof| proof.expires_at.is_some()) {
+            return Err(ZkpError::ExpiringProof);
+        }
+
+        let commitments: Vec<(BigUint, BigUint)> = proofs
+            .iter()
+            .map(|proof| (proof.r1.clone(), proof.r2.clone()))
+            .collect();
+        let weights = self.aggregation_weights(statements, &commitments);
+
+        let s = proofs
+            .iter()
+            .zip(&weights)
+            .fold(BigUint::ZERO, |acc, (proof, z)| {
+                (acc + z * &proof.s) % &self.q
+            });
+
+        Ok(AggregateProof { commitments, s })
+    }
+
+    /// Single pass over the batch:
+    /// alpha^s * prod(y1_i^(c_i z_i)) = prod(r1_i^z_i) mod p
+    /// beta^s * prod(y2_i^(c_i z_i)) = prod(r2_i^z_i) mod p
+    pub fn verify_aggregate(
+        &self,
+        statements: &[Statement],
+        aggregate: &AggregateProof,
+    ) -> Result<(), ZkpError> {
+        if statements.is_empty() || statements.len() != aggregate.commitments.len() {
+            return Err(ZkpError::Verify);
+        }
+
+        let weights = self.aggregation_weights(statements, &aggregate.commitments);
+
+        let mut lhs1 = self.modpow(&self.alpha, &aggregate.s);
+        let mut lhs2 = self.modpow(&self.beta, &aggregate.s);
+        let mut rhs1 = BigUint::from(1u32);
+        let mut rhs2 = BigUint::from(1u32);
+
+        for ((statement, (r1, r2)), z) in
+            statements.iter().zip(&aggregate.commitments).zip(&weights)
+        {
+            let c = self.fiat_shamir_challenge(
+                &statement.y1,
+                &statement.y2,
+                r1,
+                r2,
+                &statement.context,
+            );
+            let cz = (c * z) % &self.q;
+
+            lhs1 = (lhs1 * self.modpow(&statement.y1, &cz)) % &self.p;
+            lhs2 = (lhs2 * self.modpow(&statement.y2, &cz)) % &self.p;
+            rhs1 = (rhs1 * self.modpow(r1, z)) % &self.p;
+            rhs2 = (rhs2 * self.modpow(r2, z)) % &self.p;
+        }
+
+        // Unreduced commitments would otherwise sneak through the products.
+        let reduced = aggregate
+            .commitments
+            .iter()
+            .all(|(r1, r2)| *r1 < self.p && *r2 < self.p);
+
+        if reduced && lhs1 == rhs1 && lhs2 == rhs2 {
+            Ok(())
+        } else {
+            Err(ZkpError::Verify)
+        }
+    }
+
+    /// z_i = H(batch digest, i) mod q, the batch digest covering every
+    /// statement and commitment so no proof can be swapped out afterwards.
+    fn aggregation_weights(
+        &self,
+        statements: &[Statement],
+        commitments: &[(BigUint, BigUint)],
+    ) -> Vec<BigUint> {
+        let mut hasher = Sha256::new();
+        for (statement, (r1, r2)) in statements.iter().zip(commitments) {
+            hasher.update(length_prefixed(&[
+                &statement.y1.to_bytes_be(),
+                &statement.y2.to_bytes_be(),
+                &statement.context,
+                &r1.to_bytes_be(),
+                &r2.to_bytes_be(),
+            ]));
+        }
+        let digest = hasher.finalize();
+
+        (0..statements.len() as u32)
+            .map(|i| {
+                expand_hash(
+                    b"zkp_chaum_pedersen/aggregate",
+                    &digest,
+                    i,
+                    self.scalar_hash_len(),
+                ) % &self.q
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn batch(zkp: &ZKP, n: usize) -> (Vec<Statement>, Vec<Proof>) {
+        (0..n)
+            .map(|i| {
+                let x = ZKP::generate_random_below(zkp.q());
+                let (y1, y2) = zkp.compute_pair(&x);
+                let context = format!("audit entry {i}").into_bytes();
+                let proof = zkp.prove_non_interactive(&x, &context);
+                (Statement { y1, y2, context }, proof)
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn test_aggregate_verifies() {
+        let zkp = ZKP::default();
+        let (statements, proofs) = batch(&zkp, 5);
+
+        let aggregate = zkp.aggregate_proofs(&statements, &proofs).unwrap();
+        assert!(zkp.verify_aggregate(&statements, &aggregate).is_ok());
+
+        // Reordering or dropping statements breaks it.
+        let mut reordered = statements.clone();
+        reordered.swap(0, 1);
+        assert!(zkp.verify_aggregate(&reordered, &aggregate).is_err());
+        assert!(zkp.verify_aggregate(&statements[1..], &aggregate).is_err());
+        assert!(zkp
+            .verify_aggregate(&[], &zkp.aggregate_proofs(&[], &[]).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_aggregate_with_bad_proof_fails() {
+        let zkp = ZKP::default();
+        let (statements, mut proofs) = batch(&zkp, 4);
+
+        proofs[2].s += 1u32;
+        let aggregate = zkp.aggregate_proofs(&statements, &proofs).unwrap();
+        assert!(zkp.verify_aggregate(&statements, &aggregate).is_err());
+
+        let (statements, proofs) = batch(&zkp, 4);
+        let aggregate = zkp.aggregate_proofs(&statements, &proofs).unwrap();
+        let mut other_context = statements.clone();
+        other_context[3].context = b"forged".to_vec();
+        assert!(zkp.verify_aggregate(&other_context, &aggregate).is_err());
+    }
+
+    #[test]
+    fn test_aggregate_rejects_mismatched_proofs() {
+        let zkp = ZKP::default();
+        let (statements, proofs) = batch(&zkp, 3);
+
+        assert!(matches!(
+            zkp.aggregate_proofs(&statements, &proofs[..2]),
+            Err(ZkpError::ProofCount {
+                statements: 3,
+                proofs: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_aggregate_rejects_expiring_proofs() {
+        let zkp = ZKP::default();
+        let (mut statements, mut proofs) = batch(&zkp, 2);
+
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        let context = b"expiring".to_vec();
+        proofs.push(zkp.prove_non_interactive_until(&x, &context, u64::MAX));
+        statements.push(Statement { y1, y2, context });
+
+        assert!(matches!(
+            zkp.aggregate_proofs(&statements, &proofs),
+            Err(ZkpError::ExpiringProof)
+        ));
+    }
+}
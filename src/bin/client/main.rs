@@ -1,20 +1,1010 @@
-use anyhow::anyhow;
-use zkp_auth::auth_client::AuthClient;
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-pub mod zkp_auth {
-    include!("../../zkp_auth.rs");
-}
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use num_bigint::BigUint;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+    codec::CompressionEncoding,
+    transport::{Certificate, Channel, ClientTlsConfig, Identity},
+};
+use tracing_subscriber::EnvFilter;
+use zkp_chaum_pedersen::{
+    certificate_fingerprint, error_code, qualified_name, server_identity,
+    zkp_auth::{
+        self, auth_client::AuthClient, authenticate_request, authenticate_response,
+        AddDeviceRequest, AuthenticateRequest, AuthenticationAnswerRequest,
+        AuthenticationAnswerResponse, AuthenticationChallengeRequest,
+        AuthenticationChallengeResponse, CapabilitiesRequest, ChallengeCommitmentRequest,
+        DeleteAccountRequest, ErrorCode, GroupParams, HelloRequest, ListDevicesRequest,
+        ListOwnSessionsRequest, LogoutRequest, OpenChallengeRequest, ParametersRequest,
+        RecoverAccountRequest, RegisterRequest, RegistrationPuzzleRequest, RevokeDeviceRequest,
+        RevokeOwnSessionRequest, SaltRequest, UpdateCredentialsRequest,
+    },
+    AuthId, ChallengeBinding, ChallengeOpening, ChallengeSpec, KdfParams, ParamSet, PowInput,
+    Proof, Secret, SessionId, SessionTranscript, ZkpError, PARAMS_VERSION, PROTOCOL_VERSION, ZKP,
+};
+
+const USAGE: &str = "Usage: client register <user> <password> [<key>=<value> ...]
+       client <login|login-four-move|login-stream> <user> <password>
+       client rotate <user> <password> <new_password>
+       client recover <user> <recovery_code> <new_password>
+       client add-device <user> <password> <label> <device_password>
+       client devices <user> <password>
+       client revoke-device <user> <password> <label>
+       client sessions <user> <password>
+       client revoke-session <user> <password> <handle>
+       client delete <user> <password>
+       client logout <session_id>
+       client capabilities";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenvy::dotenv().map_err(|err| anyhow!("Err: {err}"))?;
-    env_logger::try_init().map_err(|err| anyhow!("Err: {err}"))?;
+    run().await.map_err(explain)
+}
+
+/// `err` with a hint for the user when the server said why it failed.
+fn explain(err: anyhow::Error) -> anyhow::Error {
+    let hint = match err.downcast_ref::<tonic::Status>().and_then(error_code) {
+        Some(ErrorCode::UserNotFound) => "No such user, register first.",
+        Some(ErrorCode::ChallengeExpired) => "The challenge expired, log in again.",
+        Some(ErrorCode::ProofInvalid) => "Wrong password.",
+        Some(ErrorCode::LockedOut) => "Too many failed logins, wait before trying again.",
+        Some(ErrorCode::RateLimited) => "Too many requests, slow down.",
+        Some(ErrorCode::RequestReplayed) => "Request refused as a replay, check the clock.",
+        Some(ErrorCode::Unspecified) | None => return err,
+    };
+    err.context(hint)
+}
+
+async fn run() -> anyhow::Result<()> {
+    dotenvy::dotenv()?;
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .try_init()
+        .map_err(|err| anyhow::anyhow!(err))?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, args)) = args.split_first() else {
+        bail!(USAGE);
+    };
 
-    let client = AuthClient::connect("http://127.0.0.1:5051")
-        .await
-        .expect("Can't connect to the server.");
+    let mut client = AuthClient::new(connect().await.context("Can't connect to the server.")?);
+    if let Some(encoding) = compression()? {
+        client = client.send_compressed(encoding).accept_compressed(encoding);
+    }
 
     log::info!("Connected to the server.");
 
+    let zkp = server_group(&mut client).await?;
+    let realm = std::env::var("REALM").unwrap_or_default();
+
+    match (command.as_str(), args) {
+        ("register", [user, password, metadata @ ..]) => {
+            let metadata = metadata
+                .iter()
+                .map(|entry| {
+                    let (key, value) = entry
+                        .split_once('=')
+                        .with_context(|| format!("Metadata is key=value, not {entry}"))?;
+                    Ok((key.to_string(), value.to_string()))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let account = Account::new(&realm, user)?;
+            register(&mut client, &zkp, &account, password, metadata).await?;
+            log::info!("Registered user: {user}");
+        }
+        ("login" | "login-four-move" | "login-stream", [user, password]) => {
+            let flow = match command.as_str() {
+                "login-four-move" => Flow::FourMove,
+                "login-stream" => Flow::Stream,
+                _ => Flow::Classic,
+            };
+            let account = Account::new(&realm, user)?;
+            let session_id = login(&mut client, &zkp, &account, password, flow).await?;
+            log::info!("Logged in, session id: {}", session_id.as_str());
+        }
+        ("rotate", [user, password, new_password]) => {
+            let account = Account::new(&realm, user)?;
+            rotate(&mut client, &zkp, &account, password, new_password).await?;
+            log::info!("Rotated key for user: {user}");
+        }
+        ("recover", [user, recovery_code, new_password]) => {
+            let account = Account::new(&realm, user)?;
+            recover(&mut client, &zkp, &account, recovery_code, new_password).await?;
+            log::info!("Recovered user: {user}");
+        }
+        ("add-device", [user, password, label, device_password]) => {
+            let account = Account::new(&realm, user)?;
+            let session_id = login(&mut client, &zkp, &account, password, Flow::Classic).await?;
+            add_device(
+                &mut client,
+                &zkp,
+                &account,
+                &session_id,
+                label,
+                device_password,
+            )
+            .await?;
+            log::info!("Added device: {label}");
+        }
+        ("devices", [user, password]) => {
+            let account = Account::new(&realm, user)?;
+            let session_id = login(&mut client, &zkp, &account, password, Flow::Classic).await?;
+            let devices = client
+                .list_devices(ListDevicesRequest {
+                    session_id: session_id.into_string(),
+                })
+                .await?
+                .into_inner()
+                .devices;
+            for device in devices {
+                log::info!("Device: {} added at {}", device.label, device.added_at);
+            }
+        }
+        ("revoke-device", [user, password, label]) => {
+            let account = Account::new(&realm, user)?;
+            let session_id = login(&mut client, &zkp, &account, password, Flow::Classic).await?;
+            client
+                .revoke_device(RevokeDeviceRequest {
+                    session_id: session_id.into_string(),
+                    label: label.to_string(),
+                })
+                .await?;
+            log::info!("Revoked device: {label}");
+        }
+        ("sessions", [user, password]) => {
+            let account = Account::new(&realm, user)?;
+            let session_id = login(&mut client, &zkp, &account, password, Flow::Classic).await?;
+            let sessions = client
+                .list_own_sessions(ListOwnSessionsRequest {
+                    session_id: session_id.into_string(),
+                })
+                .await?
+                .into_inner()
+                .sessions;
+            for session in sessions {
+                log::info!(
+                    "Session: {} device: {:?} created at {}, last seen at {}{}",
+                    session.handle,
+                    session.device,
+                    session.created_at,
+                    session.last_seen,
+                    if session.current { " (this one)" } else { "" }
+                );
+            }
+        }
+        ("revoke-session", [user, password, handle]) => {
+            let account = Account::new(&realm, user)?;
+            let session_id = login(&mut client, &zkp, &account, password, Flow::Classic).await?;
+            client
+                .revoke_own_session(RevokeOwnSessionRequest {
+                    session_id: session_id.into_string(),
+                    handle: handle.to_string(),
+                })
+                .await?;
+            log::info!("Revoked session: {handle}");
+        }
+        ("delete", [user, password]) => {
+            delete(&mut client, &zkp, &Account::new(&realm, user)?, password).await?;
+            log::info!("Deleted user: {user}");
+        }
+        ("logout", [session_id]) => {
+            client
+                .logout(LogoutRequest {
+                    session_id: session_id.to_string(),
+                })
+                .await?;
+            log::info!("Logged out.");
+        }
+        ("capabilities", []) => {
+            let capabilities = client
+                .get_capabilities(CapabilitiesRequest {})
+                .await?
+                .into_inner();
+            log::info!("Server capabilities: {capabilities:#?}");
+        }
+        _ => bail!(USAGE),
+    }
+
+    Ok(())
+}
+
+/// A channel to the server, over TLS when TLS_CA_CERT or TLS_CA_CERT_FILE
+/// says which CA to trust. Presents the certificate from TLS_CLIENT_CERT and
+/// TLS_CLIENT_KEY (or their _FILE forms) to servers that ask for one.
+async fn connect() -> anyhow::Result<Channel> {
+    let Some(ca) = pem_from_env("TLS_CA_CERT")? else {
+        return Ok(Channel::from_static("http://127.0.0.1:5051")
+            .connect()
+            .await?);
+    };
+
+    let domain = std::env::var("TLS_DOMAIN").unwrap_or_else(|_| "localhost".to_string());
+    let mut tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(ca))
+        .domain_name(domain);
+    match (
+        pem_from_env("TLS_CLIENT_CERT")?,
+        pem_from_env("TLS_CLIENT_KEY")?,
+    ) {
+        (Some(cert), Some(key)) => tls = tls.identity(Identity::from_pem(cert, key)),
+        (None, None) => {}
+        _ => bail!("TLS_CLIENT_CERT and TLS_CLIENT_KEY are set together or not at all"),
+    }
+    Ok(Channel::from_static("https://127.0.0.1:5051")
+        .tls_config(tls)?
+        .connect()
+        .await?)
+}
+
+/// PEM from the variable `name`, or read from the file `name`_FILE.
+fn pem_from_env(name: &str) -> anyhow::Result<Option<String>> {
+    if let Ok(pem) = std::env::var(name) {
+        return Ok(Some(pem));
+    }
+    match std::env::var(format!("{name}_FILE")) {
+        Ok(path) => Ok(Some(
+            std::fs::read_to_string(path.trim())
+                .with_context(|| format!("Can't read {name}_FILE"))?,
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+/// `certificate_fingerprint` of the first certificate in TLS_CLIENT_CERT,
+/// what the server binds challenges to under mutual TLS.
+fn client_certificate() -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(pem) = pem_from_env("TLS_CLIENT_CERT")? else {
+        return Ok(None);
+    };
+    let (_, rest) = pem
+        .split_once("-----BEGIN CERTIFICATE-----")
+        .context("TLS_CLIENT_CERT holds no PEM certificate")?;
+    let (body, _) = rest
+        .split_once("-----END CERTIFICATE-----")
+        .context("TLS_CLIENT_CERT has an unterminated certificate")?;
+    let body: String = body.split_whitespace().collect();
+    let der = STANDARD
+        .decode(body)
+        .context("TLS_CLIENT_CERT is not valid PEM")?;
+    Ok(Some(certificate_fingerprint(&der)))
+}
+
+/// The group the server runs on, as GetParameters says.
+async fn server_group(client: &mut AuthClient<Channel>) -> anyhow::Result<ZKP> {
+    let parameters = client
+        .get_parameters(ParametersRequest {})
+        .await?
+        .into_inner();
+    let Some(group) = parameters.group else {
+        bail!("The server did not send its group.");
+    };
+    if parameters.version != PARAMS_VERSION {
+        log::warn!(
+            "The server has parameter sets version {}, this client {PARAMS_VERSION}.",
+            parameters.version
+        );
+    }
+
+    let zkp = group_from_proto(&group).context("The server sent an invalid group.")?;
+    match group.param_set.as_str() {
+        "" => log::info!("Server group: custom ({}-bit p)", zkp.p().bits()),
+        name => log::info!("Server group: {name}"),
+    }
+    Ok(zkp)
+}
+
+fn group_from_proto(group: &GroupParams) -> Result<ZKP, ZkpError> {
+    ZKP::builder()
+        .params_from_bytes(&group.p, &group.q, &group.alpha, &group.beta)
+        .build()
+}
+
+/// A user in the realm from REALM, the default realm when unset.
+struct Account {
+    realm: String,
+    name: String,
+    /// What challenges and proofs are bound to, see `qualified_name`.
+    qualified: String,
+}
+
+impl Account {
+    fn new(realm: &str, name: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            realm: realm.to_string(),
+            name: name.to_string(),
+            qualified: qualified_name(realm, name)?,
+        })
+    }
+}
+
+/// Derives x from a fresh salt and registers (y1, y2) together with that salt,
+/// solving the server's registration puzzle first if it sets one. The user
+/// lives in the group named by PARAM_SET, the server's when unset, and is
+/// enrolled in TOTP when TOTP is set.
+async fn register(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    password: &str,
+    metadata: HashMap<String, String>,
+) -> anyhow::Result<()> {
+    let param_set = param_set()?;
+    let zkp = &match param_set {
+        Some(set) => ZKP::builder().param_set(set).build()?,
+        None => zkp.clone(),
+    };
+
+    let salt = ZKP::generate_salt()?;
+    let kdf_params = KdfParams::default();
+    let x = zkp.derive_secret_from_password(password.as_bytes(), &salt, &kdf_params)?;
+    let (y1, y2) = zkp.compute_pair(x.expose());
+    let (y1, y2) = (y1.to_bytes_be(), y2.to_bytes_be());
+
+    let difficulty = client
+        .get_registration_puzzle(RegistrationPuzzleRequest {})
+        .await?
+        .into_inner()
+        .difficulty;
+    let pow_nonce = if difficulty > 0 {
+        log::info!("Solving a {difficulty}-bit registration puzzle.");
+        PowInput {
+            name: &account.qualified,
+            y1: &y1,
+            y2: &y2,
+            salt: &salt,
+        }
+        .solve(difficulty)
+    } else {
+        0
+    };
+
+    let response = client
+        .register(RegisterRequest {
+            name: account.name.clone(),
+            y1,
+            y2,
+            salt,
+            kdf_params: Some(zkp_auth::KdfParams {
+                m_cost: kdf_params.m_cost,
+                t_cost: kdf_params.t_cost,
+                p_cost: kdf_params.p_cost,
+            }),
+            pow_nonce,
+            realm: account.realm.clone(),
+            group: param_set.map(|set| GroupParams {
+                param_set: set.name().to_string(),
+                ..Default::default()
+            }),
+            metadata,
+            totp: std::env::var("TOTP").is_ok(),
+        })
+        .await?
+        .into_inner();
+    if !response.totp_secret.is_empty() {
+        log::info!(
+            "TOTP secret for the authenticator app: {}",
+            response.totp_secret
+        );
+    }
+    log::info!(
+        "Recovery codes, each good for one `client recover`: {}",
+        response.recovery_codes.join(" ")
+    );
+
+    Ok(())
+}
+
+/// Fetches the stored salt and the user's group, and re-derives x from the
+/// password in that group. With DEVICE set, the salt is that device's and
+/// the password is the device's.
+async fn fetch_secret(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    password: &str,
+) -> anyhow::Result<(Secret<BigUint>, ZKP)> {
+    let salt_response = client
+        .get_salt(SaltRequest {
+            user: account.name.clone(),
+            realm: account.realm.clone(),
+            device: std::env::var("DEVICE").unwrap_or_default(),
+        })
+        .await?
+        .into_inner();
+    let kdf_params = salt_response
+        .kdf_params
+        .map(|params| KdfParams {
+            m_cost: params.m_cost,
+            t_cost: params.t_cost,
+            p_cost: params.p_cost,
+        })
+        .unwrap_or_default();
+    let group = match salt_response.group {
+        Some(group) => {
+            group_from_proto(&group).context("The server sent an invalid group for the user.")?
+        }
+        None => zkp.clone(),
+    };
+
+    let x =
+        group.derive_secret_from_password(password.as_bytes(), &salt_response.salt, &kdf_params)?;
+    Ok((x, group))
+}
+
+/// The user's group, for new keys of theirs.
+async fn user_group(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+) -> anyhow::Result<ZKP> {
+    let group = client
+        .get_salt(SaltRequest {
+            user: account.name.clone(),
+            realm: account.realm.clone(),
+            device: String::new(),
+        })
+        .await?
+        .into_inner()
+        .group;
+    match group {
+        Some(group) => {
+            Ok(group_from_proto(&group)
+                .context("The server sent an invalid group for the user.")?)
+        }
+        None => Ok(zkp.clone()),
+    }
+}
+
+/// How a login gets its challenge across.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    /// CreateAuthenticationChallenge, then VerifyAuthentication.
+    Classic,
+    /// The server commits to c before it sees (r1, r2).
+    FourMove,
+    /// Both moves of the classic flow over one Authenticate stream.
+    Stream,
+}
+
+/// Fetches the stored salt, re-derives x and runs one challenge/answer round
+/// the way `flow` says, with the code from TOTP_CODE for users enrolled in
+/// TOTP.
+async fn login(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    password: &str,
+    flow: Flow,
+) -> anyhow::Result<SessionId> {
+    let (x, group) = fetch_secret(client, zkp, account, password).await?;
+    // The server's key is in the server's group, the user's may differ.
+    let server_key = pinned_server_key(zkp)?;
+    let challenge_bits = challenge_bits()?;
+
+    let k = ZKP::generate_random_below(group.q());
+    let (r1, r2) = group.compute_pair(&k);
+
+    let (auth_id, c, s, answer) = match flow {
+        Flow::Stream => {
+            streamed_login(
+                client,
+                &group,
+                account,
+                challenge_bits,
+                (&r1, &r2),
+                &server_key,
+                |c| group.solve(&k, c, x.expose()),
+            )
+            .await?
+        }
+        Flow::Classic | Flow::FourMove => {
+            let (auth_id, c) = if flow == Flow::FourMove {
+                committed_challenge(client, &group, account, challenge_bits, (&r1, &r2)).await?
+            } else {
+                bound_challenge(
+                    client,
+                    &group,
+                    account,
+                    challenge_bits,
+                    (&r1, &r2),
+                    &server_key,
+                )
+                .await?
+            };
+            let s = group.solve(&k, &c, x.expose());
+
+            let (nonce, timestamp) = replay_guard();
+            let answer = client
+                .verify_authentication(AuthenticationAnswerRequest {
+                    auth_id: auth_id.to_string(),
+                    s: s.to_bytes_be(),
+                    totp_code: totp_code(),
+                    nonce,
+                    timestamp,
+                })
+                .await?
+                .into_inner();
+            (auth_id, c, s, answer)
+        }
+    };
+
+    let transcript = SessionTranscript {
+        user: &account.qualified,
+        auth_id: auth_id.as_str(),
+        session_id: &answer.session_id,
+        r1: &r1,
+        r2: &r2,
+        c: &c,
+        s: &s,
+    };
+    match &server_key {
+        Some(server_key) => verify_server(zkp, server_key, &transcript, answer.server_proof)?,
+        None => log::warn!("SERVER_Y1 / SERVER_Y2 are not set, the server is not authenticated."),
+    }
+
+    Ok(SessionId::from(answer.session_id))
+}
+
+/// Classic login: agrees on a protocol version, sends (r1, r2), gets back
+/// (auth_id, c).
+async fn bound_challenge(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    challenge_bits: u32,
+    commitment: (&BigUint, &BigUint),
+    server_key: &Option<(BigUint, BigUint)>,
+) -> anyhow::Result<(AuthId, BigUint)> {
+    let version = hello(client).await?;
+    let request = challenge_request(account, challenge_bits, commitment, version);
+    let challenge = client
+        .create_authentication_challenge(request)
+        .await?
+        .into_inner();
+
+    let c = check_challenge(
+        zkp,
+        account,
+        challenge_bits,
+        commitment,
+        server_key,
+        version,
+        &challenge,
+    )?;
+    Ok((AuthId::from(challenge.auth_id), c))
+}
+
+/// The classic login over one Authenticate stream: sends (r1, r2), answers
+/// the challenge with `solve(c)` and gets back (auth_id, c, s, session).
+async fn streamed_login(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    challenge_bits: u32,
+    commitment: (&BigUint, &BigUint),
+    server_key: &Option<(BigUint, BigUint)>,
+    solve: impl FnOnce(&BigUint) -> BigUint,
+) -> anyhow::Result<(AuthId, BigUint, BigUint, AuthenticationAnswerResponse)> {
+    let version = hello(client).await?;
+    let (sender, receiver) = mpsc::channel(2);
+    sender
+        .send(AuthenticateRequest {
+            step: Some(authenticate_request::Step::Start(challenge_request(
+                account,
+                challenge_bits,
+                commitment,
+                version,
+            ))),
+        })
+        .await?;
+    let mut responses = client
+        .authenticate(ReceiverStream::new(receiver))
+        .await?
+        .into_inner();
+
+    let Some(authenticate_response::Step::Challenge(challenge)) = responses
+        .message()
+        .await?
+        .and_then(|response| response.step)
+    else {
+        bail!("The server didn't send a challenge.");
+    };
+    let c = check_challenge(
+        zkp,
+        account,
+        challenge_bits,
+        commitment,
+        server_key,
+        version,
+        &challenge,
+    )?;
+    let s = solve(&c);
+
+    // The stream knows the attempt, no need to name it.
+    let (nonce, timestamp) = replay_guard();
+    sender
+        .send(AuthenticateRequest {
+            step: Some(authenticate_request::Step::Answer(
+                AuthenticationAnswerRequest {
+                    auth_id: String::new(),
+                    s: s.to_bytes_be(),
+                    totp_code: totp_code(),
+                    nonce,
+                    timestamp,
+                },
+            )),
+        })
+        .await?;
+    let Some(authenticate_response::Step::Session(answer)) = responses
+        .message()
+        .await?
+        .and_then(|response| response.step)
+    else {
+        bail!("The server didn't send a session.");
+    };
+
+    Ok((AuthId::from(challenge.auth_id), c, s, answer))
+}
+
+/// The protocol version the server agrees to speak.
+async fn hello(client: &mut AuthClient<Channel>) -> anyhow::Result<u32> {
+    Ok(client
+        .hello(HelloRequest {
+            max_version: PROTOCOL_VERSION,
+        })
+        .await?
+        .into_inner()
+        .version)
+}
+
+fn challenge_request(
+    account: &Account,
+    challenge_bits: u32,
+    (r1, r2): (&BigUint, &BigUint),
+    version: u32,
+) -> AuthenticationChallengeRequest {
+    let (nonce, timestamp) = replay_guard();
+    AuthenticationChallengeRequest {
+        user: account.name.clone(),
+        r1: r1.to_bytes_be(),
+        r2: r2.to_bytes_be(),
+        challenge_bits,
+        realm: account.realm.clone(),
+        protocol_version: version,
+        max_protocol_version: PROTOCOL_VERSION,
+        nonce,
+        timestamp,
+    }
+}
+
+/// A fresh nonce and the time, for servers that refuse replayed requests.
+fn replay_guard() -> (Vec<u8>, u64) {
+    let nonce = rand::random::<[u8; 16]>().to_vec();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    (nonce, now)
+}
+
+/// The c of a classic challenge, checked against the binding when the
+/// server's key is pinned.
+fn check_challenge(
+    zkp: &ZKP,
+    account: &Account,
+    challenge_bits: u32,
+    (r1, r2): (&BigUint, &BigUint),
+    server_key: &Option<(BigUint, BigUint)>,
+    version: u32,
+    challenge: &AuthenticationChallengeResponse,
+) -> anyhow::Result<BigUint> {
+    let c = zkp.parse_scalar(&challenge.c)?;
+    if let Some((server_y1, server_y2)) = server_key {
+        // A challenge relayed from someone else's login was bound to their
+        // auth_id or to another server, answering it would log them in.
+        let server_id = server_identity(server_y1, server_y2);
+        let client_cert = client_certificate()?;
+        let binding = ChallengeBinding {
+            user: &account.qualified,
+            auth_id: &challenge.auth_id,
+            server_id: &server_id,
+            tls_exporter: None,
+            client_cert: client_cert.as_deref(),
+            version,
+            max_version: PROTOCOL_VERSION,
+        };
+        zkp.check_bound_challenge(
+            &challenge_spec(zkp, challenge_bits),
+            &challenge.challenge_seed,
+            &binding,
+            (r1, r2),
+            &c,
+        )
+        .context("The challenge is not bound to this login.")?;
+    }
+
+    Ok(c)
+}
+
+/// Four-move login: the server commits to c before it gets (r1, r2), then
+/// opens it.
+async fn committed_challenge(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    challenge_bits: u32,
+    (r1, r2): (&BigUint, &BigUint),
+) -> anyhow::Result<(AuthId, BigUint)> {
+    let committed = client
+        .commit_challenge(ChallengeCommitmentRequest {
+            user: account.name.clone(),
+            challenge_bits,
+            realm: account.realm.clone(),
+        })
+        .await?
+        .into_inner();
+
+    let opened = client
+        .open_challenge(OpenChallengeRequest {
+            auth_id: committed.auth_id.clone(),
+            r1: r1.to_bytes_be(),
+            r2: r2.to_bytes_be(),
+        })
+        .await?
+        .into_inner();
+
+    let opening = ChallengeOpening {
+        c: zkp.parse_scalar(&opened.c)?,
+        nonce: opened
+            .nonce
+            .as_slice()
+            .try_into()
+            .context("The challenge opening has a bad nonce.")?,
+    };
+    zkp.check_opening(
+        &challenge_spec(zkp, challenge_bits),
+        &account.qualified,
+        &committed.auth_id,
+        &committed.commitment,
+        &opening,
+    )
+    .context("The server opened a different challenge than it committed to.")?;
+
+    Ok((AuthId::from(committed.auth_id), opening.c))
+}
+
+/// What the server hands out for CHALLENGE_BITS.
+fn challenge_spec(zkp: &ZKP, challenge_bits: u32) -> ChallengeSpec {
+    match challenge_bits {
+        0 => ChallengeSpec::full(zkp),
+        bits => ChallengeSpec {
+            bits: bits.into(),
+            rounds: 1,
+        },
+    }
+}
+
+/// Optional CHALLENGE_BITS to ask for shorter challenges, 0 for full width.
+fn challenge_bits() -> anyhow::Result<u32> {
+    match std::env::var("CHALLENGE_BITS") {
+        Ok(bits) => bits
+            .trim()
+            .parse()
+            .context("CHALLENGE_BITS is not a number"),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Optional TOTP_CODE, the current code of users enrolled in TOTP.
+fn totp_code() -> String {
+    std::env::var("TOTP_CODE").unwrap_or_default()
+}
+
+/// Optional COMPRESSION, gzip or zstd, to send and receive messages in.
+fn compression() -> anyhow::Result<Option<CompressionEncoding>> {
+    match std::env::var("COMPRESSION") {
+        Ok(name) => match name.trim() {
+            "gzip" => Ok(Some(CompressionEncoding::Gzip)),
+            "zstd" => Ok(Some(CompressionEncoding::Zstd)),
+            _ => bail!("Unknown COMPRESSION: {name}"),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Optional PARAM_SET naming the group new users register in.
+fn param_set() -> anyhow::Result<Option<ParamSet>> {
+    match std::env::var("PARAM_SET") {
+        Ok(name) => ParamSet::from_name(name.trim())
+            .map(Some)
+            .with_context(|| format!("Unknown PARAM_SET: {name}")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The server public key pinned in SERVER_Y1 / SERVER_Y2 (hex), if any.
+fn pinned_server_key(zkp: &ZKP) -> anyhow::Result<Option<(BigUint, BigUint)>> {
+    let (Ok(server_y1), Ok(server_y2)) = (std::env::var("SERVER_Y1"), std::env::var("SERVER_Y2"))
+    else {
+        return Ok(None);
+    };
+    Ok(Some((
+        zkp.parse_element(&hex::decode(server_y1.trim())?)?,
+        zkp.parse_element(&hex::decode(server_y2.trim())?)?,
+    )))
+}
+
+/// Checks the server's proof over the login transcript against the pinned
+/// server public key.
+fn verify_server(
+    zkp: &ZKP,
+    (server_y1, server_y2): &(BigUint, BigUint),
+    transcript: &SessionTranscript,
+    server_proof: Option<zkp_auth::Proof>,
+) -> anyhow::Result<()> {
+    let Some(server_proof) = server_proof else {
+        bail!("The server did not prove its identity.");
+    };
+    let server_proof = Proof {
+        r1: zkp.parse_element(&server_proof.r1)?,
+        r2: zkp.parse_element(&server_proof.r2)?,
+        s: zkp.parse_scalar(&server_proof.s)?,
+        expires_at: (server_proof.expires_at != 0).then_some(server_proof.expires_at),
+    };
+
+    zkp.verify_session(server_y1, server_y2, transcript, &server_proof)
+        .context("The server proof does not match the pinned server key.")?;
+
+    log::info!("Server identity verified.");
+    Ok(())
+}
+
+/// Moves the user to a secret derived from `new_password` and a fresh salt.
+async fn rotate(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    password: &str,
+    new_password: &str,
+) -> anyhow::Result<()> {
+    let (old_x, group) = fetch_secret(client, zkp, account, password).await?;
+
+    let new_salt = ZKP::generate_salt()?;
+    let new_kdf_params = KdfParams::default();
+    let new_x =
+        group.derive_secret_from_password(new_password.as_bytes(), &new_salt, &new_kdf_params)?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let rotation =
+        group.prove_rotation(&account.qualified, old_x.expose(), new_x.expose(), now + 30);
+
+    client
+        .update_credentials(UpdateCredentialsRequest {
+            name: account.name.clone(),
+            new_y1: rotation.new_y1.to_bytes_be(),
+            new_y2: rotation.new_y2.to_bytes_be(),
+            old_proof: Some(proof_to_proto(&rotation.old_proof)),
+            new_proof: Some(proof_to_proto(&rotation.new_proof)),
+            new_salt,
+            new_kdf_params: Some(zkp_auth::KdfParams {
+                m_cost: new_kdf_params.m_cost,
+                t_cost: new_kdf_params.t_cost,
+                p_cost: new_kdf_params.p_cost,
+            }),
+            realm: account.realm.clone(),
+        })
+        .await?;
+
     Ok(())
 }
+
+/// Moves the user, who lost their password, to a secret derived from
+/// `new_password` and a fresh salt, with one of their recovery codes.
+async fn recover(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    recovery_code: &str,
+    new_password: &str,
+) -> anyhow::Result<()> {
+    // Only the group is needed, the old salt goes with the old password.
+    let group = user_group(client, zkp, account).await?;
+
+    let new_salt = ZKP::generate_salt()?;
+    let new_kdf_params = KdfParams::default();
+    let new_x =
+        group.derive_secret_from_password(new_password.as_bytes(), &new_salt, &new_kdf_params)?;
+    let recovery = group.prove_recovery(&account.qualified, new_x.expose());
+
+    client
+        .recover_account(RecoverAccountRequest {
+            name: account.name.clone(),
+            realm: account.realm.clone(),
+            recovery_code: recovery_code.to_string(),
+            new_y1: recovery.new_y1.to_bytes_be(),
+            new_y2: recovery.new_y2.to_bytes_be(),
+            new_proof: Some(proof_to_proto(&recovery.proof)),
+            new_salt,
+            new_kdf_params: Some(zkp_auth::KdfParams {
+                m_cost: new_kdf_params.m_cost,
+                t_cost: new_kdf_params.t_cost,
+                p_cost: new_kdf_params.p_cost,
+            }),
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Adds a key for the device `label`, derived from `device_password` and a
+/// fresh salt in the user's group, over the session of a login.
+async fn add_device(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    session_id: &SessionId,
+    label: &str,
+    device_password: &str,
+) -> anyhow::Result<()> {
+    let group = user_group(client, zkp, account).await?;
+    let salt = ZKP::generate_salt()?;
+    let kdf_params = KdfParams::default();
+    let x = group.derive_secret_from_password(device_password.as_bytes(), &salt, &kdf_params)?;
+    let key = group.prove_device(&account.qualified, label, x.expose());
+
+    client
+        .add_device(AddDeviceRequest {
+            session_id: session_id.as_str().to_string(),
+            label: label.to_string(),
+            y1: key.y1.to_bytes_be(),
+            y2: key.y2.to_bytes_be(),
+            proof: Some(proof_to_proto(&key.proof)),
+            salt,
+            kdf_params: Some(zkp_auth::KdfParams {
+                m_cost: kdf_params.m_cost,
+                t_cost: kdf_params.t_cost,
+                p_cost: kdf_params.p_cost,
+            }),
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes the user with a proof of x that is good for half a minute.
+async fn delete(
+    client: &mut AuthClient<Channel>,
+    zkp: &ZKP,
+    account: &Account,
+    password: &str,
+) -> anyhow::Result<()> {
+    let (x, group) = fetch_secret(client, zkp, account, password).await?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let proof = group.prove_deletion(&account.qualified, x.expose(), now + 30);
+
+    client
+        .delete_account(DeleteAccountRequest {
+            name: account.name.clone(),
+            proof: Some(proof_to_proto(&proof)),
+            session_id: String::new(),
+            realm: account.realm.clone(),
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn proof_to_proto(proof: &Proof) -> zkp_auth::Proof {
+    zkp_auth::Proof {
+        r1: proof.r1.to_bytes_be(),
+        r2: proof.r2.to_bytes_be(),
+        s: proof.s.to_bytes_be(),
+        expires_at: proof.expires_at.unwrap_or(0),
+    }
+}
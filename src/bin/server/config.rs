@@ -0,0 +1,1002 @@
+//! Server settings from, in order of precedence, command line flags, the
+//! environment (.env included), a TOML file and the built-in defaults. The
+//! flag `--some-name` is the variable SOME_NAME and the file key
+//! `some_name`, see .env.example for what each one does. The file is named
+//! by --config or CONFIG_FILE.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ipnet::IpNet;
+use num_bigint::BigUint;
+use serde::Deserialize;
+use tonic::{
+    codec::CompressionEncoding,
+    transport::{Certificate, Identity, ServerTlsConfig},
+};
+use tracing_subscriber::EnvFilter;
+use zkp_chaum_pedersen::{
+    server::{
+        backoff,
+        cleanup::{self, CleanupConfig},
+        deadline::{Deadlines, DEFAULT_RPC_TIMEOUT},
+        grpc_impl::auth::auth_impl::{
+            LockoutPolicy, DEFAULT_CHALLENGE_TTL, DEFAULT_LOCKOUT, DEFAULT_LOGIN_HISTORY,
+            DEFAULT_SESSION_TTL,
+        },
+        grpc_web::Cors,
+        ip_filter,
+        rate_limit::RateLimit,
+        retention,
+        telemetry::LogFormat,
+        webhook::{WebhookConfig, WebhookEvent, DEFAULT_FAILURE_STREAK},
+    },
+    ParamSet, MAX_POW_DIFFICULTY, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+
+/// Where the gRPC server listens unless configured otherwise.
+pub const DEFAULT_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5051));
+
+/// Largest gRPC request taken unless configured otherwise, tonic's default.
+pub const DEFAULT_MAX_DECODING_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// A compression gRPC messages can travel in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl From<Compression> for CompressionEncoding {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Gzip => CompressionEncoding::Gzip,
+            Compression::Zstd => CompressionEncoding::Zstd,
+        }
+    }
+}
+
+/// What the server does, serve when none is given. All but gen-params run
+/// on the store the server would serve.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Serves gRPC, and REST and metrics when configured.
+    Serve,
+    /// Prints a new SERVER_SECRET_KEY in the group of SERVER_PARAM_SET, and
+    /// the SERVER_Y1 and SERVER_Y2 clients pin.
+    GenParams,
+    /// Registers a user with the public key in a key file.
+    AddUser(AddUserArgs),
+    /// Prints the registered users, one per line.
+    ListUsers,
+    /// Writes every user and live session to an encrypted archive.
+    Export(BackupArgs),
+    /// Adds the users and sessions of an archive to the store, but for
+    /// users it already has.
+    Import(BackupArgs),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct AddUserArgs {
+    pub name: String,
+    /// TOML file with the user's y1, y2 and salt, see `key_file`.
+    pub key_file: PathBuf,
+    #[arg(long, default_value = "")]
+    pub realm: String,
+    /// Lets the user's sessions call AuthAdmin.
+    #[arg(long)]
+    pub admin: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct BackupArgs {
+    /// The archive.
+    pub file: PathBuf,
+    /// Passphrase the archive is encrypted under.
+    #[arg(long, env = "BACKUP_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: String,
+}
+
+/// One layer of settings, every one of them optional. Flags and variables
+/// are parsed into one, the file into another, and the first that has a
+/// value wins.
+#[derive(Default, Parser, Deserialize)]
+#[command(name = "server", about = "Chaum-Pedersen login server")]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// TOML file with settings, overridden by flags and variables.
+    #[arg(long, env = "CONFIG_FILE")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+
+    /// Comma separated IPv4 or IPv6 addresses to serve gRPC on.
+    #[arg(long, env = "GRPC_ADDR", value_delimiter = ',')]
+    pub grpc_addr: Option<Vec<SocketAddr>>,
+    /// Unix socket to serve gRPC on as well.
+    #[arg(long, env = "UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+    /// Serve on the Unix socket alone, without TCP.
+    #[arg(long, env = "UNIX_SOCKET_ONLY", num_args = 0..=1, default_missing_value = "true")]
+    pub unix_socket_only: Option<bool>,
+    /// Address of the JSON over HTTP gateway, off when unset.
+    #[arg(long, env = "REST_ADDR")]
+    pub rest_addr: Option<SocketAddr>,
+    /// Address Prometheus scrapes /metrics from, off when unset.
+    #[arg(long, env = "METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+    /// File security events are appended to, off when unset.
+    #[arg(long, env = "AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+    /// Comma separated URLs webhooks are POSTed to, off when unset.
+    #[arg(long, env = "WEBHOOK_URLS", value_delimiter = ',')]
+    pub webhook_urls: Option<Vec<String>>,
+    /// Hex encoded HMAC-SHA256 key webhooks are signed with.
+    #[arg(long, env = "WEBHOOK_SECRET", hide_env_values = true)]
+    pub webhook_secret: Option<String>,
+    /// Comma separated events to send webhooks for, all when unset.
+    #[arg(long, env = "WEBHOOK_EVENTS", value_delimiter = ',')]
+    pub webhook_events: Option<Vec<WebhookEvent>>,
+    /// Wrong answers in a row that make a failed_logins webhook.
+    #[arg(long, env = "WEBHOOK_FAILURE_STREAK")]
+    pub webhook_failure_streak: Option<u32>,
+    /// NATS server to publish auth events to, off when unset. Needs the
+    /// server built with the nats feature.
+    #[arg(long, env = "NATS_URL", hide_env_values = true)]
+    pub nats_url: Option<String>,
+    /// Subject events are published under, followed by the event's name.
+    #[arg(long, env = "NATS_SUBJECT")]
+    pub nats_subject: Option<String>,
+    /// Which lines are logged, e.g. info or zkp_chaum_pedersen=debug.
+    #[arg(long, env = "RUST_LOG")]
+    pub log_filter: Option<String>,
+    /// Log lines as text or JSON.
+    #[arg(long, env = "LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+    /// OTLP collector to send traces to over gRPC, off when unset.
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// Origins whose pages may call over gRPC-Web, comma separated or *.
+    #[arg(long, env = "CORS_ALLOWED_ORIGINS")]
+    pub cors_allowed_origins: Option<String>,
+    /// Serve gRPC reflection, for grpcurl and grpcui.
+    #[arg(long, env = "GRPC_REFLECTION", num_args = 0..=1, default_missing_value = "true")]
+    pub grpc_reflection: Option<bool>,
+    /// Largest gRPC request taken, in bytes.
+    #[arg(long, env = "MAX_DECODING_MESSAGE_SIZE")]
+    pub max_decoding_message_size: Option<usize>,
+    /// Largest gRPC response sent, in bytes.
+    #[arg(long, env = "MAX_ENCODING_MESSAGE_SIZE")]
+    pub max_encoding_message_size: Option<usize>,
+    /// Comma separated compressions the Auth service takes and answers in.
+    #[arg(long, env = "AUTH_COMPRESSION", value_delimiter = ',')]
+    pub auth_compression: Option<Vec<Compression>>,
+    /// Comma separated compressions the AuthAdmin service takes and answers
+    /// in.
+    #[arg(long, env = "ADMIN_COMPRESSION", value_delimiter = ',')]
+    pub admin_compression: Option<Vec<Compression>>,
+    /// gRPC calls handled at once, those beyond are refused.
+    #[arg(long, env = "MAX_CONCURRENT_CALLS")]
+    pub max_concurrent_calls: Option<usize>,
+    /// gRPC calls handled at once per connection, those beyond wait.
+    #[arg(long, env = "MAX_CONCURRENT_CALLS_PER_CONNECTION")]
+    pub max_concurrent_calls_per_connection: Option<usize>,
+    /// Seconds a gRPC call has to be answered in.
+    #[arg(long, env = "RPC_TIMEOUT_SECS")]
+    pub rpc_timeout_secs: Option<u64>,
+    /// Comma separated Method=seconds deadlines, over RPC_TIMEOUT_SECS.
+    #[arg(long, env = "RPC_METHOD_TIMEOUTS", value_delimiter = ',')]
+    pub rpc_method_timeouts: Option<Vec<String>>,
+
+    /// PEM certificate chain to serve TLS with.
+    #[arg(long, env = "TLS_CERT", hide_env_values = true)]
+    pub tls_cert: Option<String>,
+    #[arg(long, env = "TLS_CERT_FILE")]
+    pub tls_cert_file: Option<PathBuf>,
+    /// PEM private key of the certificate.
+    #[arg(long, env = "TLS_KEY", hide_env_values = true)]
+    pub tls_key: Option<String>,
+    #[arg(long, env = "TLS_KEY_FILE")]
+    pub tls_key_file: Option<PathBuf>,
+    /// PEM CA bundle client certificates must chain to.
+    #[arg(long, env = "TLS_CLIENT_CA", hide_env_values = true)]
+    pub tls_client_ca: Option<String>,
+    #[arg(long, env = "TLS_CLIENT_CA_FILE")]
+    pub tls_client_ca_file: Option<PathBuf>,
+    /// Let clients without a certificate in too.
+    #[arg(long, env = "TLS_CLIENT_AUTH_OPTIONAL", num_args = 0..=1, default_missing_value = "true")]
+    pub tls_client_auth_optional: Option<bool>,
+
+    /// Where users are stored: a SQLite URL or path, postgres:// or sled://.
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    pub database_url: Option<String>,
+    /// Redis for challenges, sessions and rate limits shared by replicas.
+    #[arg(long, env = "REDIS_URL", hide_env_values = true)]
+    pub redis_url: Option<String>,
+    /// Shards of each in-memory map, without DATABASE_URL.
+    #[arg(long, env = "MEMORY_STORE_SHARDS")]
+    pub memory_store_shards: Option<usize>,
+    /// Run as one of many replicas sharing every state in the stores.
+    #[arg(long, env = "CLUSTER", num_args = 0..=1, default_missing_value = "true")]
+    pub cluster: Option<bool>,
+
+    /// Group the server's key lives in, and users that don't bring their own.
+    #[arg(long, env = "SERVER_PARAM_SET")]
+    pub server_param_set: Option<String>,
+    /// Hex encoded server secret key.
+    #[arg(long, env = "SERVER_SECRET_KEY", hide_env_values = true)]
+    pub server_secret_key: Option<String>,
+    /// Hex encoded HS256 key to sign sessions as JWTs with.
+    #[arg(long, env = "SESSION_JWT_SECRET", hide_env_values = true)]
+    pub session_jwt_secret: Option<String>,
+    /// Bearer token of the AuthAdmin service.
+    #[arg(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+    /// Let sessions of users with the admin role call AuthAdmin too.
+    #[arg(long, env = "ADMIN_SESSIONS", num_args = 0..=1, default_missing_value = "true")]
+    pub admin_sessions: Option<bool>,
+
+    /// Seconds a challenge can be answered for.
+    #[arg(long, env = "CHALLENGE_TTL_SECS")]
+    pub challenge_ttl_secs: Option<u64>,
+    /// Seconds a session lasts after login or refresh, or after its last
+    /// use with SESSION_MAX_LIFETIME_SECS.
+    #[arg(long, env = "SESSION_TTL_SECS")]
+    pub session_ttl_secs: Option<u64>,
+    /// Seconds a session lasts after login at most, with each use pushing
+    /// its expiry out by SESSION_TTL_SECS until then. Unset, uses don't.
+    #[arg(long, env = "SESSION_MAX_LIFETIME_SECS")]
+    pub session_max_lifetime_secs: Option<u64>,
+    /// Wrong answers in a row that lock a user out, 0 for never.
+    #[arg(long, env = "LOCKOUT_THRESHOLD")]
+    pub lockout_threshold: Option<u32>,
+    /// Seconds a lockout lasts.
+    #[arg(long, env = "LOCKOUT_SECS")]
+    pub lockout_secs: Option<u64>,
+    /// Milliseconds a first wrong answer is held back, doubling with each
+    /// in a row, 0 for never.
+    #[arg(long, env = "BACKOFF_BASE_MS")]
+    pub backoff_base_ms: Option<u64>,
+    /// Most milliseconds a wrong answer is held back.
+    #[arg(long, env = "BACKOFF_MAX_MS")]
+    pub backoff_max_ms: Option<u64>,
+    /// Seconds between purges of expired challenges, sessions and
+    /// lockouts, 0 for never.
+    #[arg(long, env = "CLEANUP_INTERVAL_SECS")]
+    pub cleanup_interval_secs: Option<u64>,
+    /// Expired records removed per store call of a purge.
+    #[arg(long, env = "CLEANUP_BATCH_SIZE")]
+    pub cleanup_batch_size: Option<usize>,
+    /// Days after which users that haven't logged in are deleted.
+    #[arg(long, env = "USER_RETENTION_DAYS")]
+    pub user_retention_days: Option<u32>,
+    /// Login attempts kept per user for AuthAdmin, 0 for none.
+    #[arg(long, env = "LOGIN_HISTORY_SIZE")]
+    pub login_history_size: Option<usize>,
+
+    /// Requests a minute per client IP and per user.
+    #[arg(long, env = "RATE_LIMIT_PER_MINUTE")]
+    pub rate_limit_per_minute: Option<u32>,
+    /// Seconds a challenge or answer request's timestamp may be off, and
+    /// its nonce is remembered for. Unset, replays aren't refused.
+    #[arg(long, env = "REPLAY_WINDOW_SECS")]
+    pub replay_window_secs: Option<u64>,
+    /// Comma separated addresses and CIDR blocks that may call, any when
+    /// unset.
+    #[arg(long, env = "IP_ALLOWLIST", value_delimiter = ',')]
+    pub ip_allowlist: Option<Vec<String>>,
+    /// Comma separated addresses and CIDR blocks that may not call.
+    #[arg(long, env = "IP_DENYLIST", value_delimiter = ',')]
+    pub ip_denylist: Option<Vec<String>>,
+    /// Leading zero bits of registration proof of work.
+    #[arg(long, env = "POW_DIFFICULTY")]
+    pub pow_difficulty: Option<u32>,
+    /// Oldest login protocol version accepted.
+    #[arg(long, env = "MIN_PROTOCOL_VERSION")]
+    pub min_protocol_version: Option<u32>,
+}
+
+impl Settings {
+    /// Flags and variables over the file they name, if any.
+    pub fn load() -> anyhow::Result<Self> {
+        let settings = Self::parse();
+        let file = match &settings.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        Ok(settings.or(file))
+    }
+
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        toml::from_str(toml).context("Bad configuration file")
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("Can't read {}", path.display()))?;
+        Self::from_toml(&toml).with_context(|| format!("In {}", path.display()))
+    }
+
+    /// Every setting of `self`, and those of `fallback` it leaves unset.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            config: self.config.or(fallback.config),
+            command: self.command.or(fallback.command),
+            grpc_addr: self.grpc_addr.or(fallback.grpc_addr),
+            unix_socket: self.unix_socket.or(fallback.unix_socket),
+            unix_socket_only: self.unix_socket_only.or(fallback.unix_socket_only),
+            rest_addr: self.rest_addr.or(fallback.rest_addr),
+            metrics_addr: self.metrics_addr.or(fallback.metrics_addr),
+            audit_log: self.audit_log.or(fallback.audit_log),
+            webhook_urls: self.webhook_urls.or(fallback.webhook_urls),
+            webhook_secret: self.webhook_secret.or(fallback.webhook_secret),
+            webhook_events: self.webhook_events.or(fallback.webhook_events),
+            webhook_failure_streak: self
+                .webhook_failure_streak
+                .or(fallback.webhook_failure_streak),
+            nats_url: self.nats_url.or(fallback.nats_url),
+            nats_subject: self.nats_subject.or(fallback.nats_subject),
+            log_filter: self.log_filter.or(fallback.log_filter),
+            log_format: self.log_format.or(fallback.log_format),
+            otlp_endpoint: self.otlp_endpoint.or(fallback.otlp_endpoint),
+            cors_allowed_origins: self.cors_allowed_origins.or(fallback.cors_allowed_origins),
+            grpc_reflection: self.grpc_reflection.or(fallback.grpc_reflection),
+            max_decoding_message_size: self
+                .max_decoding_message_size
+                .or(fallback.max_decoding_message_size),
+            max_encoding_message_size: self
+                .max_encoding_message_size
+                .or(fallback.max_encoding_message_size),
+            auth_compression: self.auth_compression.or(fallback.auth_compression),
+            admin_compression: self.admin_compression.or(fallback.admin_compression),
+            max_concurrent_calls: self.max_concurrent_calls.or(fallback.max_concurrent_calls),
+            max_concurrent_calls_per_connection: self
+                .max_concurrent_calls_per_connection
+                .or(fallback.max_concurrent_calls_per_connection),
+            rpc_timeout_secs: self.rpc_timeout_secs.or(fallback.rpc_timeout_secs),
+            rpc_method_timeouts: self.rpc_method_timeouts.or(fallback.rpc_method_timeouts),
+            tls_cert: self.tls_cert.or(fallback.tls_cert),
+            tls_cert_file: self.tls_cert_file.or(fallback.tls_cert_file),
+            tls_key: self.tls_key.or(fallback.tls_key),
+            tls_key_file: self.tls_key_file.or(fallback.tls_key_file),
+            tls_client_ca: self.tls_client_ca.or(fallback.tls_client_ca),
+            tls_client_ca_file: self.tls_client_ca_file.or(fallback.tls_client_ca_file),
+            tls_client_auth_optional: self
+                .tls_client_auth_optional
+                .or(fallback.tls_client_auth_optional),
+            database_url: self.database_url.or(fallback.database_url),
+            redis_url: self.redis_url.or(fallback.redis_url),
+            memory_store_shards: self.memory_store_shards.or(fallback.memory_store_shards),
+            cluster: self.cluster.or(fallback.cluster),
+            server_param_set: self.server_param_set.or(fallback.server_param_set),
+            server_secret_key: self.server_secret_key.or(fallback.server_secret_key),
+            session_jwt_secret: self.session_jwt_secret.or(fallback.session_jwt_secret),
+            admin_token: self.admin_token.or(fallback.admin_token),
+            admin_sessions: self.admin_sessions.or(fallback.admin_sessions),
+            challenge_ttl_secs: self.challenge_ttl_secs.or(fallback.challenge_ttl_secs),
+            session_ttl_secs: self.session_ttl_secs.or(fallback.session_ttl_secs),
+            session_max_lifetime_secs: self
+                .session_max_lifetime_secs
+                .or(fallback.session_max_lifetime_secs),
+            lockout_threshold: self.lockout_threshold.or(fallback.lockout_threshold),
+            lockout_secs: self.lockout_secs.or(fallback.lockout_secs),
+            backoff_base_ms: self.backoff_base_ms.or(fallback.backoff_base_ms),
+            backoff_max_ms: self.backoff_max_ms.or(fallback.backoff_max_ms),
+            cleanup_interval_secs: self
+                .cleanup_interval_secs
+                .or(fallback.cleanup_interval_secs),
+            cleanup_batch_size: self.cleanup_batch_size.or(fallback.cleanup_batch_size),
+            user_retention_days: self.user_retention_days.or(fallback.user_retention_days),
+            login_history_size: self.login_history_size.or(fallback.login_history_size),
+            rate_limit_per_minute: self
+                .rate_limit_per_minute
+                .or(fallback.rate_limit_per_minute),
+            replay_window_secs: self.replay_window_secs.or(fallback.replay_window_secs),
+            ip_allowlist: self.ip_allowlist.or(fallback.ip_allowlist),
+            ip_denylist: self.ip_denylist.or(fallback.ip_denylist),
+            pow_difficulty: self.pow_difficulty.or(fallback.pow_difficulty),
+            min_protocol_version: self.min_protocol_version.or(fallback.min_protocol_version),
+        }
+    }
+}
+
+/// The settings checked and with their defaults filled in, but for
+/// LOG_FORMAT and OTEL_EXPORTER_OTLP_ENDPOINT. Those set up logging first, so
+/// the checks can log.
+pub struct Config {
+    /// None serves.
+    pub command: Option<Command>,
+    pub grpc_addrs: Vec<SocketAddr>,
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_only: bool,
+    pub rest_addr: Option<SocketAddr>,
+    pub metrics_addr: Option<SocketAddr>,
+    pub audit_log: Option<PathBuf>,
+    /// RUST_LOG directives, None for the default.
+    pub log_filter: Option<String>,
+    /// None sends no webhooks.
+    pub webhooks: Option<WebhookConfig>,
+    /// None publishes no events.
+    #[cfg(feature = "nats")]
+    pub nats: Option<zkp_chaum_pedersen::server::nats::NatsConfig>,
+    pub cors: Cors,
+    pub grpc_reflection: bool,
+    pub max_decoding_message_size: usize,
+    /// usize::MAX is unlimited.
+    pub max_encoding_message_size: usize,
+    /// In order of preference, none to neither take nor send compressed
+    /// messages.
+    pub auth_compression: Vec<CompressionEncoding>,
+    pub admin_compression: Vec<CompressionEncoding>,
+    /// None is unlimited.
+    pub max_concurrent_calls: Option<usize>,
+    pub max_concurrent_calls_per_connection: Option<usize>,
+    pub deadlines: Deadlines,
+    /// None serves plaintext.
+    pub tls: Option<ServerTlsConfig>,
+    pub database_url: Option<String>,
+    pub redis_url: Option<String>,
+    /// None leaves it to DashMap.
+    pub memory_store_shards: Option<usize>,
+    /// Replicas go by the settings admins change in the store, see `cluster`.
+    pub cluster: bool,
+    pub param_set: ParamSet,
+    /// None generates a throwaway key for the run.
+    pub server_secret_key: Option<BigUint>,
+    pub session_jwt_secret: Option<Vec<u8>>,
+    /// None turns the admin service off, unless `admin_sessions`.
+    pub admin_token: Option<String>,
+    pub admin_sessions: bool,
+    pub challenge_ttl: Duration,
+    pub session_ttl: Duration,
+    /// Some for sliding session expiry.
+    pub session_max_lifetime: Option<Duration>,
+    pub lockout: LockoutPolicy,
+    /// Zero holds no wrong answer back.
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+    /// None turns the purge of expired records off.
+    pub cleanup: Option<CleanupConfig>,
+    /// None keeps users however long they go unused.
+    pub user_retention: Option<Duration>,
+    /// 0 keeps none.
+    pub login_history: usize,
+    /// None is unlimited.
+    pub rate_limit: Option<RateLimit>,
+    /// None takes replayed requests.
+    pub replay_window: Option<Duration>,
+    /// Empty lets every address in.
+    pub ip_allowlist: Vec<IpNet>,
+    pub ip_denylist: Vec<IpNet>,
+    pub pow_difficulty: u32,
+    pub min_protocol_version: u32,
+}
+
+impl Config {
+    pub fn from_settings(settings: Settings) -> anyhow::Result<Self> {
+        let grpc_addrs = settings.grpc_addr.unwrap_or_else(|| vec![DEFAULT_ADDR]);
+        anyhow::ensure!(!grpc_addrs.is_empty(), "GRPC_ADDR names no address");
+        let unix_socket_only = settings.unix_socket_only.unwrap_or(false);
+        if unix_socket_only && settings.unix_socket.is_none() {
+            anyhow::bail!("UNIX_SOCKET_ONLY needs UNIX_SOCKET");
+        }
+
+        let cors = match &settings.cors_allowed_origins {
+            Some(origins) => {
+                let cors = Cors::parse(origins)
+                    .context("CORS_ALLOWED_ORIGINS is not a list of origins")?;
+                log::info!("Allowing cross-origin calls from: {origins}");
+                cors
+            }
+            None => Cors::SameOrigin,
+        };
+
+        let webhooks = webhook_config(
+            settings.webhook_urls,
+            settings.webhook_secret,
+            settings.webhook_events,
+            settings.webhook_failure_streak,
+        )?;
+
+        #[cfg(feature = "nats")]
+        let nats = settings
+            .nats_url
+            .map(|url| {
+                let subject = settings.nats_subject.unwrap_or_else(|| {
+                    zkp_chaum_pedersen::server::nats::DEFAULT_SUBJECT.to_string()
+                });
+                anyhow::ensure!(
+                    zkp_chaum_pedersen::server::nats::is_subject(&subject),
+                    "NATS_SUBJECT is not a subject without wildcards: {subject}"
+                );
+                Ok(zkp_chaum_pedersen::server::nats::NatsConfig { url, subject })
+            })
+            .transpose()?;
+        #[cfg(not(feature = "nats"))]
+        if settings.nats_url.is_some() {
+            anyhow::bail!("NATS_URL needs the server built with --features nats");
+        }
+
+        let rpc_timeout = settings
+            .rpc_timeout_secs
+            .map_or(DEFAULT_RPC_TIMEOUT, Duration::from_secs);
+        anyhow::ensure!(!rpc_timeout.is_zero(), "RPC_TIMEOUT_SECS is at least 1");
+        let deadlines = Deadlines {
+            default: rpc_timeout,
+            methods: Deadlines::parse_methods(
+                settings.rpc_method_timeouts.as_deref().unwrap_or_default(),
+            )
+            .context("Bad RPC_METHOD_TIMEOUTS")?,
+        };
+
+        anyhow::ensure!(
+            settings.max_concurrent_calls != Some(0)
+                && settings.max_concurrent_calls_per_connection != Some(0),
+            "MAX_CONCURRENT_CALLS and MAX_CONCURRENT_CALLS_PER_CONNECTION are at least 1"
+        );
+
+        let cleanup_interval = settings
+            .cleanup_interval_secs
+            .map_or(cleanup::DEFAULT_INTERVAL, Duration::from_secs);
+        let cleanup_batch_size = settings
+            .cleanup_batch_size
+            .unwrap_or(cleanup::DEFAULT_BATCH_SIZE);
+        anyhow::ensure!(cleanup_batch_size > 0, "CLEANUP_BATCH_SIZE is at least 1");
+        anyhow::ensure!(
+            settings.user_retention_days != Some(0),
+            "USER_RETENTION_DAYS is at least 1"
+        );
+        let cleanup = (!cleanup_interval.is_zero()).then_some(CleanupConfig {
+            interval: cleanup_interval,
+            batch_size: cleanup_batch_size,
+        });
+
+        let tls = tls_config(
+            pem(settings.tls_cert, settings.tls_cert_file, "TLS_CERT")?,
+            pem(settings.tls_key, settings.tls_key_file, "TLS_KEY")?,
+            pem(
+                settings.tls_client_ca,
+                settings.tls_client_ca_file,
+                "TLS_CLIENT_CA",
+            )?,
+            settings.tls_client_auth_optional.unwrap_or(false),
+        )?;
+
+        let param_set = match &settings.server_param_set {
+            Some(name) => ParamSet::from_name(name.trim())
+                .with_context(|| format!("Unknown SERVER_PARAM_SET: {name}"))?,
+            None => ParamSet::default(),
+        };
+        let server_secret_key = settings
+            .server_secret_key
+            .map(|secret| hex::decode(secret.trim()).context("SERVER_SECRET_KEY is not valid hex"))
+            .transpose()?
+            .map(|secret| BigUint::from_bytes_be(&secret));
+        let session_jwt_secret = settings
+            .session_jwt_secret
+            .map(|secret| hex::decode(secret.trim()).context("SESSION_JWT_SECRET is not valid hex"))
+            .transpose()?;
+
+        anyhow::ensure!(
+            settings
+                .memory_store_shards
+                .is_none_or(|shards| shards > 1 && shards.is_power_of_two()),
+            "MEMORY_STORE_SHARDS is a power of two above 1"
+        );
+        let cluster = settings.cluster.unwrap_or(false);
+        if cluster {
+            anyhow::ensure!(
+                settings.database_url.as_deref().is_some_and(is_postgres),
+                "CLUSTER needs DATABASE_URL to name a PostgreSQL server, replicas can't share the other stores"
+            );
+            anyhow::ensure!(
+                server_secret_key.is_some(),
+                "CLUSTER needs SERVER_SECRET_KEY, replicas would have a key each"
+            );
+            anyhow::ensure!(
+                settings.rate_limit_per_minute.is_none() || settings.redis_url.is_some(),
+                "CLUSTER needs REDIS_URL to share the RATE_LIMIT_PER_MINUTE buckets"
+            );
+        }
+
+        let pow_difficulty = settings.pow_difficulty.unwrap_or(0);
+        anyhow::ensure!(
+            pow_difficulty <= MAX_POW_DIFFICULTY,
+            "POW_DIFFICULTY is at most {MAX_POW_DIFFICULTY}"
+        );
+        let min_protocol_version = settings
+            .min_protocol_version
+            .unwrap_or(MIN_PROTOCOL_VERSION);
+        anyhow::ensure!(
+            min_protocol_version <= PROTOCOL_VERSION,
+            "MIN_PROTOCOL_VERSION is at most {PROTOCOL_VERSION}"
+        );
+        let backoff_base = settings
+            .backoff_base_ms
+            .map_or(backoff::DEFAULT_BASE, Duration::from_millis);
+        let backoff_max = settings
+            .backoff_max_ms
+            .map_or(backoff::DEFAULT_MAX, Duration::from_millis);
+        anyhow::ensure!(
+            backoff_max >= backoff_base,
+            "BACKOFF_MAX_MS is at least BACKOFF_BASE_MS"
+        );
+        let session_ttl = settings
+            .session_ttl_secs
+            .map_or(DEFAULT_SESSION_TTL, Duration::from_secs);
+        let session_max_lifetime = settings.session_max_lifetime_secs.map(Duration::from_secs);
+        anyhow::ensure!(
+            session_max_lifetime.is_none_or(|max| max >= session_ttl),
+            "SESSION_MAX_LIFETIME_SECS is at least SESSION_TTL_SECS"
+        );
+        anyhow::ensure!(
+            settings.replay_window_secs != Some(0),
+            "REPLAY_WINDOW_SECS is at least 1"
+        );
+        let cidrs = |list: Option<Vec<String>>, name: &str| {
+            list.unwrap_or_default()
+                .iter()
+                .map(|cidr| ip_filter::parse_cidr(cidr))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| anyhow::anyhow!("{name}: {err}"))
+        };
+        let ip_allowlist = cidrs(settings.ip_allowlist, "IP_ALLOWLIST")?;
+        let ip_denylist = cidrs(settings.ip_denylist, "IP_DENYLIST")?;
+        if let Some(directives) = &settings.log_filter {
+            EnvFilter::try_new(directives).context("Bad RUST_LOG")?;
+        }
+
+        Ok(Self {
+            command: settings.command,
+            grpc_addrs,
+            unix_socket: settings.unix_socket,
+            unix_socket_only,
+            rest_addr: settings.rest_addr,
+            metrics_addr: settings.metrics_addr,
+            audit_log: settings.audit_log,
+            log_filter: settings.log_filter,
+            webhooks,
+            #[cfg(feature = "nats")]
+            nats,
+            cors,
+            grpc_reflection: settings.grpc_reflection.unwrap_or(false),
+            max_decoding_message_size: settings
+                .max_decoding_message_size
+                .unwrap_or(DEFAULT_MAX_DECODING_MESSAGE_SIZE),
+            max_encoding_message_size: settings.max_encoding_message_size.unwrap_or(usize::MAX),
+            auth_compression: encodings(settings.auth_compression),
+            admin_compression: encodings(settings.admin_compression),
+            max_concurrent_calls: settings.max_concurrent_calls,
+            max_concurrent_calls_per_connection: settings.max_concurrent_calls_per_connection,
+            deadlines,
+            tls,
+            database_url: settings.database_url,
+            redis_url: settings.redis_url,
+            memory_store_shards: settings.memory_store_shards,
+            cluster,
+            param_set,
+            server_secret_key,
+            session_jwt_secret,
+            admin_token: settings.admin_token.map(|token| token.trim().to_string()),
+            admin_sessions: settings.admin_sessions.unwrap_or(false),
+            challenge_ttl: settings
+                .challenge_ttl_secs
+                .map_or(DEFAULT_CHALLENGE_TTL, Duration::from_secs),
+            session_ttl,
+            session_max_lifetime,
+            lockout: LockoutPolicy {
+                threshold: settings
+                    .lockout_threshold
+                    .unwrap_or(DEFAULT_LOCKOUT.threshold),
+                cooldown: settings
+                    .lockout_secs
+                    .map_or(DEFAULT_LOCKOUT.cooldown, Duration::from_secs),
+            },
+            backoff_base,
+            backoff_max,
+            cleanup,
+            user_retention: settings.user_retention_days.map(retention::days),
+            login_history: settings.login_history_size.unwrap_or(DEFAULT_LOGIN_HISTORY),
+            rate_limit: settings.rate_limit_per_minute.map(|requests| RateLimit {
+                requests,
+                period: Duration::from_secs(60),
+            }),
+            replay_window: settings.replay_window_secs.map(Duration::from_secs),
+            ip_allowlist,
+            ip_denylist,
+            pow_difficulty,
+            min_protocol_version,
+        })
+    }
+}
+
+/// Whether DATABASE_URL names a PostgreSQL server.
+pub fn is_postgres(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+fn encodings(compression: Option<Vec<Compression>>) -> Vec<CompressionEncoding> {
+    compression
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompressionEncoding::from)
+        .collect()
+}
+
+/// Webhooks to the URLs, signed with the hex secret they need. Every event
+/// is sent unless `events` says otherwise.
+fn webhook_config(
+    urls: Option<Vec<String>>,
+    secret: Option<String>,
+    events: Option<Vec<WebhookEvent>>,
+    failure_streak: Option<u32>,
+) -> anyhow::Result<Option<WebhookConfig>> {
+    let Some(urls) = urls else {
+        if secret.is_some() {
+            anyhow::bail!("WEBHOOK_SECRET needs WEBHOOK_URLS");
+        }
+        return Ok(None);
+    };
+    let urls = urls
+        .iter()
+        .map(|url| {
+            url.trim()
+                .parse()
+                .with_context(|| format!("Bad URL in WEBHOOK_URLS: {url}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(!urls.is_empty(), "WEBHOOK_URLS names no URL");
+    let secret = secret.context("WEBHOOK_URLS needs WEBHOOK_SECRET")?;
+    let secret = hex::decode(secret.trim()).context("WEBHOOK_SECRET is not valid hex")?;
+    anyhow::ensure!(!secret.is_empty(), "WEBHOOK_SECRET is empty");
+    let failure_streak = failure_streak.unwrap_or(DEFAULT_FAILURE_STREAK);
+    anyhow::ensure!(failure_streak > 0, "WEBHOOK_FAILURE_STREAK is at least 1");
+    Ok(Some(WebhookConfig {
+        urls,
+        secret,
+        events: events.unwrap_or_else(|| WebhookEvent::value_variants().to_vec()),
+        failure_streak,
+    }))
+}
+
+/// The server certificate chain and key, plaintext when neither is set.
+/// With a client CA bundle clients must present a certificate issued by it,
+/// unless `client_auth_optional`, and logins are bound to that certificate.
+fn tls_config(
+    cert: Option<String>,
+    key: Option<String>,
+    client_ca: Option<String>,
+    client_auth_optional: bool,
+) -> anyhow::Result<Option<ServerTlsConfig>> {
+    let tls = match (cert, key) {
+        (Some(cert), Some(key)) => ServerTlsConfig::new().identity(Identity::from_pem(cert, key)),
+        (None, None) => {
+            if client_ca.is_some() {
+                anyhow::bail!("TLS_CLIENT_CA needs TLS_CERT and TLS_KEY");
+            }
+            return Ok(None);
+        }
+        _ => anyhow::bail!("TLS_CERT and TLS_KEY are set together or not at all"),
+    };
+    let Some(client_ca) = client_ca else {
+        return Ok(Some(tls));
+    };
+    log::info!(
+        "Client certificates are {}",
+        if client_auth_optional {
+            "optional"
+        } else {
+            "required"
+        }
+    );
+    Ok(Some(
+        tls.client_ca_root(Certificate::from_pem(client_ca))
+            .client_auth_optional(client_auth_optional),
+    ))
+}
+
+/// PEM given inline, or read from the file `name`_FILE.
+fn pem(
+    inline: Option<String>,
+    file: Option<PathBuf>,
+    name: &str,
+) -> anyhow::Result<Option<String>> {
+    if inline.is_some() {
+        return Ok(inline);
+    }
+    file.map(|path| {
+        std::fs::read_to_string(&path).with_context(|| format!("Can't read {name}_FILE"))
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        let flags = Settings::try_parse_from([
+            "server",
+            "--grpc-addr",
+            "127.0.0.1:6000,[::1]:6000",
+            "--unix-socket",
+            "/tmp/auth.sock",
+            "--unix-socket-only",
+            "--log-format",
+            "json",
+            "--auth-compression",
+            "zstd,gzip",
+        ])
+        .unwrap();
+        let file = Settings::from_toml(
+            r#"
+            grpc_addr = ["0.0.0.0:5051"]
+            server_param_set = "rfc5114-2048-224"
+            session_ttl_secs = 3600
+            session_max_lifetime_secs = 86400
+            rate_limit_per_minute = 30
+            replay_window_secs = 300
+            rpc_method_timeouts = ["Register=5"]
+            cleanup_interval_secs = 0
+            user_retention_days = 30
+            login_history_size = 0
+            backoff_base_ms = 0
+            ip_denylist = ["10.0.0.0/8", "2001:db8::1"]
+            memory_store_shards = 64
+            log_filter = "warn,zkp_chaum_pedersen=debug"
+            "#,
+        )
+        .unwrap();
+        let settings = flags.or(file);
+        assert_eq!(settings.log_format, Some(LogFormat::Json));
+        let config = Config::from_settings(settings).unwrap();
+
+        assert_eq!(
+            config.grpc_addrs,
+            [
+                "127.0.0.1:6000".parse().unwrap(),
+                "[::1]:6000".parse().unwrap()
+            ]
+        );
+        assert!(config.unix_socket_only);
+        assert_eq!(config.param_set, ParamSet::Rfc5114_2048_224);
+        assert_eq!(config.session_ttl, Duration::from_secs(3600));
+        assert_eq!(
+            config.session_max_lifetime,
+            Some(Duration::from_secs(86400))
+        );
+        assert_eq!(config.challenge_ttl, DEFAULT_CHALLENGE_TTL);
+        assert_eq!(config.rate_limit.unwrap().requests, 30);
+        assert_eq!(config.replay_window, Some(Duration::from_secs(300)));
+        assert_eq!(
+            config.auth_compression,
+            [CompressionEncoding::Zstd, CompressionEncoding::Gzip]
+        );
+        assert!(config.admin_compression.is_empty());
+        assert_eq!(config.deadlines.default, DEFAULT_RPC_TIMEOUT);
+        assert_eq!(config.deadlines.methods["Register"], Duration::from_secs(5));
+        assert_eq!(
+            config.max_decoding_message_size,
+            DEFAULT_MAX_DECODING_MESSAGE_SIZE
+        );
+        assert!(config.tls.is_none());
+        assert!(config.webhooks.is_none());
+        assert!(config.cleanup.is_none());
+        assert_eq!(config.user_retention, Some(retention::days(30)));
+        assert_eq!(config.login_history, 0);
+        assert!(config.backoff_base.is_zero());
+        assert_eq!(config.backoff_max, backoff::DEFAULT_MAX);
+        assert!(config.ip_allowlist.is_empty());
+        assert_eq!(
+            config.ip_denylist,
+            [
+                "10.0.0.0/8".parse::<IpNet>().unwrap(),
+                "2001:db8::1/128".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.memory_store_shards, Some(64));
+        // RUST_LOG, when set where the tests run, wins over the file.
+        assert!(config.log_filter.is_some());
+    }
+
+    #[test]
+    fn test_webhooks() {
+        let settings = Settings::try_parse_from([
+            "server",
+            "--webhook-urls",
+            "https://hooks.example.com/zkp,http://127.0.0.1:9000",
+            "--webhook-secret",
+            "0a0b",
+            "--webhook-events",
+            "failed_logins",
+        ])
+        .unwrap();
+        let webhooks = Config::from_settings(settings).unwrap().webhooks.unwrap();
+        assert_eq!(webhooks.urls.len(), 2);
+        assert_eq!(webhooks.secret, [10, 11]);
+        assert_eq!(webhooks.events, [WebhookEvent::FailedLogins]);
+        assert_eq!(webhooks.failure_streak, DEFAULT_FAILURE_STREAK);
+    }
+
+    #[test]
+    fn test_commands() {
+        let parse = |args: &[&str]| {
+            let settings = Settings::try_parse_from([&["server"], args].concat()).unwrap();
+            Config::from_settings(settings).unwrap().command
+        };
+        assert!(parse(&[]).is_none());
+        assert!(matches!(parse(&["serve"]), Some(Command::Serve)));
+        assert!(matches!(parse(&["gen-params"]), Some(Command::GenParams)));
+        assert!(matches!(parse(&["list-users"]), Some(Command::ListUsers)));
+        let Some(Command::AddUser(args)) = parse(&[
+            "add-user",
+            "alice",
+            "alice.toml",
+            "--realm",
+            "acme",
+            "--admin",
+        ]) else {
+            panic!("Not add-user");
+        };
+        assert_eq!((args.name.as_str(), args.realm.as_str()), ("alice", "acme"));
+        assert_eq!(args.key_file, Path::new("alice.toml"));
+        assert!(args.admin);
+        assert!(Settings::try_parse_from(["server", "add-user", "alice"]).is_err());
+    }
+
+    #[test]
+    fn test_bad_settings() {
+        assert!(Settings::from_toml("grpc_adr = []").is_err());
+        assert!(Settings::from_toml("log_format = \"xml\"").is_err());
+        assert!(Settings::from_toml("auth_compression = [\"brotli\"]").is_err());
+        for toml in [
+            "unix_socket_only = true",
+            "tls_cert = \"cert\"",
+            "server_param_set = \"rfc0000\"",
+            "pow_difficulty = 99",
+            "session_jwt_secret = \"not hex\"",
+            "webhook_urls = [\"https://hooks.example.com\"]",
+            "webhook_secret = \"0a0b\"",
+            "rpc_timeout_secs = 0",
+            "max_concurrent_calls = 0",
+            "cleanup_batch_size = 0",
+            "user_retention_days = 0",
+            "memory_store_shards = 1",
+            "memory_store_shards = 48",
+            "backoff_base_ms = 500\nbackoff_max_ms = 100",
+            "ip_allowlist = [\"10.0.0.0/33\"]",
+            "ip_denylist = [\"localhost\"]",
+            "log_filter = \"zkp_chaum_pedersen=loud\"",
+            "rpc_method_timeouts = [\"Register\"]",
+            "cluster = true\nserver_secret_key = \"0a\"",
+            "cluster = true\nserver_secret_key = \"0a\"\ndatabase_url = \"sqlite://zkp.db\"",
+            "cluster = true\ndatabase_url = \"postgres://db/zkp\"",
+            "cluster = true\nserver_secret_key = \"0a\"\ndatabase_url = \"postgres://db/zkp\"\nrate_limit_per_minute = 30",
+        ] {
+            let settings = Settings::from_toml(toml).unwrap();
+            assert!(Config::from_settings(settings).is_err(), "{toml}");
+        }
+    }
+
+    #[test]
+    fn test_cluster() {
+        let settings = Settings::from_toml(
+            "cluster = true
+            server_secret_key = \"0a\"
+            database_url = \"postgresql://db/zkp\"
+            redis_url = \"redis://redis\"
+            rate_limit_per_minute = 30",
+        )
+        .unwrap();
+        assert!(Config::from_settings(settings).unwrap().cluster);
+    }
+}
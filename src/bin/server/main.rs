@@ -1,31 +1,618 @@
-pub mod zkp_auth {
-    include!("../../zkp_auth.rs");
-}
+// tonic::Status is large, but it is what every handler returns anyway.
+#![allow(clippy::result_large_err)]
+
+mod config;
+mod reload;
 
-pub mod grpc_impl;
+use std::{
+    os::unix::fs::FileTypeExt,
+    path::Path,
+    sync::{atomic::AtomicU32, Arc},
+};
 
-use anyhow::anyhow;
-use zkp_auth::{
-    auth_server::{Auth, AuthServer},
-    AuthenticationAnswerRequest, AuthenticationAnswerResponse, AuthenticationChallengeRequest,
-    AuthenticationChallengeResponse, RegisterRequest, RegisterResponse,
+use anyhow::Context;
+use config::{Command, Config, Settings};
+use num_bigint::BigUint;
+use reload::Reloadable;
+use tokio::{
+    net::UnixListener,
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    task::JoinSet,
 };
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tonic::service::Routes;
+use zkp_chaum_pedersen::{
+    qualified_name,
+    server::{
+        audit::{AuditFile, AuditLog},
+        backoff::Backoff,
+        backup, cleanup, cluster,
+        deadline::DeadlineLayer,
+        grpc_impl::{
+            admin::admin_impl::{AdminAuth, AdminImpl, AdminSessions, AdminToken},
+            auth::auth_impl::AuthImpl,
+        },
+        grpc_web::GrpcWebLayer,
+        health,
+        ip_filter::{self, IpFilter, IpFilterLayer},
+        jwt::JwtIssuer,
+        key_file::KeyFile,
+        load_shed,
+        metrics::{self, Metrics, MetricsLayer},
+        panic::{self, CatchPanicLayer},
+        rate_limit::{RateLimitLayer, RateLimiter, RedisRateLimiter, TokenBucket},
+        rest,
+        store::{
+            unix_now, MemoryStore, PostgresStore, RedisStore, SessionStore, SledStore, SplitStore,
+            SqliteStore, UserInfo, UserStore,
+        },
+        systemd::{self, Activated},
+        telemetry::{self, LogFilter},
+        webhook::Webhooks,
+    },
+    validate_group,
+    zkp_auth::{self, auth_admin_server::AuthAdminServer, auth_server::AuthServer},
+    Backend, ZKP,
+};
+
+/// Users read from the store per call by list-users.
+const LIST_PAGE_SIZE: usize = 100;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenvy::dotenv().map_err(|err| anyhow!("Err: {err}"))?;
-    env_logger::try_init().map_err(|err| anyhow!("Err: {err}"))?;
+    // Settings may as well come from flags or a file.
+    dotenvy::dotenv().ok();
+    let settings = Settings::load()?;
+    let (log_filter, tracer) = telemetry::init(
+        settings.log_format.unwrap_or_default(),
+        settings.log_filter.as_deref(),
+        settings.otlp_endpoint.as_deref(),
+    )?;
+    panic::install_hook();
+    let config = Config::from_settings(settings)?;
+
+    let zkp = ZKP::builder()
+        .param_set(config.param_set)
+        .backend(Backend::Fixed2048)
+        .build()?;
+    check_group(&zkp)?;
+
+    let served = match config.command {
+        Some(Command::GenParams) => {
+            gen_params(&zkp);
+            Ok(())
+        }
+        _ => with_store(&config, zkp, log_filter).await,
+    };
+    if let Some(tracer) = tracer {
+        telemetry::shutdown(tracer);
+    }
+    served
+}
+
+/// Opens the store DATABASE_URL names.
+async fn with_store(config: &Config, zkp: ZKP, log_filter: LogFilter) -> anyhow::Result<()> {
+    let server_x = load_server_secret(config, &zkp);
+    match &config.database_url {
+        Some(url) if config::is_postgres(url) => {
+            log::info!("Storing users in PostgreSQL");
+            let store = PostgresStore::connect(url)
+                .await
+                .context("Can't connect to the database")?;
+            with_sessions(config, store, zkp, server_x, log_filter).await
+        }
+        Some(url) if url.starts_with("sled://") => {
+            let path = url.trim_start_matches("sled://");
+            log::info!("Storing users in the sled database at {path}");
+            let store = SledStore::open(path).context("Can't open the database")?;
+            with_sessions(config, store, zkp, server_x, log_filter).await
+        }
+        Some(url) => {
+            log::info!("Storing users in {url}");
+            let store = SqliteStore::connect(url)
+                .await
+                .context("Can't open the database")?;
+            with_sessions(config, store, zkp, server_x, log_filter).await
+        }
+        None => {
+            log::warn!("DATABASE_URL is not set, users are kept in memory only.");
+            let store = match config.memory_store_shards {
+                Some(shards) => MemoryStore::with_shards(shards),
+                None => MemoryStore::default(),
+            };
+            with_sessions(config, store, zkp, server_x, log_filter).await
+        }
+    }
+}
+
+/// Moves challenges and sessions to Redis when REDIS_URL is set, so server
+/// replicas share them.
+async fn with_sessions<S: UserStore + SessionStore>(
+    config: &Config,
+    store: S,
+    zkp: ZKP,
+    server_x: BigUint,
+    log_filter: LogFilter,
+) -> anyhow::Result<()> {
+    match &config.redis_url {
+        Some(url) => {
+            log::info!("Storing challenges and sessions in Redis");
+            let sessions = RedisStore::connect(url)
+                .await
+                .context("Can't connect to Redis")?;
+            let store = SplitStore {
+                users: store,
+                sessions,
+            };
+            run(config, store, zkp, server_x, log_filter).await
+        }
+        None => run(config, store, zkp, server_x, log_filter).await,
+    }
+}
+
+/// Serves, or runs the command given in its place.
+async fn run<S: UserStore + SessionStore>(
+    config: &Config,
+    store: S,
+    zkp: ZKP,
+    server_x: BigUint,
+    log_filter: LogFilter,
+) -> anyhow::Result<()> {
+    match &config.command {
+        None | Some(Command::Serve) => {
+            return serve(config, store, zkp, server_x, log_filter).await
+        }
+        Some(Command::GenParams) => unreachable!("gen-params runs without a store"),
+        Some(Command::AddUser(args)) => {
+            let toml = std::fs::read_to_string(&args.key_file)
+                .with_context(|| format!("Can't read {}", args.key_file.display()))?;
+            let name = qualified_name(&args.realm, &args.name)?;
+            let user = UserInfo {
+                admin: args.admin,
+                ..KeyFile::from_toml(&toml)?.to_user(&name, &zkp)?
+            };
+            anyhow::ensure!(
+                store.create_user(user).await?,
+                "User: {name} is already registered"
+            );
+            store.touch_user(&name, unix_now()).await?;
+            log::info!("Registered user: {name}");
+        }
+        Some(Command::ListUsers) => {
+            let mut after = String::new();
+            loop {
+                let users = store.list_users(&after, LIST_PAGE_SIZE).await?;
+                for user in &users {
+                    match user.admin {
+                        true => println!("{} (admin)", user.user_name),
+                        false => println!("{}", user.user_name),
+                    }
+                }
+                match users.last() {
+                    Some(last) if users.len() == LIST_PAGE_SIZE => {
+                        after.clone_from(&last.user_name)
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Some(Command::Export(args)) => {
+            let archive = backup::export(&store, &args.passphrase).await?;
+            std::fs::write(&args.file, archive)
+                .with_context(|| format!("Can't write {}", args.file.display()))?;
+            log::info!("Exported the users to {}", args.file.display());
+        }
+        Some(Command::Import(args)) => {
+            let archive = std::fs::read(&args.file)
+                .with_context(|| format!("Can't read {}", args.file.display()))?;
+            let imported = backup::import(&store, &archive, &args.passphrase).await?;
+            log::info!(
+                "Imported {} users and {} sessions, skipped {} users already registered",
+                imported.users,
+                imported.sessions,
+                imported.skipped
+            );
+        }
+    }
+    UserStore::shutdown(&store).await?;
+    Ok(())
+}
+
+async fn serve<S: UserStore + SessionStore>(
+    config: &Config,
+    store: S,
+    zkp: ZKP,
+    server_x: BigUint,
+    log_filter: LogFilter,
+) -> anyhow::Result<()> {
+    let store = Arc::new(store);
+    let pow_difficulty = Arc::new(AtomicU32::new(config.pow_difficulty));
+    let metrics = Arc::new(Metrics::new());
+    let audit = match &config.audit_log {
+        Some(path) => {
+            log::info!("Appending security events to {}", path.display());
+            AuditLog::new(Arc::new(
+                AuditFile::open(path).context("Can't open AUDIT_LOG")?,
+            ))
+        }
+        None => AuditLog::default(),
+    };
+    let mut auth_impl = AuthImpl::new(store.clone(), zkp, server_x)
+        .with_metrics(metrics.clone())
+        .with_audit(audit.clone())
+        .with_challenge_ttl(config.challenge_ttl)
+        .with_session_ttl(config.session_ttl)
+        .with_lockout(config.lockout)
+        .with_backoff(Backoff::new(config.backoff_base, config.backoff_max))
+        .with_pow_difficulty(pow_difficulty.clone())
+        .with_min_protocol_version(config.min_protocol_version)
+        .with_login_history(config.login_history);
+    if let Some(session_max_lifetime) = config.session_max_lifetime {
+        auth_impl = auth_impl.with_session_max_lifetime(session_max_lifetime);
+    }
+    if let Some(window) = config.replay_window {
+        auth_impl = auth_impl.with_replay_window(window);
+    }
+    if let Some(webhooks) = &config.webhooks {
+        log::info!(
+            "Sending {:?} webhooks to {} URLs",
+            webhooks.events,
+            webhooks.urls.len()
+        );
+        auth_impl = auth_impl.with_events(Arc::new(Webhooks::new(webhooks.clone())?));
+    }
+    #[cfg(feature = "nats")]
+    let nats = match &config.nats {
+        Some(nats) => {
+            log::info!("Publishing auth events to NATS under {}", nats.subject);
+            let events = Arc::new(
+                zkp_chaum_pedersen::server::nats::NatsEvents::connect(nats)
+                    .await
+                    .context("Can't connect to NATS")?,
+            );
+            auth_impl = auth_impl.with_events(events.clone());
+            Some(events)
+        }
+        None => None,
+    };
+    if let Some(secret) = &config.session_jwt_secret {
+        log::info!("Issuing sessions as JWTs");
+        auth_impl = auth_impl.with_jwt(JwtIssuer::new(secret));
+    }
+
+    let rate_limiter = rate_limiter(config).await?;
+    if let Some(rate_limiter) = &rate_limiter {
+        auth_impl = auth_impl.with_rate_limiter(rate_limiter.clone());
+    }
+    if !config.ip_allowlist.is_empty() {
+        log::info!("Only taking calls from {:?}", config.ip_allowlist);
+    }
+    let ip_filter = Arc::new(IpFilter::new(
+        config.ip_allowlist.clone(),
+        config.ip_denylist.clone(),
+    ));
+
+    // Every listener stops taking calls once this fires, and finishes those
+    // it has.
+    let (stop, stopping) = watch::channel(());
+    let mut terminate = signal(SignalKind::terminate()).context("Can't catch SIGTERM")?;
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        log::info!("Shutting down, finishing the calls in flight.");
+        systemd::notify_stopping();
+        let _ = stop.send(());
+    });
+
+    let mut listeners = JoinSet::new();
+    if let Some(rest_addr) = config.rest_addr {
+        let listener = tokio::net::TcpListener::bind(rest_addr)
+            .await
+            .context("Can't listen on REST_ADDR")?;
+        log::info!("REST gateway running at {rest_addr}");
+        // Handlers see the client's address, for the audit log.
+        let router = rest::router(auth_impl.clone())
+            .layer(axum::middleware::from_fn_with_state(
+                ip_filter.clone(),
+                ip_filter::http,
+            ))
+            .into_make_service_with_connect_info::<std::net::SocketAddr>();
+        let stopped = stopped(stopping.clone());
+        listeners.spawn(async move {
+            if let Err(err) = axum::serve(listener, router)
+                .with_graceful_shutdown(stopped)
+                .await
+            {
+                log::error!("REST gateway stopped: {err}");
+            }
+            Ok(())
+        });
+    }
+    if let Some(metrics_addr) = config.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(metrics_addr)
+            .await
+            .context("Can't listen on METRICS_ADDR")?;
+        log::info!("Metrics at http://{metrics_addr}/metrics");
+        let router = metrics::router(metrics.clone(), store.clone());
+        let stopped = stopped(stopping.clone());
+        listeners.spawn(async move {
+            if let Err(err) = axum::serve(listener, router)
+                .with_graceful_shutdown(stopped)
+                .await
+            {
+                log::error!("Metrics endpoint stopped: {err}");
+            }
+            Ok(())
+        });
+    }
+
+    let admin = if config.admin_token.is_some() || config.admin_sessions {
+        let mut admin = AuthAdminServer::new(
+            AdminImpl::new(
+                store.clone(),
+                config.session_max_lifetime.unwrap_or(config.session_ttl),
+                pow_difficulty.clone(),
+            )
+            .with_audit(audit.clone())
+            .with_user_retention(config.user_retention)
+            .with_ip_filter(ip_filter.clone())
+            .with_metrics(metrics.clone()),
+        )
+        .max_decoding_message_size(config.max_decoding_message_size)
+        .max_encoding_message_size(config.max_encoding_message_size);
+        for &encoding in &config.admin_compression {
+            admin = admin.accept_compressed(encoding).send_compressed(encoding);
+        }
+        let sessions: Option<Arc<dyn AdminSessions>> = match config.admin_sessions {
+            true => Some(Arc::new(auth_impl.clone())),
+            false => None,
+        };
+        let token = config.admin_token.clone().map(AdminToken::new);
+        Some(AdminAuth::new(admin, token, sessions))
+    } else {
+        log::info!("ADMIN_TOKEN and ADMIN_SESSIONS are not set, the admin service is off.");
+        None
+    };
 
-    let addr = "127.0.0.1:5051".to_string();
-    log::info!("Server running at {addr}");
+    let (reporter, health) = tonic_health::server::health_reporter();
+    tokio::spawn(health::watch_store(
+        store.clone(),
+        reporter,
+        stopping.clone(),
+    ));
 
-    let auth_impl = grpc_impl::auth::auth_impl::AuthImpl::default();
+    if let Some(cleanup) = config.cleanup {
+        tokio::spawn(cleanup::run(
+            store.clone(),
+            cleanup,
+            config.user_retention,
+            audit.clone(),
+            stopping.clone(),
+        ));
+    }
 
-    tonic::transport::Server::builder()
-        .add_service(AuthServer::new(auth_impl))
-        .serve(addr.parse().expect("Could not convert address"))
-        .await
-        .unwrap();
+    if config.cluster {
+        log::info!("Running as a replica, sharing state through the stores");
+        tokio::spawn(cluster::run(
+            store.clone(),
+            pow_difficulty.clone(),
+            ip_filter.clone(),
+            stopping.clone(),
+        ));
+    }
 
+    let hangups = signal(SignalKind::hangup()).context("Can't catch SIGHUP")?;
+    let reloadable = Reloadable {
+        rate_limiter: rate_limiter.clone(),
+        ip_filter: ip_filter.clone(),
+        challenge_ttl: auth_impl.challenge_ttl.clone(),
+    };
+    tokio::spawn(reload::run(
+        hangups,
+        reloadable,
+        log_filter,
+        stopping.clone(),
+    ));
+
+    let mut routes = Routes::builder();
+    routes.add_service(health);
+    let mut auth = AuthServer::new(auth_impl)
+        .max_decoding_message_size(config.max_decoding_message_size)
+        .max_encoding_message_size(config.max_encoding_message_size);
+    // Responses go out in the first of these the caller accepts.
+    for &encoding in &config.auth_compression {
+        auth = auth.accept_compressed(encoding).send_compressed(encoding);
+    }
+    routes.add_service(auth);
+    if let Some(admin) = admin {
+        routes.add_service(admin);
+    }
+    if config.grpc_reflection {
+        log::info!("Serving gRPC reflection");
+        let reflection = || {
+            tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(zkp_auth::FILE_DESCRIPTOR_SET)
+                .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        };
+        // grpcurl and grpcui ask for either version.
+        routes.add_service(reflection().build_v1()?);
+        routes.add_service(reflection().build_v1alpha()?);
+    }
+    let routes = routes.routes();
+
+    let mut server = tonic::transport::Server::builder();
+    match &config.tls {
+        Some(tls) => {
+            log::info!("Serving over TLS");
+            server = server
+                .tls_config(tls.clone())
+                .context("Bad TLS certificate or key")?;
+        }
+        None => {
+            log::warn!("TLS_CERT is not set, credentials and sessions travel in plaintext.")
+        }
+    }
+    if let Some(limit) = config.max_concurrent_calls_per_connection {
+        server = server.concurrency_limit_per_connection(limit);
+    }
+    if let Some(limit) = config.max_concurrent_calls {
+        log::info!("Handling at most {limit} calls at once, refusing the rest");
+    }
+    // Browsers speak gRPC-Web over HTTP/1.1.
+    let mut server = server
+        .trace_fn(telemetry::rpc_span)
+        .accept_http1(true)
+        .layer(GrpcWebLayer::new(config.cors.clone()))
+        .layer(MetricsLayer::new(metrics))
+        .layer(IpFilterLayer::new(ip_filter))
+        .layer(DeadlineLayer::new(config.deadlines.clone()))
+        // Inside the deadline, so a call keeps its place until its handler
+        // is done.
+        .layer(tower::util::option_layer(
+            config.max_concurrent_calls.map(load_shed::layer),
+        ))
+        .layer(RateLimitLayer::new(rate_limiter))
+        // Next to the handlers, so a panicking call still counts in the
+        // metrics as the INTERNAL it is answered with.
+        .layer(CatchPanicLayer);
+
+    // Under socket activation systemd binds the sockets, in place of
+    // GRPC_ADDR and UNIX_SOCKET.
+    let activated = systemd::listeners().context("Can't take the sockets systemd passed")?;
+    let socket_activated = !activated.is_empty();
+    for listener in activated {
+        let router = server.add_routes(routes.clone());
+        let stopped = stopped(stopping.clone());
+        match listener {
+            Activated::Tcp(listener) => {
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                log::info!("Server running at {} from systemd", listener.local_addr()?);
+                let incoming = TcpListenerStream::new(listener);
+                listeners.spawn(router.serve_with_incoming_shutdown(incoming, stopped));
+            }
+            Activated::Unix(listener) => {
+                log::info!("Server running at a Unix socket from systemd");
+                let incoming = UnixListenerStream::new(UnixListener::from_std(listener)?);
+                listeners.spawn(router.serve_with_incoming_shutdown(incoming, stopped));
+            }
+        }
+    }
+    if !socket_activated {
+        if let Some(path) = &config.unix_socket {
+            let listener = unix_socket(path)?;
+            log::info!("Server running at {}", path.display());
+            let router = server.add_routes(routes.clone());
+            listeners.spawn(router.serve_with_incoming_shutdown(
+                UnixListenerStream::new(listener),
+                stopped(stopping.clone()),
+            ));
+        }
+        if config.unix_socket_only {
+            log::info!("UNIX_SOCKET_ONLY is set, not listening on TCP.");
+        } else {
+            for &addr in &config.grpc_addrs {
+                log::info!("Server running at {addr}");
+                let router = server.add_routes(routes.clone());
+                listeners.spawn(router.serve_with_shutdown(addr, stopped(stopping.clone())));
+            }
+        }
+    }
+    systemd::notify_ready();
+
+    // Serves until shut down or a listener fails.
+    while let Some(served) = listeners.join_next().await {
+        served??;
+    }
+    store.shutdown().await?;
+    #[cfg(feature = "nats")]
+    if let Some(nats) = nats {
+        nats.flush().await;
+    }
+    log::info!("Server stopped");
+    Ok(())
+}
+
+/// Resolves once the shutdown signal came, or nobody is left to send it.
+async fn stopped(mut stopping: watch::Receiver<()>) {
+    let _ = stopping.changed().await;
+}
+
+/// A listener on the Unix socket at `path`, for a co-located process to
+/// call the server without going through TCP. A socket left behind by an
+/// earlier run is replaced.
+fn unix_socket(path: &Path) -> anyhow::Result<UnixListener> {
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path).context("Can't remove the old UNIX_SOCKET")?;
+    }
+    UnixListener::bind(path).context("Can't listen on UNIX_SOCKET")
+}
+
+/// RATE_LIMIT_PER_MINUTE requests per client IP and per user, counted in
+/// Redis when REDIS_URL is set so replicas share the budget.
+async fn rate_limiter(config: &Config) -> anyhow::Result<Option<Arc<dyn RateLimiter>>> {
+    let Some(limit) = config.rate_limit else {
+        return Ok(None);
+    };
+    log::info!("Rate limiting to {} requests a minute", limit.requests);
+
+    Ok(Some(match &config.redis_url {
+        Some(url) => Arc::new(
+            RedisRateLimiter::connect(url, limit)
+                .await
+                .context("Can't connect to Redis")?,
+        ),
+        None => Arc::new(TokenBucket::new(limit)),
+    }))
+}
+
+/// Refuses to serve on a group that fails `validate_group`, before any user
+/// can register a key in it.
+fn check_group(zkp: &ZKP) -> anyhow::Result<()> {
+    let report = validate_group(&zkp.constants()).context("The group failed validation")?;
+    log::info!(
+        "Group: {} ({}-bit p, {}-bit q)",
+        report
+            .param_set
+            .map_or("custom".to_string(), |set| set.to_string()),
+        report.p_bits,
+        report.q_bits
+    );
+    for warning in report.warnings {
+        log::warn!("Group: {warning}");
+    }
     Ok(())
 }
+
+/// A fresh server key for SERVER_SECRET_KEY, on stdout as the variables to
+/// set.
+fn gen_params(zkp: &ZKP) {
+    let server_x = ZKP::generate_random_below(zkp.q());
+    let (y1, y2) = zkp.compute_pair(&server_x);
+    println!("SERVER_SECRET_KEY={}", hex::encode(server_x.to_bytes_be()));
+    println!("SERVER_Y1={}", hex::encode(y1.to_bytes_be()));
+    println!("SERVER_Y2={}", hex::encode(y2.to_bytes_be()));
+}
+
+/// The server key pair clients pin to check who issued their session.
+/// SERVER_SECRET_KEY, or generated for this run only.
+fn load_server_secret(config: &Config, zkp: &ZKP) -> BigUint {
+    let server_x = match &config.server_secret_key {
+        Some(secret) => secret.clone(),
+        None => {
+            log::warn!("SERVER_SECRET_KEY is not set, using a throwaway server key.");
+            ZKP::generate_random_below(zkp.q())
+        }
+    };
+
+    let (y1, y2) = zkp.compute_pair(&server_x);
+    log::info!(
+        "Server public key: SERVER_Y1={} SERVER_Y2={}",
+        hex::encode(y1.to_bytes_be()),
+        hex::encode(y2.to_bytes_be())
+    );
+
+    server_x
+}
@@ -0,0 +1,113 @@
+//! SIGHUP reloads the settings that can change without a restart, with no
+//! call, challenge or session lost. They are read again as at startup,
+//! the file CONFIG_FILE names anew, and of them the server takes
+//!
+//! - RUST_LOG, unless it is no longer set,
+//! - RATE_LIMIT_PER_MINUTE, unless it was or now is unset,
+//! - IP_DENYLIST, until an admin's UpdateIpDenylist in CLUSTER mode,
+//! - CHALLENGE_TTL_SECS, for challenges issued from then on.
+//!
+//! The rest waits for a restart. Settings that don't pass
+//! `Config::from_settings` are refused whole, the old ones stay.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use tokio::{signal::unix::Signal, sync::watch};
+use zkp_chaum_pedersen::server::{
+    ip_filter::IpFilter, rate_limit::RateLimiter, telemetry::LogFilter,
+};
+
+use crate::config::{Config, Settings};
+
+/// Where the running server keeps what a reload changes, but for the log
+/// filter.
+pub struct Reloadable {
+    pub rate_limiter: Option<Arc<dyn RateLimiter>>,
+    pub ip_filter: Arc<IpFilter>,
+    /// `AuthImpl::challenge_ttl`.
+    pub challenge_ttl: Arc<AtomicU64>,
+}
+
+impl Reloadable {
+    pub fn apply(&self, config: &Config) {
+        match (&self.rate_limiter, config.rate_limit) {
+            (Some(rate_limiter), Some(limit)) => rate_limiter.set_limit(limit),
+            (None, None) => {}
+            _ => log::warn!("RATE_LIMIT_PER_MINUTE is only turned on or off by a restart."),
+        }
+        self.ip_filter.set_denylist(config.ip_denylist.clone());
+        self.challenge_ttl
+            .store(config.challenge_ttl.as_secs(), Ordering::Relaxed);
+    }
+}
+
+/// Reloads on every SIGHUP until `stopping` fires.
+pub async fn run(
+    mut hangups: Signal,
+    reloadable: Reloadable,
+    log_filter: LogFilter,
+    mut stopping: watch::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            _ = hangups.recv() => {}
+            _ = stopping.changed() => break,
+        }
+        match Settings::load().and_then(Config::from_settings) {
+            Ok(config) => {
+                if let Some(directives) = &config.log_filter {
+                    if let Err(err) = log_filter.set(directives) {
+                        log::error!("Can't change the log filter: {err}");
+                    }
+                }
+                reloadable.apply(&config);
+                log::info!("Reloaded the settings.");
+            }
+            Err(err) => log::error!("Settings not reloaded: {err:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use clap::Parser;
+    use zkp_chaum_pedersen::server::rate_limit::{RateLimit, TokenBucket};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_apply() {
+        let reloadable = Reloadable {
+            rate_limiter: Some(Arc::new(TokenBucket::new(RateLimit {
+                requests: 1,
+                period: Duration::from_secs(60),
+            }))),
+            ip_filter: Arc::default(),
+            challenge_ttl: Arc::new(AtomicU64::new(60)),
+        };
+        let settings = Settings::try_parse_from([
+            "server",
+            "--rate-limit-per-minute",
+            "3",
+            "--ip-denylist",
+            "10.0.0.0/8",
+            "--challenge-ttl-secs",
+            "30",
+        ])
+        .unwrap();
+        reloadable.apply(&Config::from_settings(settings).unwrap());
+
+        let rate_limiter = reloadable.rate_limiter.unwrap();
+        for _ in 0..3 {
+            assert!(rate_limiter.acquire("alice").await.unwrap());
+        }
+        assert!(!rate_limiter.acquire("alice").await.unwrap());
+        assert!(!reloadable.ip_filter.allows("10.1.2.3".parse().unwrap()));
+        assert_eq!(reloadable.challenge_ttl.load(Ordering::Relaxed), 30);
+    }
+}
@@ -0,0 +1,344 @@
+use std::time::Duration;
+
+use num_bigint::{BigUint, RandBigInt};
+use rand::RngCore;
+
+use crate::{
+    fixed::{self, Montgomery},
+    params::{check_order, check_params, ParamError, ParamSet},
+    ZkpConstants, ZkpError, DEFAULT_CLOCK_SKEW, ZKP,
+};
+
+/// Arithmetic used for the exponentiations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Heap allocated `num_bigint::BigUint`, works for any group size.
+    #[default]
+    BigUint,
+    /// Stack allocated `crypto_bigint::U2048` with 256-bit exponents, no heap
+    /// allocation inside the exponentiation. Needs an odd p of at most 2048
+    /// bits and q of at most 256 bits, which covers every RFC 5114 group.
+    Fixed2048,
+}
+
+/// Where beta comes from when it isn't given explicitly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BetaSource {
+    Given,
+    Derived(Vec<u8>),
+    Random,
+}
+
+/// Named-argument construction of `ZKP`, everything is checked in `build`.
+///
+/// ```
+/// use zkp_chaum_pedersen::{ParamSet, ZKP};
+///
+/// let zkp = ZKP::builder()
+///     .param_set(ParamSet::Rfc5114_2048_256)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct ZkpBuilder {
+    p: Option<BigUint>,
+    q: Option<BigUint>,
+    alpha: Option<BigUint>,
+    beta: Option<BigUint>,
+    beta_source: BetaSource,
+    backend: Backend,
+    clock_skew: Duration,
+    rng: Option<Box<dyn RngCore + Send>>,
+}
+
+impl Default for ZkpBuilder {
+    fn default() -> Self {
+        Self {
+            p: None,
+            q: None,
+            alpha: None,
+            beta: None,
+            beta_source: BetaSource::Given,
+            backend: Backend::default(),
+            clock_skew: DEFAULT_CLOCK_SKEW,
+            rng: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ZkpBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZkpBuilder")
+            .field("p", &self.p)
+            .field("q", &self.q)
+            .field("alpha", &self.alpha)
+            .field("beta", &self.beta)
+            .field("beta_source", &self.beta_source)
+            .field("backend", &self.backend)
+            .field("clock_skew", &self.clock_skew)
+            .field("rng", &self.rng.as_ref().map(|_| "custom"))
+            .finish()
+    }
+}
+
+impl ZkpBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes p, q, alpha and beta from a named group.
+    pub fn param_set(self, set: ParamSet) -> Self {
+        self.constants(set.constants())
+    }
+
+    pub fn constants(self, constants: ZkpConstants) -> Self {
+        let ZkpConstants { alpha, beta, p, q } = constants;
+        self.p(p).q(q).alpha(alpha).beta(beta)
+    }
+
+    /// Big-endian encodings, e.g. as received from a peer.
+    pub fn params_from_bytes(self, p: &[u8], q: &[u8], alpha: &[u8], beta: &[u8]) -> Self {
+        self.p(BigUint::from_bytes_be(p))
+            .q(BigUint::from_bytes_be(q))
+            .alpha(BigUint::from_bytes_be(alpha))
+            .beta(BigUint::from_bytes_be(beta))
+    }
+
+    pub fn p(mut self, p: BigUint) -> Self {
+        self.p = Some(p);
+        self
+    }
+
+    pub fn q(mut self, q: BigUint) -> Self {
+        self.q = Some(q);
+        self
+    }
+
+    pub fn alpha(mut self, alpha: BigUint) -> Self {
+        self.alpha = Some(alpha);
+        self
+    }
+
+    pub fn beta(mut self, beta: BigUint) -> Self {
+        self.beta = Some(beta);
+        self.beta_source = BetaSource::Given;
+        self
+    }
+
+    /// beta = `ZKP::derive_generator(p, q, label)`, nobody knows log_alpha(beta).
+    pub fn derive_beta(mut self, label: &[u8]) -> Self {
+        self.beta_source = BetaSource::Derived(label.to_vec());
+        self
+    }
+
+    /// beta = alpha^r for a random r from the builder's RNG. Whoever ran this
+    /// could have kept r, prefer `derive_beta` unless that's acceptable.
+    pub fn random_beta(mut self) -> Self {
+        self.beta_source = BetaSource::Random;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Slack for clocks that run behind the prover's, an expired proof is
+    /// still accepted for this long.
+    pub fn clock_skew(mut self, clock_skew: Duration) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// RNG for generated values, `rand::thread_rng` when not set.
+    pub fn rng<R: RngCore + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = Some(Box::new(rng));
+        self
+    }
+
+    pub fn build(self) -> Result<ZKP, ZkpError> {
+        let p = self.p.ok_or(ParamError::Missing("p"))?;
+        let q = self.q.ok_or(ParamError::Missing("q"))?;
+        let alpha = self.alpha.ok_or(ParamError::Missing("alpha"))?;
+
+        let beta = match self.beta_source {
+            BetaSource::Given => self.beta.ok_or(ParamError::Missing("beta"))?,
+            BetaSource::Derived(label) => ZKP::derive_generator(&p, &q, &label)?,
+            BetaSource::Random => {
+                check_order(&p, &q)?;
+                let one = BigUint::from(1u32);
+                let r = match self.rng {
+                    Some(mut rng) => rng.gen_biguint_range(&one, &q),
+                    None => rand::thread_rng().gen_biguint_range(&one, &q),
+                };
+                ZKP::exponantiate(&alpha, &r, &p)
+            }
+        };
+
+        check_params(&p, &q, &alpha, &beta)?;
+        let montgomery = match self.backend {
+            Backend::BigUint => None,
+            Backend::Fixed2048 => {
+                check_fixed_width(&p, &q)?;
+                Montgomery::new(&p, &q)
+            }
+        };
+
+        let mut zkp = ZKP::new(p, q, alpha, beta);
+        zkp.backend = self.backend;
+        zkp.montgomery = montgomery;
+        zkp.clock_skew = self.clock_skew;
+        Ok(zkp)
+    }
+}
+
+fn check_fixed_width(p: &BigUint, q: &BigUint) -> Result<(), ParamError> {
+    if p.bits() > fixed::MAX_P_BITS || !p.bit(0) {
+        return Err(ParamError::Unsupported("p"));
+    }
+    if q.bits() > fixed::MAX_EXP_BITS || !q.bit(0) {
+        return Err(ParamError::Unsupported("q"));
+    }
+    Ok(())
+}
+
+impl ZKP {
+    pub fn builder() -> ZkpBuilder {
+        ZkpBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_build_from_param_set() {
+        let zkp = ZKP::builder()
+            .param_set(ParamSet::Rfc5114_2048_256)
+            .build()
+            .unwrap();
+
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        let proof = zkp.prove_non_interactive(&x, b"builder");
+        assert!(zkp
+            .verify_non_interactive(&y1, &y2, &proof, b"builder")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_build_from_bytes_and_derived_beta() {
+        let ZkpConstants { alpha, beta, p, q } = ZkpConstants::new();
+
+        let zkp = ZKP::builder()
+            .params_from_bytes(
+                &p.to_bytes_be(),
+                &q.to_bytes_be(),
+                &alpha.to_bytes_be(),
+                &beta.to_bytes_be(),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(zkp.beta, beta);
+
+        let derived = ZKP::builder()
+            .p(p)
+            .q(q)
+            .alpha(alpha)
+            .derive_beta(crate::BETA_LABEL)
+            .build()
+            .unwrap();
+        assert_eq!(derived.beta, beta);
+    }
+
+    #[test]
+    fn test_random_beta_uses_injected_rng() {
+        let build = |seed| {
+            ZKP::builder()
+                .param_set(ParamSet::Rfc5114_1024_160)
+                .random_beta()
+                .rng(StdRng::seed_from_u64(seed))
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(build(7).beta, build(7).beta);
+        assert_ne!(build(7).beta, build(8).beta);
+    }
+
+    #[test]
+    fn test_build_validates() {
+        let n = |v: u32| BigUint::from(v);
+
+        assert!(matches!(
+            ZKP::builder().p(n(23)).q(n(11)).alpha(n(4)).build(),
+            Err(ZkpError::Param(ParamError::Missing("beta")))
+        ));
+        assert!(matches!(
+            ZKP::builder()
+                .p(n(23))
+                .q(n(11))
+                .alpha(n(4))
+                .beta(n(5))
+                .build(),
+            Err(ZkpError::Param(ParamError::BadGenerator("beta")))
+        ));
+        assert!(matches!(
+            ZKP::builder()
+                .p(n(23))
+                .q(n(7))
+                .alpha(n(4))
+                .derive_beta(b"toy")
+                .build(),
+            Err(ZkpError::Param(ParamError::OrderMismatch))
+        ));
+    }
+
+    #[test]
+    fn test_fixed_width_backend() {
+        for set in ParamSet::ALL {
+            let zkp = ZKP::builder()
+                .param_set(set)
+                .backend(Backend::Fixed2048)
+                .build()
+                .unwrap();
+            assert_eq!(zkp.backend(), Backend::Fixed2048);
+
+            let x = ZKP::generate_random_below(&zkp.q);
+            let (y1, y2) = zkp.compute_pair(&x);
+            assert_eq!(
+                (y1.clone(), y2.clone()),
+                ZKP::new(
+                    zkp.p.clone(),
+                    zkp.q.clone(),
+                    zkp.alpha.clone(),
+                    zkp.beta.clone()
+                )
+                .compute_pair(&x)
+            );
+
+            let proof = zkp.prove_non_interactive(&x, b"fixed");
+            assert!(zkp
+                .verify_non_interactive(&y1, &y2, &proof, b"fixed")
+                .is_ok());
+        }
+
+        let n = |v: u32| BigUint::from(v);
+        let wide = (n(1) << 4096) - 1u32;
+        assert_eq!(check_fixed_width(&n(23), &n(11)), Ok(()));
+        assert_eq!(
+            check_fixed_width(&wide, &n(11)),
+            Err(ParamError::Unsupported("p"))
+        );
+        assert_eq!(
+            check_fixed_width(&n(24), &n(11)),
+            Err(ParamError::Unsupported("p"))
+        );
+        assert_eq!(
+            check_fixed_width(&n(23), &wide),
+            Err(ParamError::Unsupported("q"))
+        );
+    }
+}
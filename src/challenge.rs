@@ -0,0 +1,370 @@
+use num_bigint::{BigUint, RandBigInt};
+use sha2::{Digest, Sha256};
+
+use crate::{expand_hash, length_prefixed, wide_len, ZkpError, ZKP};
+
+/// Shape of the challenges in an interactive run: `rounds` independent
+/// commitments, each answered to a challenge of at most `bits` bits.
+///
+/// A cheating prover survives one round with probability 2^-bits (or 1/q
+/// when bits covers all of q), and all of them with that to the power of
+/// `rounds`, see `soundness_bits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChallengeSpec {
+    pub bits: u64,
+    pub rounds: usize,
+}
+
+impl ChallengeSpec {
+    /// One round with challenges over all of [0, q), the classic protocol.
+    pub fn full(zkp: &ZKP) -> Self {
+        Self {
+            bits: zkp.q().bits(),
+            rounds: 1,
+        }
+    }
+
+    /// `bits`-bit challenges repeated until the run reaches `soundness_bits`.
+    pub fn for_soundness(zkp: &ZKP, bits: u64, soundness_bits: u64) -> Self {
+        let per_round = Self { bits, rounds: 1 }.bits_per_round(zkp).max(1);
+        Self {
+            bits,
+            rounds: soundness_bits.div_ceil(per_round).max(1) as usize,
+        }
+    }
+
+    /// -log2 of the chance that a prover without x passes every round.
+    pub fn soundness_bits(&self, zkp: &ZKP) -> u64 {
+        self.bits_per_round(zkp) * self.rounds as u64
+    }
+
+    /// Exclusive upper bound of a single challenge.
+    pub fn bound(&self, zkp: &ZKP) -> BigUint {
+        if self.bits >= zkp.q().bits() {
+            zkp.q().clone()
+        } else {
+            BigUint::from(1u32) << self.bits
+        }
+    }
+
+    fn bits_per_round(&self, zkp: &ZKP) -> u64 {
+        // q is not a power of two, a challenge below q only carries
+        // floor(log2 q) bits.
+        self.bits.min(zkp.q().bits() - 1)
+    }
+}
+
+/// What a challenge is tied to, so that an answer computed for one login
+/// can't be used for another, and a challenge relayed from a different
+/// connection is noticed by the prover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChallengeBinding<'a> {
+    pub user: &'a str,
+    pub auth_id: &'a str,
+    /// The verifier's public key, see `server_identity`.
+    pub server_id: &'a [u8],
+    /// Keying material exported from the TLS connection (RFC 5705), when
+    /// there is one. Ties the challenge to that exact connection.
+    pub tls_exporter: Option<&'a [u8]>,
+    /// `certificate_fingerprint` of the client certificate the connection
+    /// was made with under mutual TLS, when there is one.
+    pub client_cert: Option<&'a [u8]>,
+    /// Protocol version of the login, see `crate::version`.
+    pub version: u32,
+    /// Newest protocol version the prover speaks.
+    pub max_version: u32,
+}
+
+impl ChallengeBinding<'_> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (has_exporter, exporter): (&[u8], &[u8]) = match self.tls_exporter {
+            Some(exporter) => (&[1], exporter),
+            None => (&[0], &[]),
+        };
+        let versions = [self.version.to_be_bytes(), self.max_version.to_be_bytes()].concat();
+        let mut parts: Vec<&[u8]> = vec![
+            b"zkp_chaum_pedersen/challenge_binding",
+            self.user.as_bytes(),
+            self.auth_id.as_bytes(),
+            self.server_id,
+            has_exporter,
+            exporter,
+        ];
+        // Version 1 bindings stay what they were before versions existed.
+        if self.version >= 2 {
+            parts.push(&versions);
+        }
+        // Only bindings with a client certificate change, the others stay
+        // what they were before.
+        if let Some(fingerprint) = self.client_cert {
+            parts.extend([b"client_cert".as_slice(), fingerprint]);
+        }
+        length_prefixed(&parts)
+    }
+}
+
+/// Encoding of a verifier public key for `ChallengeBinding::server_id`.
+pub fn server_identity(y1: &BigUint, y2: &BigUint) -> Vec<u8> {
+    length_prefixed(&[&y1.to_bytes_be(), &y2.to_bytes_be()])
+}
+
+/// SHA-256 of a DER encoded certificate, how a client certificate enters
+/// `ChallengeBinding::client_cert`.
+pub fn certificate_fingerprint(der: &[u8]) -> Vec<u8> {
+    Sha256::digest(der).to_vec()
+}
+
+/// Commitments of a repeated run, one (r1, r2) per round. The nonces stay
+/// with the prover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundCommitments {
+    pub commitments: Vec<(BigUint, BigUint)>,
+}
+
+impl ZKP {
+    /// Verifier side: one challenge per round, each below `spec.bound`.
+    pub fn generate_challenges(&self, spec: &ChallengeSpec) -> Vec<BigUint> {
+        let bound = spec.bound(self);
+        let mut rng = rand::thread_rng();
+        (0..spec.rounds)
+            .map(|_| rng.gen_biguint_below(&bound))
+            .collect()
+    }
+
+    /// c = H(seed, binding, r1, r2) mod `spec.bound`. The verifier picks a
+    /// random `seed` per login and sends it along with c, so both sides can
+    /// recompute c from what they each think the session is.
+    pub fn bound_challenge(
+        &self,
+        spec: &ChallengeSpec,
+        seed: &[u8],
+        binding: &ChallengeBinding,
+        r1: &BigUint,
+        r2: &BigUint,
+    ) -> BigUint {
+        let bound = spec.bound(self);
+        let transcript = length_prefixed(&[
+            seed,
+            &binding.to_bytes(),
+            &r1.to_bytes_be(),
+            &r2.to_bytes_be(),
+        ]);
+        expand_hash(
+            b"zkp_chaum_pedersen/bound_challenge",
+            &transcript,
+            0,
+            wide_len(&bound),
+        ) % bound
+    }
+
+    /// Checks that c is the challenge for `binding`, see `bound_challenge`.
+    pub fn check_bound_challenge(
+        &self,
+        spec: &ChallengeSpec,
+        seed: &[u8],
+        binding: &ChallengeBinding,
+        (r1, r2): (&BigUint, &BigUint),
+        c: &BigUint,
+    ) -> Result<(), ZkpError> {
+        if self.bound_challenge(spec, seed, binding, r1, r2) == *c {
+            Ok(())
+        } else {
+            Err(ZkpError::Verify)
+        }
+    }
+
+    /// Prover side, first move: fresh nonces and their commitments for
+    /// `rounds` rounds.
+    pub fn commit_rounds(&self, rounds: usize) -> (Vec<BigUint>, RoundCommitments) {
+        let nonces: Vec<BigUint> = (0..rounds)
+            .map(|_| Self::generate_random_below(&self.q))
+            .collect();
+        let commitments = nonces.iter().map(|k| self.compute_pair(k)).collect();
+        (nonces, RoundCommitments { commitments })
+    }
+
+    /// Prover side, last move: one answer per round, so one challenge per
+    /// nonce.
+    pub fn solve_rounds(
+        &self,
+        nonces: &[BigUint],
+        challenges: &[BigUint],
+        x: &BigUint,
+    ) -> Result<Vec<BigUint>, ZkpError> {
+        if nonces.len() != challenges.len() {
+            return Err(ZkpError::ProofCount {
+                statements: nonces.len(),
+                proofs: challenges.len(),
+            });
+        }
+        Ok(nonces
+            .iter()
+            .zip(challenges)
+            .map(|(k, c)| self.solve(k, c, x))
+            .collect())
+    }
+
+    /// Checks every round, and that the run has the shape of `spec`, so a
+    /// prover can't get away with fewer rounds or wider challenges than agreed.
+    pub fn verify_rounds(
+        &self,
+        spec: &ChallengeSpec,
+        y1: &BigUint,
+        y2: &BigUint,
+        commitments: &RoundCommitments,
+        challenges: &[BigUint],
+        answers: &[BigUint],
+    ) -> Result<(), ZkpError> {
+        let rounds = commitments.commitments.len();
+        if rounds != spec.rounds || challenges.len() != rounds || answers.len() != rounds {
+            return Err(ZkpError::Verify);
+        }
+        let bound = spec.bound(self);
+        if challenges.iter().any(|c| *c >= bound) {
+            return Err(ZkpError::Verify);
+        }
+
+        // Every round is checked even after a failure, the timing doesn't
+        // tell which one was wrong.
+        let mut ok = true;
+        for (((r1, r2), c), s) in commitments.commitments.iter().zip(challenges).zip(answers) {
+            ok &= self.verify(r1, r2, y1, y2, c, s).is_ok();
+        }
+        if ok {
+            Ok(())
+        } else {
+            Err(ZkpError::Verify)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PROTOCOL_VERSION;
+
+    #[test]
+    fn test_challenge_spec() {
+        let zkp = ZKP::default();
+
+        let full = ChallengeSpec::full(&zkp);
+        assert_eq!(full.rounds, 1);
+        assert_eq!(full.bound(&zkp), *zkp.q());
+        assert_eq!(full.soundness_bits(&zkp), 159);
+
+        let truncated = ChallengeSpec {
+            bits: 128,
+            rounds: 1,
+        };
+        assert_eq!(truncated.bound(&zkp), BigUint::from(1u32) << 128);
+        assert_eq!(truncated.soundness_bits(&zkp), 128);
+
+        let small = ChallengeSpec::for_soundness(&zkp, 16, 128);
+        assert_eq!(small.rounds, 8);
+        assert_eq!(small.soundness_bits(&zkp), 128);
+        assert_eq!(ChallengeSpec::for_soundness(&zkp, 20, 128).rounds, 7);
+    }
+
+    #[test]
+    fn test_parallel_repetition() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        let spec = ChallengeSpec::for_soundness(&zkp, 16, 80);
+
+        let (nonces, commitments) = zkp.commit_rounds(spec.rounds);
+        let challenges = zkp.generate_challenges(&spec);
+        assert!(challenges.iter().all(|c| c.bits() <= 16));
+        let answers = zkp.solve_rounds(&nonces, &challenges, &x).unwrap();
+        assert!(zkp
+            .verify_rounds(&spec, &y1, &y2, &commitments, &challenges, &answers)
+            .is_ok());
+
+        // one bad round fails the run
+        let mut bad = answers.clone();
+        bad[2] = (&bad[2] + 1u32) % zkp.q();
+        assert!(zkp
+            .verify_rounds(&spec, &y1, &y2, &commitments, &challenges, &bad)
+            .is_err());
+
+        // dropped rounds don't count as passed
+        let short = RoundCommitments {
+            commitments: commitments.commitments[1..].to_vec(),
+        };
+        assert!(zkp
+            .verify_rounds(&spec, &y1, &y2, &short, &challenges[1..], &answers[1..])
+            .is_err());
+
+        // one challenge per nonce
+        assert!(matches!(
+            zkp.solve_rounds(&nonces, &challenges[1..], &x),
+            Err(ZkpError::ProofCount { statements, proofs })
+                if statements == spec.rounds && proofs == spec.rounds - 1
+        ));
+
+        // challenges wider than agreed
+        let wide = vec![BigUint::from(1u32) << 16; spec.rounds];
+        assert!(zkp
+            .verify_rounds(&spec, &y1, &y2, &commitments, &wide, &answers)
+            .is_err());
+    }
+
+    #[test]
+    fn test_bound_challenge() {
+        let zkp = ZKP::default();
+        let spec = ChallengeSpec::full(&zkp);
+        let (r1, r2) = zkp.compute_pair(&BigUint::from(7u32));
+        let server_id = server_identity(&BigUint::from(2u32), &BigUint::from(3u32));
+        let binding = ChallengeBinding {
+            user: "alice",
+            auth_id: "auth-1",
+            server_id: &server_id,
+            tls_exporter: None,
+            client_cert: None,
+            version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        };
+
+        let c = zkp.bound_challenge(&spec, b"seed", &binding, &r1, &r2);
+        assert!(c < *zkp.q());
+        assert!(zkp
+            .check_bound_challenge(&spec, b"seed", &binding, (&r1, &r2), &c)
+            .is_ok());
+
+        let other_auth = ChallengeBinding {
+            auth_id: "auth-2",
+            ..binding.clone()
+        };
+        let other_tls = ChallengeBinding {
+            tls_exporter: Some(b"exporter"),
+            ..binding.clone()
+        };
+        let fingerprint = certificate_fingerprint(b"certificate");
+        let other_cert = ChallengeBinding {
+            client_cert: Some(&fingerprint),
+            ..binding.clone()
+        };
+        let downgraded = ChallengeBinding {
+            version: 1,
+            ..binding.clone()
+        };
+        for other in [other_auth, other_tls, other_cert, downgraded] {
+            assert!(zkp
+                .check_bound_challenge(&spec, b"seed", &other, (&r1, &r2), &c)
+                .is_err());
+        }
+        assert!(zkp
+            .check_bound_challenge(&spec, b"other seed", &binding, (&r1, &r2), &c)
+            .is_err());
+
+        let short = ChallengeSpec {
+            bits: 128,
+            rounds: 1,
+        };
+        assert!(
+            zkp.bound_challenge(&short, b"seed", &binding, &r1, &r2)
+                .bits()
+                <= 128
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use num_bigint::BigUint;
+
+use crate::{length_prefixed, Proof, ZkpError, ZKP};
+
+impl ZKP {
+    /// Proof of x asking the server to delete `user`, refused after
+    /// `expires_at` (Unix time in seconds).
+    pub fn prove_deletion(&self, user: &str, x: &BigUint, expires_at: u64) -> Proof {
+        self.prove_non_interactive_until(x, &deletion_context(user), expires_at)
+    }
+
+    /// Checks a deletion proof against the registered (y1, y2). Proofs without
+    /// an expiry count as expired, one that leaks must not stay usable.
+    pub fn verify_deletion(
+        &self,
+        user: &str,
+        y1: &BigUint,
+        y2: &BigUint,
+        proof: &Proof,
+    ) -> Result<(), ZkpError> {
+        if proof.expires_at.is_none() {
+            return Err(ZkpError::Expired);
+        }
+        self.verify_non_interactive(y1, y2, proof, &deletion_context(user))
+    }
+}
+
+fn deletion_context(user: &str) -> Vec<u8> {
+    length_prefixed(&[b"zkp_chaum_pedersen/deletion", user.as_bytes()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deletion() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        let expires_at = u64::MAX / 2;
+
+        let proof = zkp.prove_deletion("alice", &x, expires_at);
+        assert!(zkp.verify_deletion("alice", &y1, &y2, &proof).is_ok());
+
+        // Bound to the user name.
+        assert!(zkp.verify_deletion("bob", &y1, &y2, &proof).is_err());
+
+        // A login or rotation proof of the same x doesn't delete anything.
+        let other = zkp.prove_non_interactive_until(&x, b"context", expires_at);
+        assert!(zkp.verify_deletion("alice", &y1, &y2, &other).is_err());
+
+        let timeless = Proof {
+            expires_at: None,
+            ..zkp.prove_non_interactive(&x, &deletion_context("alice"))
+        };
+        assert!(matches!(
+            zkp.verify_deletion("alice", &y1, &y2, &timeless),
+            Err(ZkpError::Expired)
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use num_bigint::BigUint;
+
+use crate::{length_prefixed, Proof, ZkpError, ZKP};
+
+/// A key a user adds for one of their devices, next to the one they
+/// registered with. Logins may prove either. The proof of its x keeps
+/// anyone from adding keys they don't own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceKey {
+    pub y1: BigUint,
+    pub y2: BigUint,
+    /// Proof of the device's x, bound to the user, the label and the key.
+    pub proof: Proof,
+}
+
+impl ZKP {
+    pub fn prove_device(&self, user: &str, label: &str, x: &BigUint) -> DeviceKey {
+        let (y1, y2) = self.compute_pair(x);
+        let context = device_context(user, label, &y1, &y2);
+
+        DeviceKey {
+            proof: self.prove_non_interactive(x, &context),
+            y1,
+            y2,
+        }
+    }
+
+    pub fn verify_device(&self, user: &str, label: &str, key: &DeviceKey) -> Result<(), ZkpError> {
+        let context = device_context(user, label, &key.y1, &key.y2);
+        self.verify_non_interactive(&key.y1, &key.y2, &key.proof, &context)
+    }
+}
+
+fn device_context(user: &str, label: &str, y1: &BigUint, y2: &BigUint) -> Vec<u8> {
+    length_prefixed(&[
+        b"zkp_chaum_pedersen/device",
+        user.as_bytes(),
+        label.as_bytes(),
+        &y1.to_bytes_be(),
+        &y2.to_bytes_be(),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_device() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(&zkp.q);
+
+        let key = zkp.prove_device("alice", "phone", &x);
+        assert_eq!((key.y1.clone(), key.y2.clone()), zkp.compute_pair(&x));
+        assert!(zkp.verify_device("alice", "phone", &key).is_ok());
+
+        // Bound to the user name and the label.
+        assert!(zkp.verify_device("bob", "phone", &key).is_err());
+        assert!(zkp.verify_device("alice", "laptop", &key).is_err());
+
+        // Someone else's key with this proof.
+        let mut hijacked = key.clone();
+        let (evil_y1, evil_y2) = zkp.compute_pair(&ZKP::generate_random_below(&zkp.q));
+        hijacked.y1 = evil_y1;
+        hijacked.y2 = evil_y2;
+        assert!(zkp.verify_device("alice", "phone", &hijacked).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use num_bigint::BigUint;
+
+use crate::{length_prefixed, ZkpError, ZKP};
+
+/// Minimal big-endian bytes: not empty and no leading zero, except for the
+/// single byte 0. This is exactly what `BigUint::to_bytes_be` produces, so
+/// every value has one accepted encoding.
+pub fn parse_uint(bytes: &[u8]) -> Result<BigUint, ZkpError> {
+    match bytes {
+        [] => Err(ZkpError::Encoding("empty integer".into())),
+        [0, _, ..] => Err(ZkpError::Encoding("integer has leading zeros".into())),
+        _ => Ok(BigUint::from_bytes_be(bytes)),
+    }
+}
+
+impl ZKP {
+    /// Strict decoding of a group element (y1, y2, r1, r2): a canonical
+    /// integer in [1, p) that lies in the order-q subgroup.
+    pub fn parse_element(&self, bytes: &[u8]) -> Result<BigUint, ZkpError> {
+        let max_len = (self.p.bits() as usize).div_ceil(8);
+        if bytes.len() > max_len {
+            return Err(ZkpError::Encoding("group element too long".into()));
+        }
+        let n = parse_uint(bytes)?;
+        if n == BigUint::ZERO || n >= self.p {
+            return Err(ZkpError::Encoding("group element out of range".into()));
+        }
+        if self.modpow(&n, &self.q) != BigUint::from(1u32) {
+            return Err(ZkpError::Encoding(
+                "group element not in the subgroup".into(),
+            ));
+        }
+        Ok(n)
+    }
+
+    /// Strict decoding of an exponent (c, s): a canonical integer in [0, q).
+    pub fn parse_scalar(&self, bytes: &[u8]) -> Result<BigUint, ZkpError> {
+        let max_len = (self.q.bits() as usize).div_ceil(8);
+        if bytes.len() > max_len {
+            return Err(ZkpError::Encoding("scalar too long".into()));
+        }
+        let n = parse_uint(bytes)?;
+        if n >= self.q {
+            return Err(ZkpError::Encoding("scalar out of range".into()));
+        }
+        Ok(n)
+    }
+}
+
+/// Splits the output of `length_prefixed` back into its parts, expecting
+/// exactly `count` of them and nothing after.
+pub(crate) fn split_length_prefixed(bytes: &[u8], count: usize) -> Result<Vec<&[u8]>, ZkpError> {
+    let mut rest = bytes;
+    let mut parts = Vec::with_capacity(count);
+    for _ in 0..count {
+        if rest.len() < 4 {
+            return Err(ZkpError::Encoding("truncated length".into()));
+        }
+        let (len, tail) = rest.split_at(4);
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        if tail.len() < len {
+            return Err(ZkpError::Encoding("truncated value".into()));
+        }
+        let (part, tail) = tail.split_at(len);
+        parts.push(part);
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err(ZkpError::Encoding("trailing bytes".into()));
+    }
+    Ok(parts)
+}
+
+pub(crate) fn encode_uints(values: &[&BigUint]) -> Vec<u8> {
+    let bytes: Vec<Vec<u8>> = values.iter().map(|n| n.to_bytes_be()).collect();
+    let parts: Vec<&[u8]> = bytes.iter().map(Vec::as_slice).collect();
+    length_prefixed(&parts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_uint_is_canonical() {
+        assert_eq!(parse_uint(&[0]).unwrap(), BigUint::ZERO);
+        assert_eq!(parse_uint(&[1, 0]).unwrap(), BigUint::from(256u32));
+        assert!(parse_uint(&[]).is_err());
+        assert!(parse_uint(&[0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_parse_element_and_scalar() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, _) = zkp.compute_pair(&x);
+        assert_eq!(zkp.parse_element(&y1.to_bytes_be()).unwrap(), y1);
+
+        // in range but outside the subgroup
+        assert!(zkp.parse_element(&(zkp.p() - 1u32).to_bytes_be()).is_err());
+        assert!(zkp.parse_element(&zkp.p().to_bytes_be()).is_err());
+        assert!(zkp.parse_element(&[0]).is_err());
+
+        assert_eq!(zkp.parse_scalar(&x.to_bytes_be()).unwrap(), x);
+        assert!(zkp.parse_scalar(&zkp.q().to_bytes_be()).is_err());
+    }
+}
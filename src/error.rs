@@ -0,0 +1,125 @@
+use prost::Message;
+use thiserror::Error;
+
+use crate::{
+    zkp_auth::{ErrorCode, ErrorDetail},
+    ParamError,
+};
+
+/// Type URL of an `ErrorDetail` packed in a `prost_types::Any`.
+const ERROR_DETAIL_TYPE_URL: &str = "type.googleapis.com/zkp_auth.ErrorDetail";
+
+/// Everything that can go wrong in this crate.
+#[derive(Debug, Error)]
+pub enum ZkpError {
+    /// Group or key derivation parameters are unusable.
+    #[error("invalid parameters: {0}")]
+    Param(#[from] ParamError),
+    /// Bytes that don't decode to what they claim to be.
+    #[error("encoding error: {0}")]
+    Encoding(String),
+    /// A proof didn't check out. Deliberately carries no detail about which
+    /// condition failed.
+    #[error("verification failed")]
+    Verify,
+    /// A proof that was valid, but whose expiry has passed.
+    #[error("proof expired")]
+    Expired,
+    /// Proofs to aggregate that don't pair up one to one with the statements,
+    /// or challenges to answer with the rounds' nonces.
+    #[error("{proofs} proofs for {statements} statements")]
+    ProofCount { statements: usize, proofs: usize },
+    /// A proof with an expiry handed to aggregation, which has no room for it.
+    #[error("proofs with an expiry don't aggregate")]
+    ExpiringProof,
+    /// The random number generator couldn't produce output.
+    #[error("rng error: {0}")]
+    Rng(#[from] rand::Error),
+}
+
+impl From<ZkpError> for tonic::Status {
+    fn from(err: ZkpError) -> Self {
+        let code = match err {
+            ZkpError::Param(_)
+            | ZkpError::Encoding(_)
+            | ZkpError::ProofCount { .. }
+            | ZkpError::ExpiringProof => tonic::Code::InvalidArgument,
+            ZkpError::Verify | ZkpError::Expired => tonic::Code::PermissionDenied,
+            ZkpError::Rng(_) => tonic::Code::Internal,
+        };
+        match err {
+            ZkpError::Verify => {
+                status_with_error_code(code, err.to_string(), ErrorCode::ProofInvalid)
+            }
+            _ => tonic::Status::new(code, err.to_string()),
+        }
+    }
+}
+
+/// google.rpc.Status, what the grpc-status-details-bin trailer holds.
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+/// A status whose details say `error_code` for clients to go by, next to
+/// `message` for people.
+pub fn status_with_error_code(
+    code: tonic::Code,
+    message: impl Into<String>,
+    error_code: ErrorCode,
+) -> tonic::Status {
+    let message = message.into();
+    let details = RpcStatus {
+        code: code as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: ERROR_DETAIL_TYPE_URL.to_string(),
+            value: ErrorDetail {
+                code: error_code as i32,
+            }
+            .encode_to_vec(),
+        }],
+    };
+    tonic::Status::with_details(code, message, details.encode_to_vec().into())
+}
+
+/// The `ErrorCode` in the details of `status`, None if the server gave it
+/// none.
+pub fn error_code(status: &tonic::Status) -> Option<ErrorCode> {
+    let details = RpcStatus::decode(status.details()).ok()?;
+    details
+        .details
+        .iter()
+        .filter(|any| any.type_url == ERROR_DETAIL_TYPE_URL)
+        .find_map(|any| ErrorDetail::decode(any.value.as_slice()).ok())
+        .and_then(|detail| ErrorCode::try_from(detail.code).ok())
+        .filter(|code| *code != ErrorCode::Unspecified)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_code() {
+        let status = status_with_error_code(
+            tonic::Code::ResourceExhausted,
+            "User: alice is locked out.",
+            ErrorCode::LockedOut,
+        );
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.message(), "User: alice is locked out.");
+        assert_eq!(error_code(&status), Some(ErrorCode::LockedOut));
+
+        let status = tonic::Status::from(ZkpError::Verify);
+        assert_eq!(error_code(&status), Some(ErrorCode::ProofInvalid));
+        assert_eq!(error_code(&tonic::Status::from(ZkpError::Expired)), None);
+        assert_eq!(error_code(&tonic::Status::not_found("Nothing.")), None);
+    }
+}
@@ -0,0 +1,155 @@
+use crypto_bigint::{
+    modular::runtime_mod::{DynResidue, DynResidueParams},
+    Encoding, Limb, Uint, U2048, U256,
+};
+use num_bigint::BigUint;
+
+use crate::to_fixed_bytes;
+
+/// Widest p the fixed-width backend takes.
+pub(crate) const MAX_P_BITS: u64 = 2048;
+/// Widest exponent the fixed-width backend takes, enough for every RFC 5114 q.
+pub(crate) const MAX_EXP_BITS: u64 = 256;
+
+const P_LIMBS: usize = U2048::LIMBS;
+const Q_LIMBS: usize = U256::LIMBS;
+
+/// Montgomery parameters for p and q, computed once when the `ZKP` is built.
+/// Working in Montgomery form keeps every product reduced without a separate
+/// division, and the values live on the stack.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Montgomery {
+    p: DynResidueParams<P_LIMBS>,
+    q: DynResidueParams<Q_LIMBS>,
+}
+
+impl Montgomery {
+    /// `None` unless p and q are odd and fit, see `MAX_P_BITS` and `MAX_EXP_BITS`.
+    pub(crate) fn new(p: &BigUint, q: &BigUint) -> Option<Self> {
+        if !p.bit(0) || !q.bit(0) || q.bits() > MAX_EXP_BITS {
+            return None;
+        }
+        Some(Self {
+            p: DynResidueParams::new(&to_uint(p)?),
+            q: DynResidueParams::new(&to_uint(q)?),
+        })
+    }
+
+    /// base^exp mod p. `None` when exp is wider than 256 bits, the caller
+    /// falls back to `BigUint::modpow` then.
+    ///
+    /// Unlike `BigUint::modpow` the running time doesn't depend on the value of
+    /// the exponent, only on its width, which is fixed at 256 bits.
+    pub(crate) fn modpow(&self, base: &BigUint, exp: &BigUint) -> Option<BigUint> {
+        Some(from_uint(self.pow(base, exp)?.retrieve()))
+    }
+
+    /// b1^e1 * b2^e2 mod p, multiplied in Montgomery form so there is only
+    /// one conversion back at the end.
+    pub(crate) fn modpow2(
+        &self,
+        (b1, e1): (&BigUint, &BigUint),
+        (b2, e2): (&BigUint, &BigUint),
+    ) -> Option<BigUint> {
+        let product = self.pow(b1, e1)? * self.pow(b2, e2)?;
+        Some(from_uint(product.retrieve()))
+    }
+
+    /// k - c * x mod q, in constant time with respect to x.
+    pub(crate) fn solve(&self, k: &BigUint, c: &BigUint, x: &BigUint) -> Option<BigUint> {
+        // Inputs at or above the modulus are fine, the conversion into
+        // Montgomery form reduces them.
+        let scalar = |n: &BigUint| Some(DynResidue::new(&to_uint(n)?, self.q));
+        let s = scalar(k)? - scalar(c)? * scalar(x)?;
+        Some(from_uint(s.retrieve()))
+    }
+
+    fn pow(&self, base: &BigUint, exp: &BigUint) -> Option<DynResidue<P_LIMBS>> {
+        if exp.bits() > MAX_EXP_BITS {
+            return None;
+        }
+        let base = DynResidue::new(&to_uint(base)?, self.p);
+        Some(base.pow(&to_uint::<Q_LIMBS>(exp)?))
+    }
+}
+
+fn to_uint<const N: usize>(n: &BigUint) -> Option<Uint<N>> {
+    let bytes = to_fixed_bytes(n, N * Limb::BYTES)?;
+    Some(Uint::<N>::from_be_slice(&bytes))
+}
+
+fn from_uint<const N: usize>(n: Uint<N>) -> BigUint
+where
+    Uint<N>: Encoding,
+{
+    BigUint::from_bytes_be(n.to_be_bytes().as_ref())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ParamSet, ZKP};
+
+    #[test]
+    fn test_matches_biguint_arithmetic() {
+        for set in ParamSet::ALL {
+            let constants = set.constants();
+            let (p, q, alpha, beta) = (
+                &constants.p,
+                &constants.q,
+                &constants.alpha,
+                &constants.beta,
+            );
+            let montgomery = Montgomery::new(p, q).unwrap();
+            let x = ZKP::generate_random_below(q);
+            let k = ZKP::generate_random_below(q);
+            let c = ZKP::generate_random_below(q);
+
+            assert_eq!(
+                montgomery.modpow(alpha, &x),
+                Some(alpha.modpow(&x, p)),
+                "{set}"
+            );
+            assert_eq!(
+                montgomery.modpow2((alpha, &x), (beta, &k)),
+                Some(alpha.modpow(&x, p) * beta.modpow(&k, p) % p),
+                "{set}"
+            );
+            let zkp = ZKP::new(p.clone(), q.clone(), alpha.clone(), beta.clone());
+            assert_eq!(
+                montgomery.solve(&k, &c, &x),
+                Some(zkp.solve(&k, &c, &x)),
+                "{set}"
+            );
+        }
+
+        // unreduced inputs
+        let montgomery = Montgomery::new(&BigUint::from(23u32), &BigUint::from(11u32)).unwrap();
+        assert_eq!(
+            montgomery.modpow(&BigUint::from(50u32), &BigUint::from(7u32)),
+            Some(BigUint::from(50u32).modpow(&BigUint::from(7u32), &BigUint::from(23u32)))
+        );
+        assert_eq!(
+            montgomery.solve(
+                &BigUint::from(30u32),
+                &BigUint::from(13u32),
+                &BigUint::from(100u32)
+            ),
+            Some(BigUint::from((30 + 11 * 1000 - 13 * 100) % 11u32))
+        );
+    }
+
+    #[test]
+    fn test_rejects_what_does_not_fit() {
+        let p = BigUint::from(23u32);
+        let q = BigUint::from(11u32);
+        let wide = BigUint::from(1u32) << 300;
+        let montgomery = Montgomery::new(&p, &q).unwrap();
+        assert_eq!(montgomery.modpow(&BigUint::from(4u32), &wide), None);
+
+        assert!(Montgomery::new(&((BigUint::from(1u32) << 4096) + 1u32), &q).is_none());
+        assert!(Montgomery::new(&(&wide + 1u32), &(&wide + 1u32)).is_none());
+        // even modulus, no Montgomery form
+        assert!(Montgomery::new(&BigUint::from(24u32), &q).is_none());
+    }
+}
@@ -0,0 +1,183 @@
+use num_bigint::BigUint;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{length_prefixed, ChallengeSpec, ZkpError, ZKP};
+
+/// Bytes of the random nonce that hides c inside its commitment.
+pub const OPENING_NONCE_LEN: usize = 32;
+
+/// Four-move variant of the login, for settings without Fiat-Shamir where
+/// the prover can't trust the verifier to pick c at random:
+///
+/// 1. verifier -> prover: H(user, auth_id, c, nonce), committing to c for
+///    this one login
+/// 2. prover -> verifier: (r1, r2)
+/// 3. verifier -> prover: (c, nonce), the prover checks it against move 1
+/// 4. prover -> verifier: s, checked with the plain `verify`
+///
+/// Since c is fixed before the verifier sees (r1, r2), it can't be chosen as
+/// a function of them. Since the commitment names the user and auth_id, a
+/// hop relaying another login's commitment and opening is caught by the
+/// prover, and the s it gets back answers only the login it was asked for.
+///
+/// This is the verifier's state between moves 1 and 3. It only opens once,
+/// `open` consumes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommittedChallenge {
+    opening: ChallengeOpening,
+}
+
+/// What the verifier reveals in move 3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChallengeOpening {
+    pub c: BigUint,
+    pub nonce: [u8; OPENING_NONCE_LEN],
+}
+
+impl ChallengeOpening {
+    /// H(user, auth_id, c, nonce), sent in move 1.
+    pub fn commitment(&self, user: &str, auth_id: &str) -> [u8; 32] {
+        Sha256::digest(length_prefixed(&[
+            b"zkp_chaum_pedersen/challenge_commitment",
+            user.as_bytes(),
+            auth_id.as_bytes(),
+            &self.c.to_bytes_be(),
+            &self.nonce,
+        ]))
+        .into()
+    }
+}
+
+impl CommittedChallenge {
+    /// The verifier's state back from its opening, e.g. after it was stored.
+    pub fn from_opening(opening: ChallengeOpening) -> Self {
+        Self { opening }
+    }
+
+    pub fn commitment(&self, user: &str, auth_id: &str) -> [u8; 32] {
+        self.opening.commitment(user, auth_id)
+    }
+
+    /// Move 3, once the prover's (r1, r2) are in.
+    pub fn open(self) -> ChallengeOpening {
+        self.opening
+    }
+}
+
+impl ZKP {
+    /// Verifier side, move 1: picks c under `spec` and commits to it.
+    pub fn commit_challenge(&self, spec: &ChallengeSpec) -> CommittedChallenge {
+        let c = self
+            .generate_challenges(&ChallengeSpec { rounds: 1, ..*spec })
+            .remove(0);
+        CommittedChallenge {
+            opening: ChallengeOpening {
+                c,
+                nonce: rand::thread_rng().gen(),
+            },
+        }
+    }
+
+    /// Prover side, move 3: checks that the opened c is the one committed to
+    /// in move 1, for `user` and `auth_id`, and has the agreed width, before
+    /// answering it.
+    pub fn check_opening(
+        &self,
+        spec: &ChallengeSpec,
+        user: &str,
+        auth_id: &str,
+        commitment: &[u8],
+        opening: &ChallengeOpening,
+    ) -> Result<(), ZkpError> {
+        if opening.c >= spec.bound(self) {
+            return Err(ZkpError::Verify);
+        }
+        if bool::from(opening.commitment(user, auth_id)[..].ct_eq(commitment)) {
+            Ok(())
+        } else {
+            Err(ZkpError::Verify)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_four_move_login() {
+        let zkp = ZKP::default();
+        let spec = ChallengeSpec::full(&zkp);
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+
+        let committed = zkp.commit_challenge(&spec);
+        let commitment = committed.commitment("alice", "auth");
+
+        let k = ZKP::generate_random_below(zkp.q());
+        let (r1, r2) = zkp.compute_pair(&k);
+
+        let opening = committed.open();
+        assert!(zkp
+            .check_opening(&spec, "alice", "auth", &commitment, &opening)
+            .is_ok());
+
+        let s = zkp.solve(&k, &opening.c, &x);
+        assert!(zkp.verify(&r1, &r2, &y1, &y2, &opening.c, &s).is_ok());
+    }
+
+    #[test]
+    fn test_opening_must_match_commitment() {
+        let zkp = ZKP::default();
+        let spec = ChallengeSpec {
+            bits: 128,
+            rounds: 1,
+        };
+        let opening = zkp.commit_challenge(&spec).open();
+        assert!(opening.c.bits() <= 128);
+        let commitment = opening.commitment("alice", "auth");
+        assert!(zkp
+            .check_opening(&spec, "alice", "auth", &commitment, &opening)
+            .is_ok());
+
+        // another login's commitment and opening, relayed
+        assert!(zkp
+            .check_opening(&spec, "alice", "other", &commitment, &opening)
+            .is_err());
+        assert!(zkp
+            .check_opening(&spec, "bob", "auth", &commitment, &opening)
+            .is_err());
+
+        // a verifier switching c after seeing (r1, r2)
+        let switched = ChallengeOpening {
+            c: (&opening.c + 1u32) % spec.bound(&zkp),
+            ..opening.clone()
+        };
+        assert!(zkp
+            .check_opening(&spec, "alice", "auth", &commitment, &switched)
+            .is_err());
+
+        let other_nonce = ChallengeOpening {
+            nonce: [0; OPENING_NONCE_LEN],
+            ..opening.clone()
+        };
+        assert!(zkp
+            .check_opening(&spec, "alice", "auth", &commitment, &other_nonce)
+            .is_err());
+        assert!(zkp
+            .check_opening(&spec, "alice", "auth", &commitment[..16], &opening)
+            .is_err());
+
+        // wider than agreed, even if committed to
+        let wide = ChallengeOpening {
+            c: BigUint::from(1u32) << 128,
+            ..opening
+        };
+        let commitment = wide.commitment("alice", "auth");
+        assert!(zkp
+            .check_opening(&spec, "alice", "auth", &commitment, &wide)
+            .is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use num_bigint::{BigUint, RandBigInt};
+use rand::RngCore;
+
+use crate::{params::ParamError, ZkpConstants, BETA_LABEL, ZKP};
+
+/// Miller-Rabin rounds, the error probability is at most 4^-64.
+const MR_ROUNDS: usize = 64;
+
+/// Trial division before Miller-Rabin, rejects most candidates cheaply.
+const SMALL_PRIMES: [u32; 53] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241,
+];
+
+/// Shape of a generated group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupKind {
+    /// p = m * q + 1 with a `q_bits` prime q, the RFC 5114 shape. Short
+    /// exponents, so proving and verifying are fast.
+    Schnorr { q_bits: u64 },
+    /// p = 2q + 1. The only subgroups are of order 2 and q, so there is nothing
+    /// small to confine an element to, but exponents are as long as p.
+    SafePrime,
+}
+
+/// Generates a fresh group of `p_bits` bits. alpha is the smallest h^m
+/// that isn't 1, beta is derived from `BETA_LABEL`, so only p and q come
+/// from `rng`.
+pub fn generate_group<R: RngCore + ?Sized>(
+    p_bits: u64,
+    kind: GroupKind,
+    rng: &mut R,
+) -> Result<ZkpConstants, ParamError> {
+    let (p, q) = match kind {
+        GroupKind::Schnorr { q_bits } => {
+            if q_bits < 2 {
+                return Err(ParamError::TooSmall("q"));
+            }
+            if p_bits <= q_bits {
+                return Err(ParamError::TooSmall("p"));
+            }
+            generate_schnorr_primes(p_bits, q_bits, rng)
+        }
+        GroupKind::SafePrime => {
+            if p_bits < 3 {
+                return Err(ParamError::TooSmall("p"));
+            }
+            generate_safe_prime(p_bits, rng)
+        }
+    };
+
+    let cofactor = (&p - 1u32) / &q;
+    let one = BigUint::from(1u32);
+    let alpha = (2u32..)
+        .map(|h| BigUint::from(h).modpow(&cofactor, &p))
+        .find(|g| *g != one)
+        .expect("Z_p^* has elements of order q.");
+    let beta = ZKP::derive_generator(&p, &q, BETA_LABEL)?;
+
+    Ok(ZkpConstants { alpha, beta, p, q })
+}
+
+/// p = 2q + 1 with both p and q prime.
+pub fn is_safe_prime<R: RngCore + ?Sized>(p: &BigUint, rng: &mut R) -> bool {
+    *p >= BigUint::from(5u32)
+        && p.bit(0)
+        && is_probable_prime(p, rng)
+        && is_probable_prime(&((p - 1u32) >> 1), rng)
+}
+
+/// Checks that `constants` is a safe-prime group: p = 2q + 1, both prime, and
+/// both generators of order q.
+pub fn check_safe_prime_group<R: RngCore + ?Sized>(
+    constants: &ZkpConstants,
+    rng: &mut R,
+) -> Result<(), ParamError> {
+    let ZkpConstants { alpha, beta, p, q } = constants;
+    crate::check_params(p, q, alpha, beta)?;
+    if *p != q * 2u32 + 1u32 {
+        return Err(ParamError::OrderMismatch);
+    }
+    if !is_probable_prime(q, rng) {
+        return Err(ParamError::NotPrime("q"));
+    }
+    if !is_probable_prime(p, rng) {
+        return Err(ParamError::NotPrime("p"));
+    }
+    Ok(())
+}
+
+/// Miller-Rabin with `MR_ROUNDS` random bases.
+pub fn is_probable_prime<R: RngCore + ?Sized>(n: &BigUint, rng: &mut R) -> bool {
+    let two = BigUint::from(2u32);
+    if *n < two {
+        return false;
+    }
+    for small in SMALL_PRIMES {
+        if *n == BigUint::from(small) {
+            return true;
+        }
+        if (n % small) == BigUint::ZERO {
+            return false;
+        }
+    }
+
+    let n_minus_1 = n - 1u32;
+    let shift = n_minus_1.trailing_zeros().expect("n > 1");
+    let d = &n_minus_1 >> shift;
+
+    'rounds: for _ in 0..MR_ROUNDS {
+        let a = rng.gen_biguint_range(&two, &n_minus_1);
+        let mut x = a.modpow(&d, n);
+        if x == BigUint::from(1u32) || x == n_minus_1 {
+            continue;
+        }
+        for _ in 1..shift {
+            x = x.modpow(&two, n);
+            if x == n_minus_1 {
+                continue 'rounds;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+fn generate_schnorr_primes<R: RngCore + ?Sized>(
+    p_bits: u64,
+    q_bits: u64,
+    rng: &mut R,
+) -> (BigUint, BigUint) {
+    let q = loop {
+        let q = random_odd(q_bits, rng);
+        if is_probable_prime(&q, rng) {
+            break q;
+        }
+    };
+    loop {
+        // p = m * q + 1 with m even, so that p is odd, and p of exactly p_bits.
+        let m = random_odd(p_bits - q_bits + 1, rng) >> 1u32 << 1u32;
+        let p = &m * &q + 1u32;
+        if p.bits() == p_bits && is_probable_prime(&p, rng) {
+            return (p, q);
+        }
+    }
+}
+
+fn generate_safe_prime<R: RngCore + ?Sized>(p_bits: u64, rng: &mut R) -> (BigUint, BigUint) {
+    loop {
+        let q = random_odd(p_bits - 1, rng);
+        let p = &q * 2u32 + 1u32;
+        // Cheap sieve on both before spending Miller-Rabin rounds on either.
+        let composite = SMALL_PRIMES.iter().any(|&small| {
+            (BigUint::from(small) < q && (&q % small) == BigUint::ZERO)
+                || (BigUint::from(small) < p && (&p % small) == BigUint::ZERO)
+        });
+        if !composite && is_probable_prime(&q, rng) && is_probable_prime(&p, rng) {
+            return (p, q);
+        }
+    }
+}
+
+/// Random odd number of exactly `bits` bits.
+fn random_odd<R: RngCore + ?Sized>(bits: u64, rng: &mut R) -> BigUint {
+    let mut n = rng.gen_biguint(bits);
+    n.set_bit(bits - 1, true);
+    n.set_bit(0, true);
+    n
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{check_params, ParamSet};
+
+    #[test]
+    fn test_is_probable_prime() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let primes = [2u64, 3, 241, 251, 65537, 2_147_483_647, 1_000_000_007];
+        for p in primes {
+            assert!(is_probable_prime(&BigUint::from(p), &mut rng), "{p}");
+        }
+        // 561 and 41041 are Carmichael numbers.
+        for n in [0u64, 1, 4, 561, 41041, 1_000_000_007 * 3] {
+            assert!(!is_probable_prime(&BigUint::from(n), &mut rng), "{n}");
+        }
+
+        for set in ParamSet::ALL {
+            let constants = set.constants();
+            assert!(is_probable_prime(&constants.p, &mut rng), "{set}");
+            assert!(is_probable_prime(&constants.q, &mut rng), "{set}");
+            assert!(!is_safe_prime(&constants.p, &mut rng), "{set}");
+        }
+    }
+
+    #[test]
+    fn test_generate_safe_prime_group() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let constants = generate_group(128, GroupKind::SafePrime, &mut rng).unwrap();
+        assert_eq!(constants.p.bits(), 128);
+        assert_eq!(constants.p, &constants.q * 2u32 + 1u32);
+        assert!(is_safe_prime(&constants.p, &mut rng));
+        assert_eq!(check_safe_prime_group(&constants, &mut rng), Ok(()));
+
+        let zkp = ZKP::builder().constants(constants).build().unwrap();
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        let proof = zkp.prove_non_interactive(&x, b"safe prime");
+        assert!(zkp
+            .verify_non_interactive(&y1, &y2, &proof, b"safe prime")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_generate_schnorr_group() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let constants = generate_group(256, GroupKind::Schnorr { q_bits: 64 }, &mut rng).unwrap();
+        let ZkpConstants { alpha, beta, p, q } = &constants;
+        assert_eq!(p.bits(), 256);
+        assert_eq!(q.bits(), 64);
+        assert_eq!(check_params(p, q, alpha, beta), Ok(()));
+        assert_eq!(
+            check_safe_prime_group(&constants, &mut rng),
+            Err(ParamError::OrderMismatch)
+        );
+
+        assert_eq!(
+            generate_group(64, GroupKind::Schnorr { q_bits: 64 }, &mut rng).err(),
+            Some(ParamError::TooSmall("p"))
+        );
+    }
+}
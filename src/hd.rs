@@ -0,0 +1,104 @@
+use num_bigint::BigUint;
+
+use crate::{expand_hash, length_prefixed, Secret, ZKP};
+
+impl ZKP {
+    /// Non-hardened child secret for `service_label`:
+    /// x' = x + t mod q, where t = H(y1, y2, service_label)
+    /// The verifier side can follow along with `derive_child_public` without
+    /// knowing x, but a service that never saw (y1, y2) can't link x' to them.
+    pub fn derive_child_secret(&self, x: &BigUint, service_label: &[u8]) -> Secret<BigUint> {
+        let (y1, y2) = self.compute_pair(x);
+        Secret::new((x + self.child_tweak(&y1, &y2, service_label)) % &self.q)
+    }
+
+    /// Public counterpart of `derive_child_secret`:
+    /// (y1', y2') = (y1 * alpha^t mod p, y2 * beta^t mod p)
+    pub fn derive_child_public(
+        &self,
+        y1: &BigUint,
+        y2: &BigUint,
+        service_label: &[u8],
+    ) -> (BigUint, BigUint) {
+        let t = self.child_tweak(y1, y2, service_label);
+        let (t1, t2) = self.compute_pair(&t);
+        ((y1 * t1) % &self.p, (y2 * t2) % &self.p)
+    }
+
+    /// Hardened child secret: x' = H(x, service_label) mod (q - 1) + 1
+    /// Fully unlinkable even for someone who knows the parent (y1, y2), at the
+    /// price of having no public derivation.
+    pub fn derive_hardened_child_secret(
+        &self,
+        x: &BigUint,
+        service_label: &[u8],
+    ) -> Secret<BigUint> {
+        let h = expand_hash(
+            b"zkp_chaum_pedersen/hardened_child",
+            &length_prefixed(&[&x.to_bytes_be(), service_label]),
+            0,
+            self.scalar_hash_len(),
+        );
+        Secret::new(self.nonzero_scalar(h))
+    }
+
+    /// t = H(y1, y2, service_label) mod q
+    fn child_tweak(&self, y1: &BigUint, y2: &BigUint, service_label: &[u8]) -> BigUint {
+        let h = expand_hash(
+            b"zkp_chaum_pedersen/child",
+            &length_prefixed(&[&y1.to_bytes_be(), &y2.to_bytes_be(), service_label]),
+            0,
+            self.scalar_hash_len(),
+        );
+        h % &self.q
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_child_public_matches_child_secret() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+
+        let child_x = zkp
+            .derive_child_secret(&x, b"mail.example.com")
+            .into_inner();
+        let child_y = zkp.derive_child_public(&y1, &y2, b"mail.example.com");
+        assert_eq!(zkp.compute_pair(&child_x), child_y);
+        assert_ne!(child_y, (y1.clone(), y2.clone()));
+
+        let other_x = zkp
+            .derive_child_secret(&x, b"shop.example.com")
+            .into_inner();
+        assert_ne!(child_x, other_x);
+
+        // The child secret is a regular secret: the protocol runs unchanged.
+        let k = ZKP::generate_random_below(&zkp.q);
+        let c = ZKP::generate_random_below(&zkp.q);
+        let (r1, r2) = zkp.compute_pair(&k);
+        let s = zkp.solve(&k, &c, &child_x);
+        assert!(zkp.verify(&r1, &r2, &child_y.0, &child_y.1, &c, &s).is_ok());
+    }
+
+    #[test]
+    fn test_hardened_child_secret() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(&zkp.q);
+
+        let child = zkp.derive_hardened_child_secret(&x, b"mail.example.com");
+        assert_eq!(
+            child,
+            zkp.derive_hardened_child_secret(&x, b"mail.example.com")
+        );
+        assert_ne!(
+            child,
+            zkp.derive_hardened_child_secret(&x, b"shop.example.com")
+        );
+        assert_ne!(child, zkp.derive_child_secret(&x, b"mail.example.com"));
+        assert!(*child.expose() >= BigUint::from(1u32) && *child.expose() < zkp.q);
+    }
+}
@@ -0,0 +1,121 @@
+//! Identifiers of login attempts and sessions. Fresh ones are 128 random
+//! bits in hex, so no two meet by chance and none is guessed. The types keep
+//! an auth_id from going where a session_id belongs; on the wire both are
+//! strings, clients may send anything there, and session ids that are JWTs
+//! aren't random at all.
+
+use std::fmt;
+
+use rand::RngCore;
+
+use crate::Secret;
+
+/// Random bytes of a fresh identifier.
+const ID_BYTES: usize = 16;
+
+fn random_id() -> String {
+    let mut bytes = [0; ID_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Names one login attempt, from its challenge to its answer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuthId(String);
+
+impl AuthId {
+    pub fn generate() -> Self {
+        Self(random_id())
+    }
+
+    /// Whether `generate` could have made it, what servers check before
+    /// looking one up.
+    pub fn is_well_formed(&self) -> bool {
+        self.0.len() == 2 * ID_BYTES && self.0.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl From<String> for AuthId {
+    fn from(auth_id: String) -> Self {
+        Self(auth_id)
+    }
+}
+
+impl From<&str> for AuthId {
+    fn from(auth_id: &str) -> Self {
+        Self(auth_id.to_string())
+    }
+}
+
+impl fmt::Display for AuthId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// What a logged in client presents, random or a JWT. It logs in whoever
+/// holds it, so unlike `AuthId` it prints as `Secret` does.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SessionId(String);
+
+impl SessionId {
+    pub fn generate() -> Self {
+        Self(random_id())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl From<String> for SessionId {
+    fn from(session_id: String) -> Self {
+        Self(session_id)
+    }
+}
+
+impl From<&str> for SessionId {
+    fn from(session_id: &str) -> Self {
+        Self(session_id.to_string())
+    }
+}
+
+impl fmt::Debug for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SessionId")
+            .field(&Secret::new(&self.0))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ids() {
+        let auth_id = AuthId::generate();
+        assert_eq!(auth_id.as_str().len(), 2 * ID_BYTES);
+        assert!(auth_id.is_well_formed());
+        assert!(!AuthId::from("abcdefABCDEF").is_well_formed());
+        assert_ne!(auth_id, AuthId::generate());
+        assert_eq!(auth_id.to_string(), auth_id.as_str());
+
+        let session_id = SessionId::generate();
+        assert_ne!(session_id, SessionId::generate());
+        assert_eq!(format!("{session_id:?}"), "SessionId([REDACTED])");
+        assert_eq!(SessionId::from("token").into_string(), "token");
+    }
+}
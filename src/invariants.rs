@@ -0,0 +1,169 @@
+use num_bigint::BigUint;
+
+use crate::ZKP;
+
+/// An honest prover who knows x convinces the verifier for fresh k and c.
+pub fn check_completeness(zkp: &ZKP, x: &BigUint) -> bool {
+    let k = ZKP::generate_random_below(zkp.q());
+    let c = ZKP::generate_random_below(zkp.q());
+    check_completeness_with(zkp, x, &k, &c)
+}
+
+/// Same as `check_completeness` with the nonce and challenge fixed, and also
+/// checks that the answer s is reduced into [0, q).
+pub fn check_completeness_with(zkp: &ZKP, x: &BigUint, k: &BigUint, c: &BigUint) -> bool {
+    let (y1, y2) = zkp.compute_pair(x);
+    let (r1, r2) = zkp.compute_pair(k);
+    let s = zkp.solve(k, c, x);
+    s < *zkp.q() && zkp.verify(&r1, &r2, &y1, &y2, c, &s).is_ok()
+}
+
+/// A prover who answers with `x_fake` for the statement of `x` must be
+/// rejected. Returns false if that answer is accepted anyway, i.e. the inputs
+/// are a soundness counterexample.
+///
+/// Two cases are expected to pass and don't count: x_fake = x mod q (it is the
+/// same secret) and c = 0 mod q (the answer doesn't depend on the secret).
+pub fn check_soundness_counterexample(
+    zkp: &ZKP,
+    x: &BigUint,
+    x_fake: &BigUint,
+    k: &BigUint,
+    c: &BigUint,
+) -> bool {
+    let q = zkp.q();
+    if x % q == x_fake % q || c % q == BigUint::ZERO {
+        return true;
+    }
+
+    let (y1, y2) = zkp.compute_pair(x);
+    let (r1, r2) = zkp.compute_pair(k);
+    let s_fake = zkp.solve(k, c, x_fake);
+    zkp.verify(&r1, &r2, &y1, &y2, c, &s_fake).is_err()
+}
+
+/// Two accepted answers to different challenges for the same commitment give
+/// away the secret, see `extract_secret`. This is what makes the protocol a
+/// proof of knowledge rather than just of existence.
+pub fn check_special_soundness(
+    zkp: &ZKP,
+    x: &BigUint,
+    k: &BigUint,
+    c1: &BigUint,
+    c2: &BigUint,
+) -> bool {
+    let q = zkp.q();
+    if c1 % q == c2 % q {
+        return true;
+    }
+
+    let s1 = zkp.solve(k, c1, x);
+    let s2 = zkp.solve(k, c2, x);
+    extract_secret(zkp, c1, &s1, c2, &s2) == Some(x % q)
+}
+
+/// x = (s1 - s2) / (c2 - c1) mod q, from s_i = k - c_i * x with the same k.
+/// `None` when c1 = c2 mod q, those two answers carry no information.
+pub fn extract_secret(
+    zkp: &ZKP,
+    c1: &BigUint,
+    s1: &BigUint,
+    c2: &BigUint,
+    s2: &BigUint,
+) -> Option<BigUint> {
+    let q = zkp.q();
+    let dc = (c2 % q + q - c1 % q) % q;
+    if dc == BigUint::ZERO {
+        return None;
+    }
+    let ds = (s1 % q + q - s2 % q) % q;
+    // q is prime, so dc^(q - 2) is its inverse.
+    let dc_inv = dc.modpow(&(q - 2u32), q);
+    Some(ds * dc_inv % q)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn is_prime(n: u64) -> bool {
+        n >= 2
+            && (2..)
+                .take_while(|d| d * d <= n)
+                .all(|d| !n.is_multiple_of(d))
+    }
+
+    /// Random prime q below 2^20, the smallest prime p = 2 * m * q + 1 above it,
+    /// a generator of the order-q subgroup and a beta hashed from `label`.
+    fn toy_group() -> impl Strategy<Value = ZKP> {
+        (3u64..1 << 20, any::<[u8; 8]>()).prop_map(|(start, label)| {
+            let q = (start..).find(|&n| is_prime(n)).unwrap();
+            let p = (1..).map(|m| 2 * m * q + 1).find(|&n| is_prime(n)).unwrap();
+            let (p, q) = (BigUint::from(p), BigUint::from(q));
+
+            let cofactor = (&p - 1u32) / &q;
+            let alpha = (2u32..)
+                .map(|h| BigUint::from(h).modpow(&cofactor, &p))
+                .find(|g| *g != BigUint::from(1u32))
+                .unwrap();
+            let beta = ZKP::derive_generator(&p, &q, &label).unwrap();
+            ZKP::new(p, q, alpha, beta)
+        })
+    }
+
+    fn below_q(zkp: &ZKP, n: u64) -> BigUint {
+        BigUint::from(n) % zkp.q()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_completeness_toy(zkp in toy_group(), x: u64, k: u64, c: u64) {
+            let x = below_q(&zkp, x);
+            prop_assert!(check_completeness(&zkp, &x));
+            prop_assert!(check_completeness_with(&zkp, &x, &below_q(&zkp, k), &below_q(&zkp, c)));
+        }
+
+        #[test]
+        fn prop_soundness_toy(zkp in toy_group(), x: u64, x_fake: u64, k: u64, c: u64) {
+            prop_assert!(check_soundness_counterexample(
+                &zkp,
+                &below_q(&zkp, x),
+                &below_q(&zkp, x_fake),
+                &below_q(&zkp, k),
+                &below_q(&zkp, c),
+            ));
+        }
+
+        #[test]
+        fn prop_special_soundness_toy(zkp in toy_group(), x: u64, k: u64, c1: u64, c2: u64) {
+            prop_assert!(check_special_soundness(
+                &zkp,
+                &below_q(&zkp, x),
+                &below_q(&zkp, k),
+                &below_q(&zkp, c1),
+                &below_q(&zkp, c2),
+            ));
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn prop_rfc5114(x: [u8; 20], x_fake: [u8; 20], k: [u8; 20], c: [u8; 20]) {
+            let zkp = ZKP::default();
+            let [x, x_fake, k, c] =
+                [x, x_fake, k, c].map(|bytes| BigUint::from_bytes_be(&bytes) % zkp.q());
+            prop_assert!(check_completeness_with(&zkp, &x, &k, &c));
+            prop_assert!(check_soundness_counterexample(&zkp, &x, &x_fake, &k, &c));
+        }
+    }
+
+    #[test]
+    fn test_extract_secret_same_challenge() {
+        let zkp = ZKP::default();
+        let c = BigUint::from(5u32);
+        assert_eq!(extract_secret(&zkp, &c, &c, &c, &c), None);
+    }
+}
@@ -0,0 +1,119 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use num_bigint::BigUint;
+use rand::RngCore;
+
+use crate::{ParamError, Secret, ZkpError, ZKP};
+
+/// Length of the per-user salts produced by `ZKP::generate_salt`.
+pub const SALT_LEN: usize = 16;
+
+/// Argon2id cost parameters used to turn a password into the secret x.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory size in KiB.
+    pub m_cost: u32,
+    /// Number of iterations.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// OWASP recommended minimum for Argon2id: 19 MiB, 2 iterations, 1 lane.
+    fn default() -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl ZKP {
+    /// x = Argon2id(password, salt) mod (q - 1) + 1
+    /// The same password, salt and params always give the same x, so the
+    /// prover can recompute its secret from user input on every login.
+    /// The salt must be at least 8 bytes long.
+    pub fn derive_secret_from_password(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+        params: &KdfParams,
+    ) -> Result<Secret<BigUint>, ZkpError> {
+        let len = self.scalar_hash_len();
+        let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(len))
+            .map_err(|err| ParamError::Kdf(err.to_string()))?;
+
+        let mut out = vec![0u8; len];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password, salt, &mut out)
+            .map_err(|err| ParamError::Kdf(err.to_string()))?;
+
+        Ok(Secret::new(
+            self.nonzero_scalar(BigUint::from_bytes_be(&out)),
+        ))
+    }
+
+    /// Fresh random salt, generated once at registration and stored by the server.
+    pub fn generate_salt() -> Result<Vec<u8>, ZkpError> {
+        let mut salt = vec![0u8; SALT_LEN];
+        rand::thread_rng().try_fill_bytes(&mut salt)?;
+        Ok(salt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FAST: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_derive_secret_from_password() {
+        let zkp = ZKP::default();
+
+        let x = zkp
+            .derive_secret_from_password(b"hunter2", b"some salt", &FAST)
+            .unwrap();
+        assert!(*x.expose() >= BigUint::from(1u32) && *x.expose() < zkp.q);
+
+        let again = zkp
+            .derive_secret_from_password(b"hunter2", b"some salt", &FAST)
+            .unwrap();
+        assert_eq!(x, again);
+
+        let other_salt = zkp
+            .derive_secret_from_password(b"hunter2", b"other salt", &FAST)
+            .unwrap();
+        assert_ne!(x, other_salt);
+
+        let other_password = zkp
+            .derive_secret_from_password(b"hunter3", b"some salt", &FAST)
+            .unwrap();
+        assert_ne!(x, other_password);
+    }
+
+    #[test]
+    fn test_generate_salt() {
+        let salt = ZKP::generate_salt().unwrap();
+        assert_eq!(salt.len(), SALT_LEN);
+        assert_ne!(salt, ZKP::generate_salt().unwrap());
+
+        let zkp = ZKP::default();
+        assert!(zkp
+            .derive_secret_from_password(b"hunter2", &salt, &FAST)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_derive_secret_from_password_rejects_short_salt() {
+        let zkp = ZKP::default();
+        assert!(zkp
+            .derive_secret_from_password(b"hunter2", b"salt", &FAST)
+            .is_err());
+    }
+}
@@ -1,5 +1,79 @@
-use num_bigint::{BigUint, RandBigInt};
+use num_bigint::{BigInt, BigUint, RandBigInt};
+use num_integer::Integer;
 use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+use subtle::{Choice, ConstantTimeEq};
+
+pub mod aggregate;
+pub mod builder;
+pub mod challenge;
+pub mod deletion;
+pub mod device;
+pub mod encoding;
+pub mod error;
+mod fixed;
+pub mod four_move;
+pub mod generate;
+pub mod hd;
+pub mod ids;
+pub mod invariants;
+pub mod kdf;
+pub mod mutual;
+pub mod params;
+pub mod pow;
+pub mod proof;
+pub mod realm;
+pub mod recovery;
+mod redact;
+pub mod rotation;
+pub mod secret;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod test_vectors;
+pub mod totp;
+pub mod version;
+
+pub mod zkp_auth {
+    include!("zkp_auth.rs");
+
+    /// Encoded `FileDescriptorSet` of zkp_auth.proto, what gRPC reflection
+    /// serves.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("zkp_auth_descriptor.bin");
+}
+
+pub use aggregate::{AggregateProof, Statement};
+pub use builder::{Backend, ZkpBuilder};
+pub use challenge::{
+    certificate_fingerprint, server_identity, ChallengeBinding, ChallengeSpec, RoundCommitments,
+};
+pub use device::DeviceKey;
+use encoding::split_length_prefixed;
+pub use error::{error_code, status_with_error_code, ZkpError};
+use fixed::Montgomery;
+pub use four_move::{ChallengeOpening, CommittedChallenge, OPENING_NONCE_LEN};
+pub use generate::{generate_group, GroupKind};
+pub use ids::{AuthId, SessionId};
+pub use kdf::{KdfParams, SALT_LEN};
+pub use mutual::SessionTranscript;
+use params::{check_order, check_params};
+pub use params::{validate_group, GroupReport, ParamError, ParamSet, PARAMS_VERSION};
+pub use pow::{PowInput, MAX_POW_DIFFICULTY};
+pub use proof::Proof;
+pub use realm::{qualified_name, split_qualified_name};
+pub use recovery::{generate_recovery_codes, hash_recovery_code, KeyRecovery, RECOVERY_CODE_COUNT};
+pub use rotation::KeyRotation;
+pub use secret::Secret;
+pub use test_vectors::TestVector;
+pub use totp::{check_totp, generate_totp_secret, totp_code, totp_secret_base32};
+pub use version::{negotiate_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/// Clock skew tolerated on proof expiry unless the builder sets another.
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Public label used to derive beta from the group, see `ZKP::derive_generator`.
+pub const BETA_LABEL: &[u8] = b"zkp_chaum_pedersen/beta";
 
 #[derive(Debug, Clone)]
 pub struct ZKP {
@@ -7,11 +81,69 @@ pub struct ZKP {
     q: BigUint,
     alpha: BigUint,
     beta: BigUint,
+    backend: Backend,
+    /// Set for `Backend::Fixed2048`.
+    montgomery: Option<Montgomery>,
+    /// How far past its expiry a proof is still accepted, see `Proof::expires_at`.
+    clock_skew: Duration,
 }
 
 impl ZKP {
     pub fn new(p: BigUint, q: BigUint, alpha: BigUint, beta: BigUint) -> Self {
-        Self { p, q, alpha, beta }
+        Self {
+            p,
+            q,
+            alpha,
+            beta,
+            backend: Backend::default(),
+            montgomery: None,
+            clock_skew: DEFAULT_CLOCK_SKEW,
+        }
+    }
+
+    pub fn p(&self) -> &BigUint {
+        &self.p
+    }
+
+    pub fn q(&self) -> &BigUint {
+        &self.q
+    }
+
+    pub fn alpha(&self) -> &BigUint {
+        &self.alpha
+    }
+
+    pub fn beta(&self) -> &BigUint {
+        &self.beta
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn clock_skew(&self) -> Duration {
+        self.clock_skew
+    }
+
+    pub fn constants(&self) -> ZkpConstants {
+        ZkpConstants {
+            alpha: self.alpha.clone(),
+            beta: self.beta.clone(),
+            p: self.p.clone(),
+            q: self.q.clone(),
+        }
+    }
+
+    /// Parameter set encoding, see `ZkpConstants::to_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.constants().to_bytes()
+    }
+
+    /// Decodes and validates a parameter set, see `ZkpConstants::from_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ZkpError> {
+        let ZkpConstants { alpha, beta, p, q } = ZkpConstants::from_bytes(bytes)?;
+        check_params(&p, &q, &alpha, &beta)?;
+        Ok(Self::new(p, q, alpha, beta))
     }
 
     /// alpha^x mod p
@@ -20,14 +152,46 @@ impl ZKP {
         n.modpow(exponent, modulus)
     }
 
+    /// (alpha^exp mod p, beta^exp mod p)
+    /// Gives (y1, y2) for the secret x, or (r1, r2) for the nonce k.
+    pub fn compute_pair(&self, exponent: &BigUint) -> (BigUint, BigUint) {
+        (
+            self.modpow(&self.alpha, exponent),
+            self.modpow(&self.beta, exponent),
+        )
+    }
+
+    /// base^exp mod p with the configured backend.
+    pub(crate) fn modpow(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        self.montgomery
+            .as_ref()
+            .and_then(|montgomery| montgomery.modpow(base, exponent))
+            .unwrap_or_else(|| base.modpow(exponent, &self.p))
+    }
+
+    /// b1^e1 * b2^e2 mod p with the configured backend.
+    fn modpow2(&self, b1: &BigUint, e1: &BigUint, b2: &BigUint, e2: &BigUint) -> BigUint {
+        self.montgomery
+            .as_ref()
+            .and_then(|montgomery| montgomery.modpow2((b1, e1), (b2, e2)))
+            .unwrap_or_else(|| b1.modpow(e1, &self.p) * b2.modpow(e2, &self.p) % &self.p)
+    }
+
     /// output: s = k - c * x mod q
+    /// Done in signed arithmetic with a floored modulus, so the result lands in
+    /// [0, q) without branching on whether k < c * x.
     pub fn solve(&self, k: &BigUint, c: &BigUint, x: &BigUint) -> BigUint {
-        // Gently handle negative modulus problem.
-        if *k >= c * x {
-            (k - c * x).modpow(&BigUint::from(1u32), &self.q)
-        } else {
-            &self.q - (c * x - k).modpow(&BigUint::from(1u32), &self.q)
+        if let Some(s) = self
+            .montgomery
+            .as_ref()
+            .and_then(|montgomery| montgomery.solve(k, c, x))
+        {
+            return s;
         }
+        let s = (BigInt::from(k.clone()) - BigInt::from(c * x))
+            .mod_floor(&BigInt::from(self.q.clone()));
+        // mod_floor with a positive modulus is never negative.
+        s.into_parts().1
     }
 
     /// cond1: r1 = alpha^s * y1^c
@@ -40,16 +204,58 @@ impl ZKP {
         y2: &BigUint,
         c: &BigUint,
         s: &BigUint,
-    ) -> bool {
-        let cond1 = *r1
-            == (&self.alpha.modpow(s, &self.p) * y1.modpow(c, &self.p))
-                .modpow(&BigUint::from(1u32), &self.p);
+    ) -> Result<(), ZkpError> {
+        let cond1 = self.ct_eq_mod_p(r1, &self.modpow2(&self.alpha, s, y1, c));
+        let cond2 = self.ct_eq_mod_p(r2, &self.modpow2(&self.beta, s, y2, c));
+
+        // Both conditions are always evaluated, the timing doesn't tell which one failed.
+        if bool::from(cond1 & cond2) {
+            Ok(())
+        } else {
+            Err(ZkpError::Verify)
+        }
+    }
+
+    /// Constant-time a == b over fixed-width encodings as long as p.
+    /// Values that don't fit in that width are never equal to a reduced one.
+    fn ct_eq_mod_p(&self, a: &BigUint, b: &BigUint) -> Choice {
+        let len = (self.p.bits() as usize).div_ceil(8);
+        match (to_fixed_bytes(a, len), to_fixed_bytes(b, len)) {
+            (Some(a), Some(b)) => a.ct_eq(&b),
+            _ => Choice::from(0),
+        }
+    }
 
-        let cond2 = *r2
-            == (&self.beta.modpow(s, &self.p) * y2.modpow(c, &self.p))
-                .modpow(&BigUint::from(1u32), &self.p);
+    /// Hashes `label` into the order-q subgroup, nothing-up-my-sleeve style:
+    /// output: h^((p-1)/q) mod p, where h = H(label, counter) mod p
+    /// The counter is bumped until the result is not the identity. Fails
+    /// unless q divides p - 1, there is no such subgroup then.
+    pub fn derive_generator(p: &BigUint, q: &BigUint, label: &[u8]) -> Result<BigUint, ParamError> {
+        check_order(p, q)?;
+        let one = BigUint::from(1u32);
+        let cofactor = (p - &one) / q;
+        let len = wide_len(p);
+
+        let mut counter = 0u32;
+        loop {
+            let h = expand_hash(b"zkp_chaum_pedersen/derive_generator", label, counter, len) % p;
+            let g = h.modpow(&cofactor, p);
+            if g > one {
+                return Ok(g);
+            }
+            counter += 1;
+        }
+    }
+
+    /// Hash output length for reductions mod q, see `wide_len`.
+    pub(crate) fn scalar_hash_len(&self) -> usize {
+        wide_len(&self.q)
+    }
 
-        cond1 && cond2
+    /// Reduces wide hash output into [1, q), for secrets that must not be zero.
+    pub(crate) fn nonzero_scalar(&self, h: BigUint) -> BigUint {
+        let one = BigUint::from(1u32);
+        h % (&self.q - &one) + one
     }
 
     pub fn generate_random_below(bound: &BigUint) -> BigUint {
@@ -69,7 +275,7 @@ impl ZKP {
 impl Default for ZKP {
     fn default() -> Self {
         let ZkpConstants { alpha, beta, p, q } = ZkpConstants::new();
-        Self { alpha, beta, p, q }
+        Self::new(p, q, alpha, beta)
     }
 }
 
@@ -92,7 +298,7 @@ impl ZkpConstants {
         A151AF5F 0DC8B4BD 45BF37DF 365C1A65 E68CFDA7 6D4DA708
         DF1FB2BC 2E4A4371"#,
             ))
-            .unwrap(),
+            .expect("Could not decode the hex."),
         );
 
         let q = BigUint::from_bytes_be(
@@ -109,16 +315,43 @@ impl ZkpConstants {
         D662A4D1 8E73AFA3 2D779D59 18D08BC8 858F4DCE F97C2A24
         855E6EEB 22B3B2E5"#,
             ))
-            .unwrap(),
+            .expect("Could not decode the hex."),
         );
 
-        // beta: alpha^i is also a generator
-        let beta = alpha.modpow(&ZKP::generate_random_below(&q), &p);
+        // beta: hashed into the subgroup so nobody knows log_alpha(beta)
+        let beta = ZKP::derive_generator(&p, &q, BETA_LABEL).expect("q divides p - 1.");
 
         ZkpConstants { alpha, beta, p, q }
     }
 }
 
+impl ZkpConstants {
+    /// p, q, alpha, beta in that order, each big-endian with a 4-byte length prefix.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        length_prefixed(&[
+            &self.p.to_bytes_be(),
+            &self.q.to_bytes_be(),
+            &self.alpha.to_bytes_be(),
+            &self.beta.to_bytes_be(),
+        ])
+    }
+
+    /// Inverse of `to_bytes`. Only the framing is checked here, use
+    /// `ZKP::from_bytes` to also check that the values form a valid group.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ZkpError> {
+        let parts = split_length_prefixed(bytes, 4)?;
+        let [p, q, alpha, beta] =
+            [parts[0], parts[1], parts[2], parts[3]].map(BigUint::from_bytes_be);
+        Ok(ZkpConstants { alpha, beta, p, q })
+    }
+}
+
+impl Default for ZkpConstants {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 fn clear_whitespaces(s: &str) -> String {
     s.to_string()
         .chars()
@@ -126,6 +359,51 @@ fn clear_whitespaces(s: &str) -> String {
         .collect()
 }
 
+/// Big-endian encoding of `n` left-padded with zeros to exactly `len` bytes.
+fn to_fixed_bytes(n: &BigUint, len: usize) -> Option<Vec<u8>> {
+    let bytes = n.to_bytes_be();
+    if bytes.len() > len {
+        return None;
+    }
+    let mut out = vec![0u8; len - bytes.len()];
+    out.extend_from_slice(&bytes);
+    Some(out)
+}
+
+/// Concatenates `parts` with a 4-byte length before each, so that different
+/// splits of the same bytes never hash the same.
+fn length_prefixed(parts: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for part in parts {
+        out.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        out.extend_from_slice(part);
+    }
+    out
+}
+
+/// Bytes of hash output to take before reducing mod `bound`. The extra 16
+/// bytes keep the bias of the reduction negligible.
+fn wide_len(bound: &BigUint) -> usize {
+    (bound.bits() as usize).div_ceil(8) + 16
+}
+
+/// Stretches SHA-256 over (domain, counter, block, label) to `len` bytes.
+fn expand_hash(domain: &[u8], label: &[u8], counter: u32, len: usize) -> BigUint {
+    let mut out = Vec::with_capacity(len);
+    let mut block = 0u32;
+    while out.len() < len {
+        let mut hasher = Sha256::new();
+        hasher.update(domain);
+        hasher.update(counter.to_be_bytes());
+        hasher.update(block.to_be_bytes());
+        hasher.update(label);
+        out.extend_from_slice(&hasher.finalize());
+        block += 1;
+    }
+    out.truncate(len);
+    BigUint::from_bytes_be(&out)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -157,13 +435,33 @@ mod test {
         assert_eq!(s, BigUint::from(5u32));
 
         let verification = zkp.verify(&r1, &r2, &y1, &y2, &c, &s);
-        assert!(verification);
+        assert!(verification.is_ok());
 
         // fake secret
         let x_fake = BigUint::from(7u32);
         let s_fake = zkp.solve(&k, &c, &x_fake);
         let verification = zkp.verify(&r1, &r2, &y1, &y2, &c, &s_fake);
-        assert!(!verification);
+        assert!(verification.is_err());
+    }
+
+    #[test]
+    fn test_compute_pair() {
+        let zkp = ZKP::new(
+            BigUint::from(23u32),
+            BigUint::from(11u32),
+            BigUint::from(4u32),
+            BigUint::from(9u32),
+        );
+
+        // (4^6, 9^6) and (4^7, 9^7) mod 23, as in test_toy_example
+        assert_eq!(
+            zkp.compute_pair(&BigUint::from(6u32)),
+            (BigUint::from(2u32), BigUint::from(3u32))
+        );
+        assert_eq!(
+            zkp.compute_pair(&BigUint::from(7u32)),
+            (BigUint::from(8u32), BigUint::from(4u32))
+        );
     }
 
     #[test]
@@ -188,7 +486,7 @@ mod test {
         let s = zkp.solve(&k, &c, &x);
 
         let verification = zkp.verify(&r1, &r2, &y1, &y2, &c, &s);
-        assert!(verification);
+        assert!(verification.is_ok());
     }
 
     /*
@@ -231,6 +529,123 @@ mod test {
         let s = zkp.solve(&k, &c, &x);
 
         let verification = zkp.verify(&r1, &r2, &y1, &y2, &c, &s);
-        assert!(verification);
+        assert!(verification.is_ok());
+    }
+
+    #[test]
+    fn test_solve_reduces_into_range() {
+        let zkp = ZKP::new(
+            BigUint::from(23u32),
+            BigUint::from(11u32),
+            BigUint::from(4u32),
+            BigUint::from(9u32),
+        );
+
+        // k >= c * x
+        assert_eq!(
+            zkp.solve(
+                &BigUint::from(10u32),
+                &BigUint::from(1u32),
+                &BigUint::from(3u32)
+            ),
+            BigUint::from(7u32)
+        );
+        // k < c * x, where the old branch used to compute q - 0 = q for multiples of q
+        assert_eq!(
+            zkp.solve(
+                &BigUint::from(0u32),
+                &BigUint::from(1u32),
+                &BigUint::from(11u32)
+            ),
+            BigUint::from(0u32)
+        );
+        assert_eq!(
+            zkp.solve(
+                &BigUint::from(1u32),
+                &BigUint::from(2u32),
+                &BigUint::from(3u32)
+            ),
+            BigUint::from(6u32)
+        );
+    }
+
+    #[test]
+    fn test_verify_rejects_unreduced_commitment() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(&zkp.q);
+        let k = ZKP::generate_random_below(&zkp.q);
+        let c = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        let (r1, r2) = zkp.compute_pair(&k);
+        let s = zkp.solve(&k, &c, &x);
+        assert!(zkp.verify(&r1, &r2, &y1, &y2, &c, &s).is_ok());
+
+        // r1 + p is congruent to r1 but isn't the canonical encoding.
+        let r1_shifted = &r1 + &zkp.p;
+        assert!(zkp.verify(&r1_shifted, &r2, &y1, &y2, &c, &s).is_err());
+        let r1_huge = &r1 << 2048;
+        assert!(zkp.verify(&r1_huge, &r2, &y1, &y2, &c, &s).is_err());
+    }
+
+    #[test]
+    fn test_parameter_bytes_roundtrip() {
+        let zkp = ZKP::default();
+        assert_eq!(zkp.constants().p, *zkp.p());
+
+        let bytes = zkp.to_bytes();
+        let decoded = ZKP::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.p(), zkp.p());
+        assert_eq!(decoded.q(), zkp.q());
+        assert_eq!(decoded.alpha(), zkp.alpha());
+        assert_eq!(decoded.beta(), zkp.beta());
+
+        assert!(matches!(
+            ZkpConstants::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ZkpError::Encoding(_))
+        ));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            ZkpConstants::from_bytes(&trailing),
+            Err(ZkpError::Encoding(_))
+        ));
+
+        // Well framed, but beta isn't in the subgroup.
+        let mut constants = zkp.constants();
+        constants.beta += 1u32;
+        assert!(matches!(
+            ZKP::from_bytes(&constants.to_bytes()),
+            Err(ZkpError::Param(ParamError::BadGenerator("beta")))
+        ));
+    }
+
+    #[test]
+    fn test_derive_generator() {
+        let ZkpConstants { alpha, beta, p, q } = ZkpConstants::new();
+
+        let one = BigUint::from(1u32);
+        assert_ne!(beta, one);
+        assert_ne!(beta, alpha);
+        assert_eq!(ZKP::exponantiate(&beta, &q, &p), one);
+
+        // Deterministic per label, distinct across labels.
+        assert_eq!(beta, ZKP::derive_generator(&p, &q, BETA_LABEL).unwrap());
+        assert_ne!(
+            beta,
+            ZKP::derive_generator(&p, &q, b"another label").unwrap()
+        );
+
+        // Toy group: the order-11 subgroup of Z_23^*.
+        let (p, q) = (BigUint::from(23u32), BigUint::from(11u32));
+        let g = ZKP::derive_generator(&p, &q, b"toy").unwrap();
+        assert_ne!(g, one);
+        assert_eq!(ZKP::exponantiate(&g, &q, &p), one);
+
+        // No order-q subgroup to hash into.
+        let derive = |p: u32, q: u32| ZKP::derive_generator(&p.into(), &q.into(), b"toy");
+        assert_eq!(derive(23, 7), Err(ParamError::OrderMismatch));
+        assert_eq!(derive(23, 29), Err(ParamError::OrderMismatch));
+        assert_eq!(derive(0, 11), Err(ParamError::TooSmall("p")));
+        assert_eq!(derive(23, 0), Err(ParamError::TooSmall("q")));
     }
 }
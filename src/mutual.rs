@@ -0,0 +1,98 @@
+use num_bigint::BigUint;
+
+use crate::{length_prefixed, Proof, ZkpError, ZKP};
+
+/// Everything both sides saw during one login, the server signs off on it
+/// with a proof of its own secret so the client knows who issued the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionTranscript<'a> {
+    pub user: &'a str,
+    pub auth_id: &'a str,
+    pub session_id: &'a str,
+    pub r1: &'a BigUint,
+    pub r2: &'a BigUint,
+    pub c: &'a BigUint,
+    pub s: &'a BigUint,
+}
+
+impl SessionTranscript<'_> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        length_prefixed(&[
+            b"zkp_chaum_pedersen/session",
+            self.user.as_bytes(),
+            self.auth_id.as_bytes(),
+            self.session_id.as_bytes(),
+            &self.r1.to_bytes_be(),
+            &self.r2.to_bytes_be(),
+            &self.c.to_bytes_be(),
+            &self.s.to_bytes_be(),
+        ])
+    }
+}
+
+impl ZKP {
+    /// Server side: proof of the server secret bound to the transcript.
+    pub fn prove_session(&self, server_x: &BigUint, transcript: &SessionTranscript) -> Proof {
+        self.prove_non_interactive(server_x, &transcript.to_bytes())
+    }
+
+    /// Client side: checks the proof against the pinned server public key
+    /// before trusting the session id in the transcript.
+    pub fn verify_session(
+        &self,
+        server_y1: &BigUint,
+        server_y2: &BigUint,
+        transcript: &SessionTranscript,
+        proof: &Proof,
+    ) -> Result<(), ZkpError> {
+        self.verify_non_interactive(server_y1, server_y2, proof, &transcript.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_proof() {
+        let zkp = ZKP::default();
+        let server_x = ZKP::generate_random_below(zkp.q());
+        let (server_y1, server_y2) = zkp.compute_pair(&server_x);
+
+        let (r1, r2, c, s) = (
+            BigUint::from(1u32),
+            BigUint::from(2u32),
+            BigUint::from(3u32),
+            BigUint::from(4u32),
+        );
+        let transcript = SessionTranscript {
+            user: "alice",
+            auth_id: "auth",
+            session_id: "session",
+            r1: &r1,
+            r2: &r2,
+            c: &c,
+            s: &s,
+        };
+
+        let proof = zkp.prove_session(&server_x, &transcript);
+        assert!(zkp
+            .verify_session(&server_y1, &server_y2, &transcript, &proof)
+            .is_ok());
+
+        // A different session id, e.g. swapped in by a man in the middle.
+        let forged = SessionTranscript {
+            session_id: "attacker",
+            ..transcript.clone()
+        };
+        assert!(zkp
+            .verify_session(&server_y1, &server_y2, &forged, &proof)
+            .is_err());
+
+        // A server that doesn't hold the pinned key.
+        let impostor = zkp.prove_session(&ZKP::generate_random_below(zkp.q()), &transcript);
+        assert!(zkp
+            .verify_session(&server_y1, &server_y2, &transcript, &impostor)
+            .is_err());
+    }
+}
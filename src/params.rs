@@ -0,0 +1,368 @@
+use std::fmt;
+
+use num_bigint::BigUint;
+use thiserror::Error;
+
+use crate::{clear_whitespaces, generate::is_probable_prime, ZkpConstants, BETA_LABEL, ZKP};
+
+/// Below this `validate_group` refuses a group outright.
+pub const MIN_P_BITS: u64 = 1024;
+/// Below this `validate_group` refuses a group outright.
+pub const MIN_Q_BITS: u64 = 160;
+
+/// Revision of the named sets' constants, bumped whenever one of them
+/// changes, e.g. with a new `BETA_LABEL`. Peers on different revisions may
+/// mean different groups by the same name.
+pub const PARAMS_VERSION: u32 = 1;
+
+/// Betas up to alpha^SMALL_POWERS are treated as known discrete logs.
+const SMALL_POWERS: u32 = 64;
+
+/// Named groups the crate knows about, all from RFC 5114 section 2.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ParamSet {
+    /// 1024-bit p with a 160-bit prime order subgroup, what `ZkpConstants::new` returns.
+    #[default]
+    Rfc5114_1024_160,
+    /// 2048-bit p with a 224-bit prime order subgroup.
+    Rfc5114_2048_224,
+    /// 2048-bit p with a 256-bit prime order subgroup.
+    Rfc5114_2048_256,
+}
+
+impl ParamSet {
+    pub const ALL: [ParamSet; 3] = [
+        ParamSet::Rfc5114_1024_160,
+        ParamSet::Rfc5114_2048_224,
+        ParamSet::Rfc5114_2048_256,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParamSet::Rfc5114_1024_160 => "rfc5114-1024-160",
+            ParamSet::Rfc5114_2048_224 => "rfc5114-2048-224",
+            ParamSet::Rfc5114_2048_256 => "rfc5114-2048-256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|set| set.name() == name)
+    }
+
+    /// The named set these are exactly the constants of, if any.
+    pub fn of(constants: &ZkpConstants) -> Option<Self> {
+        Self::ALL.into_iter().find(|set| {
+            let named = set.constants();
+            named.p == constants.p
+                && named.q == constants.q
+                && named.alpha == constants.alpha
+                && named.beta == constants.beta
+        })
+    }
+
+    /// The group with alpha from the RFC and beta derived from `BETA_LABEL`.
+    pub fn constants(&self) -> ZkpConstants {
+        match self {
+            ParamSet::Rfc5114_1024_160 => ZkpConstants::new(),
+            ParamSet::Rfc5114_2048_224 => constants_from_hex(
+                r#"AD107E1E 9123A9D0 D660FAA7 9559C51F A20D64E5 683B9FD1
+        B54B1597 B61D0A75 E6FA141D F95A56DB AF9A3C40 7BA1DF15
+        EB3D688A 309C180E 1DE6B85A 1274A0A6 6D3F8152 AD6AC212
+        9037C9ED EFDA4DF8 D91E8FEF 55B7394B 7AD5B7D0 B6C12207
+        C9F98D11 ED34DBF6 C6BA0B2C 8BBC27BE 6A00E0A0 B9C49708
+        B3BF8A31 70918836 81286130 BC8985DB 1602E714 415D9330
+        278273C7 DE31EFDC 7310F712 1FD5A074 15987D9A DC0A486D
+        CDF93ACC 44328387 315D75E1 98C641A4 80CD86A1 B9E587E8
+        BE60E69C C928B2B9 C52172E4 13042E9B 23F10B0E 16E79763
+        C9B53DCF 4BA80A29 E3FB73C1 6B8E75B9 7EF363E2 FFA31F71
+        CF9DE538 4E71B81C 0AC4DFFE 0C10E64F"#,
+                r#"801C0D34 C58D93FE 99717710 1F80535A 4738CEBC BF389A99
+        B36371EB"#,
+                r#"AC4032EF 4F2D9AE3 9DF30B5C 8FFDAC50 6CDEBE7B 89998CAF
+        74866A08 CFE4FFE3 A6824A4E 10B9A6F0 DD921F01 A70C4AFA
+        AB739D77 00C29F52 C57DB17C 620A8652 BE5E9001 A8D66AD7
+        C1766910 1999024A F4D02727 5AC1348B B8A762D0 521BC98A
+        E2471504 22EA1ED4 09939D54 DA7460CD B5F6C6B2 50717CBE
+        F180EB34 118E98D1 19529A45 D6F83456 6E3025E3 16A330EF
+        BB77A86F 0C1AB15B 051AE3D4 28C8F8AC B70A8137 150B8EEB
+        10E183ED D19963DD D9E263E4 770589EF 6AA21E7F 5F2FF381
+        B539CCE3 409D13CD 566AFBB4 8D6C0191 81E1BCFE 94B30269
+        EDFE72FE 9B6AA4BD 7B5A0F1C 71CFFF4C 19C418E1 F6EC0179
+        81BC087F 2A7065B3 84B890D3 191F2BFA"#,
+            ),
+            ParamSet::Rfc5114_2048_256 => constants_from_hex(
+                r#"87A8E61D B4B6663C FFBBD19C 65195999 8CEEF608 660DD0F2
+        5D2CEED4 435E3B00 E00DF8F1 D61957D4 FAF7DF45 61B2AA30
+        16C3D911 34096FAA 3BF4296D 830E9A7C 209E0C64 97517ABD
+        5A8A9D30 6BCF67ED 91F9E672 5B4758C0 22E0B1EF 4275BF7B
+        6C5BFC11 D45F9088 B941F54E B1E59BB8 BC39A0BF 12307F5C
+        4FDB70C5 81B23F76 B63ACAE1 CAA6B790 2D525267 35488A0E
+        F13C6D9A 51BFA4AB 3AD83477 96524D8E F6A167B5 A41825D9
+        67E144E5 14056425 1CCACB83 E6B486F6 B3CA3F79 71506026
+        C0B857F6 89962856 DED4010A BD0BE621 C3A3960A 54E710C3
+        75F26375 D7014103 A4B54330 C198AF12 6116D227 6E11715F
+        693877FA D7EF09CA DB094AE9 1E1A1597"#,
+                r#"8CF83642 A709A097 B4479976 40129DA2 99B1A47D 1EB3750B
+        A308B0FE 64F5FBD3"#,
+                r#"3FB32C9B 73134D0B 2E775066 60EDBD48 4CA7B18F 21EF2054
+        07F4793A 1A0BA125 10DBC150 77BE463F FF4FED4A AC0BB555
+        BE3A6C1B 0C6B47B1 BC3773BF 7E8C6F62 901228F8 C28CBB18
+        A55AE313 41000A65 0196F931 C77A57F2 DDF463E5 E9EC144B
+        777DE62A AAB8A862 8AC376D2 82D6ED38 64E67982 428EBC83
+        1D14348F 6F2F9193 B5045AF2 767164E1 DFC967C1 FB3F2E55
+        A4BD1BFF E83B9C80 D052B985 D182EA0A DB2A3B73 13D3FE14
+        C8484B1E 052588B9 B7D2BBD2 DF016199 ECD06E15 57CD0915
+        B3353BBB 64E0EC37 7FD02837 0DF92B52 C7891428 CDC67EB6
+        184B523D 1DB246C3 2F630784 90F00EF8 D647D148 D4795451
+        5E2327CF EF98C582 664B4C0F 6CC41659"#,
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ParamSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Why a set of parameters was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParamError {
+    /// A parameter was never set.
+    #[error("{0} is not set")]
+    Missing(&'static str),
+    /// A parameter is too small to form a group.
+    #[error("{0} is too small")]
+    TooSmall(&'static str),
+    /// A parameter doesn't fit the selected backend.
+    #[error("{0} is not supported by the backend")]
+    Unsupported(&'static str),
+    /// A parameter that must be prime isn't.
+    #[error("{0} is not prime")]
+    NotPrime(&'static str),
+    /// q does not divide p - 1, so there is no subgroup of order q.
+    #[error("q does not divide p - 1")]
+    OrderMismatch,
+    /// A generator is out of range or doesn't have order q.
+    #[error("{0} is not a generator of order q")]
+    BadGenerator(&'static str),
+    /// The group is valid but known to be too weak to use.
+    #[error("weak group: {0}")]
+    Weak(&'static str),
+    /// alpha and beta must be independent generators.
+    #[error("alpha and beta are the same")]
+    SameGenerators,
+    /// Argon2 rejected its cost parameters or the salt.
+    #[error("key derivation: {0}")]
+    Kdf(String),
+}
+
+/// Checks that alpha and beta generate the order-q subgroup of Z_p^*.
+pub fn check_params(
+    p: &BigUint,
+    q: &BigUint,
+    alpha: &BigUint,
+    beta: &BigUint,
+) -> Result<(), ParamError> {
+    let one = BigUint::from(1u32);
+
+    check_order(p, q)?;
+
+    for (name, g) in [("alpha", alpha), ("beta", beta)] {
+        if *g <= one || g >= p || ZKP::exponantiate(g, q, p) != one {
+            return Err(ParamError::BadGenerator(name));
+        }
+    }
+
+    if alpha == beta {
+        return Err(ParamError::SameGenerators);
+    }
+
+    Ok(())
+}
+
+/// What `validate_group` found out about a group that passed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupReport {
+    pub p_bits: u64,
+    pub q_bits: u64,
+    /// p = 2q + 1.
+    pub safe_prime: bool,
+    /// The named set these are exactly the constants of, if any.
+    pub param_set: Option<ParamSet>,
+    /// beta = `ZKP::derive_generator(p, q, BETA_LABEL)`, so nobody knows
+    /// log_alpha(beta).
+    pub beta_derived: bool,
+    /// Acceptable but worth a look.
+    pub warnings: Vec<&'static str>,
+}
+
+/// Full health check of a group, more thorough and much slower than
+/// `check_params`: p and q are tested for primality, generator orders are
+/// checked, and small or otherwise known-weak groups are refused.
+pub fn validate_group(constants: &ZkpConstants) -> Result<GroupReport, ParamError> {
+    let ZkpConstants { alpha, beta, p, q } = constants;
+    check_params(p, q, alpha, beta)?;
+
+    let mut rng = rand::thread_rng();
+    if !is_probable_prime(p, &mut rng) {
+        return Err(ParamError::NotPrime("p"));
+    }
+    if !is_probable_prime(q, &mut rng) {
+        return Err(ParamError::NotPrime("q"));
+    }
+
+    let (p_bits, q_bits) = (p.bits(), q.bits());
+    if p_bits < MIN_P_BITS {
+        return Err(ParamError::Weak("p is too short"));
+    }
+    if q_bits < MIN_Q_BITS {
+        return Err(ParamError::Weak("q is too short"));
+    }
+
+    // Someone who picked beta = alpha^k for a small k knows the relation.
+    let mut power = alpha.clone();
+    for _ in 0..SMALL_POWERS {
+        if power == *beta || (&power * beta) % p == BigUint::from(1u32) {
+            return Err(ParamError::Weak("beta is a small power of alpha"));
+        }
+        power = (power * alpha) % p;
+    }
+
+    let beta_derived = *beta == ZKP::derive_generator(p, q, BETA_LABEL)?;
+    let param_set = ParamSet::of(constants);
+
+    let mut warnings = Vec::new();
+    if p_bits < 2048 {
+        warnings.push("p is shorter than 2048 bits");
+    }
+    if q_bits < 224 {
+        warnings.push("q is shorter than 224 bits");
+    }
+    if !beta_derived {
+        warnings.push("beta is not derived from BETA_LABEL, its discrete log may be known");
+    }
+
+    Ok(GroupReport {
+        p_bits,
+        q_bits,
+        safe_prime: *p == q * 2u32 + 1u32,
+        param_set,
+        beta_derived,
+        warnings,
+    })
+}
+
+/// Checks that Z_p^* has a subgroup of order q at all.
+pub(crate) fn check_order(p: &BigUint, q: &BigUint) -> Result<(), ParamError> {
+    if *p < BigUint::from(5u32) {
+        return Err(ParamError::TooSmall("p"));
+    }
+    if *q < BigUint::from(2u32) {
+        return Err(ParamError::TooSmall("q"));
+    }
+    if (p - 1u32) % q != BigUint::ZERO {
+        return Err(ParamError::OrderMismatch);
+    }
+    Ok(())
+}
+
+fn constants_from_hex(p: &str, q: &str, alpha: &str) -> ZkpConstants {
+    let decode = |s: &str| {
+        BigUint::from_bytes_be(
+            &hex::decode(clear_whitespaces(s)).expect("Could not decode the hex."),
+        )
+    };
+    let (p, q, alpha) = (decode(p), decode(q), decode(alpha));
+    let beta = ZKP::derive_generator(&p, &q, BETA_LABEL).expect("q divides p - 1.");
+
+    ZkpConstants { alpha, beta, p, q }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_named_sets_are_valid_groups() {
+        for set in ParamSet::ALL {
+            let ZkpConstants { alpha, beta, p, q } = set.constants();
+            assert_eq!(check_params(&p, &q, &alpha, &beta), Ok(()), "{set}");
+            assert_eq!(ParamSet::from_name(set.name()), Some(set));
+        }
+
+        let ZkpConstants { p, q, .. } = ParamSet::Rfc5114_2048_256.constants();
+        assert_eq!(p.bits(), 2048);
+        assert_eq!(q.bits(), 256);
+    }
+
+    #[test]
+    fn test_check_params_rejects_bad_groups() {
+        let n = |v: u32| BigUint::from(v);
+
+        assert_eq!(check_params(&n(23), &n(11), &n(4), &n(9)), Ok(()));
+        assert_eq!(
+            check_params(&n(23), &n(7), &n(4), &n(9)),
+            Err(ParamError::OrderMismatch)
+        );
+        // 5 has order 22 mod 23.
+        assert_eq!(
+            check_params(&n(23), &n(11), &n(5), &n(9)),
+            Err(ParamError::BadGenerator("alpha"))
+        );
+        assert_eq!(
+            check_params(&n(23), &n(11), &n(4), &n(1)),
+            Err(ParamError::BadGenerator("beta"))
+        );
+        assert_eq!(
+            check_params(&n(23), &n(11), &n(4), &n(4)),
+            Err(ParamError::SameGenerators)
+        );
+    }
+
+    #[test]
+    fn test_validate_group() {
+        let report = validate_group(&ParamSet::Rfc5114_2048_256.constants()).unwrap();
+        assert_eq!(report.p_bits, 2048);
+        assert_eq!(report.q_bits, 256);
+        assert!(!report.safe_prime);
+        assert_eq!(report.param_set, Some(ParamSet::Rfc5114_2048_256));
+        assert!(report.beta_derived);
+        assert!(report.warnings.is_empty());
+
+        let report = validate_group(&ZkpConstants::new()).unwrap();
+        assert_eq!(report.param_set, Some(ParamSet::Rfc5114_1024_160));
+        assert_eq!(report.warnings.len(), 2);
+
+        let mut constants = ZkpConstants::new();
+        constants.beta = constants.alpha.modpow(&BigUint::from(5u32), &constants.p);
+        assert_eq!(
+            validate_group(&constants),
+            Err(ParamError::Weak("beta is a small power of alpha"))
+        );
+
+        let n = |v: u32| BigUint::from(v);
+        assert_eq!(
+            validate_group(&ZkpConstants {
+                alpha: n(4),
+                beta: n(9),
+                p: n(23),
+                q: n(11),
+            }),
+            Err(ParamError::Weak("p is too short"))
+        );
+        // 33 isn't prime but 10 and 23 both square to 1 mod 33, so it passes
+        // check_params with q = 2.
+        assert_eq!(
+            validate_group(&ZkpConstants {
+                alpha: n(10),
+                beta: n(23),
+                p: n(33),
+                q: n(2),
+            }),
+            Err(ParamError::NotPrime("p"))
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use num_bigint::BigUint;
+
+use crate::{
+    encoding::{encode_uints, parse_uint, split_length_prefixed},
+    expand_hash, length_prefixed, ZkpError, ZKP,
+};
+
+/// Non-interactive Chaum-Pedersen proof, the challenge is recomputed
+/// from the transcript (Fiat-Shamir) instead of being sent by the verifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    pub r1: BigUint,
+    pub r2: BigUint,
+    pub s: BigUint,
+    /// Unix time in seconds after which the proof no longer verifies. Part
+    /// of the Fiat-Shamir transcript, so it can't be pushed back.
+    pub expires_at: Option<u64>,
+}
+
+impl Proof {
+    /// r1, r2, s in that order, each big-endian with a 4-byte length prefix,
+    /// then `expires_at` as 8 big-endian bytes if there is one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = encode_uints(&[&self.r1, &self.r2, &self.s]);
+        if let Some(expires_at) = self.expires_at {
+            bytes.extend(length_prefixed(&[&expires_at.to_be_bytes()]));
+        }
+        bytes
+    }
+
+    /// Inverse of `to_bytes`, anything `to_bytes` wouldn't produce is rejected.
+    /// The values aren't checked against a group, see `ZKP::parse_proof`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ZkpError> {
+        let (parts, expires_at) = split_proof(bytes)?;
+        Ok(Self {
+            r1: parse_uint(parts[0])?,
+            r2: parse_uint(parts[1])?,
+            s: parse_uint(parts[2])?,
+            expires_at,
+        })
+    }
+}
+
+/// The three values of a proof and its expiry, if it has one.
+fn split_proof(bytes: &[u8]) -> Result<(Vec<&[u8]>, Option<u64>), ZkpError> {
+    if let Ok(parts) = split_length_prefixed(bytes, 3) {
+        return Ok((parts, None));
+    }
+    let mut parts = split_length_prefixed(bytes, 4)?;
+    let expires_at = parts
+        .pop()
+        .and_then(|expires_at| expires_at.try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(|| ZkpError::Encoding("expiry is not 8 bytes".into()))?;
+    Ok((parts, Some(expires_at)))
+}
+
+impl ZKP {
+    /// `Proof::from_bytes` plus range and subgroup checks on every value.
+    pub fn parse_proof(&self, bytes: &[u8]) -> Result<Proof, ZkpError> {
+        let (parts, expires_at) = split_proof(bytes)?;
+        Ok(Proof {
+            r1: self.parse_element(parts[0])?,
+            r2: self.parse_element(parts[1])?,
+            s: self.parse_scalar(parts[2])?,
+            expires_at,
+        })
+    }
+
+    /// c = H(p, q, alpha, beta, y1, y2, r1, r2, context) mod q
+    /// `context` binds the proof to whatever it is used for, a proof made for
+    /// one context does not verify under another.
+    pub fn fiat_shamir_challenge(
+        &self,
+        y1: &BigUint,
+        y2: &BigUint,
+        r1: &BigUint,
+        r2: &BigUint,
+        context: &[u8],
+    ) -> BigUint {
+        self.timed_challenge(y1, y2, r1, r2, context, None)
+    }
+
+    /// `fiat_shamir_challenge` with the expiry appended to the transcript
+    /// when there is one.
+    fn timed_challenge(
+        &self,
+        y1: &BigUint,
+        y2: &BigUint,
+        r1: &BigUint,
+        r2: &BigUint,
+        context: &[u8],
+        expires_at: Option<u64>,
+    ) -> BigUint {
+        let mut transcript = length_prefixed(&[
+            &self.p.to_bytes_be(),
+            &self.q.to_bytes_be(),
+            &self.alpha.to_bytes_be(),
+            &self.beta.to_bytes_be(),
+            &y1.to_bytes_be(),
+            &y2.to_bytes_be(),
+            &r1.to_bytes_be(),
+            &r2.to_bytes_be(),
+            context,
+        ]);
+        if let Some(expires_at) = expires_at {
+            transcript.extend(length_prefixed(&[&expires_at.to_be_bytes()]));
+        }
+        expand_hash(
+            b"zkp_chaum_pedersen/fiat_shamir",
+            &transcript,
+            0,
+            self.scalar_hash_len(),
+        ) % &self.q
+    }
+
+    /// Proves knowledge of x for (y1, y2) = (alpha^x, beta^x) without a verifier round trip.
+    pub fn prove_non_interactive(&self, x: &BigUint, context: &[u8]) -> Proof {
+        self.prove_timed(x, context, None)
+    }
+
+    /// `prove_non_interactive` for a proof that stops verifying after
+    /// `expires_at` (Unix time in seconds), e.g. one handed out offline.
+    pub fn prove_non_interactive_until(
+        &self,
+        x: &BigUint,
+        context: &[u8],
+        expires_at: u64,
+    ) -> Proof {
+        self.prove_timed(x, context, Some(expires_at))
+    }
+
+    fn prove_timed(&self, x: &BigUint, context: &[u8], expires_at: Option<u64>) -> Proof {
+        let (y1, y2) = self.compute_pair(x);
+        let k = Self::generate_random_below(&self.q);
+        let (r1, r2) = self.compute_pair(&k);
+        let c = self.timed_challenge(&y1, &y2, &r1, &r2, context, expires_at);
+        let s = self.solve(&k, &c, x);
+        Proof {
+            r1,
+            r2,
+            s,
+            expires_at,
+        }
+    }
+
+    /// Rejects a proof past its expiry, with `clock_skew` of slack, as
+    /// `ZkpError::Expired`.
+    pub fn verify_non_interactive(
+        &self,
+        y1: &BigUint,
+        y2: &BigUint,
+        proof: &Proof,
+        context: &[u8],
+    ) -> Result<(), ZkpError> {
+        self.verify_non_interactive_at(y1, y2, proof, context, SystemTime::now())
+    }
+
+    /// `verify_non_interactive` with the current time given.
+    pub fn verify_non_interactive_at(
+        &self,
+        y1: &BigUint,
+        y2: &BigUint,
+        proof: &Proof,
+        context: &[u8],
+        now: SystemTime,
+    ) -> Result<(), ZkpError> {
+        let c = self.timed_challenge(y1, y2, &proof.r1, &proof.r2, context, proof.expires_at);
+        self.verify(&proof.r1, &proof.r2, y1, y2, &c, &proof.s)?;
+
+        if let Some(expires_at) = proof.expires_at {
+            // Clocks before 1970 count as 0, that only makes proofs live longer.
+            let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            if now > expires_at.saturating_add(self.clock_skew.as_secs()) {
+                return Err(ZkpError::Expired);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ParamSet;
+
+    #[test]
+    fn test_non_interactive_proof() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+
+        let proof = zkp.prove_non_interactive(&x, b"context");
+        assert!(zkp
+            .verify_non_interactive(&y1, &y2, &proof, b"context")
+            .is_ok());
+        assert!(zkp
+            .verify_non_interactive(&y1, &y2, &proof, b"other context")
+            .is_err());
+
+        let x_fake = ZKP::generate_random_below(&zkp.q);
+        let fake = zkp.prove_non_interactive(&x_fake, b"context");
+        assert!(zkp
+            .verify_non_interactive(&y1, &y2, &fake, b"context")
+            .is_err());
+    }
+
+    #[test]
+    fn test_time_bound_proof() {
+        let zkp = ZKP::builder()
+            .param_set(ParamSet::Rfc5114_1024_160)
+            .clock_skew(Duration::from_secs(30))
+            .build()
+            .unwrap();
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        let expires_at = 1_700_000_000;
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+
+        let proof = zkp.prove_non_interactive_until(&x, b"context", expires_at);
+        assert_eq!(proof.expires_at, Some(expires_at));
+        assert!(zkp
+            .verify_non_interactive_at(&y1, &y2, &proof, b"context", at(expires_at))
+            .is_ok());
+        assert!(zkp
+            .verify_non_interactive_at(&y1, &y2, &proof, b"context", at(expires_at + 30))
+            .is_ok());
+        assert!(matches!(
+            zkp.verify_non_interactive_at(&y1, &y2, &proof, b"context", at(expires_at + 31)),
+            Err(ZkpError::Expired)
+        ));
+        assert!(matches!(
+            zkp.verify_non_interactive(&y1, &y2, &proof, b"context"),
+            Err(ZkpError::Expired)
+        ));
+
+        // the expiry is in the transcript, it can't be extended or dropped
+        for expires_at in [Some(expires_at + 3600), None] {
+            let moved = Proof {
+                expires_at,
+                ..proof.clone()
+            };
+            assert!(matches!(
+                zkp.verify_non_interactive_at(&y1, &y2, &moved, b"context", at(0)),
+                Err(ZkpError::Verify)
+            ));
+        }
+
+        let bytes = proof.to_bytes();
+        assert_eq!(Proof::from_bytes(&bytes).unwrap(), proof);
+        assert_eq!(zkp.parse_proof(&bytes).unwrap(), proof);
+        let mut short_expiry = proof.to_bytes();
+        short_expiry.truncate(short_expiry.len() - 12);
+        short_expiry.extend(length_prefixed(&[&[1, 2, 3, 4]]));
+        assert!(Proof::from_bytes(&short_expiry).is_err());
+    }
+
+    #[test]
+    fn test_proof_bytes_roundtrip() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(&zkp.q);
+        let proof = zkp.prove_non_interactive(&x, b"context");
+
+        let bytes = proof.to_bytes();
+        assert_eq!(Proof::from_bytes(&bytes).unwrap(), proof);
+        assert_eq!(zkp.parse_proof(&bytes).unwrap(), proof);
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            Proof::from_bytes(&trailing),
+            Err(ZkpError::Encoding(_))
+        ));
+        assert!(Proof::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let unreduced = Proof {
+            s: &proof.s + zkp.q(),
+            ..proof
+        };
+        assert!(Proof::from_bytes(&unreduced.to_bytes()).is_ok());
+        assert!(zkp.parse_proof(&unreduced.to_bytes()).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Realms: independent user sets served by one server. A user is known
+//! internally by its qualified name, which is what challenges and proofs are
+//! bound to, so a login in one realm means nothing in another.
+
+use crate::ZkpError;
+
+/// Separates realm and user in a qualified name. Never part of either.
+pub const REALM_SEPARATOR: char = '/';
+
+/// Longest realm name, in bytes.
+pub const MAX_REALM_LEN: usize = 64;
+
+/// Longest user name, in bytes.
+pub const MAX_USER_LEN: usize = 128;
+
+/// `realm/user`, or just `user` in the default realm "". Realms are ASCII
+/// letters, digits, '-', '_' and '.'. User names are any printable text
+/// but for whitespace and the separator, at most `MAX_USER_LEN` bytes.
+pub fn qualified_name(realm: &str, user: &str) -> Result<String, ZkpError> {
+    if user.contains(REALM_SEPARATOR) {
+        return Err(ZkpError::Encoding(format!(
+            "user names can't contain '{REALM_SEPARATOR}'"
+        )));
+    }
+    if user.is_empty() || user.len() > MAX_USER_LEN {
+        return Err(ZkpError::Encoding(format!(
+            "user names are 1 to {MAX_USER_LEN} bytes"
+        )));
+    }
+    if user.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err(ZkpError::Encoding(
+            "user names can't contain whitespace or control characters".into(),
+        ));
+    }
+    if realm.is_empty() {
+        return Ok(user.to_string());
+    }
+    if realm.len() > MAX_REALM_LEN
+        || !realm
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+    {
+        return Err(ZkpError::Encoding(format!("bad realm name: {realm}")));
+    }
+    Ok(format!("{realm}{REALM_SEPARATOR}{user}"))
+}
+
+/// (realm, user) of a qualified name.
+pub fn split_qualified_name(name: &str) -> (&str, &str) {
+    name.split_once(REALM_SEPARATOR).unwrap_or(("", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_qualified_name() {
+        assert_eq!(qualified_name("", "alice").unwrap(), "alice");
+        assert_eq!(qualified_name("acme", "alice").unwrap(), "acme/alice");
+        assert_eq!(split_qualified_name("acme/alice"), ("acme", "alice"));
+        assert_eq!(split_qualified_name("alice"), ("", "alice"));
+
+        // A default realm user can't pose as one of another realm.
+        assert!(qualified_name("", "acme/alice").is_err());
+        assert_eq!(
+            qualified_name("", "zoë@example.com").unwrap(),
+            "zoë@example.com"
+        );
+        assert!(qualified_name("", "").is_err());
+        assert!(qualified_name("", "alice smith").is_err());
+        assert!(qualified_name("", "alice\u{0}").is_err());
+        assert!(qualified_name("", &"a".repeat(MAX_USER_LEN + 1)).is_err());
+        assert!(qualified_name("ac me", "alice").is_err());
+        assert!(qualified_name(&"a".repeat(MAX_REALM_LEN + 1), "alice").is_err());
+    }
+}
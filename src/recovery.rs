@@ -0,0 +1,136 @@
+//! One-time recovery codes, for a user who lost their password and so x.
+//! The server hands them out once at registration and keeps only their
+//! hashes. Presenting one in RecoverAccount moves the user to a new key,
+//! proven like the new key of a rotation, and uses the code up.
+
+use num_bigint::BigUint;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::{length_prefixed, totp_secret_base32, Proof, ZkpError, ZKP};
+
+/// Codes handed out at registration.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Random bytes behind a code, 16 base32 characters.
+const RECOVERY_CODE_BYTES: usize = 10;
+
+/// A new key for a user who lost x, with a proof of the new x so nobody
+/// recovers onto keys they don't own. The recovery code is what says the
+/// user may.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRecovery {
+    pub new_y1: BigUint,
+    pub new_y2: BigUint,
+    /// Proof of the new x, bound to the user and the new values.
+    pub proof: Proof,
+}
+
+impl ZKP {
+    pub fn prove_recovery(&self, user: &str, new_x: &BigUint) -> KeyRecovery {
+        let (new_y1, new_y2) = self.compute_pair(new_x);
+        let context = recovery_context(user, &new_y1, &new_y2);
+
+        KeyRecovery {
+            proof: self.prove_non_interactive(new_x, &context),
+            new_y1,
+            new_y2,
+        }
+    }
+
+    pub fn verify_recovery(&self, user: &str, recovery: &KeyRecovery) -> Result<(), ZkpError> {
+        let context = recovery_context(user, &recovery.new_y1, &recovery.new_y2);
+        self.verify_non_interactive(
+            &recovery.new_y1,
+            &recovery.new_y2,
+            &recovery.proof,
+            &context,
+        )
+    }
+}
+
+fn recovery_context(user: &str, new_y1: &BigUint, new_y2: &BigUint) -> Vec<u8> {
+    length_prefixed(&[
+        b"zkp_chaum_pedersen/recovery",
+        user.as_bytes(),
+        &new_y1.to_bytes_be(),
+        &new_y2.to_bytes_be(),
+    ])
+}
+
+/// `RECOVERY_CODE_COUNT` fresh codes, base32 in groups of four like
+/// "ABCD-EFGH-IJKL-MNOP".
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0; RECOVERY_CODE_BYTES];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let encoded = totp_secret_base32(&bytes);
+            let groups: Vec<&str> = encoded
+                .as_bytes()
+                .chunks(4)
+                .map(|group| std::str::from_utf8(group).expect("base32 is ASCII."))
+                .collect();
+            groups.join("-")
+        })
+        .collect()
+}
+
+/// What the server keeps of the user's `code`. Case, dashes and spaces
+/// don't count, so a code typed as read out still matches.
+pub fn hash_recovery_code(user: &str, code: &str) -> [u8; 32] {
+    let normalized: String = code
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let mut hasher = Sha256::new();
+    hasher.update(length_prefixed(&[
+        b"zkp_chaum_pedersen/recovery_code",
+        user.as_bytes(),
+        normalized.as_bytes(),
+    ]));
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recovery() {
+        let zkp = ZKP::default();
+        let new_x = ZKP::generate_random_below(&zkp.q);
+
+        let recovery = zkp.prove_recovery("alice", &new_x);
+        assert_eq!(
+            (recovery.new_y1.clone(), recovery.new_y2.clone()),
+            zkp.compute_pair(&new_x)
+        );
+        assert!(zkp.verify_recovery("alice", &recovery).is_ok());
+
+        // Bound to the user name.
+        assert!(zkp.verify_recovery("bob", &recovery).is_err());
+
+        // Someone else's keys with this proof.
+        let mut hijacked = recovery.clone();
+        let (evil_y1, evil_y2) = zkp.compute_pair(&ZKP::generate_random_below(&zkp.q));
+        hijacked.new_y1 = evil_y1;
+        hijacked.new_y2 = evil_y2;
+        assert!(zkp.verify_recovery("alice", &hijacked).is_err());
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), 19);
+        assert_ne!(codes[0], codes[1]);
+
+        let hash = hash_recovery_code("alice", &codes[0]);
+        let typed = codes[0].replace('-', " ").to_lowercase();
+        assert_eq!(hash_recovery_code("alice", &typed), hash);
+        assert_ne!(hash_recovery_code("bob", &codes[0]), hash);
+        assert_ne!(hash_recovery_code("alice", &codes[1]), hash);
+    }
+}
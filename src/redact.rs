@@ -0,0 +1,219 @@
+//! Hand-written `Debug` for the protocol messages carrying commitments,
+//! responses or session ids, see `skip_debug` in build.rs.
+
+use std::fmt;
+
+use crate::{
+    zkp_auth::{
+        AddDeviceRequest, AuthenticationAnswerRequest, AuthenticationAnswerResponse,
+        AuthenticationChallengeRequest, DeleteAccountRequest, ListDevicesRequest,
+        ListOwnSessionsRequest, LogoutRequest, OpenChallengeRequest, Proof, RecoverAccountRequest,
+        RefreshSessionRequest, RefreshSessionResponse, RegisterResponse, RevokeDeviceRequest,
+        RevokeOwnSessionRequest, RevokeSessionRequest, SessionInfo, UpdateCredentialsRequest,
+        ValidateSessionRequest,
+    },
+    Secret,
+};
+
+impl fmt::Debug for RegisterResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisterResponse")
+            .field("totp_secret", &Secret::new(&self.totp_secret))
+            .field("recovery_codes", &Secret::new(&self.recovery_codes))
+            .finish()
+    }
+}
+
+impl fmt::Debug for AuthenticationChallengeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthenticationChallengeRequest")
+            .field("user", &self.user)
+            .field("r1", &Secret::new(&self.r1))
+            .field("r2", &Secret::new(&self.r2))
+            .field("challenge_bits", &self.challenge_bits)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+impl fmt::Debug for OpenChallengeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenChallengeRequest")
+            .field("auth_id", &self.auth_id)
+            .field("r1", &Secret::new(&self.r1))
+            .field("r2", &Secret::new(&self.r2))
+            .finish()
+    }
+}
+
+impl fmt::Debug for AuthenticationAnswerRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthenticationAnswerRequest")
+            .field("auth_id", &self.auth_id)
+            .field("s", &Secret::new(&self.s))
+            .field("totp_code", &Secret::new(&self.totp_code))
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+impl fmt::Debug for AuthenticationAnswerResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthenticationAnswerResponse")
+            .field("session_id", &Secret::new(&self.session_id))
+            .field("server_proof", &self.server_proof)
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proof")
+            .field("r1", &Secret::new(&self.r1))
+            .field("r2", &Secret::new(&self.r2))
+            .field("s", &Secret::new(&self.s))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl fmt::Debug for UpdateCredentialsRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpdateCredentialsRequest")
+            .field("name", &self.name)
+            .field("new_y1", &self.new_y1)
+            .field("new_y2", &self.new_y2)
+            .field("old_proof", &self.old_proof)
+            .field("new_proof", &self.new_proof)
+            .field("new_salt", &self.new_salt)
+            .field("new_kdf_params", &self.new_kdf_params)
+            .finish()
+    }
+}
+
+impl fmt::Debug for RecoverAccountRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecoverAccountRequest")
+            .field("name", &self.name)
+            .field("realm", &self.realm)
+            .field("recovery_code", &Secret::new(&self.recovery_code))
+            .field("new_y1", &self.new_y1)
+            .field("new_y2", &self.new_y2)
+            .field("new_proof", &self.new_proof)
+            .field("new_salt", &self.new_salt)
+            .field("new_kdf_params", &self.new_kdf_params)
+            .finish()
+    }
+}
+
+impl fmt::Debug for DeleteAccountRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeleteAccountRequest")
+            .field("name", &self.name)
+            .field("proof", &self.proof)
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
+
+impl fmt::Debug for RefreshSessionRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefreshSessionRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
+
+impl fmt::Debug for RefreshSessionResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefreshSessionResponse")
+            .field("session_id", &Secret::new(&self.session_id))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl fmt::Debug for LogoutRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogoutRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
+
+impl fmt::Debug for ValidateSessionRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValidateSessionRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
+
+impl fmt::Debug for AddDeviceRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AddDeviceRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .field("label", &self.label)
+            .field("y1", &self.y1)
+            .field("y2", &self.y2)
+            .field("proof", &self.proof)
+            .field("salt", &self.salt)
+            .field("kdf_params", &self.kdf_params)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ListDevicesRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ListDevicesRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
+
+impl fmt::Debug for RevokeDeviceRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RevokeDeviceRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .field("label", &self.label)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ListOwnSessionsRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ListOwnSessionsRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
+
+impl fmt::Debug for RevokeOwnSessionRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RevokeOwnSessionRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
+impl fmt::Debug for SessionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionInfo")
+            .field("session_id", &Secret::new(&self.session_id))
+            .field("created_at", &self.created_at)
+            .field("expires_at", &self.expires_at)
+            .field("device", &self.device)
+            .field("last_seen", &self.last_seen)
+            .finish()
+    }
+}
+
+impl fmt::Debug for RevokeSessionRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RevokeSessionRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
@@ -0,0 +1,142 @@
+use num_bigint::BigUint;
+
+use crate::{length_prefixed, Proof, ZkpError, ZKP};
+
+/// Replacement (y1', y2') for a registered user, carrying proofs of both the
+/// old and the new secret so the server can accept it without any other
+/// identity check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRotation {
+    pub new_y1: BigUint,
+    pub new_y2: BigUint,
+    /// Proof of the old x, bound to the user and the new values, and
+    /// expiring so a captured rotation can't be replayed later on.
+    pub old_proof: Proof,
+    /// Proof of the new x, so nobody rotates onto keys they don't own.
+    pub new_proof: Proof,
+}
+
+impl ZKP {
+    /// Rotation from old_x to new_x, refused after `expires_at` (Unix time in
+    /// seconds).
+    pub fn prove_rotation(
+        &self,
+        user: &str,
+        old_x: &BigUint,
+        new_x: &BigUint,
+        expires_at: u64,
+    ) -> KeyRotation {
+        let (new_y1, new_y2) = self.compute_pair(new_x);
+        let context = rotation_context(user, &new_y1, &new_y2);
+
+        KeyRotation {
+            old_proof: self.prove_non_interactive_until(old_x, &context, expires_at),
+            new_proof: self.prove_non_interactive_until(new_x, &context, expires_at),
+            new_y1,
+            new_y2,
+        }
+    }
+
+    /// Checks `rotation` against the currently registered (old_y1, old_y2).
+    /// An old proof without an expiry counts as expired.
+    pub fn verify_rotation(
+        &self,
+        user: &str,
+        old_y1: &BigUint,
+        old_y2: &BigUint,
+        rotation: &KeyRotation,
+    ) -> Result<(), ZkpError> {
+        if rotation.old_proof.expires_at.is_none() {
+            return Err(ZkpError::Expired);
+        }
+        let context = rotation_context(user, &rotation.new_y1, &rotation.new_y2);
+
+        self.verify_non_interactive(old_y1, old_y2, &rotation.old_proof, &context)?;
+        self.verify_non_interactive(
+            &rotation.new_y1,
+            &rotation.new_y2,
+            &rotation.new_proof,
+            &context,
+        )
+    }
+}
+
+fn rotation_context(user: &str, new_y1: &BigUint, new_y2: &BigUint) -> Vec<u8> {
+    length_prefixed(&[
+        b"zkp_chaum_pedersen/rotation",
+        user.as_bytes(),
+        &new_y1.to_bytes_be(),
+        &new_y2.to_bytes_be(),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXPIRES_AT: u64 = u64::MAX / 2;
+
+    #[test]
+    fn test_rotation() {
+        let zkp = ZKP::default();
+        let old_x = ZKP::generate_random_below(&zkp.q);
+        let new_x = ZKP::generate_random_below(&zkp.q);
+        let (old_y1, old_y2) = zkp.compute_pair(&old_x);
+
+        let rotation = zkp.prove_rotation("alice", &old_x, &new_x, EXPIRES_AT);
+        assert_eq!(
+            (rotation.new_y1.clone(), rotation.new_y2.clone()),
+            zkp.compute_pair(&new_x)
+        );
+        assert!(zkp
+            .verify_rotation("alice", &old_y1, &old_y2, &rotation)
+            .is_ok());
+
+        // Bound to the user name.
+        assert!(zkp
+            .verify_rotation("bob", &old_y1, &old_y2, &rotation)
+            .is_err());
+
+        // Swapping in other keys breaks both proofs.
+        let mut hijacked = rotation.clone();
+        let (evil_y1, evil_y2) = zkp.compute_pair(&ZKP::generate_random_below(&zkp.q));
+        hijacked.new_y1 = evil_y1;
+        hijacked.new_y2 = evil_y2;
+        assert!(zkp
+            .verify_rotation("alice", &old_y1, &old_y2, &hijacked)
+            .is_err());
+
+        // Without the old secret.
+        let forged = zkp.prove_rotation("alice", &new_x, &new_x, EXPIRES_AT);
+        assert!(zkp
+            .verify_rotation("alice", &old_y1, &old_y2, &forged)
+            .is_err());
+    }
+
+    #[test]
+    fn test_rotation_must_expire() {
+        let zkp = ZKP::default();
+        let old_x = ZKP::generate_random_below(&zkp.q);
+        let new_x = ZKP::generate_random_below(&zkp.q);
+        let (old_y1, old_y2) = zkp.compute_pair(&old_x);
+
+        let expired = zkp.prove_rotation("alice", &old_x, &new_x, 1);
+        assert!(matches!(
+            zkp.verify_rotation("alice", &old_y1, &old_y2, &expired),
+            Err(ZkpError::Expired)
+        ));
+
+        let (new_y1, new_y2) = zkp.compute_pair(&new_x);
+        let context = rotation_context("alice", &new_y1, &new_y2);
+        let timeless = KeyRotation {
+            old_proof: zkp.prove_non_interactive(&old_x, &context),
+            new_proof: zkp.prove_non_interactive(&new_x, &context),
+            new_y1,
+            new_y2,
+        };
+        assert!(matches!(
+            zkp.verify_rotation("alice", &old_y1, &old_y2, &timeless),
+            Err(ZkpError::Expired)
+        ));
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// Keeps its contents out of logs: `Debug` and `Display` print `[REDACTED]`.
+/// Use `expose` at the point where the value is actually needed.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zkp_auth::{AuthenticationAnswerRequest, AuthenticationAnswerResponse};
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let secret = Secret::new("hunter2".to_string());
+        assert_eq!(format!("{secret:?}"), "[REDACTED]");
+        assert_eq!(format!("{secret}"), "[REDACTED]");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn test_protocol_messages_are_redacted() {
+        let request = AuthenticationAnswerRequest {
+            auth_id: "auth-id".to_string(),
+            s: vec![0xde, 0xad, 0xbe, 0xef],
+            totp_code: String::new(),
+            nonce: Vec::new(),
+            timestamp: 0,
+        };
+        let printed = format!("{request:?}");
+        assert!(printed.contains("auth-id"));
+        assert!(printed.contains("[REDACTED]"));
+        assert!(!printed.contains("222"));
+
+        let response = AuthenticationAnswerResponse {
+            session_id: "session-id".to_string(),
+            server_proof: None,
+            expires_at: 0,
+        };
+        assert!(!format!("{response:?}").contains("session-id"));
+    }
+}
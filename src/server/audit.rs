@@ -0,0 +1,177 @@
+//! Security events, kept apart from the debug logs: who registered, asked
+//! for a challenge, answered one, got locked out or lost a session, when and
+//! from where. Each event is one JSON object a line, appended to AUDIT_LOG.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+use crate::server::store::unix_now;
+
+/// Something worth an audit record, tagged by "event" in it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Registered {
+        user: String,
+    },
+    ChallengeIssued {
+        user: String,
+        auth_id: String,
+    },
+    /// An answer to the challenge of auth_id, right or wrong.
+    Verified {
+        user: String,
+        auth_id: String,
+        success: bool,
+    },
+    LockedOut {
+        user: String,
+        until: u64,
+    },
+    LoggedOut {
+        user: String,
+    },
+    /// By the admin service.
+    SessionRevoked {
+        user: Option<String>,
+    },
+    /// A recovery code moved the user to a new key.
+    Recovered {
+        user: String,
+    },
+    /// By the user, or the admin service when `by_admin`.
+    UserDeleted {
+        user: String,
+        by_admin: bool,
+    },
+    /// Unused for longer than the retention of users, see `retention`.
+    UserPruned {
+        user: String,
+    },
+    /// The admin role granted or taken away by the admin service.
+    AdminSet {
+        user: String,
+        admin: bool,
+    },
+}
+
+/// One line of the audit log.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'a> {
+    /// Unix time in seconds.
+    pub time: u64,
+    /// None over a Unix socket, or when the gateway didn't say.
+    pub source_ip: Option<IpAddr>,
+    #[serde(flatten)]
+    pub event: &'a AuditEvent,
+}
+
+/// Where audit records end up.
+pub trait AuditSink: std::fmt::Debug + Send + Sync + 'static {
+    fn append(&self, record: &AuditRecord) -> std::io::Result<()>;
+}
+
+/// Appends to a file, which is created if missing and never truncated.
+#[derive(Debug)]
+pub struct AuditFile {
+    file: Mutex<File>,
+}
+
+impl AuditFile {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for AuditFile {
+    fn append(&self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        // One write per record, so lines from concurrent calls don't mix.
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        file.write_all(&line)?;
+        file.flush()
+    }
+}
+
+/// Records events to the sink, if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    sink: Option<Arc<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    pub fn new(sink: Arc<dyn AuditSink>) -> Self {
+        Self { sink: Some(sink) }
+    }
+
+    /// Records `event` as happening now. A record that can't be written
+    /// doesn't fail the call it is about, but is logged.
+    pub fn record(&self, source_ip: Option<IpAddr>, event: AuditEvent) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let record = AuditRecord {
+            time: unix_now(),
+            source_ip,
+            event: &event,
+        };
+        if let Err(err) = sink.append(&record) {
+            log::error!("Can't write to the audit log: {err}, lost {event:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ZKP;
+
+    #[test]
+    fn test_audit_file() {
+        let path = std::env::temp_dir().join(format!(
+            "zkp_auth_audit_{}.log",
+            ZKP::generate_random_string(8)
+        ));
+        let audit = AuditLog::new(Arc::new(AuditFile::open(&path).unwrap()));
+        audit.record(
+            Some("192.0.2.1".parse().unwrap()),
+            AuditEvent::Registered {
+                user: "alice".to_string(),
+            },
+        );
+        // Reopening appends.
+        let audit = AuditLog::new(Arc::new(AuditFile::open(&path).unwrap()));
+        audit.record(
+            None,
+            AuditEvent::LockedOut {
+                user: "alice".to_string(),
+                until: 10,
+            },
+        );
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "registered");
+        assert_eq!(lines[0]["user"], "alice");
+        assert_eq!(lines[0]["source_ip"], "192.0.2.1");
+        assert_eq!(lines[1]["event"], "locked_out");
+        assert_eq!(lines[1]["until"], 10);
+        assert!(lines[1]["source_ip"].is_null());
+    }
+}
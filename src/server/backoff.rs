@@ -0,0 +1,128 @@
+//! Slows online guessing down before the lockout kicks in. A wrong answer
+//! is held back for a delay that doubles with each failure in a row, of the
+//! user or of the client IP whichever has more, with jitter so the delay
+//! can't be timed away. The user's failures are those the store counts for
+//! the lockout, the IP's are counted here in process memory.
+
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use rand::Rng;
+
+/// Delay after a first wrong answer unless configured otherwise.
+pub const DEFAULT_BASE: Duration = Duration::from_millis(250);
+
+/// Longest delay unless configured otherwise.
+pub const DEFAULT_MAX: Duration = Duration::from_secs(8);
+
+/// An IP's failures are forgotten once it has gone this long without one.
+const FORGET_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// IPs tracked before those forgotten are swept out.
+const MAX_TRACKED_IPS: usize = 10_000;
+
+#[derive(Debug)]
+pub struct Backoff {
+    /// Zero turns the delays off.
+    pub base: Duration,
+    pub max: Duration,
+    /// IP to its failures in a row and when the last came.
+    ips: DashMap<IpAddr, (u32, Instant)>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(DEFAULT_BASE, DEFAULT_MAX)
+    }
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            ips: DashMap::new(),
+        }
+    }
+
+    /// Counts a wrong answer from `ip`, and waits out the delay for it and
+    /// the `user_failures` in a row of the user it was for.
+    pub async fn failed(&self, user_failures: u32, ip: Option<IpAddr>) {
+        if self.base.is_zero() {
+            return;
+        }
+        let ip_failures = ip.map_or(0, |ip| self.count(ip));
+        let delay = self.delay(user_failures.max(ip_failures));
+        tokio::time::sleep(delay).await;
+    }
+
+    /// A right answer from `ip` ends its failures in a row.
+    pub fn succeeded(&self, ip: Option<IpAddr>) {
+        if let Some(ip) = ip {
+            self.ips.remove(&ip);
+        }
+    }
+
+    /// Somewhere between half and all of `base` doubled for each failure
+    /// after the first, at most `max`.
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+        let doublings = (failures - 1).min(31);
+        let delay = self.base.saturating_mul(1 << doublings).min(self.max);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    fn count(&self, ip: IpAddr) -> u32 {
+        if self.ips.len() >= MAX_TRACKED_IPS {
+            self.ips
+                .retain(|_, (_, last)| last.elapsed() < FORGET_AFTER);
+        }
+        let now = Instant::now();
+        let mut entry = self.ips.entry(ip).or_insert((0, now));
+        let (failures, last) = entry.value_mut();
+        if now.duration_since(*last) >= FORGET_AFTER {
+            *failures = 0;
+        }
+        *failures += 1;
+        *last = now;
+        *failures
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(backoff.delay(0), Duration::ZERO);
+        for (failures, full) in [(1, 100), (2, 200), (4, 800), (5, 1000), (40, 1000)] {
+            let delay = backoff.delay(failures);
+            let full = Duration::from_millis(full);
+            assert!(delay >= full / 2 && delay <= full, "{failures}: {delay:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ip_failures() {
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_secs(1));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        backoff.failed(0, Some(ip)).await;
+        backoff.failed(0, Some(ip)).await;
+        assert_eq!(backoff.ips.get(&ip).unwrap().0, 2);
+
+        // Any user's failures go by the IP's when it has more.
+        let started = Instant::now();
+        backoff.failed(1, Some(ip)).await;
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        backoff.succeeded(Some(ip));
+        assert!(backoff.ips.is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+//! Backups of the user store, for moving users from one backend to another.
+//! `server export FILE` writes every user, their unused recovery codes and
+//! live sessions to an archive encrypted under BACKUP_PASSPHRASE, and
+//! `server import FILE` adds them to the store DATABASE_URL and REDIS_URL
+//! name, keeping the users it already has. sled takes one process at a time,
+//! stop the server first with it.
+//!
+//! An archive is "ZKPBAK1\n", the Argon2id salt of the passphrase, a nonce,
+//! and the ChaCha20-Poly1305 sealed JSON of `Contents`.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use argon2::Argon2;
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    server::store::{unix_now, Session, SessionStore, UserInfo, UserStore},
+    SessionId, SALT_LEN,
+};
+
+/// What every archive starts with, the format version included.
+const MAGIC: &[u8; 8] = b"ZKPBAK1\n";
+
+/// Users read from the store per call.
+const PAGE_SIZE: usize = 100;
+
+/// What an archive holds, each record as the stores keep it in JSON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Contents {
+    /// `UserInfo::to_json` of each user.
+    users: Vec<String>,
+    /// session_id and `Session::to_json` of each session that hasn't
+    /// expired.
+    sessions: Vec<(String, String)>,
+    /// User name and the hex hashes of their unused recovery codes.
+    /// Archives from before these were kept have none.
+    #[serde(default)]
+    recovery_codes: Vec<(String, Vec<String>)>,
+}
+
+/// What an import did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Imported {
+    pub users: u64,
+    /// Users the store already had under their name, left as they were.
+    pub skipped: u64,
+    pub sessions: u64,
+}
+
+/// Every user and live session in `store`, as an archive sealed under
+/// `passphrase`.
+pub async fn export<S: UserStore + SessionStore>(
+    store: &S,
+    passphrase: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let mut contents = Contents::default();
+    let mut exported = HashSet::new();
+    let mut after = String::new();
+    loop {
+        let users = store.list_users(&after, PAGE_SIZE).await?;
+        for user in &users {
+            contents.users.push(user.to_json());
+            let hashes = store.list_recovery_codes(&user.user_name).await?;
+            if !hashes.is_empty() {
+                contents.recovery_codes.push((
+                    user.user_name.clone(),
+                    hashes.iter().map(hex::encode).collect(),
+                ));
+            }
+            exported.insert(user.user_name.clone());
+        }
+        match users.last() {
+            Some(last) if users.len() == PAGE_SIZE => after.clone_from(&last.user_name),
+            _ => break,
+        }
+    }
+    // One pass over the sessions, listing them per user scans them all for
+    // each user on Redis.
+    for (session_id, session) in store.list_all_sessions().await? {
+        if !session.is_expired() && exported.contains(&session.user_name) {
+            contents
+                .sessions
+                .push((session_id.into_string(), session.to_json()));
+        }
+    }
+    let json = serde_json::to_vec(&contents).expect("Strings serialize.");
+    seal(&json, passphrase)
+}
+
+/// Adds the users of `archive` to `store`, and the recovery codes and
+/// sessions of those it didn't have. A user it had keeps their key, and gets
+/// none of the archive's sessions.
+pub async fn import<S: UserStore + SessionStore>(
+    store: &S,
+    archive: &[u8],
+    passphrase: &str,
+) -> anyhow::Result<Imported> {
+    let json = open(archive, passphrase)?;
+    let contents: Contents = serde_json::from_slice(&json).context("Not an archive")?;
+
+    let mut imported = Imported::default();
+    let mut created = HashSet::new();
+    for user in &contents.users {
+        let user = UserInfo::from_json(user)?;
+        let name = user.user_name.clone();
+        if store.create_user(user).await? {
+            store.touch_user(&name, unix_now()).await?;
+            created.insert(name);
+            imported.users += 1;
+        } else {
+            log::warn!("User: {name} is already registered, skipped.");
+            imported.skipped += 1;
+        }
+    }
+    for (user_name, hashes) in &contents.recovery_codes {
+        if created.contains(user_name) {
+            let hashes = hashes
+                .iter()
+                .map(|hash| {
+                    let hash = hex::decode(hash).ok()?;
+                    <[u8; 32]>::try_from(hash).ok()
+                })
+                .collect::<Option<Vec<_>>>()
+                .with_context(|| format!("Bad recovery code hash for user: {user_name}"))?;
+            store.set_recovery_codes(user_name, hashes).await?;
+        }
+    }
+    for (session_id, session) in &contents.sessions {
+        let session = Session::from_json(session)?;
+        if created.contains(&session.user_name) && !session.is_expired() {
+            store
+                .save_session(&SessionId::from(session_id.as_str()), session)
+                .await?;
+            imported.sessions += 1;
+        }
+    }
+    Ok(imported)
+}
+
+/// The key an archive is sealed with.
+fn key(passphrase: &str, salt: &[u8]) -> anyhow::Result<LessSafeKey> {
+    anyhow::ensure!(!passphrase.is_empty(), "BACKUP_PASSPHRASE is empty");
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow::anyhow!("Can't derive the archive key: {err}"))?;
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &key).expect("The key is 32 bytes.");
+    Ok(LessSafeKey::new(key))
+}
+
+fn seal(plaintext: &[u8], passphrase: &str) -> anyhow::Result<Vec<u8>> {
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut archive = [&MAGIC[..], &salt, &nonce].concat();
+    let mut sealed = plaintext.to_vec();
+    key(passphrase, &salt)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&archive),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow::anyhow!("Can't seal the archive"))?;
+    archive.extend(sealed);
+    Ok(archive)
+}
+
+fn open(archive: &[u8], passphrase: &str) -> anyhow::Result<Vec<u8>> {
+    let header_len = MAGIC.len() + SALT_LEN + NONCE_LEN;
+    anyhow::ensure!(
+        archive.len() > header_len && archive.starts_with(MAGIC),
+        "Not an archive"
+    );
+    let (header, sealed) = archive.split_at(header_len);
+    let salt = &header[MAGIC.len()..MAGIC.len() + SALT_LEN];
+    let nonce = Nonce::try_assume_unique_for_key(&header[MAGIC.len() + SALT_LEN..])
+        .expect("The nonce is NONCE_LEN bytes.");
+
+    let mut plaintext = sealed.to_vec();
+    let opened = key(passphrase, salt)?
+        .open_in_place(nonce, Aad::from(header), &mut plaintext)
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase, or the archive is damaged"))?;
+    Ok(opened.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{hash_recovery_code, server::store::MemoryStore, KdfParams};
+
+    fn user(name: &str, y1: u32) -> UserInfo {
+        UserInfo {
+            user_name: name.to_string(),
+            y1: BigUint::from(y1),
+            y2: BigUint::from(9u32),
+            salt: vec![0; 16],
+            kdf_params: KdfParams::default(),
+            group: None,
+            ..Default::default()
+        }
+    }
+
+    fn session(user_name: &str, expires_at: u64) -> Session {
+        Session {
+            user_name: user_name.to_string(),
+            created_at: unix_now() - 10,
+            expires_at,
+            device: None,
+            last_seen: unix_now() - 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let from = MemoryStore::default();
+        for name in ["alice", "bob"] {
+            from.put_user(user(name, 4)).await.unwrap();
+            from.save_session(&SessionId::from(name), session(name, unix_now() + 60))
+                .await
+                .unwrap();
+        }
+        from.save_session(&SessionId::from("gone"), session("alice", unix_now() - 1))
+            .await
+            .unwrap();
+        let archive = export(&from, "correct horse").await.unwrap();
+        assert!(archive.starts_with(MAGIC));
+
+        // bob is taken, and keeps his key and sessions.
+        let to = MemoryStore::default();
+        to.put_user(user("bob", 16)).await.unwrap();
+        assert!(import(&to, &archive, "wrong horse").await.is_err());
+        let imported = import(&to, &archive, "correct horse").await.unwrap();
+        assert_eq!(
+            imported,
+            Imported {
+                users: 1,
+                skipped: 1,
+                sessions: 1,
+            }
+        );
+        assert_eq!(
+            to.get_user("alice").await.unwrap().unwrap().y1,
+            BigUint::from(4u32)
+        );
+        assert_eq!(
+            to.get_user("bob").await.unwrap().unwrap().y1,
+            BigUint::from(16u32)
+        );
+        assert!(to
+            .get_session(&SessionId::from("alice"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(to
+            .get_session(&SessionId::from("gone"))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(to
+            .get_session(&SessionId::from("bob"))
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut damaged = archive.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert!(import(&to, &damaged, "correct horse").await.is_err());
+        assert!(import(&to, b"ZKPBAK1\n", "correct horse").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_keeps_recovery_codes() {
+        let code = "AAAA-AAAA-AAAA-AAAA";
+        let from = MemoryStore::default();
+        from.put_user(user("alice", 4)).await.unwrap();
+        from.set_recovery_codes("alice", vec![hash_recovery_code("alice", code)])
+            .await
+            .unwrap();
+        let archive = export(&from, "correct horse").await.unwrap();
+
+        let to = MemoryStore::default();
+        import(&to, &archive, "correct horse").await.unwrap();
+        let hash = hash_recovery_code("alice", code);
+        assert!(to.use_recovery_code("alice", &hash).await.unwrap());
+        assert!(!to.use_recovery_code("alice", &hash).await.unwrap());
+    }
+}
@@ -0,0 +1,180 @@
+//! Purges expired challenges, sessions and revocations, and lockouts that
+//! have ended, which every store but Redis keeps until something removes
+//! them. Runs every CLEANUP_INTERVAL_SECS, in store calls of at most
+//! CLEANUP_BATCH_SIZE records so none holds the store up for long, and
+//! prunes users past USER_RETENTION_DAYS along, see `retention`.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+use crate::server::{
+    audit::AuditLog,
+    retention,
+    store::{unix_now, SessionStore, StoreError, UserStore},
+};
+
+/// Time between purges unless configured otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Records removed per store call unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CleanupConfig {
+    pub interval: Duration,
+    pub batch_size: usize,
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+/// Purges every `config.interval`, the first time right away, until
+/// `stopping` fires. Users unused for longer than `user_retention` go too.
+pub async fn run<S: UserStore + SessionStore>(
+    store: Arc<S>,
+    config: CleanupConfig,
+    user_retention: Option<Duration>,
+    audit: AuditLog,
+    mut stopping: watch::Receiver<()>,
+) {
+    let mut ticks = tokio::time::interval(config.interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = stopping.changed() => break,
+        }
+        match purge(store.as_ref(), config.batch_size).await {
+            Ok(0) => {}
+            Ok(purged) => log::info!("Purged {purged} expired records"),
+            Err(err) => log::warn!("Can't purge expired records: {err}"),
+        }
+        let Some(user_retention) = user_retention else {
+            continue;
+        };
+        match retention::prune_users(store.as_ref(), user_retention, false, &audit, None).await {
+            Ok(pruned) if pruned.is_empty() => {}
+            Ok(pruned) => log::warn!("Pruned {} unused users", pruned.len()),
+            Err(err) => log::warn!("Can't prune unused users: {err}"),
+        }
+    }
+}
+
+/// Purges batch after batch until one comes back short, returns how many
+/// records went.
+pub async fn purge<S: SessionStore>(store: &S, batch_size: usize) -> Result<u64, StoreError> {
+    let mut purged = 0;
+    loop {
+        let batch = store.purge_expired(unix_now(), batch_size).await?;
+        purged += batch;
+        if batch < batch_size as u64 {
+            return Ok(purged);
+        }
+        // Calls waiting on the store get their turn in between.
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        server::store::{AuthAttempt, ChallengeState, MemoryStore, Session},
+        AuthId, SessionId,
+    };
+    #[cfg(all(feature = "sqlite", feature = "sled"))]
+    use crate::{
+        server::store::{SledStore, SqliteStore},
+        ZKP,
+    };
+
+    /// Saves an expired and a live record of each kind, and checks that
+    /// only the expired ones go.
+    async fn check_purge<S: SessionStore>(store: S) {
+        let now = unix_now();
+        for (auth_id, expires_at) in [("old", now - 1), ("new", now + 60)] {
+            let attempt = AuthAttempt {
+                user_name: "alice".to_string(),
+                state: ChallengeState::Opened {
+                    r1: BigUint::from(1u32),
+                    r2: BigUint::from(2u32),
+                    c: BigUint::from(3u32),
+                },
+                expires_at,
+            };
+            store
+                .save_challenge(&AuthId::from(auth_id), attempt)
+                .await
+                .unwrap();
+            let session = Session {
+                user_name: "alice".to_string(),
+                created_at: now - 120,
+                expires_at,
+                device: None,
+                last_seen: now - 120,
+            };
+            let session_id = SessionId::from(auth_id);
+            store.save_session(&session_id, session).await.unwrap();
+            store.revoke_session(&session_id, expires_at).await.unwrap();
+        }
+        store.lock_user("alice", now - 1).await.unwrap();
+        store.lock_user("bob", now + 60).await.unwrap();
+        // Failures since the lockout ended are still counted.
+        store.lock_user("carol", now - 1).await.unwrap();
+        store.record_failure("carol").await.unwrap();
+
+        // Two full batches of 2, and an empty one that ends the purge.
+        assert_eq!(purge(&store, 2).await.unwrap(), 4);
+        assert_eq!(purge(&store, 2).await.unwrap(), 0);
+
+        assert!(store
+            .consume_auth_id(&AuthId::from("old"))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .consume_auth_id(&AuthId::from("new"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(store
+            .get_session(&SessionId::from("old"))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .get_session(&SessionId::from("new"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(store.is_revoked(&SessionId::from("new")).await.unwrap());
+        assert_eq!(store.locked_until("alice").await.unwrap(), None);
+        assert_eq!(store.locked_until("bob").await.unwrap(), Some(now + 60));
+        assert_eq!(store.locked_until("carol").await.unwrap(), Some(now - 1));
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        check_purge(MemoryStore::default()).await;
+
+        #[cfg(all(feature = "sqlite", feature = "sled"))]
+        {
+            let path = std::env::temp_dir().join(format!(
+                "zkp_auth_cleanup_{}",
+                ZKP::generate_random_string(8)
+            ));
+            let url = format!("sqlite://{}.db", path.display());
+            check_purge(SqliteStore::connect(&url).await.unwrap()).await;
+            check_purge(SledStore::open(&path).unwrap()).await;
+        }
+    }
+}
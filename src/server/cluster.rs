@@ -0,0 +1,105 @@
+//! CLUSTER mode, for replicas behind a load balancer that doesn't pin
+//! callers to one of them. Every handler already keeps its state in the
+//! store, challenges consumed once by `consume_auth_id` and sessions
+//! refreshed once by `remove_session`, so it comes down to a store replicas
+//! share: users in PostgreSQL, and challenges, sessions and rate limits in
+//! PostgreSQL or Redis. `Config::from_settings` refuses anything else.
+//!
+//! The settings held by each replica, the proof of work difficulty
+//! SetPowDifficulty changes and the IP denylist UpdateIpDenylist does, are
+//! read back from the store every `SYNC_INTERVAL`.
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+use crate::server::{
+    ip_filter::{self, IpFilter},
+    store::{SessionStore, StoreError},
+};
+
+/// Time a replica takes at most to follow a SetPowDifficulty or
+/// UpdateIpDenylist on another.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Syncs every `SYNC_INTERVAL`, the first time right away, until `stopping`
+/// fires.
+pub async fn run<S: SessionStore>(
+    store: Arc<S>,
+    pow_difficulty: Arc<AtomicU32>,
+    ip_filter: Arc<IpFilter>,
+    mut stopping: watch::Receiver<()>,
+) {
+    let mut ticks = tokio::time::interval(SYNC_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = stopping.changed() => break,
+        }
+        if let Err(err) = sync(store.as_ref(), &pow_difficulty, &ip_filter).await {
+            log::warn!("Can't read the shared settings: {err}");
+        }
+    }
+}
+
+/// Takes the difficulty and denylist an admin set last, on whichever
+/// replica.
+pub async fn sync<S: SessionStore>(
+    store: &S,
+    pow_difficulty: &AtomicU32,
+    ip_filter: &IpFilter,
+) -> Result<(), StoreError> {
+    if let Some(difficulty) = store.pow_difficulty().await? {
+        if pow_difficulty.swap(difficulty, Ordering::Relaxed) != difficulty {
+            log::warn!("Registration proof of work difficulty set to {difficulty}.");
+        }
+    }
+    if let Some(cidrs) = store.ip_denylist().await? {
+        let denylist = cidrs
+            .iter()
+            .map(|cidr| ip_filter::parse_cidr(cidr))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(StoreError::corrupt)?;
+        if ip_filter.denylist() != denylist {
+            log::warn!("IP denylist set to {cidrs:?}.");
+            ip_filter.set_denylist(denylist);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::store::MemoryStore;
+
+    #[tokio::test]
+    async fn test_sync() {
+        let store = MemoryStore::default();
+        let pow_difficulty = AtomicU32::new(8);
+        let denied = ip_filter::parse_cidr("10.0.0.0/8").unwrap();
+        let ip_filter = IpFilter::new(Vec::new(), vec![denied]);
+
+        // POW_DIFFICULTY and IP_DENYLIST hold until an admin sets them.
+        sync(&store, &pow_difficulty, &ip_filter).await.unwrap();
+        assert_eq!(pow_difficulty.load(Ordering::Relaxed), 8);
+        assert_eq!(ip_filter.denylist(), [denied]);
+
+        store.set_pow_difficulty(12).await.unwrap();
+        store
+            .set_ip_denylist(&["192.0.2.0/24".to_string()])
+            .await
+            .unwrap();
+        sync(&store, &pow_difficulty, &ip_filter).await.unwrap();
+        assert_eq!(pow_difficulty.load(Ordering::Relaxed), 12);
+        assert!(ip_filter.allows("10.0.0.1".parse().unwrap()));
+        assert!(!ip_filter.allows("192.0.2.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,199 @@
+//! Server-side deadlines of gRPC calls: RPC_TIMEOUT_SECS for every method
+//! RPC_METHOD_TIMEOUTS doesn't give one of its own. A call past its
+//! deadline is answered DEADLINE_EXCEEDED, but its handler runs on in the
+//! background to the end, so a store update is never cut in half: a
+//! refreshed session whose old id is gone but whose new one isn't saved,
+//! say. A caller's own grpc-timeout is honored by tonic as well.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+use tracing::Instrument;
+
+/// Deadline of every call unless configured otherwise.
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deadlines {
+    pub default: Duration,
+    /// By method name, the last part of the path: "Register" say.
+    pub methods: HashMap<String, Duration>,
+}
+
+impl Default for Deadlines {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_RPC_TIMEOUT,
+            methods: HashMap::new(),
+        }
+    }
+}
+
+impl Deadlines {
+    /// Deadlines by method from `Method=seconds` entries.
+    pub fn parse_methods(entries: &[String]) -> anyhow::Result<HashMap<String, Duration>> {
+        entries
+            .iter()
+            .map(|entry| {
+                let (method, secs) = entry
+                    .split_once('=')
+                    .with_context(|| format!("{entry} is not Method=seconds"))?;
+                let secs: u64 = secs
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("{entry} is not Method=seconds"))?;
+                anyhow::ensure!(secs > 0, "The deadline of {method} is 0");
+                Ok((method.trim().to_string(), Duration::from_secs(secs)))
+            })
+            .collect()
+    }
+
+    /// The deadline of a call to `path`.
+    fn of(&self, path: &str) -> Duration {
+        let method = path.rsplit('/').next().unwrap_or_default();
+        self.methods.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+/// Puts every gRPC call under its deadline.
+#[derive(Clone)]
+pub struct DeadlineLayer {
+    deadlines: Deadlines,
+}
+
+impl DeadlineLayer {
+    pub fn new(deadlines: Deadlines) -> Self {
+        Self { deadlines }
+    }
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeadlineService {
+            inner,
+            deadlines: self.deadlines.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DeadlineService<S> {
+    inner: S,
+    deadlines: Deadlines,
+}
+
+impl<S, B> Service<http::Request<B>> for DeadlineService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let path = request.uri().path().to_string();
+        let deadline = self.deadlines.of(&path);
+
+        Box::pin(async move {
+            // The handler gets a task of its own, which isn't dropped with
+            // this future when the deadline passes or the caller hangs up.
+            let handler = tokio::spawn(inner.call(request).in_current_span());
+            match tokio::time::timeout(deadline, handler).await {
+                Ok(Ok(response)) => response,
+                Ok(Err(err)) => match err.try_into_panic() {
+                    Ok(panic) => std::panic::resume_unwind(panic),
+                    Err(err) => Ok(Status::internal(err.to_string()).into_http()),
+                },
+                Err(_) => {
+                    log::warn!("{path} ran past its deadline of {deadline:?}.");
+                    Ok(Status::deadline_exceeded(format!(
+                        "No answer within the server's deadline of {deadline:?}."
+                    ))
+                    .into_http())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let finished = finished.clone();
+            tower::service_fn(move |_: http::Request<()>| {
+                let finished = finished.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    finished.store(true, Ordering::SeqCst);
+                    Ok::<_, Infallible>(http::Response::new(BoxBody::default()))
+                }
+            })
+        };
+        let mut service = DeadlineLayer::new(Deadlines {
+            default: Duration::from_secs(10),
+            methods: HashMap::from([("Register".to_string(), Duration::from_millis(10))]),
+        })
+        .layer(handler);
+
+        let request = http::Request::post("/zkp_auth.Auth/GetSalt")
+            .body(())
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+        assert!(finished.swap(false, Ordering::SeqCst));
+
+        // Under a deadline shorter than the handler, which still finishes.
+        let request = http::Request::post("/zkp_auth.Auth/Register")
+            .body(())
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], "4");
+        assert!(!finished.load(Ordering::SeqCst));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_parse_methods() {
+        let methods =
+            Deadlines::parse_methods(&["Register=5".to_string(), " GetSalt = 1".to_string()])
+                .unwrap();
+        assert_eq!(methods["Register"], Duration::from_secs(5));
+        assert_eq!(methods["GetSalt"], Duration::from_secs(1));
+        assert!(Deadlines::parse_methods(&["Register".to_string()]).is_err());
+        assert!(Deadlines::parse_methods(&["Register=0".to_string()]).is_err());
+    }
+}
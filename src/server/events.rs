@@ -0,0 +1,23 @@
+//! Hooks into the login lifecycle, for side effects `AuthImpl` doesn't know
+//! about. Each hook runs inline in the call it is about, so one with slow
+//! work to do should hand it to a task of its own.
+
+use crate::{server::grpc_impl::auth::auth_impl::Peer, AuthId};
+
+/// Called by `AuthImpl` as users register and log in. Every method does
+/// nothing unless overridden.
+#[tonic::async_trait]
+pub trait AuthEvents: std::fmt::Debug + Send + Sync + 'static {
+    /// `user` registered.
+    async fn on_registered(&self, _user: &str, _peer: &Peer) {}
+
+    /// A challenge for `user` went out under auth_id.
+    async fn on_challenge_issued(&self, _user: &str, _auth_id: &AuthId, _peer: &Peer) {}
+
+    /// `user` answered the challenge of auth_id right, a session follows.
+    async fn on_auth_success(&self, _user: &str, _auth_id: &AuthId, _peer: &Peer) {}
+
+    /// `user` answered the challenge of auth_id wrong, or over another
+    /// connection than it was bound to.
+    async fn on_auth_failure(&self, _user: &str, _auth_id: &AuthId, _peer: &Peer) {}
+}
@@ -0,0 +1,816 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use subtle::ConstantTimeEq;
+use tonic::{body::BoxBody, server::NamedService, Code, Response, Status};
+use tower::Service;
+
+use crate::{
+    qualified_name,
+    server::{
+        audit::{AuditEvent, AuditLog},
+        grpc_impl::auth::auth_impl::Peer,
+        ip_filter::{self, IpFilter},
+        metrics::Metrics,
+        retention,
+        store::{unix_now, SessionStore, UserInfo, UserStore},
+    },
+    split_qualified_name,
+    zkp_auth::{
+        self, auth_admin_server::AuthAdmin, DeleteUserRequest, DeleteUserResponse,
+        GetMetricsRequest, GetMetricsResponse, GetUserRequest, GetUserResponse,
+        ListLoginAttemptsRequest, ListLoginAttemptsResponse, ListSessionsRequest,
+        ListSessionsResponse, ListUsersRequest, ListUsersResponse, PruneUsersRequest,
+        PruneUsersResponse, RevokeSessionRequest, RevokeSessionResponse, SessionInfo,
+        SetAdminRequest, SetAdminResponse, SetPowDifficultyRequest, SetPowDifficultyResponse,
+        UpdateIpDenylistRequest, UpdateIpDenylistResponse,
+    },
+    Secret, SessionId, MAX_POW_DIFFICULTY,
+};
+
+/// Users per ListUsers page unless the request asks for fewer.
+const DEFAULT_PAGE_SIZE: u32 = 100;
+
+/// Most users a ListUsers page holds, whatever the request asks for.
+const MAX_PAGE_SIZE: u32 = 1000;
+
+/// Operator RPCs over the same store as `AuthImpl`. Only reachable through
+/// `AdminToken`.
+#[derive(Debug)]
+pub struct AdminImpl<S> {
+    pub store: S,
+    /// `AuthImpl::session_ttl`, or `session_max_lifetime` with sliding
+    /// expiry, no session it issued lives longer.
+    pub session_ttl: Duration,
+    /// `AuthImpl::pow_difficulty`.
+    pub pow_difficulty: Arc<AtomicU32>,
+    pub audit: AuditLog,
+    /// USER_RETENTION_DAYS, what PruneUsers goes by unless told otherwise.
+    pub user_retention: Option<Duration>,
+    /// The filter in front of the services, whose denylist UpdateIpDenylist
+    /// changes.
+    pub ip_filter: Arc<IpFilter>,
+    /// `AuthImpl::metrics`, what GetMetrics reads.
+    pub metrics: Arc<Metrics>,
+}
+
+impl<S: UserStore + SessionStore> AdminImpl<S> {
+    pub fn new(store: S, session_ttl: Duration, pow_difficulty: Arc<AtomicU32>) -> Self {
+        Self {
+            store,
+            session_ttl,
+            pow_difficulty,
+            audit: AuditLog::default(),
+            user_retention: None,
+            ip_filter: Arc::default(),
+            metrics: Arc::default(),
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn with_user_retention(mut self, user_retention: Option<Duration>) -> Self {
+        self.user_retention = user_retention;
+        self
+    }
+
+    pub fn with_ip_filter(mut self, ip_filter: Arc<IpFilter>) -> Self {
+        self.ip_filter = ip_filter;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl<S: UserStore + SessionStore> AuthAdmin for AdminImpl<S> {
+    async fn list_users(
+        &self,
+        request: tonic::Request<ListUsersRequest>,
+    ) -> std::result::Result<tonic::Response<ListUsersResponse>, tonic::Status> {
+        log::info!("Processing list_users: {:?}", request.get_ref());
+        let request = request.into_inner();
+        let page_size = match request.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            page_size => page_size.min(MAX_PAGE_SIZE),
+        } as usize;
+
+        // One more than asked for says whether there is a next page.
+        let mut users = self
+            .store
+            .list_users(&request.page_token, page_size + 1)
+            .await?;
+        let next_page_token = if users.len() > page_size {
+            users.truncate(page_size);
+            users
+                .last()
+                .map(|user| user.user_name.clone())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        Ok(Response::new(ListUsersResponse {
+            users: users.iter().map(user_to_proto).collect(),
+            next_page_token,
+        }))
+    }
+
+    async fn get_user(
+        &self,
+        request: tonic::Request<GetUserRequest>,
+    ) -> std::result::Result<tonic::Response<GetUserResponse>, tonic::Status> {
+        log::info!("Processing get_user: {:?}", request.get_ref());
+        let request = request.into_inner();
+        let name = qualified_name(&request.realm, &request.name)?;
+        let Some(user) = self.store.get_user(&name).await? else {
+            return Err(not_found(&name));
+        };
+        let locked_until = self
+            .store
+            .locked_until(&name)
+            .await?
+            .filter(|&until| until > unix_now())
+            .unwrap_or(0);
+
+        Ok(Response::new(GetUserResponse {
+            user: Some(user_to_proto(&user)),
+            locked_until,
+        }))
+    }
+
+    async fn delete_user(
+        &self,
+        request: tonic::Request<DeleteUserRequest>,
+    ) -> std::result::Result<tonic::Response<DeleteUserResponse>, tonic::Status> {
+        log::info!("Processing delete_user: {:?}", request.get_ref());
+        let peer = Peer::of(&request);
+        let request = request.into_inner();
+        let name = qualified_name(&request.realm, &request.name)?;
+
+        // The user goes first, see `AuthImpl::delete_account`.
+        if !self.store.delete_user(&name).await? {
+            return Err(not_found(&name));
+        }
+        let sessions = self.store.purge_user(&name).await?;
+        log::warn!("Deleted user: {name} and their {sessions} sessions.");
+        self.audit.record(
+            peer.ip,
+            AuditEvent::UserDeleted {
+                user: name,
+                by_admin: true,
+            },
+        );
+
+        Ok(Response::new(DeleteUserResponse {}))
+    }
+
+    async fn set_admin(
+        &self,
+        request: tonic::Request<SetAdminRequest>,
+    ) -> std::result::Result<tonic::Response<SetAdminResponse>, tonic::Status> {
+        log::info!("Processing set_admin: {:?}", request.get_ref());
+        let peer = Peer::of(&request);
+        let request = request.into_inner();
+        let name = qualified_name(&request.realm, &request.name)?;
+
+        let user = self
+            .store
+            .get_user(&name)
+            .await?
+            .ok_or_else(|| not_found(&name))?;
+        if user.admin != request.admin {
+            // Only the flag, a concurrent rotation or deletion isn't undone.
+            if !self.store.set_admin(&name, request.admin).await? {
+                return Err(not_found(&name));
+            }
+            log::warn!("User: {name} admin role set to {}.", request.admin);
+            self.audit.record(
+                peer.ip,
+                AuditEvent::AdminSet {
+                    user: name,
+                    admin: request.admin,
+                },
+            );
+        }
+
+        Ok(Response::new(SetAdminResponse {}))
+    }
+
+    async fn list_sessions(
+        &self,
+        request: tonic::Request<ListSessionsRequest>,
+    ) -> std::result::Result<tonic::Response<ListSessionsResponse>, tonic::Status> {
+        log::info!("Processing list_sessions: {:?}", request.get_ref());
+        let request = request.into_inner();
+        let user = qualified_name(&request.realm, &request.user)?;
+
+        let mut sessions: Vec<SessionInfo> = self
+            .store
+            .list_sessions(&user)
+            .await?
+            .into_iter()
+            .filter(|(_, session)| !session.is_expired())
+            .map(|(session_id, session)| SessionInfo {
+                session_id: session_id.into_string(),
+                created_at: session.created_at,
+                expires_at: session.expires_at,
+                device: session.device.unwrap_or_default(),
+                last_seen: session.last_seen,
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.created_at);
+
+        Ok(Response::new(ListSessionsResponse { sessions }))
+    }
+
+    async fn revoke_session(
+        &self,
+        request: tonic::Request<RevokeSessionRequest>,
+    ) -> std::result::Result<tonic::Response<RevokeSessionResponse>, tonic::Status> {
+        log::info!("Processing revoke_session: {:?}", request.get_ref());
+        let peer = Peer::of(&request);
+        let session_id = SessionId::from(request.into_inner().session_id);
+
+        // A session this store doesn't know may still be a JWT out there,
+        // keep it on the list for as long as any session could last.
+        let session = self.store.remove_session(&session_id).await?;
+        let expires_at = match &session {
+            Some(session) => {
+                log::warn!("Revoked a session of user: {}", session.user_name);
+                session.expires_at
+            }
+            None => unix_now().saturating_add(self.session_ttl.as_secs()),
+        };
+        self.store.revoke_session(&session_id, expires_at).await?;
+        self.audit.record(
+            peer.ip,
+            AuditEvent::SessionRevoked {
+                user: session.map(|session| session.user_name),
+            },
+        );
+
+        Ok(Response::new(RevokeSessionResponse {}))
+    }
+
+    async fn set_pow_difficulty(
+        &self,
+        request: tonic::Request<SetPowDifficultyRequest>,
+    ) -> std::result::Result<tonic::Response<SetPowDifficultyResponse>, tonic::Status> {
+        log::info!("Processing set_pow_difficulty: {:?}", request.get_ref());
+        let difficulty = request.into_inner().difficulty;
+        if difficulty > MAX_POW_DIFFICULTY {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                format!("Difficulty is at most {MAX_POW_DIFFICULTY} bits."),
+            ));
+        }
+
+        // For the other replicas in CLUSTER mode.
+        self.store.set_pow_difficulty(difficulty).await?;
+        self.pow_difficulty.store(difficulty, Ordering::Relaxed);
+        log::warn!("Registration proof of work difficulty set to {difficulty}.");
+        Ok(Response::new(SetPowDifficultyResponse {}))
+    }
+
+    async fn prune_users(
+        &self,
+        request: tonic::Request<PruneUsersRequest>,
+    ) -> std::result::Result<tonic::Response<PruneUsersResponse>, tonic::Status> {
+        log::info!("Processing prune_users: {:?}", request.get_ref());
+        let peer = Peer::of(&request);
+        let request = request.into_inner();
+        let retention = match request.older_than_days {
+            0 => self.user_retention.ok_or_else(|| {
+                Status::new(
+                    Code::InvalidArgument,
+                    "USER_RETENTION_DAYS is not set, older_than_days is needed.",
+                )
+            })?,
+            days => retention::days(days),
+        };
+
+        let users = retention::prune_users(
+            &self.store,
+            retention,
+            request.dry_run,
+            &self.audit,
+            peer.ip,
+        )
+        .await?;
+        if !request.dry_run {
+            log::warn!(
+                "Pruned {} users unused for {} days.",
+                users.len(),
+                retention.as_secs() / retention::days(1).as_secs()
+            );
+        }
+
+        Ok(Response::new(PruneUsersResponse { users }))
+    }
+
+    async fn list_login_attempts(
+        &self,
+        request: tonic::Request<ListLoginAttemptsRequest>,
+    ) -> std::result::Result<tonic::Response<ListLoginAttemptsResponse>, tonic::Status> {
+        log::info!("Processing list_login_attempts: {:?}", request.get_ref());
+        let request = request.into_inner();
+        let user = qualified_name(&request.realm, &request.user)?;
+        let limit = match request.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+
+        let attempts = self
+            .store
+            .list_attempts(&user, limit)
+            .await?
+            .into_iter()
+            .map(|attempt| zkp_auth::LoginAttempt {
+                at: attempt.at,
+                success: attempt.success,
+                source_ip: attempt.source_ip.unwrap_or_default(),
+                auth_id: attempt.auth_id,
+            })
+            .collect();
+
+        Ok(Response::new(ListLoginAttemptsResponse { attempts }))
+    }
+
+    async fn update_ip_denylist(
+        &self,
+        request: tonic::Request<UpdateIpDenylistRequest>,
+    ) -> std::result::Result<tonic::Response<UpdateIpDenylistResponse>, tonic::Status> {
+        log::info!("Processing update_ip_denylist: {:?}", request.get_ref());
+        let request = request.into_inner();
+        let parse = |cidrs: &[String]| {
+            cidrs
+                .iter()
+                .map(|cidr| ip_filter::parse_cidr(cidr))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| Status::new(Code::InvalidArgument, err))
+        };
+        let (add, remove) = (parse(&request.add)?, parse(&request.remove)?);
+
+        let mut denylist = self.ip_filter.denylist();
+        if !add.is_empty() || !remove.is_empty() {
+            denylist.retain(|net| !remove.contains(net));
+            for net in add {
+                if !denylist.contains(&net) {
+                    denylist.push(net);
+                }
+            }
+            let cidrs: Vec<String> = denylist.iter().map(ToString::to_string).collect();
+            // For the other replicas in CLUSTER mode.
+            self.store.set_ip_denylist(&cidrs).await?;
+            self.ip_filter.set_denylist(denylist.clone());
+            log::warn!("IP denylist set to {cidrs:?}.");
+        }
+
+        Ok(Response::new(UpdateIpDenylistResponse {
+            denylist: denylist.iter().map(ToString::to_string).collect(),
+        }))
+    }
+
+    async fn get_metrics(
+        &self,
+        request: tonic::Request<GetMetricsRequest>,
+    ) -> std::result::Result<tonic::Response<GetMetricsResponse>, tonic::Status> {
+        log::info!("Processing get_metrics: {:?}", request.get_ref());
+        self.metrics.refresh(&self.store).await?;
+        Ok(Response::new(GetMetricsResponse {
+            json: self.metrics.snapshot().to_string(),
+        }))
+    }
+}
+
+fn user_to_proto(user: &UserInfo) -> zkp_auth::User {
+    let (realm, name) = split_qualified_name(&user.user_name);
+    zkp_auth::User {
+        name: name.to_string(),
+        realm: realm.to_string(),
+        y1: user.y1.to_bytes_be(),
+        y2: user.y2.to_bytes_be(),
+        kdf_params: Some(zkp_auth::KdfParams {
+            m_cost: user.kdf_params.m_cost,
+            t_cost: user.kdf_params.t_cost,
+            p_cost: user.kdf_params.p_cost,
+        }),
+        created_at: user.created_at,
+        last_login: user.last_login.unwrap_or(0),
+        metadata: user.metadata.clone().into_iter().collect(),
+        admin: user.admin,
+        totp: user.totp_secret.is_some(),
+    }
+}
+
+fn not_found(name: &str) -> Status {
+    Status::new(Code::NotFound, format!("User: {name} not found."))
+}
+
+/// ADMIN_TOKEN, compared in constant time.
+#[derive(Debug, Clone)]
+pub struct AdminToken(Secret<String>);
+
+impl AdminToken {
+    pub fn new(token: String) -> Self {
+        Self(Secret::new(token))
+    }
+
+    pub fn matches(&self, presented: &str) -> bool {
+        presented
+            .as_bytes()
+            .ct_eq(self.0.expose().as_bytes())
+            .into()
+    }
+}
+
+/// Looks up the admins behind sessions, for `AdminAuth`.
+#[tonic::async_trait]
+pub trait AdminSessions: Send + Sync + 'static {
+    /// The user session_id is a live session of, if they are an admin.
+    async fn admin(&self, session_id: &SessionId) -> Result<Option<String>, Status>;
+}
+
+/// Lets a call to the service it wraps through only with "authorization:
+/// Bearer <credential>", the credential being ADMIN_TOKEN or, with
+/// ADMIN_SESSIONS, a session of an admin. Sessions are looked up in the
+/// store, which a tonic interceptor can't wait on, so this wraps the
+/// service as `InterceptedService` would.
+#[derive(Clone)]
+pub struct AdminAuth<S> {
+    inner: S,
+    token: Option<AdminToken>,
+    sessions: Option<Arc<dyn AdminSessions>>,
+}
+
+impl<S> AdminAuth<S> {
+    pub fn new(
+        inner: S,
+        token: Option<AdminToken>,
+        sessions: Option<Arc<dyn AdminSessions>>,
+    ) -> Self {
+        Self {
+            inner,
+            token,
+            sessions,
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for AdminAuth<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<http::Request<B>> for AdminAuth<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        // Taken out so the credential goes no further, e.g. into the logs.
+        let presented = request
+            .headers_mut()
+            .remove("authorization")
+            .and_then(|value| Some(value.to_str().ok()?.strip_prefix("Bearer ")?.to_string()))
+            .unwrap_or_default();
+        let by_token = self
+            .token
+            .as_ref()
+            .is_some_and(|token| token.matches(&presented));
+        let sessions = self.sessions.clone().filter(|_| !presented.is_empty());
+        Box::pin(async move {
+            if !by_token {
+                let admin = match sessions {
+                    Some(sessions) => sessions.admin(&SessionId::from(presented)).await,
+                    None => Ok(None),
+                };
+                match admin {
+                    Ok(Some(user)) => log::info!("Admin call by user: {user}."),
+                    Ok(None) => {
+                        let status = Status::new(
+                            Code::Unauthenticated,
+                            "Missing or wrong admin credentials.",
+                        );
+                        return Ok(status.into_http());
+                    }
+                    Err(status) => return Ok(status.into_http()),
+                }
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+    use tonic::Request;
+
+    use super::*;
+    #[cfg(all(feature = "sqlite", feature = "sled"))]
+    use crate::server::store::{SledStore, SqliteStore};
+    use crate::{
+        server::store::{LoginAttempt, MemoryStore, Session},
+        KdfParams, ZKP,
+    };
+
+    /// An admin session for "root".
+    struct RootSession;
+
+    #[tonic::async_trait]
+    impl AdminSessions for RootSession {
+        async fn admin(&self, session_id: &SessionId) -> Result<Option<String>, Status> {
+            Ok((session_id.as_str() == "root-session").then(|| "root".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_auth() {
+        let handler = tower::service_fn(|request: http::Request<()>| async move {
+            assert!(request.headers().get("authorization").is_none());
+            Ok::<_, std::convert::Infallible>(http::Response::new(BoxBody::default()))
+        });
+        let token = Some(AdminToken::new("hunter2".to_string()));
+        let call = |auth: &mut AdminAuth<_>, value: Option<&str>| {
+            let mut request = http::Request::post("/zkp_auth.AuthAdmin/ListUsers");
+            if let Some(value) = value {
+                request = request.header("authorization", value);
+            }
+            auth.call(request.body(()).unwrap())
+        };
+        let passed =
+            |response: http::Response<BoxBody>| response.headers().get("grpc-status").is_none();
+
+        let mut auth = AdminAuth::new(handler, token, None);
+        assert!(passed(
+            call(&mut auth, Some("Bearer hunter2")).await.unwrap()
+        ));
+        assert!(!passed(
+            call(&mut auth, Some("Bearer hunter3")).await.unwrap()
+        ));
+        assert!(!passed(call(&mut auth, Some("hunter2")).await.unwrap()));
+        assert!(!passed(call(&mut auth, None).await.unwrap()));
+        assert!(!passed(
+            call(&mut auth, Some("Bearer root-session")).await.unwrap()
+        ));
+
+        let mut auth = AdminAuth::new(handler, None, Some(Arc::new(RootSession)));
+        assert!(passed(
+            call(&mut auth, Some("Bearer root-session")).await.unwrap()
+        ));
+        assert!(!passed(
+            call(&mut auth, Some("Bearer hunter2")).await.unwrap()
+        ));
+        assert!(!passed(call(&mut auth, Some("Bearer ")).await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_session() {
+        let admin = AdminImpl::new(
+            MemoryStore::default(),
+            Duration::from_secs(60),
+            Arc::default(),
+        );
+        let session = Session {
+            user_name: "alice".to_string(),
+            created_at: unix_now(),
+            expires_at: unix_now() + 60,
+            device: None,
+            last_seen: unix_now(),
+        };
+        admin
+            .store
+            .save_session(&SessionId::from("session"), session)
+            .await
+            .unwrap();
+
+        for session_id in ["session", "unknown"] {
+            admin
+                .revoke_session(Request::new(RevokeSessionRequest {
+                    session_id: session_id.to_string(),
+                }))
+                .await
+                .unwrap();
+            assert!(admin
+                .store
+                .is_revoked(&SessionId::from(session_id))
+                .await
+                .unwrap());
+        }
+        assert!(admin
+            .store
+            .get_session(&SessionId::from("session"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_users_pages() {
+        let admin = AdminImpl::new(
+            MemoryStore::default(),
+            Duration::from_secs(60),
+            Arc::default(),
+        );
+        for name in ["carol", "alice", "bob"] {
+            admin
+                .store
+                .put_user(UserInfo {
+                    user_name: name.to_string(),
+                    y1: BigUint::from(4u32),
+                    y2: BigUint::from(9u32),
+                    salt: vec![0; 16],
+                    kdf_params: KdfParams::default(),
+                    group: None,
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        let list = |page_token: String| {
+            admin.list_users(Request::new(ListUsersRequest {
+                page_size: 2,
+                page_token,
+            }))
+        };
+        let first = list(String::new()).await.unwrap().into_inner();
+        let names: Vec<_> = first.users.iter().map(|user| user.name.as_str()).collect();
+        assert_eq!(names, ["alice", "bob"]);
+        let second = list(first.next_page_token).await.unwrap().into_inner();
+        assert_eq!(second.users[0].name, "carol");
+        assert!(second.next_page_token.is_empty());
+
+        admin
+            .delete_user(Request::new(DeleteUserRequest {
+                name: "bob".to_string(),
+                realm: String::new(),
+            }))
+            .await
+            .unwrap();
+        let status = admin
+            .get_user(Request::new(GetUserRequest {
+                name: "bob".to_string(),
+                realm: String::new(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_update_ip_denylist() {
+        let admin = AdminImpl::new(
+            MemoryStore::default(),
+            Duration::from_secs(60),
+            Arc::default(),
+        );
+        let update = |add: &[&str], remove: &[&str]| {
+            admin.update_ip_denylist(Request::new(UpdateIpDenylistRequest {
+                add: add.iter().map(|cidr| cidr.to_string()).collect(),
+                remove: remove.iter().map(|cidr| cidr.to_string()).collect(),
+            }))
+        };
+
+        let denylist = update(&["10.0.0.0/8", "192.0.2.1"], &[])
+            .await
+            .unwrap()
+            .into_inner()
+            .denylist;
+        assert_eq!(denylist, ["10.0.0.0/8", "192.0.2.1/32"]);
+        assert!(!admin.ip_filter.allows("10.1.2.3".parse().unwrap()));
+        assert_eq!(
+            admin.store.ip_denylist().await.unwrap(),
+            Some(denylist.clone())
+        );
+
+        let denylist = update(&[], &["10.0.0.0/8"])
+            .await
+            .unwrap()
+            .into_inner()
+            .denylist;
+        assert_eq!(denylist, ["192.0.2.1/32"]);
+        assert!(admin.ip_filter.allows("10.1.2.3".parse().unwrap()));
+
+        let err = update(&["10.0.0.0/33"], &[]).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        assert_eq!(admin.ip_filter.denylist().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_metrics() {
+        let admin = AdminImpl::new(
+            MemoryStore::default(),
+            Duration::from_secs(60),
+            Arc::default(),
+        );
+        admin.metrics.registered("acme/alice", &ZKP::default());
+        admin
+            .store
+            .put_user(UserInfo {
+                user_name: "acme/alice".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let json = admin
+            .get_metrics(Request::new(GetMetricsRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .json;
+        let metrics: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let registrations = &metrics["zkp_registrations_total"][0];
+        assert_eq!(registrations["labels"]["tenant"], "acme");
+        assert_eq!(registrations["value"], 1.0);
+        // The gauges are read from the store first.
+        assert_eq!(metrics["zkp_stored_users"][0]["value"], 1.0);
+    }
+
+    /// Keeps 3 of 5 attempts, and checks ListLoginAttempts and delete_user
+    /// go by them.
+    async fn check_login_attempts<S: UserStore + SessionStore>(store: S) {
+        let admin = AdminImpl::new(store, Duration::from_secs(60), Arc::default());
+        for i in 0..5 {
+            let attempt = LoginAttempt {
+                at: 1_700_000_000 + i,
+                success: i % 2 == 0,
+                source_ip: (i > 0).then(|| "10.0.0.1".to_string()),
+                auth_id: format!("auth{i}"),
+            };
+            admin
+                .store
+                .record_attempt("acme/alice", attempt, 3)
+                .await
+                .unwrap();
+        }
+        let admin = &admin;
+        let list = |limit| async move {
+            admin
+                .list_login_attempts(Request::new(ListLoginAttemptsRequest {
+                    user: "alice".to_string(),
+                    realm: "acme".to_string(),
+                    limit,
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .attempts
+        };
+
+        let attempts = list(0).await;
+        let auth_ids: Vec<_> = attempts.iter().map(|a| a.auth_id.as_str()).collect();
+        assert_eq!(auth_ids, ["auth4", "auth3", "auth2"]);
+        assert_eq!(attempts[0].at, 1_700_000_004);
+        assert!(attempts[0].success && !attempts[1].success);
+        assert_eq!(attempts[0].source_ip, "10.0.0.1");
+        assert_eq!(list(1).await.len(), 1);
+
+        admin.store.delete_user("acme/alice").await.unwrap();
+        assert!(list(0).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_login_attempts() {
+        check_login_attempts(MemoryStore::default()).await;
+
+        #[cfg(all(feature = "sqlite", feature = "sled"))]
+        {
+            let path = std::env::temp_dir().join(format!(
+                "zkp_auth_attempts_{}",
+                ZKP::generate_random_string(8)
+            ));
+            let url = format!("sqlite://{}.db", path.display());
+            check_login_attempts(SqliteStore::connect(&url).await.unwrap()).await;
+            check_login_attempts(SledStore::open(&path).unwrap()).await;
+        }
+    }
+}
@@ -0,0 +1,3234 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Response, Status, Streaming};
+use tracing::Instrument;
+
+use crate::{
+    certificate_fingerprint, check_totp,
+    encoding::parse_uint,
+    generate_recovery_codes, generate_totp_secret, hash_recovery_code, negotiate_version,
+    qualified_name,
+    server::{
+        audit::{AuditEvent, AuditLog},
+        backoff::Backoff,
+        events::AuthEvents,
+        grpc_impl::admin::admin_impl::AdminSessions,
+        jwt::JwtIssuer,
+        metrics::Metrics,
+        rate_limit::{too_many_requests, RateLimiter},
+        replay::NonceCache,
+        store::{
+            unix_now, AuthAttempt, ChallengeState, Device, LoginAttempt, Session, SessionStore,
+            UserInfo, UserStore,
+        },
+    },
+    server_identity, split_qualified_name, status_with_error_code, totp_secret_base32,
+    validate_group,
+    zkp_auth::{
+        self, auth_server::Auth, authenticate_request, authenticate_response, AddDeviceRequest,
+        AddDeviceResponse, AuthenticateRequest, AuthenticateResponse, AuthenticationAnswerRequest,
+        AuthenticationAnswerResponse, AuthenticationChallengeRequest,
+        AuthenticationChallengeResponse, CapabilitiesRequest, CapabilitiesResponse,
+        ChallengeCommitmentRequest, ChallengeCommitmentResponse, DeleteAccountRequest,
+        DeleteAccountResponse, ErrorCode, GroupParams, HelloRequest, HelloResponse,
+        ListDevicesRequest, ListDevicesResponse, ListOwnSessionsRequest, ListOwnSessionsResponse,
+        LogoutRequest, LogoutResponse, OpenChallengeRequest, OpenChallengeResponse, OwnSession,
+        ParametersRequest, ParametersResponse, RecoverAccountRequest, RecoverAccountResponse,
+        RefreshSessionRequest, RefreshSessionResponse, RegisterRequest, RegisterResponse,
+        RegistrationPuzzleRequest, RegistrationPuzzleResponse, RevokeDeviceRequest,
+        RevokeDeviceResponse, RevokeOwnSessionRequest, RevokeOwnSessionResponse, SaltRequest,
+        SaltResponse, UpdateCredentialsRequest, UpdateCredentialsResponse, ValidateSessionRequest,
+        ValidateSessionResponse,
+    },
+    AuthId, ChallengeBinding, ChallengeSpec, DeviceKey, KdfParams, KeyRecovery, KeyRotation,
+    ParamSet, PowInput, Proof, Secret, SessionId, SessionTranscript, ZkpConstants, ZkpError,
+    MIN_PROTOCOL_VERSION, PARAMS_VERSION, PROTOCOL_VERSION, SALT_LEN, ZKP,
+};
+
+/// Bytes of randomness behind each challenge.
+const CHALLENGE_SEED_LEN: usize = 32;
+
+/// Shortest challenge the server hands out, in bits of soundness.
+const MIN_SOUNDNESS_BITS: u64 = 128;
+
+/// Widest p a registration may bring its own group with. Checking that p is
+/// prime gets slow fast, and nobody needs more.
+const MAX_GROUP_P_BITS: u64 = 4096;
+
+/// Longest salt a user may store.
+pub const MAX_SALT_LEN: usize = 64;
+
+/// Most metadata entries a user registers with, and the longest key and
+/// value, in bytes.
+const MAX_METADATA_ENTRIES: usize = 16;
+const MAX_METADATA_KEY_LEN: usize = 64;
+const MAX_METADATA_VALUE_LEN: usize = 1024;
+
+/// Most devices a user adds, and the longest label of one, in bytes.
+const MAX_DEVICES: usize = 16;
+const MAX_DEVICE_LABEL_LEN: usize = 64;
+
+/// How stale a session's last_seen may get before a use of it is noted,
+/// so validating a session doesn't write to the store every time.
+const LAST_SEEN_RESOLUTION: u64 = 60;
+
+/// Longest session_id taken, room enough for a JWT. Random ones are much
+/// shorter.
+const MAX_SESSION_ID_LEN: usize = 1024;
+
+/// How long a challenge can be answered for unless configured otherwise.
+pub const DEFAULT_CHALLENGE_TTL: Duration = Duration::from_secs(60);
+
+/// Locks a user out for five minutes after five wrong answers in a row.
+pub const DEFAULT_LOCKOUT: LockoutPolicy = LockoutPolicy {
+    threshold: 5,
+    cooldown: Duration::from_secs(5 * 60),
+};
+
+/// How long a session lasts unless configured otherwise.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Login attempts kept per user unless configured otherwise.
+pub const DEFAULT_LOGIN_HISTORY: usize = 20;
+
+/// When repeated wrong answers lock a user out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Wrong answers in a row that trigger a lockout, 0 to never lock out.
+    pub threshold: u32,
+    /// How long the lockout lasts.
+    pub cooldown: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthImpl<S> {
+    pub store: S,
+    pub zkp: ZKP,
+    /// The server's own secret, proven to clients on every login.
+    pub server_x: Secret<BigUint>,
+    /// `server_identity` of the server's public key, bound into challenges.
+    pub server_id: Vec<u8>,
+    /// Seconds an auth_id stays valid after its challenge went out, shared
+    /// by the clones serving each listener so a reload changes them all.
+    pub challenge_ttl: Arc<AtomicU64>,
+    /// How long a session lasts after login or its last refresh, or with
+    /// sliding expiry after its last use.
+    pub session_ttl: Duration,
+    /// Turns on sliding expiry: every use of a session pushes its expiry
+    /// out to `session_ttl` from then, up to this long after the login.
+    /// None keeps expiries where login or refresh put them.
+    pub session_max_lifetime: Option<Duration>,
+    /// Hands out session ids as signed JWTs when set, random strings
+    /// otherwise.
+    pub jwt: Option<JwtIssuer>,
+    pub lockout: LockoutPolicy,
+    /// Holds wrong answers back before the lockout, shared by the clones
+    /// serving each listener.
+    pub backoff: Arc<Backoff>,
+    /// Budget per user for registration, challenges and answers.
+    pub rate_limiter: Option<Arc<dyn RateLimiter>>,
+    /// Makes challenge and answer requests carry a nonce and timestamp
+    /// that aren't replayed, shared by the clones serving each listener.
+    /// None takes requests without.
+    pub nonces: Option<Arc<NonceCache>>,
+    /// Leading zero bits a registration's proof of work needs, 0 to not ask
+    /// for one. Shared with `AdminImpl`, which changes it at runtime.
+    pub pow_difficulty: Arc<AtomicU32>,
+    /// Oldest protocol version logins may use.
+    pub min_protocol_version: u32,
+    /// Answers to challenges kept per user for ListLoginAttempts, 0 to keep
+    /// none.
+    pub login_history: usize,
+    pub metrics: Arc<Metrics>,
+    pub audit: AuditLog,
+    /// Hooks called as users register and log in, in order.
+    pub events: Vec<Arc<dyn AuthEvents>>,
+}
+
+impl<S: UserStore + SessionStore> AuthImpl<S> {
+    pub fn new(store: S, zkp: ZKP, server_x: BigUint) -> Self {
+        let (server_y1, server_y2) = zkp.compute_pair(&server_x);
+        Self {
+            store,
+            server_id: server_identity(&server_y1, &server_y2),
+            zkp,
+            server_x: Secret::new(server_x),
+            challenge_ttl: Arc::new(AtomicU64::new(DEFAULT_CHALLENGE_TTL.as_secs())),
+            session_ttl: DEFAULT_SESSION_TTL,
+            session_max_lifetime: None,
+            jwt: None,
+            lockout: DEFAULT_LOCKOUT,
+            backoff: Arc::default(),
+            rate_limiter: None,
+            nonces: None,
+            pow_difficulty: Arc::default(),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            login_history: DEFAULT_LOGIN_HISTORY,
+            metrics: Arc::default(),
+            audit: AuditLog::default(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn with_challenge_ttl(mut self, challenge_ttl: Duration) -> Self {
+        self.challenge_ttl = Arc::new(AtomicU64::new(challenge_ttl.as_secs()));
+        self
+    }
+
+    pub fn with_session_ttl(mut self, session_ttl: Duration) -> Self {
+        self.session_ttl = session_ttl;
+        self
+    }
+
+    pub fn with_session_max_lifetime(mut self, session_max_lifetime: Duration) -> Self {
+        self.session_max_lifetime = Some(session_max_lifetime);
+        self
+    }
+
+    pub fn with_jwt(mut self, jwt: JwtIssuer) -> Self {
+        self.jwt = Some(jwt);
+        self
+    }
+
+    pub fn with_lockout(mut self, lockout: LockoutPolicy) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Arc::new(backoff);
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<dyn RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Refuses replayed challenge and answer requests, and those made more
+    /// than `window` from now.
+    pub fn with_replay_window(mut self, window: Duration) -> Self {
+        self.nonces = Some(Arc::new(NonceCache::new(window)));
+        self
+    }
+
+    pub fn with_pow_difficulty(mut self, pow_difficulty: Arc<AtomicU32>) -> Self {
+        self.pow_difficulty = pow_difficulty;
+        self
+    }
+
+    pub fn with_min_protocol_version(mut self, min_protocol_version: u32) -> Self {
+        self.min_protocol_version = min_protocol_version;
+        self
+    }
+
+    pub fn with_login_history(mut self, login_history: usize) -> Self {
+        self.login_history = login_history;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Adds a hook, called after those added before it.
+    pub fn with_events(mut self, events: Arc<dyn AuthEvents>) -> Self {
+        self.events.push(events);
+        self
+    }
+
+    /// The newest protocol version this server and a prover speaking up to
+    /// `max_version` have in common.
+    fn negotiate(&self, max_version: u32) -> Result<u32, Status> {
+        negotiate_version(max_version, self.min_protocol_version).ok_or_else(|| {
+            Status::new(
+                Code::FailedPrecondition,
+                format!(
+                    "Protocol versions up to {max_version} are not supported, this server speaks {}..={PROTOCOL_VERSION}.",
+                    self.min_protocol_version
+                ),
+            )
+        })
+    }
+
+    /// (version, max_version) of a challenge request, refusing a prover
+    /// talked down from a newer version both sides speak.
+    fn check_versions(&self, version: u32, max_version: u32) -> Result<(u32, u32), Status> {
+        // Provers from before versions existed send neither.
+        let version = version.max(1);
+        let max_version = max_version.max(version);
+        let expected = self.negotiate(max_version)?;
+        if version != expected {
+            log::warn!("A prover speaking version {max_version} asked for version {version}.");
+            return Err(Status::new(
+                Code::FailedPrecondition,
+                format!("Protocol version {version} is a downgrade from {expected}."),
+            ));
+        }
+        Ok((version, max_version))
+    }
+
+    /// The group the user's exchanges happen in.
+    fn group<'a>(&'a self, user_info: &'a UserInfo) -> &'a ZKP {
+        user_info.group.as_ref().unwrap_or(&self.zkp)
+    }
+
+    /// The group a registration asks for, None for the server's.
+    async fn group_from_proto(&self, group: Option<GroupParams>) -> Result<Option<ZKP>, Status> {
+        let Some(group) = group else {
+            return Ok(None);
+        };
+        let constants = if group.param_set.is_empty() {
+            // Every number of the group is below p, so none is longer.
+            let max_len = MAX_GROUP_P_BITS.div_ceil(8) as usize;
+            if [&group.p, &group.q, &group.alpha, &group.beta]
+                .iter()
+                .any(|bytes| bytes.len() > max_len)
+            {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    format!("Groups have at most a {MAX_GROUP_P_BITS}-bit p."),
+                ));
+            }
+            let constants = ZkpConstants {
+                p: parse_uint(&group.p)?,
+                q: parse_uint(&group.q)?,
+                alpha: parse_uint(&group.alpha)?,
+                beta: parse_uint(&group.beta)?,
+            };
+            if constants.p.bits() > MAX_GROUP_P_BITS {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    format!("Groups have at most a {MAX_GROUP_P_BITS}-bit p."),
+                ));
+            }
+            tokio::task::spawn_blocking(move || validate_group(&constants).map(|_| constants))
+                .await
+                .map_err(|err| Status::new(Code::Internal, err.to_string()))?
+                .map_err(ZkpError::from)?
+        } else {
+            ParamSet::from_name(&group.param_set)
+                .ok_or_else(|| {
+                    Status::new(
+                        Code::InvalidArgument,
+                        format!("Unknown parameter set: {}.", group.param_set),
+                    )
+                })?
+                .constants()
+        };
+
+        if constants.to_bytes() == self.zkp.to_bytes() {
+            return Ok(None);
+        }
+        let ZkpConstants { alpha, beta, p, q } = constants;
+        Ok(Some(ZKP::new(p, q, alpha, beta)))
+    }
+
+    async fn user(&self, user_name: &str) -> Result<UserInfo, Status> {
+        self.store
+            .get_user(user_name)
+            .await?
+            .ok_or_else(|| user_not_found(user_name))
+    }
+
+    /// Takes the attempt out of the store, the auth_id is spent whatever the
+    /// caller does with it next.
+    async fn consume_attempt(&self, auth_id: &AuthId) -> Result<AuthAttempt, Status> {
+        check_auth_id(auth_id)?;
+        // Some stores drop attempts as they expire, an unknown one most
+        // likely did.
+        let not_found = || {
+            status_with_error_code(
+                Code::NotFound,
+                format!("Auth ID: {auth_id} not found."),
+                ErrorCode::ChallengeExpired,
+            )
+        };
+        let attempt = self
+            .store
+            .consume_auth_id(auth_id)
+            .await?
+            .ok_or_else(not_found)?;
+        if attempt.is_expired() {
+            log::warn!("Auth ID: {auth_id} expired.");
+            return Err(not_found());
+        }
+        Ok(attempt)
+    }
+
+    /// Takes one request from the user's budget.
+    async fn check_rate(&self, user_name: &str) -> Result<(), Status> {
+        let Some(limiter) = &self.rate_limiter else {
+            return Ok(());
+        };
+        if !limiter.acquire(&format!("user:{user_name}")).await? {
+            log::warn!("User: {user_name} is over the rate limit.");
+            return Err(too_many_requests());
+        }
+        Ok(())
+    }
+
+    /// Takes the nonce of a challenge or answer request, when replays are
+    /// refused.
+    pub fn check_replay(&self, nonce: &[u8], timestamp: u64) -> Result<(), Status> {
+        match &self.nonces {
+            Some(nonces) => nonces.check(nonce, timestamp),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuses users locked out by `record_failure`.
+    async fn check_lockout(&self, user_name: &str) -> Result<(), Status> {
+        let locked_until = self.store.locked_until(user_name).await?.unwrap_or(0);
+        let now = unix_now();
+        if locked_until > now {
+            return Err(status_with_error_code(
+                Code::ResourceExhausted,
+                format!(
+                    "User: {user_name} is locked out for {} more seconds.",
+                    locked_until - now
+                ),
+                ErrorCode::LockedOut,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Counts a wrong answer from `peer`, locking the user out once they
+    /// reach the `lockout` threshold. Returns the user's failures in a row.
+    async fn record_failure(&self, user_name: &str, peer: &Peer) -> Result<u32, Status> {
+        let failures = self.store.record_failure(user_name).await?;
+        if self.lockout.threshold != 0 && failures >= self.lockout.threshold {
+            log::warn!("User: {user_name} locked out after {failures} failed logins.");
+            let until = unix_now().saturating_add(self.lockout.cooldown.as_secs());
+            self.store.lock_user(user_name, until).await?;
+            self.audit.record(
+                peer.ip,
+                AuditEvent::LockedOut {
+                    user: user_name.to_string(),
+                    until,
+                },
+            );
+        }
+        Ok(failures)
+    }
+
+    /// The session if it exists, isn't revoked and hasn't expired.
+    async fn live_session(&self, session_id: &SessionId) -> Result<Option<Session>, Status> {
+        check_session_id(session_id)?;
+        // A token that is in the store was signed here, but a store shared
+        // with a server under another key shouldn't vouch for its tokens.
+        if let Some(jwt) = &self.jwt {
+            if jwt.verify(session_id.as_str()).is_err() {
+                return Ok(None);
+            }
+        }
+
+        if self.store.is_revoked(session_id).await? {
+            return Ok(None);
+        }
+
+        let Some(mut session) = self.store.get_session(session_id).await? else {
+            return Ok(None);
+        };
+        if session.is_expired() {
+            return Ok(None);
+        }
+        let now = unix_now();
+        let expires_at = match self.session_max_lifetime {
+            Some(_) => self
+                .session_expiry(session.created_at, now)
+                .max(session.expires_at),
+            None => session.expires_at,
+        };
+        if expires_at > session.expires_at
+            || now >= session.last_seen.saturating_add(LAST_SEEN_RESOLUTION)
+        {
+            // The session stands either way, as long as it was going to.
+            match self.store.touch_session(session_id, now, expires_at).await {
+                Ok(()) => {
+                    session.last_seen = now;
+                    session.expires_at = expires_at;
+                }
+                Err(err) => log::warn!(
+                    "Can't note the use of a session of {}: {err}",
+                    session.user_name
+                ),
+            }
+        }
+        Ok(Some(session))
+    }
+
+    /// The user a live session belongs to, for the calls users make about
+    /// themselves.
+    async fn session_user(&self, session_id: &SessionId) -> Result<UserInfo, Status> {
+        let session = self.user_session(session_id).await?;
+        self.user(&session.user_name).await
+    }
+
+    /// The live session, or the status for a caller without one.
+    async fn user_session(&self, session_id: &SessionId) -> Result<Session, Status> {
+        self.live_session(session_id)
+            .await?
+            .ok_or_else(|| Status::new(Code::Unauthenticated, "Session expired or unknown."))
+    }
+
+    /// When a session of the login at `created_at` expires if issued or,
+    /// with sliding expiry, used at `now`.
+    fn session_expiry(&self, created_at: u64, now: u64) -> u64 {
+        let expires_at = now.saturating_add(self.session_ttl.as_secs());
+        match self.session_max_lifetime {
+            Some(max) => expires_at.min(created_at.saturating_add(max.as_secs())),
+            None => expires_at,
+        }
+    }
+
+    fn expires_at(&self) -> u64 {
+        unix_now().saturating_add(self.challenge_ttl.load(Ordering::Relaxed))
+    }
+
+    /// Refuses `kind` proofs that live longer than a challenge would, so
+    /// one that leaks is soon of no use.
+    fn check_fresh(&self, proof: &Proof, kind: &str) -> Result<(), Status> {
+        if proof
+            .expires_at
+            .is_some_and(|expires_at| expires_at > self.expires_at())
+        {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                format!(
+                    "{kind} proofs expire within {} seconds.",
+                    self.challenge_ttl.load(Ordering::Relaxed)
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves 1 and 2 of a classic login: a challenge for (r1, r2) bound to a
+    /// fresh auth_id, and to the peer's client certificate when the
+    /// connection has one. The caller keeps the attempt until the answer
+    /// comes.
+    pub async fn bound_challenge(
+        &self,
+        request: AuthenticationChallengeRequest,
+        peer: &Peer,
+    ) -> Result<(AuthAttempt, AuthenticationChallengeResponse), Status> {
+        self.check_replay(&request.nonce, request.timestamp)?;
+        let user = qualified_name(&request.realm, &request.user)?;
+        self.check_rate(&user).await?;
+        let user_info = self.user(&user).await?;
+        self.check_lockout(&user).await?;
+
+        let zkp = self.group(&user_info);
+        let r1 = zkp.parse_element(&request.r1)?;
+        let r2 = zkp.parse_element(&request.r2)?;
+        let spec = challenge_spec(zkp, request.challenge_bits)?;
+        let (version, max_version) =
+            self.check_versions(request.protocol_version, request.max_protocol_version)?;
+
+        let auth_id = AuthId::generate();
+        let challenge_seed = rand::random::<[u8; CHALLENGE_SEED_LEN]>().to_vec();
+        let binding = ChallengeBinding {
+            user: &user,
+            auth_id: auth_id.as_str(),
+            server_id: &self.server_id,
+            tls_exporter: None,
+            client_cert: peer.cert.as_deref(),
+            version,
+            max_version,
+        };
+        let c = zkp.bound_challenge(&spec, &challenge_seed, &binding, &r1, &r2);
+
+        let attempt = AuthAttempt {
+            user_name: user,
+            state: ChallengeState::Bound {
+                r1,
+                r2,
+                c: c.clone(),
+                seed: challenge_seed.clone(),
+                spec,
+                version,
+                max_version,
+            },
+            expires_at: self.expires_at(),
+        };
+        self.challenge_issued(zkp, &attempt.user_name, &auth_id, peer)
+            .await;
+        let challenge = AuthenticationChallengeResponse {
+            auth_id: auth_id.into_string(),
+            c: c.to_bytes_be(),
+            challenge_seed,
+        };
+        Ok((attempt, challenge))
+    }
+
+    /// Move 3 of a login: checks s against the attempt taken out for
+    /// auth_id and issues a session if it is right. A challenge bound to a
+    /// client certificate only takes answers made with the same one. Users
+    /// enrolled in TOTP also need the code of the moment in totp_code.
+    pub async fn check_answer(
+        &self,
+        auth_id: &AuthId,
+        attempt: AuthAttempt,
+        s: &[u8],
+        totp_code: &str,
+        peer: &Peer,
+    ) -> Result<AuthenticationAnswerResponse, Status> {
+        let user_name = &attempt.user_name;
+        let user_info = self.user(user_name).await?;
+        let zkp = self.group(&user_info);
+        let s = zkp.parse_scalar(s)?;
+
+        let (r1, r2, c) = match attempt.state {
+            // The challenge must belong to this auth_id, not just to the user.
+            ChallengeState::Bound {
+                r1,
+                r2,
+                c,
+                seed,
+                spec,
+                version,
+                max_version,
+            } => {
+                let binding = ChallengeBinding {
+                    user: user_name,
+                    auth_id: auth_id.as_str(),
+                    server_id: &self.server_id,
+                    tls_exporter: None,
+                    client_cert: peer.cert.as_deref(),
+                    version,
+                    max_version,
+                };
+                if let Err(err) = zkp.check_bound_challenge(&spec, &seed, &binding, (&r1, &r2), &c)
+                {
+                    log::warn!("Auth ID: {auth_id} does not match the pending challenge.");
+                    self.verified(zkp, user_name, auth_id, false, peer).await;
+                    return Err(err.into());
+                }
+                (r1, r2, c)
+            }
+            ChallengeState::Opened { r1, r2, c } => (r1, r2, c),
+            ChallengeState::Committed(_) => {
+                return Err(Status::new(
+                    Code::FailedPrecondition,
+                    format!("Auth ID: {auth_id} has no open challenge."),
+                ));
+            }
+        };
+
+        self.check_rate(user_name).await?;
+        self.check_lockout(user_name).await?;
+        // The user's own key, or else any of their devices'.
+        let device = match zkp.verify(&r1, &r2, &user_info.y1, &user_info.y2, &c, &s) {
+            Ok(()) => None,
+            Err(err) => {
+                let device = user_info
+                    .devices
+                    .iter()
+                    .find(|device| zkp.verify(&r1, &r2, &device.y1, &device.y2, &c, &s).is_ok());
+                let Some(device) = device else {
+                    log::warn!("Auth ID: {auth_id} bad solution to the challenge.");
+                    self.verified(zkp, user_name, auth_id, false, peer).await;
+                    let failures = self.record_failure(user_name, peer).await?;
+                    self.backoff.failed(failures, peer.ip).await;
+                    return Err(err.into());
+                };
+                log::info!("Auth ID: {auth_id} answered by device: {}", device.label);
+                Some(device.label.clone())
+            }
+        };
+        // A wrong code counts as a wrong answer, lockout and backoff alike.
+        if let Some(secret) = &user_info.totp_secret {
+            if !check_totp(secret, totp_code, unix_now()) {
+                log::warn!("Auth ID: {auth_id} without the right TOTP code.");
+                self.verified(zkp, user_name, auth_id, false, peer).await;
+                let failures = self.record_failure(user_name, peer).await?;
+                self.backoff.failed(failures, peer.ip).await;
+                return Err(Status::new(
+                    Code::Unauthenticated,
+                    "TOTP code missing or wrong.",
+                ));
+            }
+        }
+        self.verified(zkp, user_name, auth_id, true, peer).await;
+        self.backoff.succeeded(peer.ip);
+        self.store.clear_failures(user_name).await?;
+        self.store.record_login(user_name, unix_now()).await?;
+
+        let (session_id, session) = self.issue_session(user_name, unix_now(), device).await?;
+
+        // The server's key lives in the server's group, whatever the user's.
+        let server_proof = self.zkp.prove_session(
+            self.server_x.expose(),
+            &SessionTranscript {
+                user: user_name,
+                auth_id: auth_id.as_str(),
+                session_id: session_id.as_str(),
+                r1: &r1,
+                r2: &r2,
+                c: &c,
+                s: &s,
+            },
+        );
+
+        Ok(AuthenticationAnswerResponse {
+            session_id: session_id.into_string(),
+            server_proof: Some(proof_to_proto(&server_proof)),
+            expires_at: session.expires_at,
+        })
+    }
+
+    /// Counts, audits and reports a challenge that went out.
+    async fn challenge_issued(&self, zkp: &ZKP, user_name: &str, auth_id: &AuthId, peer: &Peer) {
+        self.metrics.challenge_issued(user_name, zkp);
+        self.audit.record(
+            peer.ip,
+            AuditEvent::ChallengeIssued {
+                user: user_name.to_string(),
+                auth_id: auth_id.to_string(),
+            },
+        );
+        for events in &self.events {
+            events.on_challenge_issued(user_name, auth_id, peer).await;
+        }
+    }
+
+    /// Counts, audits, keeps and reports an answer to the challenge of
+    /// auth_id.
+    async fn verified(
+        &self,
+        zkp: &ZKP,
+        user_name: &str,
+        auth_id: &AuthId,
+        success: bool,
+        peer: &Peer,
+    ) {
+        self.metrics.verification(user_name, zkp, success);
+        if self.login_history > 0 {
+            let attempt = LoginAttempt {
+                at: unix_now(),
+                success,
+                source_ip: peer.ip.map(|ip| ip.to_string()),
+                auth_id: auth_id.to_string(),
+            };
+            // The answer stands, the history is only for the operator.
+            if let Err(err) = self
+                .store
+                .record_attempt(user_name, attempt, self.login_history)
+                .await
+            {
+                log::warn!("Can't keep the login attempt of {user_name}: {err}");
+            }
+        }
+        self.audit.record(
+            peer.ip,
+            AuditEvent::Verified {
+                user: user_name.to_string(),
+                auth_id: auth_id.to_string(),
+                success,
+            },
+        );
+        for events in &self.events {
+            if success {
+                events.on_auth_success(user_name, auth_id, peer).await;
+            } else {
+                events.on_auth_failure(user_name, auth_id, peer).await;
+            }
+        }
+    }
+
+    /// Stores a session for `user_name` under a fresh session_id, counting
+    /// its age from `created_at`, and notes the user as used for retention.
+    /// `device` is the label of the device key the login proved, if any.
+    async fn issue_session(
+        &self,
+        user_name: &str,
+        created_at: u64,
+        device: Option<String>,
+    ) -> Result<(SessionId, Session), Status> {
+        let session = Session {
+            user_name: user_name.to_string(),
+            created_at,
+            expires_at: self.session_expiry(created_at, unix_now()),
+            device,
+            last_seen: unix_now(),
+        };
+        let session_id = match &self.jwt {
+            // Resource servers go by the token's exp alone, which can't
+            // slide, so it says when the session ends at the latest.
+            Some(jwt) => SessionId::from(match self.session_max_lifetime {
+                Some(max) => jwt.issue(&Session {
+                    expires_at: created_at.saturating_add(max.as_secs()),
+                    ..session.clone()
+                }),
+                None => jwt.issue(&session),
+            }),
+            None => SessionId::generate(),
+        };
+        self.store
+            .save_session(&session_id, session.clone())
+            .await?;
+        // Checked after the save, so a delete_account racing this one has
+        // either left no user to find or purges the session itself.
+        if let Err(status) = self.user(user_name).await {
+            self.store.remove_session(&session_id).await?;
+            return Err(status);
+        }
+        self.store.touch_user(user_name, unix_now()).await?;
+        Ok((session_id, session))
+    }
+}
+
+#[tonic::async_trait]
+impl<S: UserStore + SessionStore + Clone> Auth for AuthImpl<S> {
+    async fn hello(
+        &self,
+        request: tonic::Request<HelloRequest>,
+    ) -> std::result::Result<tonic::Response<HelloResponse>, tonic::Status> {
+        log::info!("Processing hello: {:?}", request);
+        let version = self.negotiate(request.into_inner().max_version.max(1))?;
+        Ok(Response::new(HelloResponse { version }))
+    }
+
+    async fn get_capabilities(
+        &self,
+        request: tonic::Request<CapabilitiesRequest>,
+    ) -> std::result::Result<tonic::Response<CapabilitiesResponse>, tonic::Status> {
+        log::info!("Processing get_capabilities: {:?}", request);
+        let to_strings = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        Ok(Response::new(CapabilitiesResponse {
+            group_kinds: to_strings(&["mod-p"]),
+            param_sets: ParamSet::ALL
+                .iter()
+                .map(|set| set.name().to_string())
+                .collect(),
+            custom_groups: true,
+            login_flows: to_strings(&["classic", "four-move"]),
+            proof_kinds: to_strings(&["interactive", "fiat-shamir"]),
+            session_token: match self.jwt {
+                Some(_) => "jwt",
+                None => "opaque",
+            }
+            .to_string(),
+            min_protocol_version: self.min_protocol_version,
+            max_protocol_version: PROTOCOL_VERSION,
+        }))
+    }
+
+    async fn get_parameters(
+        &self,
+        request: tonic::Request<ParametersRequest>,
+    ) -> std::result::Result<tonic::Response<ParametersResponse>, tonic::Status> {
+        log::info!("Processing get_parameters: {:?}", request);
+        let group = GroupParams {
+            param_set: ParamSet::of(&self.zkp.constants())
+                .map(|set| set.name().to_string())
+                .unwrap_or_default(),
+            ..group_to_proto(&self.zkp)
+        };
+        Ok(Response::new(ParametersResponse {
+            group: Some(group),
+            version: PARAMS_VERSION,
+        }))
+    }
+
+    async fn get_registration_puzzle(
+        &self,
+        request: tonic::Request<RegistrationPuzzleRequest>,
+    ) -> std::result::Result<tonic::Response<RegistrationPuzzleResponse>, tonic::Status> {
+        log::info!("Processing get_registration_puzzle: {:?}", request);
+        Ok(Response::new(RegistrationPuzzleResponse {
+            difficulty: self.pow_difficulty.load(Ordering::Relaxed),
+        }))
+    }
+
+    async fn register(
+        &self,
+        request: tonic::Request<RegisterRequest>,
+    ) -> std::result::Result<tonic::Response<RegisterResponse>, tonic::Status> {
+        log::info!("Processing register request: {:?}", request);
+        let peer = Peer::of(&request);
+
+        let RegisterRequest {
+            name,
+            y1,
+            y2,
+            salt,
+            kdf_params,
+            pow_nonce,
+            realm,
+            group,
+            metadata,
+            totp,
+        } = request.into_inner();
+
+        let name = qualified_name(&realm, &name)?;
+        check_salt(&salt)?;
+        check_metadata(&metadata)?;
+        self.check_rate(&name).await?;
+
+        let pow = PowInput {
+            name: &name,
+            y1: &y1,
+            y2: &y2,
+            salt: &salt,
+        };
+        let difficulty = self.pow_difficulty.load(Ordering::Relaxed);
+        if !pow.check(difficulty, pow_nonce) {
+            log::warn!("Registration of user: {name} without a proof of work.");
+            return Err(Status::new(
+                Code::FailedPrecondition,
+                format!("Proof of work for difficulty {difficulty} missing or wrong."),
+            ));
+        }
+
+        let group = self.group_from_proto(group).await?;
+        let zkp = group.as_ref().unwrap_or(&self.zkp);
+        let y1 = zkp.parse_element(&y1)?;
+        let y2 = zkp.parse_element(&y2)?;
+        let kdf_params = kdf_params_from_proto(kdf_params);
+        let totp_secret = totp.then(generate_totp_secret);
+
+        // Changing the key of a taken name is UpdateCredentials, which
+        // takes a proof of the old secret.
+        let created = self
+            .store
+            .create_user(UserInfo {
+                user_name: name.clone(),
+                y1,
+                y2,
+                salt,
+                kdf_params,
+                group: group.clone(),
+                created_at: unix_now(),
+                last_login: None,
+                metadata: metadata.into_iter().collect(),
+                admin: false,
+                totp_secret: totp_secret.clone(),
+                devices: Vec::new(),
+            })
+            .await?;
+        if !created {
+            log::warn!("User: {name} is already registered.");
+            return Err(Status::new(
+                Code::AlreadyExists,
+                format!("User: {name} already exists."),
+            ));
+        }
+        self.store.touch_user(&name, unix_now()).await?;
+        let recovery_codes = generate_recovery_codes();
+        let hashes = recovery_codes
+            .iter()
+            .map(|code| hash_recovery_code(&name, code))
+            .collect();
+        self.store.set_recovery_codes(&name, hashes).await?;
+        self.metrics.registered(&name, zkp);
+        self.audit
+            .record(peer.ip, AuditEvent::Registered { user: name.clone() });
+        for events in &self.events {
+            events.on_registered(&name, &peer).await;
+        }
+
+        Ok(Response::new(RegisterResponse {
+            totp_secret: totp_secret
+                .as_deref()
+                .map(totp_secret_base32)
+                .unwrap_or_default(),
+            recovery_codes,
+        }))
+    }
+
+    async fn get_salt(
+        &self,
+        request: tonic::Request<SaltRequest>,
+    ) -> std::result::Result<tonic::Response<SaltResponse>, tonic::Status> {
+        log::info!("Processing get_salt: {:?}", request);
+        let request = request.into_inner();
+        let user = qualified_name(&request.realm, &request.user)?;
+        let user_info = self.user(&user).await?;
+
+        let (salt, kdf_params) = if request.device.is_empty() {
+            (user_info.salt, user_info.kdf_params)
+        } else {
+            let device = user_info
+                .devices
+                .into_iter()
+                .find(|device| device.label == request.device)
+                .ok_or_else(|| {
+                    Status::new(
+                        Code::NotFound,
+                        format!("User: {user} has no device: {}", request.device),
+                    )
+                })?;
+            (device.salt, device.kdf_params)
+        };
+        let KdfParams {
+            m_cost,
+            t_cost,
+            p_cost,
+        } = kdf_params;
+
+        Ok(Response::new(SaltResponse {
+            salt,
+            kdf_params: Some(zkp_auth::KdfParams {
+                m_cost,
+                t_cost,
+                p_cost,
+            }),
+            group: user_info.group.as_ref().map(group_to_proto),
+        }))
+    }
+
+    async fn create_authentication_challenge(
+        &self,
+        request: tonic::Request<AuthenticationChallengeRequest>,
+    ) -> std::result::Result<tonic::Response<AuthenticationChallengeResponse>, tonic::Status> {
+        log::info!("Processing create_authentication_challenge: {:?}", request);
+        let peer = Peer::of(&request);
+        let (attempt, challenge) = self.bound_challenge(request.into_inner(), &peer).await?;
+        self.store
+            .save_challenge(&AuthId::from(challenge.auth_id.as_str()), attempt)
+            .await?;
+        Ok(Response::new(challenge))
+    }
+
+    async fn commit_challenge(
+        &self,
+        request: tonic::Request<ChallengeCommitmentRequest>,
+    ) -> std::result::Result<tonic::Response<ChallengeCommitmentResponse>, tonic::Status> {
+        log::info!("Processing commit_challenge: {:?}", request);
+        let peer = Peer::of(&request);
+        let request = request.into_inner();
+
+        let user = qualified_name(&request.realm, &request.user)?;
+        self.check_rate(&user).await?;
+        let user_info = self.user(&user).await?;
+        self.check_lockout(&user).await?;
+
+        let zkp = self.group(&user_info);
+        let spec = challenge_spec(zkp, request.challenge_bits)?;
+
+        let auth_id = AuthId::generate();
+        let committed = zkp.commit_challenge(&spec);
+        let commitment = committed.commitment(&user, auth_id.as_str()).to_vec();
+
+        let challenge = AuthAttempt {
+            user_name: user.clone(),
+            state: ChallengeState::Committed(committed),
+            expires_at: self.expires_at(),
+        };
+        self.store.save_challenge(&auth_id, challenge).await?;
+        self.challenge_issued(zkp, &user, &auth_id, &peer).await;
+
+        Ok(Response::new(ChallengeCommitmentResponse {
+            auth_id: auth_id.into_string(),
+            commitment,
+        }))
+    }
+
+    async fn open_challenge(
+        &self,
+        request: tonic::Request<OpenChallengeRequest>,
+    ) -> std::result::Result<tonic::Response<OpenChallengeResponse>, tonic::Status> {
+        log::info!("Processing open_challenge: {:?}", request);
+        let request = request.into_inner();
+        let auth_id = AuthId::from(request.auth_id);
+
+        // Opens at most once, a second (r1, r2) doesn't get to see c again.
+        let challenge = self.consume_attempt(&auth_id).await?;
+        let ChallengeState::Committed(committed) = challenge.state else {
+            return Err(Status::new(
+                Code::FailedPrecondition,
+                format!("Auth ID: {auth_id} has no committed challenge."),
+            ));
+        };
+
+        let user_info = self.user(&challenge.user_name).await?;
+        let zkp = self.group(&user_info);
+        let r1 = zkp.parse_element(&request.r1)?;
+        let r2 = zkp.parse_element(&request.r2)?;
+        let opening = committed.open();
+
+        let opened = AuthAttempt {
+            user_name: challenge.user_name,
+            state: ChallengeState::Opened {
+                r1,
+                r2,
+                c: opening.c.clone(),
+            },
+            expires_at: challenge.expires_at,
+        };
+        self.store.save_challenge(&auth_id, opened).await?;
+
+        Ok(Response::new(OpenChallengeResponse {
+            c: opening.c.to_bytes_be(),
+            nonce: opening.nonce.to_vec(),
+        }))
+    }
+
+    async fn verify_authentication(
+        &self,
+        request: tonic::Request<AuthenticationAnswerRequest>,
+    ) -> std::result::Result<tonic::Response<AuthenticationAnswerResponse>, tonic::Status> {
+        log::info!("Processing verify_authentication: {:?}", request);
+        let peer = Peer::of(&request);
+        let request = request.into_inner();
+        let auth_id = AuthId::from(request.auth_id);
+        self.check_replay(&request.nonce, request.timestamp)?;
+
+        // One answer per auth_id, right or wrong.
+        let attempt = self.consume_attempt(&auth_id).await?;
+        let answer = self
+            .check_answer(&auth_id, attempt, &request.s, &request.totp_code, &peer)
+            .await?;
+        Ok(Response::new(answer))
+    }
+
+    type AuthenticateStream = ReceiverStream<Result<AuthenticateResponse, Status>>;
+
+    async fn authenticate(
+        &self,
+        request: tonic::Request<Streaming<AuthenticateRequest>>,
+    ) -> std::result::Result<tonic::Response<Self::AuthenticateStream>, tonic::Status> {
+        log::info!("Processing authenticate: {:?}", request);
+        let peer = Peer::of(&request);
+        let mut requests = request.into_inner();
+
+        let Some(authenticate_request::Step::Start(start)) = next_step(&mut requests).await? else {
+            return Err(unexpected_step("start"));
+        };
+        let (attempt, challenge) = self.bound_challenge(start, &peer).await?;
+        let auth_id = AuthId::from(challenge.auth_id.as_str());
+
+        let (sender, receiver) = mpsc::channel(2);
+        let challenge = AuthenticateResponse {
+            step: Some(authenticate_response::Step::Challenge(challenge)),
+        };
+        sender
+            .try_send(Ok(challenge))
+            .expect("The channel is empty and its receiver alive.");
+
+        // The attempt stays in this task and is gone with the stream.
+        let auth = self.clone();
+        tokio::spawn(
+            async move {
+                let session = async {
+                    let step = tokio::time::timeout(attempt.ttl(), next_step(&mut requests))
+                        .await
+                        .map_err(|_| {
+                            log::warn!("Auth ID: {auth_id} expired.");
+                            status_with_error_code(
+                                Code::DeadlineExceeded,
+                                format!("Auth ID: {auth_id} expired."),
+                                ErrorCode::ChallengeExpired,
+                            )
+                        })??;
+                    let Some(authenticate_request::Step::Answer(answer)) = step else {
+                        return Err(unexpected_step("answer"));
+                    };
+                    auth.check_replay(&answer.nonce, answer.timestamp)?;
+                    auth.check_answer(&auth_id, attempt, &answer.s, &answer.totp_code, &peer)
+                        .await
+                };
+                let session = session.await.map(|session| AuthenticateResponse {
+                    step: Some(authenticate_response::Step::Session(session)),
+                });
+                // Nobody to tell when the prover hung up.
+                let _ = sender.send(session).await;
+            }
+            .in_current_span(),
+        );
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn update_credentials(
+        &self,
+        request: tonic::Request<UpdateCredentialsRequest>,
+    ) -> std::result::Result<tonic::Response<UpdateCredentialsResponse>, tonic::Status> {
+        log::info!("Processing update_credentials: {:?}", request);
+        let UpdateCredentialsRequest {
+            name,
+            new_y1,
+            new_y2,
+            old_proof,
+            new_proof,
+            new_salt,
+            new_kdf_params,
+            realm,
+        } = request.into_inner();
+
+        let name = qualified_name(&realm, &name)?;
+        check_salt(&new_salt)?;
+        self.check_rate(&name).await?;
+        self.check_lockout(&name).await?;
+
+        // The new key stays in the group the user registered in.
+        let user_info = self.user(&name).await?;
+        let zkp = self.group(&user_info);
+        let rotation = KeyRotation {
+            new_y1: zkp.parse_element(&new_y1)?,
+            new_y2: zkp.parse_element(&new_y2)?,
+            old_proof: proof_from_proto(zkp, old_proof)?,
+            new_proof: proof_from_proto(zkp, new_proof)?,
+        };
+        self.check_fresh(&rotation.old_proof, "Rotation")?;
+
+        zkp.verify_rotation(&name, &user_info.y1, &user_info.y2, &rotation)
+            .inspect_err(|_| log::warn!("User: {name} bad key rotation proof."))?;
+
+        self.store
+            .put_user(UserInfo {
+                user_name: name,
+                y1: rotation.new_y1,
+                y2: rotation.new_y2,
+                salt: new_salt,
+                kdf_params: kdf_params_from_proto(new_kdf_params),
+                ..user_info
+            })
+            .await?;
+
+        Ok(Response::new(UpdateCredentialsResponse {}))
+    }
+
+    async fn recover_account(
+        &self,
+        request: tonic::Request<RecoverAccountRequest>,
+    ) -> std::result::Result<tonic::Response<RecoverAccountResponse>, tonic::Status> {
+        log::info!("Processing recover_account: {:?}", request);
+        let peer = Peer::of(&request);
+        let RecoverAccountRequest {
+            name,
+            realm,
+            recovery_code,
+            new_y1,
+            new_y2,
+            new_proof,
+            new_salt,
+            new_kdf_params,
+        } = request.into_inner();
+
+        let name = qualified_name(&realm, &name)?;
+        check_salt(&new_salt)?;
+        // No lockout check, a user who forgot the password may well be
+        // locked out by trying.
+        self.check_rate(&name).await?;
+
+        // The new key stays in the group the user registered in.
+        let user_info = self.user(&name).await?;
+        let zkp = self.group(&user_info);
+        let recovery = KeyRecovery {
+            new_y1: zkp.parse_element(&new_y1)?,
+            new_y2: zkp.parse_element(&new_y2)?,
+            proof: proof_from_proto(zkp, new_proof)?,
+        };
+        zkp.verify_recovery(&name, &recovery)
+            .inspect_err(|_| log::warn!("User: {name} bad recovery proof."))?;
+
+        // Only a checked request uses the code up.
+        let hash = hash_recovery_code(&name, &recovery_code);
+        if !self.store.use_recovery_code(&name, &hash).await? {
+            log::warn!("User: {name} unknown or used recovery code.");
+            return Err(Status::new(
+                Code::PermissionDenied,
+                "Recovery code unknown or used.",
+            ));
+        }
+
+        self.store
+            .put_user(UserInfo {
+                user_name: name.clone(),
+                y1: recovery.new_y1,
+                y2: recovery.new_y2,
+                salt: new_salt,
+                kdf_params: kdf_params_from_proto(new_kdf_params),
+                // Devices added with the old password may be the thief's.
+                devices: Vec::new(),
+                ..user_info
+            })
+            .await?;
+        // Whoever had the old password loses what it got them.
+        let sessions = self.store.purge_user(&name).await?;
+        log::warn!("Recovered user: {name}, ended their {sessions} sessions.");
+        self.audit
+            .record(peer.ip, AuditEvent::Recovered { user: name });
+
+        Ok(Response::new(RecoverAccountResponse {}))
+    }
+
+    async fn delete_account(
+        &self,
+        request: tonic::Request<DeleteAccountRequest>,
+    ) -> std::result::Result<tonic::Response<DeleteAccountResponse>, tonic::Status> {
+        log::info!("Processing delete_account: {:?}", request);
+        let peer = Peer::of(&request);
+        let DeleteAccountRequest {
+            name,
+            proof,
+            session_id,
+            realm,
+        } = request.into_inner();
+
+        let name = qualified_name(&realm, &name)?;
+        self.check_rate(&name).await?;
+        let user_info = self.user(&name).await?;
+
+        if proof.is_some() {
+            let zkp = self.group(&user_info);
+            let proof = proof_from_proto(zkp, proof)?;
+            self.check_fresh(&proof, "Deletion")?;
+            zkp.verify_deletion(&name, &user_info.y1, &user_info.y2, &proof)
+                .inspect_err(|_| log::warn!("User: {name} bad deletion proof."))?;
+        } else {
+            let session = self.live_session(&SessionId::from(session_id)).await?;
+            if session.is_none_or(|session| session.user_name != name) {
+                return Err(Status::new(
+                    Code::Unauthenticated,
+                    "Session expired, unknown or not the user's.",
+                ));
+            }
+        }
+
+        // The user goes first, so no new login or refresh for it ends in a
+        // session once its challenges and sessions are purged, see
+        // `issue_session`.
+        self.store.delete_user(&name).await?;
+        let sessions = self.store.purge_user(&name).await?;
+        log::warn!("Deleted user: {name} and their {sessions} sessions.");
+        self.audit.record(
+            peer.ip,
+            AuditEvent::UserDeleted {
+                user: name,
+                by_admin: false,
+            },
+        );
+
+        Ok(Response::new(DeleteAccountResponse {}))
+    }
+
+    async fn refresh_session(
+        &self,
+        request: tonic::Request<RefreshSessionRequest>,
+    ) -> std::result::Result<tonic::Response<RefreshSessionResponse>, tonic::Status> {
+        log::info!("Processing refresh_session: {:?}", request);
+        let session_id = SessionId::from(request.into_inner().session_id);
+        check_session_id(&session_id)?;
+
+        // The old session_id is spent even if the new one never arrives, a
+        // stolen one can't be refreshed alongside the owner's.
+        let session = self.store.remove_session(&session_id).await?;
+        let revoked = self.store.is_revoked(&session_id).await?;
+        let Some(session) = session.filter(|session| !session.is_expired() && !revoked) else {
+            return Err(Status::new(
+                Code::Unauthenticated,
+                "Session expired or unknown.",
+            ));
+        };
+
+        let (session_id, refreshed) = self
+            .issue_session(&session.user_name, session.created_at, session.device)
+            .await?;
+
+        Ok(Response::new(RefreshSessionResponse {
+            session_id: session_id.into_string(),
+            expires_at: refreshed.expires_at,
+        }))
+    }
+
+    async fn logout(
+        &self,
+        request: tonic::Request<LogoutRequest>,
+    ) -> std::result::Result<tonic::Response<LogoutResponse>, tonic::Status> {
+        log::info!("Processing logout: {:?}", request);
+        let peer = Peer::of(&request);
+        let session_id = SessionId::from(request.into_inner().session_id);
+        check_session_id(&session_id)?;
+
+        if let Some(session) = self.store.remove_session(&session_id).await? {
+            log::info!("User: {} logged out.", session.user_name);
+            self.audit.record(
+                peer.ip,
+                AuditEvent::LoggedOut {
+                    user: session.user_name,
+                },
+            );
+        }
+
+        Ok(Response::new(LogoutResponse {}))
+    }
+
+    async fn validate_session(
+        &self,
+        request: tonic::Request<ValidateSessionRequest>,
+    ) -> std::result::Result<tonic::Response<ValidateSessionResponse>, tonic::Status> {
+        log::info!("Processing validate_session: {:?}", request);
+        let session_id = SessionId::from(request.into_inner().session_id);
+
+        let response = match self.live_session(&session_id).await? {
+            Some(session) => {
+                let (realm, user) = split_qualified_name(&session.user_name);
+                ValidateSessionResponse {
+                    valid: true,
+                    user: user.to_string(),
+                    expires_at: session.expires_at,
+                    realm: realm.to_string(),
+                }
+            }
+            None => ValidateSessionResponse::default(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn add_device(
+        &self,
+        request: tonic::Request<AddDeviceRequest>,
+    ) -> std::result::Result<tonic::Response<AddDeviceResponse>, tonic::Status> {
+        log::info!("Processing add_device: {:?}", request);
+        let AddDeviceRequest {
+            session_id,
+            label,
+            y1,
+            y2,
+            proof,
+            salt,
+            kdf_params,
+        } = request.into_inner();
+
+        check_device_label(&label)?;
+        check_salt(&salt)?;
+        let mut user_info = self.session_user(&SessionId::from(session_id)).await?;
+        let name = user_info.user_name.clone();
+
+        // The device's key lives in the user's group, like theirs.
+        let zkp = self.group(&user_info);
+        let key = DeviceKey {
+            y1: zkp.parse_element(&y1)?,
+            y2: zkp.parse_element(&y2)?,
+            proof: proof_from_proto(zkp, proof)?,
+        };
+        zkp.verify_device(&name, &label, &key)
+            .inspect_err(|_| log::warn!("User: {name} bad device proof."))?;
+
+        if user_info.devices.iter().any(|device| device.label == label) {
+            return Err(Status::new(
+                Code::AlreadyExists,
+                format!("User: {name} already has a device: {label}"),
+            ));
+        }
+        if user_info.devices.len() >= MAX_DEVICES {
+            return Err(Status::new(
+                Code::FailedPrecondition,
+                format!("Users have at most {MAX_DEVICES} devices."),
+            ));
+        }
+        user_info.devices.push(Device {
+            label: label.clone(),
+            y1: key.y1,
+            y2: key.y2,
+            salt,
+            kdf_params: kdf_params_from_proto(kdf_params),
+            added_at: unix_now(),
+        });
+        if !self.store.set_devices(&name, &user_info.devices).await? {
+            return Err(user_not_found(&name));
+        }
+        log::info!("User: {name} added device: {label}");
+
+        Ok(Response::new(AddDeviceResponse {}))
+    }
+
+    async fn list_devices(
+        &self,
+        request: tonic::Request<ListDevicesRequest>,
+    ) -> std::result::Result<tonic::Response<ListDevicesResponse>, tonic::Status> {
+        log::info!("Processing list_devices: {:?}", request);
+        let session_id = SessionId::from(request.into_inner().session_id);
+        let user_info = self.session_user(&session_id).await?;
+
+        Ok(Response::new(ListDevicesResponse {
+            devices: user_info
+                .devices
+                .into_iter()
+                .map(|device| zkp_auth::Device {
+                    label: device.label,
+                    added_at: device.added_at,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn revoke_device(
+        &self,
+        request: tonic::Request<RevokeDeviceRequest>,
+    ) -> std::result::Result<tonic::Response<RevokeDeviceResponse>, tonic::Status> {
+        log::info!("Processing revoke_device: {:?}", request);
+        let RevokeDeviceRequest { session_id, label } = request.into_inner();
+        let mut user_info = self.session_user(&SessionId::from(session_id)).await?;
+        let name = user_info.user_name.clone();
+
+        let devices = user_info.devices.len();
+        user_info.devices.retain(|device| device.label != label);
+        if user_info.devices.len() == devices {
+            return Err(Status::new(
+                Code::NotFound,
+                format!("User: {name} has no device: {label}"),
+            ));
+        }
+        if !self.store.set_devices(&name, &user_info.devices).await? {
+            return Err(user_not_found(&name));
+        }
+
+        // A lost device is logged out too, refresh would keep it in forever.
+        let sessions = self.store.list_sessions(&name).await?;
+        let mut ended = 0;
+        for (id, _) in sessions
+            .into_iter()
+            .filter(|(_, session)| session.device.as_deref() == Some(label.as_str()))
+        {
+            // Listed for JWTs, which resource servers may check without the store.
+            if let Some(session) = self.store.remove_session(&id).await? {
+                self.store.revoke_session(&id, session.expires_at).await?;
+                ended += 1;
+            }
+        }
+        log::warn!("User: {name} revoked device: {label}, ended its {ended} sessions.");
+
+        Ok(Response::new(RevokeDeviceResponse {}))
+    }
+
+    async fn list_own_sessions(
+        &self,
+        request: tonic::Request<ListOwnSessionsRequest>,
+    ) -> std::result::Result<tonic::Response<ListOwnSessionsResponse>, tonic::Status> {
+        log::info!("Processing list_own_sessions: {:?}", request);
+        let session_id = SessionId::from(request.into_inner().session_id);
+        let session = self.user_session(&session_id).await?;
+
+        let mut sessions: Vec<OwnSession> = self
+            .store
+            .list_sessions(&session.user_name)
+            .await?
+            .into_iter()
+            .filter(|(_, session)| !session.is_expired())
+            .map(|(id, session)| OwnSession {
+                handle: session_handle(&id),
+                device: session.device.unwrap_or_default(),
+                created_at: session.created_at,
+                last_seen: session.last_seen,
+                expires_at: session.expires_at,
+                current: id == session_id,
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.created_at);
+
+        Ok(Response::new(ListOwnSessionsResponse { sessions }))
+    }
+
+    async fn revoke_own_session(
+        &self,
+        request: tonic::Request<RevokeOwnSessionRequest>,
+    ) -> std::result::Result<tonic::Response<RevokeOwnSessionResponse>, tonic::Status> {
+        log::info!("Processing revoke_own_session: {:?}", request);
+        let peer = Peer::of(&request);
+        let RevokeOwnSessionRequest { session_id, handle } = request.into_inner();
+        let user_name = self
+            .user_session(&SessionId::from(session_id))
+            .await?
+            .user_name;
+
+        let target = self
+            .store
+            .list_sessions(&user_name)
+            .await?
+            .into_iter()
+            .map(|(id, _)| id)
+            .find(|id| session_handle(id) == handle)
+            .ok_or_else(|| {
+                Status::new(
+                    Code::NotFound,
+                    format!("User: {user_name} has no session: {handle}"),
+                )
+            })?;
+
+        // Listed for JWTs, which resource servers may check without the store.
+        if let Some(session) = self.store.remove_session(&target).await? {
+            self.store
+                .revoke_session(&target, session.expires_at)
+                .await?;
+        }
+        log::warn!("User: {user_name} revoked their session: {handle}");
+        self.audit.record(
+            peer.ip,
+            AuditEvent::SessionRevoked {
+                user: Some(user_name),
+            },
+        );
+
+        Ok(Response::new(RevokeOwnSessionResponse {}))
+    }
+}
+
+/// The next step the prover sent on an Authenticate stream, None once it
+/// closed the stream.
+async fn next_step(
+    requests: &mut Streaming<AuthenticateRequest>,
+) -> Result<Option<authenticate_request::Step>, Status> {
+    Ok(requests.message().await?.and_then(|request| request.step))
+}
+
+/// Admins' sessions are sessions like any other, checked as ValidateSession
+/// does.
+#[tonic::async_trait]
+impl<S: UserStore + SessionStore> AdminSessions for AuthImpl<S> {
+    async fn admin(&self, session_id: &SessionId) -> Result<Option<String>, Status> {
+        if check_session_id(session_id).is_err() {
+            return Ok(None);
+        }
+        let Some(session) = self.live_session(session_id).await? else {
+            return Ok(None);
+        };
+        let user = self.store.get_user(&session.user_name).await?;
+        Ok(user.filter(|user| user.admin).map(|user| user.user_name))
+    }
+}
+
+/// Who is on the other end of a call.
+#[derive(Debug, Clone, Default)]
+pub struct Peer {
+    /// None over a Unix socket.
+    pub ip: Option<IpAddr>,
+    /// `certificate_fingerprint` of the certificate the client presented
+    /// under mutual TLS, the leaf when it sent a chain.
+    pub cert: Option<Vec<u8>>,
+}
+
+impl Peer {
+    pub fn of<T>(request: &tonic::Request<T>) -> Self {
+        Self {
+            ip: request.remote_addr().map(|addr| addr.ip()),
+            cert: request
+                .peer_certs()
+                .and_then(|certs| certs.first().map(|cert| certificate_fingerprint(cert))),
+        }
+    }
+}
+
+fn user_not_found(user_name: &str) -> Status {
+    status_with_error_code(
+        Code::NotFound,
+        format!("User: {user_name} not found."),
+        ErrorCode::UserNotFound,
+    )
+}
+
+fn unexpected_step(expected: &str) -> Status {
+    Status::new(
+        Code::FailedPrecondition,
+        format!("Expected the {expected} step of the login."),
+    )
+}
+
+fn check_salt(salt: &[u8]) -> Result<(), Status> {
+    if !(SALT_LEN..=MAX_SALT_LEN).contains(&salt.len()) {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            format!("Salt must be {SALT_LEN} to {MAX_SALT_LEN} bytes."),
+        ));
+    }
+    Ok(())
+}
+
+fn check_device_label(label: &str) -> Result<(), Status> {
+    if label.is_empty() || label.len() > MAX_DEVICE_LABEL_LEN {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            format!("Device labels are 1 to {MAX_DEVICE_LABEL_LEN} bytes."),
+        ));
+    }
+    Ok(())
+}
+
+fn check_metadata(metadata: &HashMap<String, String>) -> Result<(), Status> {
+    let too_long = |(key, value): (&String, &String)| {
+        key.len() > MAX_METADATA_KEY_LEN || value.len() > MAX_METADATA_VALUE_LEN
+    };
+    if metadata.len() > MAX_METADATA_ENTRIES || metadata.iter().any(too_long) {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            format!(
+                "Metadata is at most {MAX_METADATA_ENTRIES} entries, keys of at most \
+                 {MAX_METADATA_KEY_LEN} bytes and values of at most {MAX_METADATA_VALUE_LEN}."
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses what can't be an auth_id this server handed out, before it gets
+/// near the store.
+fn check_auth_id(auth_id: &AuthId) -> Result<(), Status> {
+    if !auth_id.is_well_formed() {
+        return Err(Status::new(Code::InvalidArgument, "Malformed auth ID."));
+    }
+    Ok(())
+}
+
+/// What a session goes by in ListOwnSessions, a digest of its session_id
+/// that doesn't log anyone in.
+fn session_handle(session_id: &SessionId) -> String {
+    let digest = Sha256::digest(session_id.as_str().as_bytes());
+    hex::encode(&digest[..8])
+}
+
+fn check_session_id(session_id: &SessionId) -> Result<(), Status> {
+    if session_id.as_str().len() > MAX_SESSION_ID_LEN {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            format!("Session IDs are at most {MAX_SESSION_ID_LEN} bytes."),
+        ));
+    }
+    Ok(())
+}
+
+/// Single-round challenges as wide as the client asked for, as long as that
+/// keeps `MIN_SOUNDNESS_BITS`.
+fn challenge_spec(zkp: &ZKP, challenge_bits: u32) -> Result<ChallengeSpec, Status> {
+    if challenge_bits == 0 {
+        return Ok(ChallengeSpec::full(zkp));
+    }
+    let spec = ChallengeSpec {
+        bits: challenge_bits.into(),
+        rounds: 1,
+    };
+    if spec.soundness_bits(zkp) < MIN_SOUNDNESS_BITS {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            format!("Challenges must keep at least {MIN_SOUNDNESS_BITS} bits of soundness."),
+        ));
+    }
+    Ok(spec)
+}
+
+fn kdf_params_from_proto(kdf_params: Option<zkp_auth::KdfParams>) -> KdfParams {
+    kdf_params
+        .map(|params| KdfParams {
+            m_cost: params.m_cost,
+            t_cost: params.t_cost,
+            p_cost: params.p_cost,
+        })
+        .unwrap_or_default()
+}
+
+fn group_to_proto(zkp: &ZKP) -> GroupParams {
+    GroupParams {
+        param_set: String::new(),
+        p: zkp.p().to_bytes_be(),
+        q: zkp.q().to_bytes_be(),
+        alpha: zkp.alpha().to_bytes_be(),
+        beta: zkp.beta().to_bytes_be(),
+    }
+}
+
+fn proof_to_proto(proof: &Proof) -> zkp_auth::Proof {
+    zkp_auth::Proof {
+        r1: proof.r1.to_bytes_be(),
+        r2: proof.r2.to_bytes_be(),
+        s: proof.s.to_bytes_be(),
+        expires_at: proof.expires_at.unwrap_or(0),
+    }
+}
+
+fn proof_from_proto(zkp: &ZKP, proof: Option<zkp_auth::Proof>) -> Result<Proof, Status> {
+    let Some(proof) = proof else {
+        return Err(Status::new(Code::InvalidArgument, "Missing proof."));
+    };
+    Ok(Proof {
+        r1: zkp.parse_element(&proof.r1)?,
+        r2: zkp.parse_element(&proof.r2)?,
+        s: zkp.parse_scalar(&proof.s)?,
+        expires_at: (proof.expires_at != 0).then_some(proof.expires_at),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::Request;
+
+    use super::*;
+    use crate::{
+        error_code,
+        server::{
+            rate_limit::{RateLimit, TokenBucket},
+            store::MemoryStore,
+        },
+        totp_code,
+        zkp_auth::{auth_client::AuthClient, auth_server::AuthServer},
+        ChallengeOpening, RECOVERY_CODE_COUNT,
+    };
+
+    /// A server with "alice" registered, and her secret.
+    async fn setup(challenge_ttl: Duration) -> (AuthImpl<Arc<MemoryStore>>, BigUint) {
+        let zkp = ZKP::default();
+        let server_x = ZKP::generate_random_below(zkp.q());
+        let auth = AuthImpl::new(Arc::<MemoryStore>::default(), zkp.clone(), server_x)
+            .with_challenge_ttl(challenge_ttl);
+
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        auth.register(Request::new(RegisterRequest {
+            name: "alice".to_string(),
+            y1: y1.to_bytes_be(),
+            y2: y2.to_bytes_be(),
+            salt: vec![0; SALT_LEN],
+            kdf_params: None,
+            pow_nonce: 0,
+            realm: String::new(),
+            group: None,
+            metadata: [("plan".to_string(), "pro".to_string())].into(),
+            totp: false,
+        }))
+        .await
+        .unwrap();
+        (auth, x)
+    }
+
+    /// Starts a classic login for "alice", returns k and the challenge.
+    async fn challenge(
+        auth: &AuthImpl<Arc<MemoryStore>>,
+    ) -> (BigUint, AuthenticationChallengeResponse) {
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let challenge = auth
+            .create_authentication_challenge(Request::new(AuthenticationChallengeRequest {
+                user: "alice".to_string(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+                challenge_bits: 0,
+                realm: String::new(),
+                protocol_version: PROTOCOL_VERSION,
+                max_protocol_version: PROTOCOL_VERSION,
+                nonce: Vec::new(),
+                timestamp: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        (k, challenge)
+    }
+
+    async fn answer(
+        auth: &AuthImpl<Arc<MemoryStore>>,
+        auth_id: &str,
+        s: &BigUint,
+    ) -> Result<AuthenticationAnswerResponse, Status> {
+        auth.verify_authentication(Request::new(AuthenticationAnswerRequest {
+            auth_id: auth_id.to_string(),
+            s: s.to_bytes_be(),
+            totp_code: String::new(),
+            nonce: Vec::new(),
+            timestamp: 0,
+        }))
+        .await
+        .map(Response::into_inner)
+    }
+
+    /// A full classic login for "alice".
+    async fn login(auth: &AuthImpl<Arc<MemoryStore>>, x: &BigUint) -> AuthenticationAnswerResponse {
+        let (k, challenge) = challenge(auth).await;
+        let c = BigUint::from_bytes_be(&challenge.c);
+        let s = auth.zkp.solve(&k, &c, x);
+        answer(auth, &challenge.auth_id, &s).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_admin_sessions() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let session_id = login(&auth, &x).await.session_id;
+        assert_eq!(
+            auth.admin(&SessionId::from(session_id.as_str()))
+                .await
+                .unwrap(),
+            None
+        );
+
+        let mut user = auth.store.get_user("alice").await.unwrap().unwrap();
+        user.admin = true;
+        auth.store.put_user(user).await.unwrap();
+        assert_eq!(
+            auth.admin(&SessionId::from(session_id.as_str()))
+                .await
+                .unwrap(),
+            Some("alice".to_string())
+        );
+        assert_eq!(auth.admin(&SessionId::from("unknown")).await.unwrap(), None);
+        assert_eq!(
+            auth.admin(&SessionId::from("x".repeat(4096)))
+                .await
+                .unwrap(),
+            None
+        );
+
+        auth.store
+            .remove_session(&SessionId::from(session_id.as_str()))
+            .await
+            .unwrap();
+        assert_eq!(
+            auth.admin(&SessionId::from(session_id.as_str()))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_totp_second_factor() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let (y1, y2) = auth.zkp.compute_pair(&BigUint::from(2u32));
+        let registered = auth
+            .register(Request::new(RegisterRequest {
+                name: "bob".to_string(),
+                y1: y1.to_bytes_be(),
+                y2: y2.to_bytes_be(),
+                salt: vec![0; SALT_LEN],
+                kdf_params: None,
+                pow_nonce: 0,
+                realm: String::new(),
+                group: None,
+                metadata: Default::default(),
+                totp: true,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let bob = auth.store.get_user("bob").await.unwrap().unwrap();
+        let secret = bob.totp_secret.unwrap();
+        assert_eq!(registered.totp_secret, totp_secret_base32(&secret));
+
+        let mut alice = auth.store.get_user("alice").await.unwrap().unwrap();
+        alice.totp_secret = Some(secret.clone());
+        auth.store.put_user(alice).await.unwrap();
+        let answer = |auth_id: String, s: BigUint, totp_code: String| {
+            auth.verify_authentication(Request::new(AuthenticationAnswerRequest {
+                auth_id,
+                s: s.to_bytes_be(),
+                totp_code,
+                nonce: Vec::new(),
+                timestamp: 0,
+            }))
+        };
+        // Too short to ever be right.
+        for totp_code in [String::new(), "12345".to_string()] {
+            let (k, challenge) = challenge(&auth).await;
+            let s = auth
+                .zkp
+                .solve(&k, &BigUint::from_bytes_be(&challenge.c), &x);
+            let status = answer(challenge.auth_id, s, totp_code).await.unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+        let attempts = auth.store.list_attempts("alice", 10).await.unwrap();
+        assert_eq!(attempts.len(), 2);
+        assert!(attempts.iter().all(|attempt| !attempt.success));
+
+        let (k, challenge) = challenge(&auth).await;
+        let s = auth
+            .zkp
+            .solve(&k, &BigUint::from_bytes_be(&challenge.c), &x);
+        let code = totp_code(&secret, unix_now());
+        assert!(answer(challenge.auth_id, s, code).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_logins_for_one_user() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+
+        // Two devices ask for a challenge before either answers.
+        let attempts = [challenge(&auth).await, challenge(&auth).await];
+
+        let mut sessions = Vec::new();
+        for (k, challenge) in attempts.into_iter().rev() {
+            let c = BigUint::from_bytes_be(&challenge.c);
+            let s = auth.zkp.solve(&k, &c, &x);
+            let answer = answer(&auth, &challenge.auth_id, &s).await.unwrap();
+            sessions.push(answer.session_id);
+        }
+        assert_ne!(sessions[0], sessions[1]);
+    }
+
+    #[tokio::test]
+    async fn test_user_timestamps_and_metadata() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let user = auth.store.get_user("alice").await.unwrap().unwrap();
+        assert!(user.created_at > 0);
+        assert_eq!(user.last_login, None);
+        assert_eq!(user.metadata["plan"], "pro");
+
+        login(&auth, &x).await;
+        let user = auth.store.get_user("alice").await.unwrap().unwrap();
+        assert!(user.last_login.is_some());
+        assert_eq!(user.metadata["plan"], "pro");
+        let attempts = auth.store.list_attempts("alice", 10).await.unwrap();
+        assert_eq!(attempts.len(), 1);
+        assert!(attempts[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_auth_id_is_single_use() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let (k, challenge) = challenge(&auth).await;
+        let c = BigUint::from_bytes_be(&challenge.c);
+
+        // A wrong answer spends the auth_id as well as a right one.
+        let wrong = auth.zkp.solve(&k, &c, &(&x + 1u32));
+        let status = answer(&auth, &challenge.auth_id, &wrong).await.unwrap_err();
+        assert_eq!(error_code(&status), Some(ErrorCode::ProofInvalid));
+        let right = auth.zkp.solve(&k, &c, &x);
+        let status = answer(&auth, &challenge.auth_id, &right).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(error_code(&status), Some(ErrorCode::ChallengeExpired));
+    }
+
+    #[tokio::test]
+    async fn test_four_move_login() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let commit = || async {
+            auth.commit_challenge(Request::new(ChallengeCommitmentRequest {
+                user: "alice".to_string(),
+                challenge_bits: 0,
+                realm: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+        };
+        let committed = commit().await;
+        let other = commit().await;
+
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let opened = auth
+            .open_challenge(Request::new(OpenChallengeRequest {
+                auth_id: committed.auth_id.clone(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let opening = ChallengeOpening {
+            c: BigUint::from_bytes_be(&opened.c),
+            nonce: opened.nonce.try_into().unwrap(),
+        };
+
+        // The commitment is for alice's login under this auth_id only.
+        let spec = ChallengeSpec::full(&auth.zkp);
+        let check = |user, auth_id: &str, commitment: &[u8]| {
+            auth.zkp
+                .check_opening(&spec, user, auth_id, commitment, &opening)
+        };
+        assert!(check("alice", &committed.auth_id, &committed.commitment).is_ok());
+        assert!(check("alice", &other.auth_id, &committed.commitment).is_err());
+        assert!(check("alice", &other.auth_id, &other.commitment).is_err());
+        assert!(check("bob", &committed.auth_id, &committed.commitment).is_err());
+
+        let s = auth.zkp.solve(&k, &opening.c, &x);
+        let status = answer(&auth, &other.auth_id, &s).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        answer(&auth, &committed.auth_id, &s).await.unwrap();
+    }
+
+    /// Writes down every hook called.
+    #[derive(Debug, Default)]
+    struct Recorder(std::sync::Mutex<Vec<String>>);
+
+    #[tonic::async_trait]
+    impl AuthEvents for Recorder {
+        async fn on_challenge_issued(&self, user: &str, _auth_id: &AuthId, _peer: &Peer) {
+            self.0.lock().unwrap().push(format!("challenge {user}"));
+        }
+
+        async fn on_auth_success(&self, user: &str, _auth_id: &AuthId, _peer: &Peer) {
+            self.0.lock().unwrap().push(format!("success {user}"));
+        }
+
+        async fn on_auth_failure(&self, user: &str, _auth_id: &AuthId, _peer: &Peer) {
+            self.0.lock().unwrap().push(format!("failure {user}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let recorder = Arc::new(Recorder::default());
+        let auth = auth.with_events(recorder.clone());
+
+        let (k, challenge) = challenge(&auth).await;
+        let c = BigUint::from_bytes_be(&challenge.c);
+        let wrong = auth.zkp.solve(&k, &c, &(&x + 1u32));
+        answer(&auth, &challenge.auth_id, &wrong).await.unwrap_err();
+        login(&auth, &x).await;
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "challenge alice",
+                "failure alice",
+                "challenge alice",
+                "success alice"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_rotates_it() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let login = login(&auth, &x).await;
+        assert!(login.expires_at > unix_now());
+
+        let refresh = |session_id: String| {
+            auth.refresh_session(Request::new(RefreshSessionRequest { session_id }))
+        };
+        let refreshed = refresh(login.session_id.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_ne!(refreshed.session_id, login.session_id);
+        let session = auth
+            .store
+            .get_session(&SessionId::from(refreshed.session_id.as_str()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.user_name, "alice");
+
+        let status = refresh(login.session_id).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_logout_ends_session() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let login = login(&auth, &x).await;
+
+        let logout = |session_id: String| auth.logout(Request::new(LogoutRequest { session_id }));
+        logout(login.session_id.clone()).await.unwrap();
+        assert!(auth
+            .store
+            .get_session(&SessionId::from(login.session_id.as_str()))
+            .await
+            .unwrap()
+            .is_none());
+        // Logging out twice is not an error.
+        logout(login.session_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sliding_session() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let auth = auth
+            .with_session_ttl(Duration::from_secs(60))
+            .with_session_max_lifetime(Duration::from_secs(120));
+        let session_id = login(&auth, &x).await.session_id;
+        let validate = || {
+            auth.validate_session(Request::new(ValidateSessionRequest {
+                session_id: session_id.clone(),
+            }))
+        };
+        // Puts the session at `age` seconds old, expiring in `left`.
+        let (store, id) = (&auth.store, &SessionId::from(session_id.as_str()));
+        let age = |age: u64, left: u64| async move {
+            let mut session = store.get_session(id).await.unwrap().unwrap();
+            session.created_at = unix_now() - age;
+            session.expires_at = unix_now() + left;
+            store.save_session(id, session).await.unwrap();
+        };
+
+        age(30, 10).await;
+        let valid = validate().await.unwrap().into_inner();
+        assert!(valid.expires_at >= unix_now() + 59);
+        let stored = auth
+            .store
+            .get_session(&SessionId::from(session_id.as_str()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.expires_at, valid.expires_at);
+
+        // No further than the lifetime from the login, refreshed or not.
+        age(100, 10).await;
+        let valid = validate().await.unwrap().into_inner();
+        assert!(valid.expires_at <= unix_now() + 20);
+        let refreshed = auth
+            .refresh_session(Request::new(RefreshSessionRequest {
+                session_id: session_id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(refreshed.expires_at <= unix_now() + 20);
+    }
+
+    #[tokio::test]
+    async fn test_validate_session() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let login = login(&auth, &x).await;
+
+        let validate = |session_id: &str| {
+            auth.validate_session(Request::new(ValidateSessionRequest {
+                session_id: session_id.to_string(),
+            }))
+        };
+        let valid = validate(&login.session_id).await.unwrap().into_inner();
+        assert!(valid.valid);
+        assert_eq!(valid.user, "alice");
+        assert_eq!(valid.expires_at, login.expires_at);
+
+        let unknown = validate("unknown").await.unwrap().into_inner();
+        assert_eq!(unknown, ValidateSessionResponse::default());
+
+        // Expired sessions are invalid even before the store drops them.
+        let session = Session {
+            user_name: "alice".to_string(),
+            created_at: 0,
+            expires_at: 1,
+            device: None,
+            last_seen: 0,
+        };
+        auth.store
+            .save_session(&SessionId::from("stale"), session)
+            .await
+            .unwrap();
+        assert!(!validate("stale").await.unwrap().into_inner().valid);
+
+        auth.store
+            .revoke_session(
+                &SessionId::from(login.session_id.as_str()),
+                login.expires_at,
+            )
+            .await
+            .unwrap();
+        assert!(
+            !validate(&login.session_id)
+                .await
+                .unwrap()
+                .into_inner()
+                .valid
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lockout_after_failed_logins() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let auth = auth.with_lockout(LockoutPolicy {
+            threshold: 2,
+            cooldown: Duration::from_secs(60),
+        });
+
+        // A success in between starts the count over.
+        let wrong_answer = || async {
+            let (k, challenge) = challenge(&auth).await;
+            let c = BigUint::from_bytes_be(&challenge.c);
+            let s = auth.zkp.solve(&k, &c, &(&x + 1u32));
+            answer(&auth, &challenge.auth_id, &s).await.unwrap_err()
+        };
+        wrong_answer().await;
+        login(&auth, &x).await;
+        wrong_answer().await;
+        wrong_answer().await;
+
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let status = auth
+            .create_authentication_challenge(Request::new(AuthenticationChallengeRequest {
+                user: "alice".to_string(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+                challenge_bits: 0,
+                realm: String::new(),
+                protocol_version: PROTOCOL_VERSION,
+                max_protocol_version: PROTOCOL_VERSION,
+                nonce: Vec::new(),
+                timestamp: 0,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(error_code(&status), Some(ErrorCode::LockedOut));
+    }
+
+    #[tokio::test]
+    async fn test_backoff_after_failed_logins() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let auth = auth.with_backoff(Backoff::new(
+            Duration::from_millis(200),
+            Duration::from_secs(10),
+        ));
+
+        // The second wrong answer in a row waits 200 to 400ms, a third would
+        // wait 400 to 800ms.
+        let wrong_answer = || async {
+            let (k, challenge) = challenge(&auth).await;
+            let c = BigUint::from_bytes_be(&challenge.c);
+            let s = auth.zkp.solve(&k, &c, &(&x + 1u32));
+            let started = std::time::Instant::now();
+            answer(&auth, &challenge.auth_id, &s).await.unwrap_err();
+            started.elapsed()
+        };
+        wrong_answer().await;
+        assert!(wrong_answer().await >= Duration::from_millis(200));
+        login(&auth, &x).await;
+        assert!(wrong_answer().await < Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_user() {
+        let (auth, _) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let auth = auth.with_rate_limiter(Arc::new(TokenBucket::new(RateLimit {
+            requests: 1,
+            period: Duration::from_secs(60),
+        })));
+
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let request = AuthenticationChallengeRequest {
+            user: "alice".to_string(),
+            r1: r1.to_bytes_be(),
+            r2: r2.to_bytes_be(),
+            challenge_bits: 0,
+            realm: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+            nonce: Vec::new(),
+            timestamp: 0,
+        };
+        auth.create_authentication_challenge(Request::new(request.clone()))
+            .await
+            .unwrap();
+        let status = auth
+            .create_authentication_challenge(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(error_code(&status), Some(ErrorCode::RateLimited));
+    }
+
+    #[tokio::test]
+    async fn test_replayed_requests_are_refused() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let auth = auth.with_replay_window(Duration::from_secs(60));
+
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let mut request = AuthenticationChallengeRequest {
+            user: "alice".to_string(),
+            r1: r1.to_bytes_be(),
+            r2: r2.to_bytes_be(),
+            challenge_bits: 0,
+            realm: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+            nonce: Vec::new(),
+            timestamp: unix_now(),
+        };
+        let status = auth
+            .create_authentication_challenge(Request::new(request.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        request.nonce = vec![1; 16];
+        let challenge = auth
+            .create_authentication_challenge(Request::new(request.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        let status = auth
+            .create_authentication_challenge(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(&status), Some(ErrorCode::RequestReplayed));
+
+        // The answer needs a nonce of its own.
+        let c = BigUint::from_bytes_be(&challenge.c);
+        let answer = AuthenticationAnswerRequest {
+            auth_id: challenge.auth_id,
+            s: auth.zkp.solve(&k, &c, &x).to_bytes_be(),
+            totp_code: String::new(),
+            nonce: vec![1; 16],
+            timestamp: unix_now(),
+        };
+        let status = auth
+            .verify_authentication(Request::new(answer.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(&status), Some(ErrorCode::RequestReplayed));
+        auth.verify_authentication(Request::new(AuthenticationAnswerRequest {
+            nonce: vec![2; 16],
+            ..answer
+        }))
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_challenge_is_refused() {
+        let (auth, x) = setup(Duration::ZERO).await;
+        let (k, challenge) = challenge(&auth).await;
+        let c = BigUint::from_bytes_be(&challenge.c);
+
+        let s = auth.zkp.solve(&k, &c, &x);
+        let status = answer(&auth, &challenge.auth_id, &s).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(error_code(&status), Some(ErrorCode::ChallengeExpired));
+    }
+
+    #[tokio::test]
+    async fn test_registration_needs_proof_of_work() {
+        let (auth, _) = setup(DEFAULT_CHALLENGE_TTL).await;
+        auth.pow_difficulty.store(8, Ordering::Relaxed);
+
+        let puzzle = auth
+            .get_registration_puzzle(Request::new(RegistrationPuzzleRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(puzzle.difficulty, 8);
+
+        let (y1, y2) = auth.zkp.compute_pair(&BigUint::from(2u32));
+        let mut request = RegisterRequest {
+            name: "bob".to_string(),
+            y1: y1.to_bytes_be(),
+            y2: y2.to_bytes_be(),
+            salt: vec![0; SALT_LEN],
+            kdf_params: None,
+            pow_nonce: 0,
+            realm: String::new(),
+            group: None,
+            metadata: Default::default(),
+            totp: false,
+        };
+        let pow = PowInput {
+            name: &request.name,
+            y1: &request.y1,
+            y2: &request.y2,
+            salt: &request.salt,
+        };
+        let nonce = pow.solve(puzzle.difficulty);
+        request.pow_nonce = (0..).find(|&n| !pow.check(8, n)).unwrap();
+
+        let status = auth
+            .register(Request::new(request.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        request.pow_nonce = nonce;
+        auth.register(Request::new(request)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_register_refuses_taken_name() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+
+        let (y1, y2) = auth.zkp.compute_pair(&BigUint::from(2u32));
+        let status = auth
+            .register(Request::new(RegisterRequest {
+                name: "alice".to_string(),
+                y1: y1.to_bytes_be(),
+                y2: y2.to_bytes_be(),
+                salt: vec![1; SALT_LEN],
+                kdf_params: None,
+                pow_nonce: 0,
+                realm: String::new(),
+                group: None,
+                metadata: Default::default(),
+                totp: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+        assert!(!login(&auth, &x).await.session_id.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_update_credentials_needs_old_secret() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let new_x = ZKP::generate_random_below(auth.zkp.q());
+        let update = |old_x: &BigUint, expires_at: u64| {
+            let rotation = auth.zkp.prove_rotation("alice", old_x, &new_x, expires_at);
+            UpdateCredentialsRequest {
+                name: "alice".to_string(),
+                new_y1: rotation.new_y1.to_bytes_be(),
+                new_y2: rotation.new_y2.to_bytes_be(),
+                old_proof: Some(proof_to_proto(&rotation.old_proof)),
+                new_proof: Some(proof_to_proto(&rotation.new_proof)),
+                new_salt: vec![1; SALT_LEN],
+                new_kdf_params: None,
+                realm: String::new(),
+            }
+        };
+
+        let wrong_x = ZKP::generate_random_below(auth.zkp.q());
+        assert!(auth
+            .update_credentials(Request::new(update(&wrong_x, auth.expires_at())))
+            .await
+            .is_err());
+        // Rotations that could be replayed for long are refused.
+        let status = auth
+            .update_credentials(Request::new(update(&x, unix_now() + 24 * 60 * 60)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status = auth
+            .update_credentials(Request::new(update(&x, unix_now() - 120)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        auth.update_credentials(Request::new(update(&x, auth.expires_at())))
+            .await
+            .unwrap();
+        assert!(!login(&auth, &new_x).await.session_id.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recover_account() {
+        let (auth, _) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let (y1, y2) = auth.zkp.compute_pair(&BigUint::from(2u32));
+        let registered = auth
+            .register(Request::new(RegisterRequest {
+                name: "bob".to_string(),
+                y1: y1.to_bytes_be(),
+                y2: y2.to_bytes_be(),
+                salt: vec![0; SALT_LEN],
+                kdf_params: None,
+                pow_nonce: 0,
+                realm: String::new(),
+                group: None,
+                metadata: Default::default(),
+                totp: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(registered.recovery_codes.len(), RECOVERY_CODE_COUNT);
+        let (session_id, _) = auth.issue_session("bob", unix_now(), None).await.unwrap();
+
+        let new_x = ZKP::generate_random_below(auth.zkp.q());
+        let recover = |user: &str, recovery_code: &str| {
+            let recovery = auth.zkp.prove_recovery(user, &new_x);
+            auth.recover_account(Request::new(RecoverAccountRequest {
+                name: "bob".to_string(),
+                realm: String::new(),
+                recovery_code: recovery_code.to_string(),
+                new_y1: recovery.new_y1.to_bytes_be(),
+                new_y2: recovery.new_y2.to_bytes_be(),
+                new_proof: Some(proof_to_proto(&recovery.proof)),
+                new_salt: vec![1; SALT_LEN],
+                new_kdf_params: None,
+            }))
+        };
+
+        let status = recover("bob", "AAAA-AAAA-AAAA-AAAA").await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        // A proof for someone else doesn't use the code up.
+        let code = &registered.recovery_codes[0];
+        assert!(recover("alice", code).await.is_err());
+        recover("bob", &code.to_lowercase()).await.unwrap();
+
+        let bob = auth.store.get_user("bob").await.unwrap().unwrap();
+        assert_eq!((bob.y1, bob.y2), auth.zkp.compute_pair(&new_x));
+        assert_eq!(bob.salt, vec![1; SALT_LEN]);
+        assert!(auth
+            .store
+            .get_session(&SessionId::from(session_id.as_str()))
+            .await
+            .unwrap()
+            .is_none());
+
+        let status = recover("bob", code).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        recover("bob", &registered.recovery_codes[1]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recovery_drops_devices() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let session_id = login(&auth, &x).await.session_id;
+        let device_x = ZKP::generate_random_below(auth.zkp.q());
+        let key = auth.zkp.prove_device("alice", "phone", &device_x);
+        auth.add_device(Request::new(AddDeviceRequest {
+            session_id,
+            label: "phone".to_string(),
+            y1: key.y1.to_bytes_be(),
+            y2: key.y2.to_bytes_be(),
+            proof: Some(proof_to_proto(&key.proof)),
+            salt: vec![1; SALT_LEN],
+            kdf_params: None,
+        }))
+        .await
+        .unwrap();
+        login(&auth, &device_x).await;
+
+        let code = "AAAA-AAAA-AAAA-AAAA";
+        auth.store
+            .set_recovery_codes("alice", vec![hash_recovery_code("alice", code)])
+            .await
+            .unwrap();
+        let new_x = ZKP::generate_random_below(auth.zkp.q());
+        let recovery = auth.zkp.prove_recovery("alice", &new_x);
+        auth.recover_account(Request::new(RecoverAccountRequest {
+            name: "alice".to_string(),
+            realm: String::new(),
+            recovery_code: code.to_string(),
+            new_y1: recovery.new_y1.to_bytes_be(),
+            new_y2: recovery.new_y2.to_bytes_be(),
+            new_proof: Some(proof_to_proto(&recovery.proof)),
+            new_salt: vec![2; SALT_LEN],
+            new_kdf_params: None,
+        }))
+        .await
+        .unwrap();
+
+        let (k, challenge) = challenge(&auth).await;
+        let c = BigUint::from_bytes_be(&challenge.c);
+        let s = auth.zkp.solve(&k, &c, &device_x);
+        assert!(answer(&auth, &challenge.auth_id, &s).await.is_err());
+        login(&auth, &new_x).await;
+    }
+
+    #[tokio::test]
+    async fn test_devices() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let session_id = login(&auth, &x).await.session_id;
+
+        let device_x = ZKP::generate_random_below(auth.zkp.q());
+        let add = |user: &str, label: &str, session_id: &str| {
+            let key = auth.zkp.prove_device(user, label, &device_x);
+            auth.add_device(Request::new(AddDeviceRequest {
+                session_id: session_id.to_string(),
+                label: label.to_string(),
+                y1: key.y1.to_bytes_be(),
+                y2: key.y2.to_bytes_be(),
+                proof: Some(proof_to_proto(&key.proof)),
+                salt: vec![1; SALT_LEN],
+                kdf_params: None,
+            }))
+        };
+        let status = add("alice", "phone", "unknown").await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let status = add("bob", "phone", &session_id).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let status = add("alice", "", &session_id).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        add("alice", "phone", &session_id).await.unwrap();
+        let status = add("alice", "phone", &session_id).await.unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+
+        let salt = auth
+            .get_salt(Request::new(SaltRequest {
+                user: "alice".to_string(),
+                realm: String::new(),
+                device: "phone".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(salt.salt, vec![1; SALT_LEN]);
+        let device_session = login(&auth, &device_x).await.session_id;
+        let session = auth
+            .live_session(&SessionId::from(device_session.as_str()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.device.as_deref(), Some("phone"));
+        let device_session = auth
+            .refresh_session(Request::new(RefreshSessionRequest {
+                session_id: device_session,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .session_id;
+        login(&auth, &x).await;
+
+        let devices = auth
+            .list_devices(Request::new(ListDevicesRequest {
+                session_id: session_id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .devices;
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].label, "phone");
+
+        let revoke = |label: &str| {
+            auth.revoke_device(Request::new(RevokeDeviceRequest {
+                session_id: session_id.clone(),
+                label: label.to_string(),
+            }))
+        };
+        assert_eq!(revoke("laptop").await.unwrap_err().code(), Code::NotFound);
+        revoke("phone").await.unwrap();
+        let device_session = SessionId::from(device_session);
+        assert!(auth.live_session(&device_session).await.unwrap().is_none());
+        assert!(auth.store.is_revoked(&device_session).await.unwrap());
+        assert!(auth
+            .live_session(&SessionId::from(session_id.as_str()))
+            .await
+            .unwrap()
+            .is_some());
+        let (k, challenge) = challenge(&auth).await;
+        let c = BigUint::from_bytes_be(&challenge.c);
+        let s = auth.zkp.solve(&k, &c, &device_x);
+        assert!(answer(&auth, &challenge.auth_id, &s).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_own_sessions() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let session_id = login(&auth, &x).await.session_id;
+        let other = SessionId::from(login(&auth, &x).await.session_id);
+
+        let list = |session_id: &str| {
+            auth.list_own_sessions(Request::new(ListOwnSessionsRequest {
+                session_id: session_id.to_string(),
+            }))
+        };
+        let status = list("unknown").await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let sessions = list(&session_id).await.unwrap().into_inner().sessions;
+        assert_eq!(sessions.len(), 2);
+        let current: Vec<_> = sessions.iter().filter(|session| session.current).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(
+            current[0].handle,
+            session_handle(&SessionId::from(session_id.as_str()))
+        );
+        assert!(sessions
+            .iter()
+            .all(|session| session.device.is_empty() && session.handle != other.as_str()));
+
+        // A use of a session last seen long ago is noted.
+        let mut stale = auth.store.get_session(&other).await.unwrap().unwrap();
+        stale.last_seen = 0;
+        auth.store.save_session(&other, stale).await.unwrap();
+        auth.validate_session(Request::new(ValidateSessionRequest {
+            session_id: other.clone().into_string(),
+        }))
+        .await
+        .unwrap();
+        let seen = auth.store.get_session(&other).await.unwrap().unwrap();
+        assert!(seen.last_seen > 0);
+
+        let revoke = |handle: &str| {
+            auth.revoke_own_session(Request::new(RevokeOwnSessionRequest {
+                session_id: session_id.clone(),
+                handle: handle.to_string(),
+            }))
+        };
+        let status = revoke("0000000000000000").await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        revoke(&session_handle(&other)).await.unwrap();
+        assert!(auth.live_session(&other).await.unwrap().is_none());
+        assert!(auth.store.is_revoked(&other).await.unwrap());
+        let sessions = list(&session_id).await.unwrap().into_inner().sessions;
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_account() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let session_id = login(&auth, &x).await.session_id;
+        let (_, pending) = challenge(&auth).await;
+
+        let delete = |proof: Option<Proof>, session_id: &str| {
+            auth.delete_account(Request::new(DeleteAccountRequest {
+                name: "alice".to_string(),
+                proof: proof.as_ref().map(proof_to_proto),
+                session_id: session_id.to_string(),
+                realm: String::new(),
+            }))
+        };
+        let wrong_x = ZKP::generate_random_below(auth.zkp.q());
+        let forged = auth
+            .zkp
+            .prove_deletion("alice", &wrong_x, auth.expires_at());
+        assert!(delete(Some(forged), "").await.is_err());
+        let stale = auth
+            .zkp
+            .prove_deletion("alice", &x, unix_now() + 24 * 60 * 60);
+        assert!(delete(Some(stale), "").await.is_err());
+        assert!(delete(None, "unknown").await.is_err());
+
+        let proof = auth.zkp.prove_deletion("alice", &x, auth.expires_at());
+        delete(Some(proof), "").await.unwrap();
+        assert!(auth.store.get_user("alice").await.unwrap().is_none());
+        assert!(auth
+            .store
+            .get_session(&SessionId::from(session_id.as_str()))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(auth
+            .store
+            .consume_auth_id(&AuthId::from(pending.auth_id.as_str()))
+            .await
+            .unwrap()
+            .is_none());
+        let status = delete(None, &session_id).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(error_code(&status), Some(ErrorCode::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_no_session_for_deleted_user() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let session_id = login(&auth, &x).await.session_id;
+
+        // A refresh that took the old session out just before the deletion
+        // purged the user's sessions.
+        let session = auth
+            .store
+            .remove_session(&SessionId::from(session_id.as_str()))
+            .await
+            .unwrap()
+            .unwrap();
+        auth.store.delete_user("alice").await.unwrap();
+        auth.store.purge_user("alice").await.unwrap();
+
+        let status = auth
+            .issue_session("alice", session.created_at, None)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert!(auth.store.list_sessions("alice").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_realms_are_isolated() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let acme_x = ZKP::generate_random_below(auth.zkp.q());
+        let (y1, y2) = auth.zkp.compute_pair(&acme_x);
+        auth.register(Request::new(RegisterRequest {
+            name: "alice".to_string(),
+            y1: y1.to_bytes_be(),
+            y2: y2.to_bytes_be(),
+            salt: vec![0; SALT_LEN],
+            kdf_params: None,
+            pow_nonce: 0,
+            realm: "acme".to_string(),
+            group: None,
+            metadata: Default::default(),
+            totp: false,
+        }))
+        .await
+        .unwrap();
+
+        // Default realm alice's secret doesn't answer acme alice's challenge.
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let challenge = auth
+            .create_authentication_challenge(Request::new(AuthenticationChallengeRequest {
+                user: "alice".to_string(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+                challenge_bits: 0,
+                realm: "acme".to_string(),
+                protocol_version: PROTOCOL_VERSION,
+                max_protocol_version: PROTOCOL_VERSION,
+                nonce: Vec::new(),
+                timestamp: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let c = BigUint::from_bytes_be(&challenge.c);
+        let s = auth.zkp.solve(&k, &c, &x);
+        assert!(answer(&auth, &challenge.auth_id, &s).await.is_err());
+
+        let user_info = auth.store.get_user("acme/alice").await.unwrap().unwrap();
+        assert_eq!((user_info.y1, user_info.y2), (y1, y2));
+
+        let session_id = login(&auth, &x).await.session_id;
+        let valid = auth
+            .validate_session(Request::new(ValidateSessionRequest { session_id }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((valid.user.as_str(), valid.realm.as_str()), ("alice", ""));
+    }
+
+    #[tokio::test]
+    async fn test_register_in_own_group() {
+        let (auth, _) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let zkp = ZKP::builder()
+            .param_set(ParamSet::Rfc5114_2048_256)
+            .build()
+            .unwrap();
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        let register = |param_set: &str| {
+            auth.register(Request::new(RegisterRequest {
+                name: "bob".to_string(),
+                y1: y1.to_bytes_be(),
+                y2: y2.to_bytes_be(),
+                salt: vec![0; SALT_LEN],
+                kdf_params: None,
+                pow_nonce: 0,
+                realm: String::new(),
+                group: Some(GroupParams {
+                    param_set: param_set.to_string(),
+                    ..Default::default()
+                }),
+                metadata: Default::default(),
+                totp: false,
+            }))
+        };
+        let status = register("rfc5114-512-64").await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        register(ParamSet::Rfc5114_2048_256.name()).await.unwrap();
+
+        let salt = auth
+            .get_salt(Request::new(SaltRequest {
+                user: "bob".to_string(),
+                realm: String::new(),
+                device: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(salt.group, Some(group_to_proto(&zkp)));
+
+        let k = ZKP::generate_random_below(zkp.q());
+        let (r1, r2) = zkp.compute_pair(&k);
+        let challenge = auth
+            .create_authentication_challenge(Request::new(AuthenticationChallengeRequest {
+                user: "bob".to_string(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+                challenge_bits: 0,
+                realm: String::new(),
+                protocol_version: PROTOCOL_VERSION,
+                max_protocol_version: PROTOCOL_VERSION,
+                nonce: Vec::new(),
+                timestamp: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let c = BigUint::from_bytes_be(&challenge.c);
+        assert!(c < *zkp.q());
+        let s = zkp.solve(&k, &c, &x);
+        answer(&auth, &challenge.auth_id, &s).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_malformed_requests() {
+        let (auth, _) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let challenge = |user: &str, r1: Vec<u8>| {
+            auth.create_authentication_challenge(Request::new(AuthenticationChallengeRequest {
+                user: user.to_string(),
+                r1,
+                r2: r2.to_bytes_be(),
+                challenge_bits: 0,
+                realm: String::new(),
+                protocol_version: PROTOCOL_VERSION,
+                max_protocol_version: PROTOCOL_VERSION,
+                nonce: Vec::new(),
+                timestamp: 0,
+            }))
+        };
+        let not_in_subgroup = (auth.zkp.p() - 1u32).to_bytes_be();
+        let mut leading_zero = r1.to_bytes_be();
+        leading_zero.insert(0, 0);
+        for (user, r1) in [
+            ("alice", Vec::new()),
+            ("alice", vec![0]),
+            ("alice", not_in_subgroup),
+            ("alice", leading_zero),
+            ("alice", auth.zkp.p().to_bytes_be()),
+            ("", r1.to_bytes_be()),
+            ("alice smith", r1.to_bytes_be()),
+            (&"a".repeat(200), r1.to_bytes_be()),
+        ] {
+            let status = challenge(user, r1).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{status:?}");
+        }
+
+        for auth_id in ["", "short", "abcdefghijk!", &"a".repeat(1000)] {
+            let status = answer(&auth, auth_id, &k).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+
+        let status = auth
+            .validate_session(Request::new(ValidateSessionRequest {
+                session_id: "a".repeat(MAX_SESSION_ID_LEN + 1),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // A custom group is bounded before it is parsed.
+        let status = auth
+            .register(Request::new(RegisterRequest {
+                name: "bob".to_string(),
+                y1: vec![4],
+                y2: vec![9],
+                salt: vec![0; SALT_LEN],
+                kdf_params: None,
+                pow_nonce: 0,
+                realm: String::new(),
+                group: Some(GroupParams {
+                    p: vec![0xff; 1 << 20],
+                    ..Default::default()
+                }),
+                metadata: Default::default(),
+                totp: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status = auth
+            .register(Request::new(RegisterRequest {
+                name: "bob".to_string(),
+                y1: vec![4],
+                y2: vec![9],
+                salt: vec![0; MAX_SALT_LEN + 1],
+                kdf_params: None,
+                pow_nonce: 0,
+                realm: String::new(),
+                group: None,
+                metadata: Default::default(),
+                totp: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status = auth
+            .register(Request::new(RegisterRequest {
+                name: "bob".to_string(),
+                y1: vec![4],
+                y2: vec![9],
+                salt: vec![0; SALT_LEN],
+                kdf_params: None,
+                pow_nonce: 0,
+                realm: String::new(),
+                group: None,
+                metadata: (0..=MAX_METADATA_ENTRIES)
+                    .map(|i| (i.to_string(), String::new()))
+                    .collect(),
+                totp: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_parameters() {
+        let (auth, _) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let parameters = auth
+            .get_parameters(Request::new(ParametersRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(parameters.version, PARAMS_VERSION);
+
+        let group = parameters.group.unwrap();
+        assert_eq!(group.param_set, ParamSet::Rfc5114_1024_160.name());
+        let zkp = ZKP::builder()
+            .params_from_bytes(&group.p, &group.q, &group.alpha, &group.beta)
+            .build()
+            .unwrap();
+        assert_eq!(zkp.to_bytes(), auth.zkp.to_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_protocol_version_downgrade() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let hello = |max_version: u32| auth.hello(Request::new(HelloRequest { max_version }));
+        assert_eq!(hello(0).await.unwrap().into_inner().version, 1);
+        assert_eq!(
+            hello(PROTOCOL_VERSION + 1)
+                .await
+                .unwrap()
+                .into_inner()
+                .version,
+            PROTOCOL_VERSION
+        );
+
+        let k = ZKP::generate_random_below(auth.zkp.q());
+        let (r1, r2) = auth.zkp.compute_pair(&k);
+        let request = |protocol_version: u32, max_protocol_version: u32| {
+            Request::new(AuthenticationChallengeRequest {
+                user: "alice".to_string(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+                challenge_bits: 0,
+                realm: String::new(),
+                protocol_version,
+                max_protocol_version,
+                nonce: Vec::new(),
+                timestamp: 0,
+            })
+        };
+        let status = auth
+            .create_authentication_challenge(request(1, PROTOCOL_VERSION))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        // Provers from before versions still log in, unless the server
+        // refuses version 1.
+        let legacy = auth
+            .create_authentication_challenge(request(0, 0))
+            .await
+            .unwrap()
+            .into_inner();
+        let c = BigUint::from_bytes_be(&legacy.c);
+        let s = auth.zkp.solve(&k, &c, &x);
+        answer(&auth, &legacy.auth_id, &s).await.unwrap();
+
+        let auth = auth.with_min_protocol_version(PROTOCOL_VERSION);
+        let status = auth
+            .create_authentication_challenge(request(0, 0))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities() {
+        let (auth, _) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let capabilities = auth
+            .get_capabilities(Request::new(CapabilitiesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(capabilities.param_sets.len(), ParamSet::ALL.len());
+        assert_eq!(capabilities.session_token, "opaque");
+        assert_eq!(
+            (
+                capabilities.min_protocol_version,
+                capabilities.max_protocol_version
+            ),
+            (MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)
+        );
+
+        let auth = auth.with_jwt(JwtIssuer::new(b"secret"));
+        let capabilities = auth
+            .get_capabilities(Request::new(CapabilitiesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(capabilities.session_token, "jwt");
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_stream() {
+        let (auth, x) = setup(DEFAULT_CHALLENGE_TTL).await;
+        let zkp = auth.zkp.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(AuthServer::new(auth))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = tonic::transport::Channel::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = AuthClient::new(channel);
+
+        let k = ZKP::generate_random_below(zkp.q());
+        let (r1, r2) = zkp.compute_pair(&k);
+        let start = authenticate_request::Step::Start(AuthenticationChallengeRequest {
+            user: "alice".to_string(),
+            r1: r1.to_bytes_be(),
+            r2: r2.to_bytes_be(),
+            challenge_bits: 0,
+            realm: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+            nonce: Vec::new(),
+            timestamp: 0,
+        });
+        let (sender, receiver) = mpsc::channel(2);
+        sender
+            .send(AuthenticateRequest { step: Some(start) })
+            .await
+            .unwrap();
+        let mut responses = client
+            .authenticate(ReceiverStream::new(receiver))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(authenticate_response::Step::Challenge(challenge)) = responses
+            .message()
+            .await
+            .unwrap()
+            .and_then(|response| response.step)
+        else {
+            panic!("Expected a challenge.");
+        };
+
+        let c = BigUint::from_bytes_be(&challenge.c);
+        let answer = authenticate_request::Step::Answer(AuthenticationAnswerRequest {
+            auth_id: String::new(),
+            s: zkp.solve(&k, &c, &x).to_bytes_be(),
+            totp_code: String::new(),
+            nonce: Vec::new(),
+            timestamp: 0,
+        });
+        sender
+            .send(AuthenticateRequest {
+                step: Some(answer.clone()),
+            })
+            .await
+            .unwrap();
+        let Some(authenticate_response::Step::Session(session)) = responses
+            .message()
+            .await
+            .unwrap()
+            .and_then(|response| response.step)
+        else {
+            panic!("Expected a session.");
+        };
+        assert!(!session.session_id.is_empty());
+        assert!(responses.message().await.unwrap().is_none());
+
+        // An answer before any challenge has nothing to answer.
+        let status = client
+            .authenticate(tokio_stream::iter([AuthenticateRequest {
+                step: Some(answer),
+            }]))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+}
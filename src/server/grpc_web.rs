@@ -0,0 +1,408 @@
+//! gRPC-Web, so browser provers can call the services directly with no proxy
+//! in front. Browsers can't read HTTP/2 trailers, a gRPC-Web response carries
+//! them as a last frame of the body instead, base64 encoded along with the
+//! rest in the text variant. Cross-origin calls are allowed per `Cors`.
+//!
+//! Browsers only make unary calls over gRPC-Web, so requests and responses
+//! are translated whole.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{BufMut, Bytes, BytesMut};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use http_body_util::{BodyExt, Full};
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+
+const GRPC: &str = "application/grpc";
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+/// Flags the frame of a gRPC-Web response that holds the trailers.
+const TRAILERS_FLAG: u8 = 0x80;
+
+/// Request headers a browser prover may send cross-origin.
+const ALLOW_HEADERS: &str = "content-type, x-grpc-web, x-user-agent, grpc-timeout, authorization";
+
+/// Response headers a cross-origin prover may read, the status of a
+/// trailers-only response among them.
+const EXPOSE_HEADERS: &str = "grpc-status, grpc-message, grpc-status-details-bin";
+
+/// Seconds a browser may cache a preflight answer.
+const MAX_AGE_SECS: &str = "86400";
+
+/// Origins whose pages may call the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Cors {
+    /// Same-origin pages only, no CORS headers are sent.
+    #[default]
+    SameOrigin,
+    Any,
+    Origins(Vec<HeaderValue>),
+}
+
+impl Cors {
+    /// "*" for any origin, or a comma separated list of them.
+    pub fn parse(origins: &str) -> Result<Self, http::header::InvalidHeaderValue> {
+        if origins.trim() == "*" {
+            return Ok(Self::Any);
+        }
+        let origins = origins
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(HeaderValue::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::Origins(origins))
+    }
+
+    /// The Access-Control-Allow-Origin answer to `origin`, None when it may
+    /// not call.
+    fn allow(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+        let origin = origin?;
+        match self {
+            Self::SameOrigin => None,
+            Self::Any => Some(HeaderValue::from_static("*")),
+            Self::Origins(origins) => origins.contains(origin).then(|| origin.clone()),
+        }
+    }
+
+    fn add_headers(&self, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+        if !matches!(self, Self::SameOrigin) {
+            headers.append(header::VARY, HeaderValue::from_static("origin"));
+        }
+        if let Some(allowed) = self.allow(origin) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSE_HEADERS),
+            );
+        }
+    }
+}
+
+/// How a gRPC-Web body is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Binary,
+    /// Base64, for clients that can't handle binary bodies.
+    Text,
+}
+
+impl Encoding {
+    /// None for anything that isn't a gRPC-Web request.
+    fn of(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        if content_type.starts_with(GRPC_WEB_TEXT) {
+            Some(Self::Text)
+        } else if content_type.starts_with(GRPC_WEB) {
+            Some(Self::Binary)
+        } else {
+            None
+        }
+    }
+
+    fn content_type(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Self::Binary => "application/grpc-web+proto",
+            Self::Text => "application/grpc-web-text+proto",
+        })
+    }
+}
+
+/// Serves gRPC-Web and CORS preflights next to plain gRPC, which passes
+/// through untouched.
+#[derive(Debug, Clone, Default)]
+pub struct GrpcWebLayer {
+    cors: Cors,
+}
+
+impl GrpcWebLayer {
+    pub fn new(cors: Cors) -> Self {
+        Self { cors }
+    }
+}
+
+impl<S> Layer<S> for GrpcWebLayer {
+    type Service = GrpcWebService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcWebService {
+            inner,
+            cors: self.cors.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcWebService<S> {
+    inner: S,
+    cors: Cors,
+}
+
+impl<S> Service<http::Request<BoxBody>> for GrpcWebService<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        // See `RateLimitService::call`.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let cors = self.cors.clone();
+        let origin = request.headers().get(header::ORIGIN).cloned();
+
+        if request.method() == Method::OPTIONS {
+            let mut response = http::Response::new(tonic::body::empty_body());
+            *response.status_mut() = StatusCode::NO_CONTENT;
+            cors.add_headers(origin.as_ref(), response.headers_mut());
+            if cors.allow(origin.as_ref()).is_some() {
+                let headers = response.headers_mut();
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    HeaderValue::from_static("POST"),
+                );
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_HEADERS,
+                    HeaderValue::from_static(ALLOW_HEADERS),
+                );
+                headers.insert(
+                    header::ACCESS_CONTROL_MAX_AGE,
+                    HeaderValue::from_static(MAX_AGE_SECS),
+                );
+            }
+            return Box::pin(async move { Ok(response) });
+        }
+
+        let Some(encoding) = Encoding::of(request.headers()) else {
+            return Box::pin(inner.call(request));
+        };
+
+        Box::pin(async move {
+            let response = match to_grpc(request, encoding).await {
+                Ok(request) => inner.call(request).await?,
+                Err(status) => status.into_http(),
+            };
+            let mut response = from_grpc(response, encoding).await;
+            cors.add_headers(origin.as_ref(), response.headers_mut());
+            Ok(response)
+        })
+    }
+}
+
+/// The plain gRPC request a gRPC-Web one stands for.
+async fn to_grpc(
+    request: http::Request<BoxBody>,
+    encoding: Encoding,
+) -> Result<http::Request<BoxBody>, Status> {
+    let (mut parts, body) = request.into_parts();
+    let mut body = body.collect().await?.to_bytes();
+    if encoding == Encoding::Text {
+        body = STANDARD
+            .decode(&body)
+            .map_err(|_| Status::invalid_argument("The body is not valid base64."))?
+            .into();
+    }
+
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(GRPC));
+    parts
+        .headers
+        .insert(header::TE, HeaderValue::from_static("trailers"));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.version = http::Version::HTTP_2;
+    Ok(http::Request::from_parts(
+        parts,
+        tonic::body::boxed(Full::new(body)),
+    ))
+}
+
+/// The gRPC-Web response to send for a plain gRPC one, trailers moved into
+/// the body.
+async fn from_grpc(
+    response: http::Response<BoxBody>,
+    encoding: Encoding,
+) -> http::Response<BoxBody> {
+    let (mut parts, body) = response.into_parts();
+    let mut bytes = BytesMut::new();
+    let trailers = match body.collect().await {
+        Ok(collected) => {
+            let trailers = collected.trailers().cloned();
+            bytes.put(collected.to_bytes());
+            trailers
+        }
+        // A body that breaks off still ends the call with a status.
+        Err(status) => {
+            let mut trailers = HeaderMap::new();
+            status
+                .add_header(&mut trailers)
+                .expect("A status always fits in headers.");
+            Some(trailers)
+        }
+    };
+    if let Some(trailers) = trailers {
+        bytes.put(trailers_frame(&trailers));
+    }
+
+    let body = match encoding {
+        Encoding::Binary => bytes.freeze(),
+        Encoding::Text => STANDARD.encode(&bytes).into(),
+    };
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, encoding.content_type());
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.version = http::Version::HTTP_11;
+    http::Response::from_parts(parts, tonic::body::boxed(Full::new(body)))
+}
+
+/// The trailers as the last frame of a gRPC-Web body, in HTTP/1 header
+/// syntax.
+fn trailers_frame(trailers: &HeaderMap) -> Bytes {
+    let mut block = BytesMut::new();
+    for (name, value) in trailers {
+        block.put(name.as_str().as_bytes());
+        block.put(&b":"[..]);
+        block.put(value.as_bytes());
+        block.put(&b"\r\n"[..]);
+    }
+
+    let mut frame = BytesMut::with_capacity(5 + block.len());
+    frame.put_u8(TRAILERS_FLAG);
+    frame.put_u32(block.len() as u32);
+    frame.put(block);
+    frame.freeze()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use prost::Message;
+
+    use super::*;
+    use crate::{
+        server::{grpc_impl::auth::auth_impl::AuthImpl, store::MemoryStore},
+        zkp_auth::{auth_server::AuthServer, ParametersRequest, ParametersResponse},
+        ZKP,
+    };
+
+    fn web_service(cors: Cors) -> GrpcWebService<AuthServer<AuthImpl<Arc<MemoryStore>>>> {
+        let zkp = ZKP::default();
+        let server_x = ZKP::generate_random_below(zkp.q());
+        let auth = AuthImpl::new(Arc::<MemoryStore>::default(), zkp, server_x);
+        GrpcWebLayer::new(cors).layer(AuthServer::new(auth))
+    }
+
+    fn get_parameters(content_type: &'static str, body: Bytes) -> http::Request<BoxBody> {
+        http::Request::post("/zkp_auth.Auth/GetParameters")
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ORIGIN, "https://app.example.com")
+            .body(tonic::body::boxed(Full::new(body)))
+            .unwrap()
+    }
+
+    /// A gRPC frame holding an empty ParametersRequest.
+    fn request_frame() -> Bytes {
+        let mut frame = BytesMut::new();
+        frame.put_u8(0);
+        frame.put_u32(ParametersRequest {}.encoded_len() as u32);
+        frame.freeze()
+    }
+
+    /// The message and the trailer block of a gRPC-Web response body.
+    fn split_frames(body: &[u8]) -> (ParametersResponse, String) {
+        let len = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+        let message = ParametersResponse::decode(&body[5..5 + len]).unwrap();
+        let trailers = &body[5 + len..];
+        assert_eq!(trailers[0], TRAILERS_FLAG);
+        (message, String::from_utf8(trailers[5..].to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_grpc_web_binary_and_text() {
+        let origins = Cors::parse("https://app.example.com, https://other.example.com").unwrap();
+        let mut service = web_service(origins);
+
+        let response = service
+            .call(get_parameters(
+                "application/grpc-web+proto",
+                request_frame(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let (parameters, trailers) = split_frames(&body);
+        assert!(parameters.group.is_some());
+        assert!(trailers.contains("grpc-status:0\r\n"));
+
+        let text = STANDARD.encode(request_frame());
+        let response = service
+            .call(get_parameters("application/grpc-web-text", text.into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/grpc-web-text+proto"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let (parameters, _) = split_frames(&STANDARD.decode(&body).unwrap());
+        assert!(parameters.group.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let preflight = |origin: &'static str| {
+            http::Request::options("/zkp_auth.Auth/GetParameters")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(tonic::body::empty_body())
+                .unwrap()
+        };
+
+        let mut service = web_service(Cors::parse("https://app.example.com").unwrap());
+        let response = service
+            .call(preflight("https://app.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "POST"
+        );
+        let response = service
+            .call(preflight("https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let mut service = web_service(Cors::SameOrigin);
+        let response = service
+            .call(preflight("https://app.example.com"))
+            .await
+            .unwrap();
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
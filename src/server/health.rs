@@ -0,0 +1,124 @@
+//! grpc.health.v1.Health for load balancers and Kubernetes probes. The
+//! server ("") and the Auth service report SERVING while the store answers,
+//! and NOT_SERVING when it doesn't or the server is shutting down.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+use tonic_health::{server::HealthReporter, ServingStatus};
+
+use crate::{
+    server::store::{SessionStore, UserStore},
+    zkp_auth::auth_server::SERVICE_NAME,
+};
+
+/// How often the store is checked.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Services whose status follows the store.
+const SERVICES: [&str; 2] = ["", SERVICE_NAME];
+
+/// Keeps the reported status in step with the store until `stopping` fires.
+pub async fn watch_store<S: UserStore + SessionStore>(
+    store: Arc<S>,
+    mut reporter: HealthReporter,
+    mut stopping: watch::Receiver<()>,
+) {
+    let mut probes = tokio::time::interval(PROBE_INTERVAL);
+    let mut reported = None;
+    loop {
+        tokio::select! {
+            _ = probes.tick() => {}
+            _ = stopping.changed() => break,
+        }
+        let status = match probe(store.as_ref()).await {
+            Ok(()) => ServingStatus::Serving,
+            Err(err) => {
+                log::warn!("Health: the store is unreachable: {err}");
+                ServingStatus::NotServing
+            }
+        };
+        if reported != Some(status) {
+            report(&mut reporter, status).await;
+            reported = Some(status);
+        }
+    }
+    report(&mut reporter, ServingStatus::NotServing).await;
+}
+
+async fn probe<S: UserStore + SessionStore>(
+    store: &S,
+) -> Result<(), crate::server::store::StoreError> {
+    UserStore::ping(store).await?;
+    SessionStore::ping(store).await
+}
+
+async fn report(reporter: &mut HealthReporter, status: ServingStatus) {
+    for service in SERVICES {
+        reporter.set_service_status(service, status).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Channel;
+    use tonic_health::pb::{
+        health_check_response::ServingStatus as Status, health_client::HealthClient,
+        HealthCheckRequest,
+    };
+
+    use super::*;
+    use crate::server::store::MemoryStore;
+
+    /// None while the service is still unknown to the health service, which
+    /// answers NotFound until the first report.
+    async fn status(client: &mut HealthClient<Channel>) -> Option<Status> {
+        let request = HealthCheckRequest {
+            service: SERVICE_NAME.to_string(),
+        };
+        let response = client.check(request).await.ok()?;
+        Some(response.into_inner().status())
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let (reporter, service) = tonic_health::server::health_reporter();
+        let (stop, stopping) = watch::channel(());
+        let watching = tokio::spawn(watch_store(
+            Arc::<MemoryStore>::default(),
+            reporter,
+            stopping,
+        ));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = Channel::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+
+        // The first probe runs right away, but not necessarily before the
+        // first check.
+        let mut serving = false;
+        for _ in 0..50 {
+            serving = status(&mut client).await == Some(Status::Serving);
+            if serving {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(serving);
+
+        stop.send(()).unwrap();
+        watching.await.unwrap();
+        assert_eq!(status(&mut client).await, Some(Status::NotServing));
+    }
+}
@@ -0,0 +1,214 @@
+//! Client IP allow and deny lists, checked in front of every gRPC service
+//! and the REST gateway before any handler runs. IP_ALLOWLIST, when set,
+//! lets only its addresses in, and IP_DENYLIST keeps its addresses out
+//! whatever the allowlist says. AuthAdmin UpdateIpDenylist changes the
+//! denylist at runtime, see `cluster` for how replicas follow.
+//!
+//! Callers over the Unix socket have no address and are always let in.
+
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use tonic::{body::BoxBody, transport::server::TcpConnectInfo, Status};
+use tower::{Layer, Service};
+
+/// `cidr` as a network, a bare address as the network of just that one.
+pub fn parse_cidr(cidr: &str) -> Result<IpNet, String> {
+    let cidr = cidr.trim();
+    cidr.parse::<IpNet>()
+        .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("{cidr} is not an IP address or CIDR block"))
+}
+
+/// The allowlist, fixed at startup, and the denylist, which isn't.
+#[derive(Debug, Default)]
+pub struct IpFilter {
+    /// Empty lets every address in.
+    allow: Vec<IpNet>,
+    deny: RwLock<Vec<IpNet>>,
+}
+
+impl IpFilter {
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> Self {
+        Self {
+            allow,
+            deny: RwLock::new(deny),
+        }
+    }
+
+    /// Whether `ip` may call, IPv4 addresses mapped into IPv6 taken as the
+    /// IPv4 ones.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip));
+        let denied = self
+            .deny
+            .read()
+            .unwrap()
+            .iter()
+            .any(|net| net.contains(&ip));
+        allowed && !denied
+    }
+
+    pub fn denylist(&self) -> Vec<IpNet> {
+        self.deny.read().unwrap().clone()
+    }
+
+    pub fn set_denylist(&self, deny: Vec<IpNet>) {
+        *self.deny.write().unwrap() = deny;
+    }
+}
+
+/// Refuses gRPC calls from addresses the filter doesn't allow.
+#[derive(Debug, Clone)]
+pub struct IpFilterLayer {
+    filter: Arc<IpFilter>,
+}
+
+impl IpFilterLayer {
+    pub fn new(filter: Arc<IpFilter>) -> Self {
+        Self { filter }
+    }
+}
+
+impl<S> Layer<S> for IpFilterLayer {
+    type Service = IpFilterService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpFilterService {
+            inner,
+            filter: self.filter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IpFilterService<S> {
+    inner: S,
+    filter: Arc<IpFilter>,
+}
+
+impl<S, B> Service<http::Request<B>> for IpFilterService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let denied = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr)
+            .filter(|addr| !self.filter.allows(addr.ip()));
+        Box::pin(async move {
+            match denied {
+                Some(addr) => Ok(not_allowed(addr.ip()).into_http()),
+                None => inner.call(request).await,
+            }
+        })
+    }
+}
+
+/// The REST gateway's counterpart of `IpFilterLayer`.
+pub async fn http(
+    State(filter): State<Arc<IpFilter>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match client {
+        Some(ConnectInfo(addr)) if !filter.allows(addr.ip()) => {
+            let status = not_allowed(addr.ip());
+            (http::StatusCode::FORBIDDEN, status.message().to_string()).into_response()
+        }
+        _ => next.run(request).await,
+    }
+}
+
+fn not_allowed(ip: IpAddr) -> Status {
+    log::warn!("IP: {ip} is not allowed, call refused.");
+    Status::permission_denied("Calls from this address are not allowed.")
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    fn nets(cidrs: &[&str]) -> Vec<IpNet> {
+        cidrs.iter().map(|cidr| parse_cidr(cidr).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_allows() {
+        assert_eq!(parse_cidr(" 10.0.0.1 ").unwrap().to_string(), "10.0.0.1/32");
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("localhost").is_err());
+
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        assert!(IpFilter::default().allows(ip("203.0.113.7")));
+
+        let filter = IpFilter::new(
+            nets(&["10.0.0.0/8", "2001:db8::/32"]),
+            nets(&["10.1.0.0/16"]),
+        );
+        assert!(filter.allows(ip("10.2.3.4")));
+        assert!(filter.allows(ip("::ffff:10.2.3.4")));
+        assert!(filter.allows(ip("2001:db8::1")));
+        assert!(!filter.allows(ip("10.1.2.3")));
+        assert!(!filter.allows(ip("203.0.113.7")));
+
+        filter.set_denylist(Vec::new());
+        assert!(filter.allows(ip("10.1.2.3")));
+    }
+
+    #[tokio::test]
+    async fn test_layer() {
+        let handler = tower::service_fn(|_: http::Request<()>| async {
+            Ok::<_, Infallible>(http::Response::new(BoxBody::default()))
+        });
+        let filter = Arc::new(IpFilter::new(Vec::new(), nets(&["10.0.0.0/8"])));
+        let mut service = IpFilterLayer::new(filter).layer(handler);
+
+        let call = |addr: Option<&str>| {
+            let mut request = http::Request::post("/zkp_auth.Auth/GetSalt")
+                .body(())
+                .unwrap();
+            request.extensions_mut().insert(TcpConnectInfo {
+                local_addr: None,
+                remote_addr: addr.map(|addr| addr.parse().unwrap()),
+            });
+            request
+        };
+        let response = service.call(call(Some("10.0.0.1:5000"))).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], "7");
+        let response = service.call(call(Some("192.0.2.1:5000"))).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+        // Unix socket callers.
+        let response = service.call(call(None)).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+    }
+}
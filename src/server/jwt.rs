@@ -0,0 +1,90 @@
+//! Session ids as signed JWTs, so a resource server holding the key can check
+//! a session without asking this server.
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::{server::store::Session, ZKP};
+
+/// What a session token says, in the registered claim names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// The user the session belongs to.
+    pub sub: String,
+    /// Unix time in seconds of the login.
+    pub iat: u64,
+    /// Unix time in seconds from which the token is refused.
+    pub exp: u64,
+    /// Random, two sessions of one user in the same second still differ.
+    pub jti: String,
+}
+
+/// Signs session tokens with HS256 under a shared secret.
+#[derive(Clone)]
+pub struct JwtIssuer {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl std::fmt::Debug for JwtIssuer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtIssuer").finish_non_exhaustive()
+    }
+}
+
+impl JwtIssuer {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn issue(&self, session: &Session) -> String {
+        let claims = Claims {
+            sub: session.user_name.clone(),
+            iat: session.created_at,
+            exp: session.expires_at,
+            jti: ZKP::generate_random_string(12),
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)
+            .expect("HS256 signing doesn't fail.")
+    }
+
+    /// The claims of a token signed with this key and not expired yet.
+    pub fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        Ok(jsonwebtoken::decode(token, &self.decoding, &validation)?.claims)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::store::unix_now;
+
+    #[test]
+    fn test_jwt_roundtrip() {
+        let issuer = JwtIssuer::new(b"secret");
+        let session = Session {
+            user_name: "alice".to_string(),
+            created_at: unix_now(),
+            expires_at: unix_now() + 60,
+            device: None,
+            last_seen: unix_now(),
+        };
+        let token = issuer.issue(&session);
+
+        let claims = issuer.verify(&token).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.exp, session.expires_at);
+        assert!(JwtIssuer::new(b"other secret").verify(&token).is_err());
+
+        let expired = Session {
+            expires_at: unix_now() - 1,
+            ..session
+        };
+        assert!(issuer.verify(&issuer.issue(&expired)).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Public keys handed to `server add-user`, for registering a user from
+//! the server's side, e.g. one whose key was made offline. A key file is
+//! TOML with the user's y1 and y2 and the salt their secret is derived
+//! with, in hex, and the Argon2id costs when not the defaults:
+//!
+//! ```toml
+//! y1 = "5c1a..."
+//! y2 = "0e7b..."
+//! salt = "9f3d..."
+//! # m_cost = 19456
+//! # t_cost = 2
+//! # p_cost = 1
+//! ```
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    server::{
+        grpc_impl::auth::auth_impl::MAX_SALT_LEN,
+        store::{unix_now, UserInfo},
+    },
+    KdfParams, SALT_LEN, ZKP,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyFile {
+    pub y1: String,
+    pub y2: String,
+    pub salt: String,
+    pub m_cost: Option<u32>,
+    pub t_cost: Option<u32>,
+    pub p_cost: Option<u32>,
+}
+
+impl KeyFile {
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        toml::from_str(toml).context("Bad key file")
+    }
+
+    /// The user `user_name` with this key, checked as Register checks
+    /// keys in `zkp`, the server's group.
+    pub fn to_user(&self, user_name: &str, zkp: &ZKP) -> anyhow::Result<UserInfo> {
+        let element = |name, value: &str| {
+            let bytes = hex::decode(value.trim()).with_context(|| format!("{name} is not hex"))?;
+            zkp.parse_element(&bytes)
+                .with_context(|| format!("{name} is not a key in the server's group"))
+        };
+        let salt = hex::decode(self.salt.trim()).context("salt is not hex")?;
+        anyhow::ensure!(
+            (SALT_LEN..=MAX_SALT_LEN).contains(&salt.len()),
+            "salt must be {SALT_LEN} to {MAX_SALT_LEN} bytes"
+        );
+        let defaults = KdfParams::default();
+        Ok(UserInfo {
+            user_name: user_name.to_string(),
+            y1: element("y1", &self.y1)?,
+            y2: element("y2", &self.y2)?,
+            salt,
+            kdf_params: KdfParams {
+                m_cost: self.m_cost.unwrap_or(defaults.m_cost),
+                t_cost: self.t_cost.unwrap_or(defaults.t_cost),
+                p_cost: self.p_cost.unwrap_or(defaults.p_cost),
+            },
+            group: None,
+            created_at: unix_now(),
+            last_login: None,
+            metadata: BTreeMap::new(),
+            admin: false,
+            totp_secret: None,
+            devices: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::*;
+
+    #[test]
+    fn test_to_user() {
+        let zkp = ZKP::default();
+        let (y1, y2) = zkp.compute_pair(&BigUint::from(42u32));
+        let toml = format!(
+            "y1 = \"{}\"\ny2 = \"{}\"\nsalt = \"{}\"\nt_cost = 3\n",
+            hex::encode(y1.to_bytes_be()),
+            hex::encode(y2.to_bytes_be()),
+            hex::encode([7; SALT_LEN]),
+        );
+        let user = KeyFile::from_toml(&toml)
+            .unwrap()
+            .to_user("alice", &zkp)
+            .unwrap();
+        assert_eq!((&user.y1, &user.y2), (&y1, &y2));
+        assert_eq!(user.kdf_params.t_cost, 3);
+        assert_eq!(user.kdf_params.m_cost, KdfParams::default().m_cost);
+
+        let bad = [
+            toml.replace(&hex::encode(y2.to_bytes_be()), "02"),
+            toml.replace(&hex::encode([7; SALT_LEN]), "0707"),
+            toml.replace("t_cost", "cost"),
+        ];
+        for toml in bad {
+            let user = KeyFile::from_toml(&toml).and_then(|file| file.to_user("alice", &zkp));
+            assert!(user.is_err(), "{toml}");
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Load shedding: with MAX_CONCURRENT_CALLS set, calls beyond that many in
+//! flight are refused at once with UNAVAILABLE, for the client to retry,
+//! instead of queueing behind a burst of 2048-bit verifications and making
+//! everyone wait.
+
+use tonic::{body::BoxBody, Status};
+use tower::{
+    layer::util::{Identity, Stack},
+    limit::GlobalConcurrencyLimitLayer,
+    load_shed::{error::Overloaded, LoadShedLayer},
+    util::MapResultLayer,
+    BoxError, ServiceBuilder,
+};
+
+type Answer = Result<http::Response<BoxBody>, BoxError>;
+
+/// tower's layers, which refuse calls past the limit with `Overloaded`, and
+/// the answer a caller gets for one.
+pub type LoadShed = Stack<
+    GlobalConcurrencyLimitLayer,
+    Stack<LoadShedLayer, Stack<MapResultLayer<fn(Answer) -> Answer>, Identity>>,
+>;
+
+/// At most `max_calls` calls at once, across every connection.
+pub fn layer(max_calls: usize) -> LoadShed {
+    ServiceBuilder::new()
+        .map_result(overloaded as fn(Answer) -> Answer)
+        .load_shed()
+        .layer(GlobalConcurrencyLimitLayer::new(max_calls))
+        .into_inner()
+}
+
+/// Answers UNAVAILABLE for a shed call, tonic would drop the connection.
+fn overloaded(answer: Answer) -> Answer {
+    answer.or_else(|err| {
+        if !err.is::<Overloaded>() {
+            return Err(err);
+        }
+        log::warn!("Overloaded, refusing a call.");
+        Ok(Status::unavailable("The server is overloaded, try again later.").into_http())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::Infallible, time::Duration};
+
+    use tokio::sync::oneshot;
+    use tower::{Layer, ServiceExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_shed() {
+        let (release, released) = oneshot::channel::<()>();
+        let released = std::sync::Arc::new(std::sync::Mutex::new(Some(released)));
+        let handler = tower::service_fn(move |_: http::Request<()>| {
+            let released = released.lock().unwrap().take();
+            async move {
+                // The first call waits to be released, the others don't.
+                if let Some(released) = released {
+                    let _ = released.await;
+                }
+                Ok::<_, Infallible>(http::Response::new(BoxBody::default()))
+            }
+        });
+        let service = layer(1).layer(handler);
+
+        let first = tokio::spawn(service.clone().oneshot(http::Request::new(())));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let shed = service.clone().oneshot(http::Request::new(())).await;
+        assert_eq!(shed.unwrap().headers()["grpc-status"], "14");
+
+        release.send(()).unwrap();
+        let first = first.await.unwrap().unwrap();
+        assert!(first.headers().get("grpc-status").is_none());
+        let next = service.oneshot(http::Request::new(())).await.unwrap();
+        assert!(next.headers().get("grpc-status").is_none());
+    }
+}
@@ -0,0 +1,447 @@
+//! Prometheus metrics, served as text at /metrics on METRICS_ADDR, and as
+//! JSON by AuthAdmin GetMetrics for deployments without Prometheus.
+//! `AuthImpl` counts registrations, challenges and verifications by tenant
+//! (the realm) and parameter set, `MetricsLayer` times every gRPC call, and
+//! the user and session gauges are read from the store on each scrape.
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use prometheus::{
+    proto::MetricType, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use serde_json::{json, Map, Value};
+use tonic::body::BoxBody;
+use tower::{Layer, Service};
+
+use crate::{
+    server::store::{SessionStore, StoreError, UserStore},
+    split_qualified_name, ParamSet, ZKP,
+};
+
+/// Only calls to these services are timed, so unknown paths can't add
+/// labels without bound.
+const TIMED_PREFIXES: &[&str] = &["/zkp_auth.", "/grpc.health."];
+
+/// Realms that get a tenant label of their own, the first ones counted.
+/// Later ones share `OTHER_TENANTS`.
+pub const MAX_TENANT_LABELS: usize = 100;
+
+/// Tenant label of the realms past `MAX_TENANT_LABELS`, which no realm is
+/// named.
+pub const OTHER_TENANTS: &str = "(other)";
+
+/// Parameter set label of groups that aren't a named `ParamSet`.
+const CUSTOM_PARAM_SET: &str = "custom";
+
+pub struct Metrics {
+    registry: Registry,
+    /// By tenant and param_set, as are the other counters.
+    registrations: IntCounterVec,
+    challenges: IntCounterVec,
+    /// Also by result, "success" or "failure".
+    verifications: IntCounterVec,
+    sessions: IntGauge,
+    users: IntGauge,
+    /// By method, the gRPC path. Streaming calls count until the response
+    /// headers.
+    rpc_duration: HistogramVec,
+    /// Realms with a tenant label of their own.
+    tenants: Mutex<HashSet<String>>,
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registrations = IntCounterVec::new(
+            Opts::new("zkp_registrations_total", "Users registered."),
+            &["tenant", "param_set"],
+        )
+        .expect("The name is valid.");
+        let challenges = IntCounterVec::new(
+            Opts::new(
+                "zkp_challenges_issued_total",
+                "Login challenges handed out.",
+            ),
+            &["tenant", "param_set"],
+        )
+        .expect("The name is valid.");
+        let verifications = IntCounterVec::new(
+            Opts::new("zkp_verifications_total", "Answers to login challenges."),
+            &["tenant", "param_set", "result"],
+        )
+        .expect("The name is valid.");
+        let sessions = IntGauge::new("zkp_active_sessions", "Sessions that haven't expired.")
+            .expect("The name is valid.");
+        let users =
+            IntGauge::new("zkp_stored_users", "Users in the store.").expect("The name is valid.");
+        let rpc_duration = HistogramVec::new(
+            HistogramOpts::new("zkp_rpc_duration_seconds", "Time taken by gRPC calls."),
+            &["method"],
+        )
+        .expect("The name is valid.");
+
+        let registry = Registry::new();
+        for collector in [
+            Box::new(registrations.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(challenges.clone()),
+            Box::new(verifications.clone()),
+            Box::new(sessions.clone()),
+            Box::new(users.clone()),
+            Box::new(rpc_duration.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("Each metric is registered once.");
+        }
+
+        Self {
+            registry,
+            registrations,
+            challenges,
+            verifications,
+            sessions,
+            users,
+            rpc_duration,
+            tenants: Mutex::default(),
+        }
+    }
+
+    /// Counts a registration of `user_name`, a qualified name, in `zkp`.
+    pub fn registered(&self, user_name: &str, zkp: &ZKP) {
+        let (tenant, param_set) = self.labels(user_name, zkp);
+        self.registrations
+            .with_label_values(&[tenant, param_set])
+            .inc();
+    }
+
+    pub fn challenge_issued(&self, user_name: &str, zkp: &ZKP) {
+        let (tenant, param_set) = self.labels(user_name, zkp);
+        self.challenges
+            .with_label_values(&[tenant, param_set])
+            .inc();
+    }
+
+    /// Counts an answer to a login challenge.
+    pub fn verification(&self, user_name: &str, zkp: &ZKP, success: bool) {
+        let (tenant, param_set) = self.labels(user_name, zkp);
+        let result = if success { "success" } else { "failure" };
+        self.verifications
+            .with_label_values(&[tenant, param_set, result])
+            .inc();
+    }
+
+    /// Tenant and param_set of a user, at most `MAX_TENANT_LABELS` + 1
+    /// tenants and one more param_set than there are `ParamSet`s.
+    fn labels<'a>(&self, user_name: &'a str, zkp: &ZKP) -> (&'a str, &'static str) {
+        let (realm, _) = split_qualified_name(user_name);
+        let tenant = {
+            let mut tenants = self.tenants.lock().expect("No lock holder panics.");
+            if tenants.contains(realm) {
+                realm
+            } else if tenants.len() < MAX_TENANT_LABELS {
+                tenants.insert(realm.to_string());
+                realm
+            } else {
+                OTHER_TENANTS
+            }
+        };
+        let param_set = ParamSet::of(&zkp.constants()).map_or(CUSTOM_PARAM_SET, |set| set.name());
+        (tenant, param_set)
+    }
+
+    /// Reads the gauges from the store.
+    pub async fn refresh<S: UserStore + SessionStore>(&self, store: &S) -> Result<(), StoreError> {
+        self.users.set(store.count_users().await? as i64);
+        self.sessions.set(store.count_sessions().await? as i64);
+        Ok(())
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut text = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut text)
+            .expect("Writing to a Vec doesn't fail.");
+        String::from_utf8(text).expect("The text format is UTF-8.")
+    }
+
+    /// Every metric as a JSON object, by name a list of its series: their
+    /// labels and value, or count and sum for histograms.
+    pub fn snapshot(&self) -> Value {
+        let mut metrics = Map::new();
+        for family in self.registry.gather() {
+            let series = family
+                .get_metric()
+                .iter()
+                .map(|metric| {
+                    let labels: Map<String, Value> = metric
+                        .get_label()
+                        .iter()
+                        .map(|label| (label.get_name().to_string(), label.get_value().into()))
+                        .collect();
+                    match family.get_field_type() {
+                        MetricType::COUNTER => {
+                            json!({ "labels": labels, "value": metric.get_counter().get_value() })
+                        }
+                        MetricType::GAUGE => {
+                            json!({ "labels": labels, "value": metric.get_gauge().get_value() })
+                        }
+                        _ => {
+                            let histogram = metric.get_histogram();
+                            json!({
+                                "labels": labels,
+                                "count": histogram.get_sample_count(),
+                                "sum": histogram.get_sample_sum(),
+                            })
+                        }
+                    }
+                })
+                .collect();
+            metrics.insert(family.get_name().to_string(), Value::Array(series));
+        }
+        Value::Object(metrics)
+    }
+}
+
+/// GET /metrics, for a Prometheus server to scrape.
+pub fn router<S: UserStore + SessionStore>(metrics: Arc<Metrics>, store: Arc<S>) -> Router {
+    Router::new()
+        .route("/metrics", get(scrape::<S>))
+        .with_state((metrics, store))
+}
+
+async fn scrape<S: UserStore + SessionStore>(
+    State((metrics, store)): State<(Arc<Metrics>, Arc<S>)>,
+) -> (StatusCode, String) {
+    // The gauges keep their last values when the store is down.
+    if let Err(err) = metrics.refresh(store.as_ref()).await {
+        log::warn!("Metrics: can't count users and sessions: {err}");
+    }
+    (StatusCode::OK, metrics.encode())
+}
+
+/// Times every gRPC call by method.
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> Service<http::Request<B>> for MetricsService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let path = request.uri().path().to_string();
+        let timed = TIMED_PREFIXES.iter().any(|prefix| path.starts_with(prefix));
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+
+        Box::pin(async move {
+            let response = inner.call(request).await?;
+            // grpc-status 12 is UNIMPLEMENTED, a method that doesn't exist.
+            let unimplemented = response
+                .headers()
+                .get("grpc-status")
+                .is_some_and(|status| status == "12");
+            if timed && !unimplemented {
+                metrics
+                    .rpc_duration
+                    .with_label_values(&[&path])
+                    .observe(started.elapsed().as_secs_f64());
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        server::{grpc_impl::auth::auth_impl::AuthImpl, store::MemoryStore},
+        zkp_auth::{
+            auth_server::Auth, AuthenticationAnswerRequest, AuthenticationChallengeRequest,
+            RegisterRequest,
+        },
+        ZKP,
+    };
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let zkp = ZKP::default();
+        let store = Arc::<MemoryStore>::default();
+        let metrics = Arc::new(Metrics::new());
+        let auth = AuthImpl::new(
+            store.clone(),
+            zkp.clone(),
+            ZKP::generate_random_below(zkp.q()),
+        )
+        .with_metrics(metrics.clone());
+
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        auth.register(tonic::Request::new(RegisterRequest {
+            name: "alice".to_string(),
+            realm: "acme".to_string(),
+            y1: y1.to_bytes_be(),
+            y2: y2.to_bytes_be(),
+            salt: vec![0; 16],
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+        for right in [false, true] {
+            let k = ZKP::generate_random_below(zkp.q());
+            let (r1, r2) = zkp.compute_pair(&k);
+            let challenge = auth
+                .create_authentication_challenge(tonic::Request::new(
+                    AuthenticationChallengeRequest {
+                        user: "alice".to_string(),
+                        realm: "acme".to_string(),
+                        r1: r1.to_bytes_be(),
+                        r2: r2.to_bytes_be(),
+                        ..Default::default()
+                    },
+                ))
+                .await
+                .unwrap()
+                .into_inner();
+            let c = BigUint::from_bytes_be(&challenge.c);
+            let s = if right {
+                zkp.solve(&k, &c, &x)
+            } else {
+                zkp.solve(&k, &c, &(&x + 1u32))
+            };
+            let answered = auth
+                .verify_authentication(tonic::Request::new(AuthenticationAnswerRequest {
+                    auth_id: challenge.auth_id,
+                    s: s.to_bytes_be(),
+                    totp_code: String::new(),
+                    nonce: Vec::new(),
+                    timestamp: 0,
+                }))
+                .await;
+            assert_eq!(answered.is_ok(), right);
+        }
+
+        let request = http::Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = router(metrics.clone(), store).call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        let param_set = ParamSet::of(&zkp.constants()).unwrap().name();
+        for line in [
+            format!("zkp_registrations_total{{param_set=\"{param_set}\",tenant=\"acme\"}} 1"),
+            format!(
+                "zkp_challenges_issued_total{{param_set=\"{param_set}\",tenant=\"acme\"}} 2"
+            ),
+            format!(
+                "zkp_verifications_total{{param_set=\"{param_set}\",result=\"failure\",tenant=\"acme\"}} 1"
+            ),
+            format!(
+                "zkp_verifications_total{{param_set=\"{param_set}\",result=\"success\",tenant=\"acme\"}} 1"
+            ),
+            "zkp_stored_users 1".to_string(),
+            "zkp_active_sessions 1".to_string(),
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{line} missing from {text}"
+            );
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            snapshot["zkp_registrations_total"],
+            json!([{
+                "labels": { "tenant": "acme", "param_set": param_set },
+                "value": 1.0,
+            }])
+        );
+        assert_eq!(snapshot["zkp_stored_users"][0]["value"], 1.0);
+    }
+
+    #[test]
+    fn test_tenant_labels_are_bounded() {
+        let metrics = Metrics::new();
+        let zkp = ZKP::default();
+        for i in 0..MAX_TENANT_LABELS + 10 {
+            metrics.challenge_issued(&format!("realm{i}/alice"), &zkp);
+        }
+        metrics.challenge_issued("realm0/bob", &zkp);
+
+        let series = metrics.snapshot()["zkp_challenges_issued_total"].clone();
+        assert_eq!(series.as_array().unwrap().len(), MAX_TENANT_LABELS + 1);
+        let count = |tenant: &str| {
+            series
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|series| series["labels"]["tenant"] == tenant)
+                .map(|series| series["value"].clone())
+        };
+        assert_eq!(count("realm0"), Some(json!(2.0)));
+        assert_eq!(count(OTHER_TENANTS), Some(json!(10.0)));
+    }
+}
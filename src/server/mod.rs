@@ -0,0 +1,39 @@
+//! The Auth server, for applications that want it inside their own tonic
+//! server rather than running the `server` binary. `AuthService` builds the
+//! Auth service over a store and a group, to mount next to the
+//! application's services or to serve on its own. Everything the binary
+//! wires around it, the admin service, the REST gateway, rate limits and
+//! the rest, is here too for those who want more than the basics.
+
+// tonic::Status is large, but it is what every handler returns anyway.
+#![allow(clippy::result_large_err)]
+
+pub mod audit;
+pub mod backoff;
+pub mod backup;
+pub mod cleanup;
+pub mod cluster;
+pub mod deadline;
+pub mod events;
+pub mod grpc_impl;
+pub mod grpc_web;
+pub mod health;
+pub mod ip_filter;
+pub mod jwt;
+pub mod key_file;
+pub mod load_shed;
+pub mod metrics;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod panic;
+pub mod rate_limit;
+pub mod replay;
+pub mod rest;
+pub mod retention;
+pub mod service;
+pub mod store;
+pub mod systemd;
+pub mod telemetry;
+pub mod webhook;
+
+pub use service::{AuthService, AuthServiceBuilder, ServeError};
@@ -0,0 +1,143 @@
+//! Auth events published to NATS, built with `--features nats` and on when
+//! NATS_URL is set. Each event is a JSON message on NATS_SUBJECT and its
+//! name, `zkp.auth.auth_failure` say, so other systems can subscribe to
+//! `zkp.auth.>` or the events they care about.
+
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::{
+    server::{events::AuthEvents, grpc_impl::auth::auth_impl::Peer, store::unix_now},
+    AuthId,
+};
+
+/// Subject events are published under unless configured otherwise.
+pub const DEFAULT_SUBJECT: &str = "zkp.auth";
+
+#[derive(Debug, Clone)]
+pub struct NatsConfig {
+    pub url: String,
+    pub subject: String,
+}
+
+/// One event, the body of a message.
+#[derive(Debug, Serialize)]
+struct Message<'a> {
+    event: &'static str,
+    /// Unix time in seconds.
+    time: u64,
+    user: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_id: Option<&'a str>,
+    source_ip: Option<IpAddr>,
+}
+
+/// Publishes events as `AuthImpl` reports them.
+#[derive(Debug)]
+pub struct NatsEvents {
+    client: async_nats::Client,
+    subject: String,
+}
+
+impl NatsEvents {
+    pub async fn connect(config: &NatsConfig) -> anyhow::Result<Self> {
+        let client = async_nats::connect(&config.url).await?;
+        Ok(Self {
+            client,
+            subject: config.subject.clone(),
+        })
+    }
+
+    /// Sends the messages still buffered, before the server exits.
+    pub async fn flush(&self) {
+        if let Err(err) = self.client.flush().await {
+            log::warn!("Can't send the last events to NATS: {err}");
+        }
+    }
+
+    /// Publishes `message` on the subject of its event. The client buffers
+    /// while NATS is away, and a full buffer waits, so the wait is left to
+    /// a task of its own.
+    fn publish(&self, message: Message) {
+        let subject = format!("{}.{}", self.subject, message.event);
+        let body = serde_json::to_vec(&message).expect("The message serializes.");
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            if let Err(err) = client.publish(subject.clone(), body.into()).await {
+                log::warn!("Can't publish to {subject}: {err}");
+            }
+        });
+    }
+}
+
+#[tonic::async_trait]
+impl AuthEvents for NatsEvents {
+    async fn on_registered(&self, user: &str, peer: &Peer) {
+        self.publish(Message {
+            event: "registered",
+            time: unix_now(),
+            user,
+            auth_id: None,
+            source_ip: peer.ip,
+        });
+    }
+
+    async fn on_challenge_issued(&self, user: &str, auth_id: &AuthId, peer: &Peer) {
+        self.publish(Message {
+            event: "challenge_issued",
+            time: unix_now(),
+            user,
+            auth_id: Some(auth_id.as_str()),
+            source_ip: peer.ip,
+        });
+    }
+
+    async fn on_auth_success(&self, user: &str, auth_id: &AuthId, peer: &Peer) {
+        self.publish(Message {
+            event: "auth_success",
+            time: unix_now(),
+            user,
+            auth_id: Some(auth_id.as_str()),
+            source_ip: peer.ip,
+        });
+    }
+
+    async fn on_auth_failure(&self, user: &str, auth_id: &AuthId, peer: &Peer) {
+        self.publish(Message {
+            event: "auth_failure",
+            time: unix_now(),
+            user,
+            auth_id: Some(auth_id.as_str()),
+            source_ip: peer.ip,
+        });
+    }
+}
+
+/// Whether `subject` can be published to: dot separated tokens, none of
+/// them empty or a wildcard.
+pub fn is_subject(subject: &str) -> bool {
+    subject.split('.').all(|token| {
+        !token.is_empty()
+            && token
+                .chars()
+                .all(|c| !c.is_whitespace() && c != '*' && c != '>')
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_subject() {
+        assert!(is_subject(DEFAULT_SUBJECT));
+        assert!(is_subject("acme-prod.auth_events"));
+        assert!(!is_subject(""));
+        assert!(!is_subject("zkp..auth"));
+        assert!(!is_subject("zkp.auth."));
+        assert!(!is_subject("zkp.*"));
+        assert!(!is_subject("zkp.>"));
+        assert!(!is_subject("zkp auth"));
+    }
+}
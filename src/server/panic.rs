@@ -0,0 +1,126 @@
+//! A panic in a handler, one of the `expect`s of byte handling say, is
+//! answered INTERNAL with the request ID to quote, instead of taking the
+//! caller's connection down with every other call on it. The panic hook
+//! logs each panic with its backtrace, under the span of its call.
+
+use std::{
+    backtrace::Backtrace,
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+
+use crate::server::telemetry;
+
+/// Logs panics as errors, with their backtrace, in place of the default
+/// hook's line on stderr.
+pub fn install_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::force_capture();
+        log::error!("{info}\n{backtrace}");
+    }));
+}
+
+/// Answers INTERNAL for a gRPC call whose handler panics.
+#[derive(Debug, Clone, Default)]
+pub struct CatchPanicLayer;
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanic<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CatchPanic { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct CatchPanic<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for CatchPanic<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            // The handler may panic making its future as well as polling it.
+            let Ok(handler) = catch_unwind(AssertUnwindSafe(|| inner.call(request))) else {
+                return Ok(internal());
+            };
+            let mut handler = std::pin::pin!(handler);
+            std::future::poll_fn(|cx| {
+                catch_unwind(AssertUnwindSafe(|| handler.as_mut().poll(cx)))
+                    .unwrap_or_else(|_| Poll::Ready(Ok(internal())))
+            })
+            .await
+        })
+    }
+}
+
+/// The answer to a call that panicked, naming it for the logs.
+fn internal() -> http::Response<BoxBody> {
+    let message = match telemetry::request_id() {
+        Some(request_id) => format!("Internal error, request ID {request_id}."),
+        None => "Internal error.".to_string(),
+    };
+    Status::internal(message).into_http()
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use tracing::Instrument;
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_catch_panic() {
+        let _subscriber = tracing_subscriber::registry()
+            .with(telemetry::RequestIds)
+            .set_default();
+        let handler = tower::service_fn(|request: http::Request<()>| async move {
+            assert_eq!(request.uri().path(), "/zkp_auth.Auth/GetSalt", "Bad path");
+            Ok::<_, Infallible>(http::Response::new(BoxBody::default()))
+        });
+        let mut service = CatchPanicLayer.layer(handler);
+
+        let request = http::Request::post("/zkp_auth.Auth/Register")
+            .header("x-request-id", "req-1")
+            .body(())
+            .unwrap();
+        let span = telemetry::rpc_span(&request);
+        let response = service.call(request).instrument(span).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], "13");
+        assert!(response.headers()["grpc-message"]
+            .to_str()
+            .unwrap()
+            .contains("req-1"));
+
+        // The service goes on answering.
+        let request = http::Request::post("/zkp_auth.Auth/GetSalt")
+            .body(())
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+    }
+}
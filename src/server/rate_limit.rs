@@ -0,0 +1,262 @@
+//! Request budgets per client IP and per user for the RPCs an attacker would
+//! hammer: registration, challenges, answers and key rotation. The per-IP limit is a tower
+//! layer in front of every service, the per-user one is checked in
+//! `AuthImpl` once the user name is known.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+#[cfg(feature = "redis")]
+use redis::aio::ConnectionManager;
+use tonic::{body::BoxBody, transport::server::TcpConnectInfo, Code, Status};
+use tower::{Layer, Service};
+
+#[cfg(feature = "redis")]
+use crate::server::store::unix_now;
+use crate::{server::store::StoreError, status_with_error_code, zkp_auth::ErrorCode};
+
+/// RPCs that count against the per-IP budget.
+const LIMITED_PATHS: &[&str] = &[
+    "/zkp_auth.Auth/Register",
+    "/zkp_auth.Auth/CreateAuthenticationChallenge",
+    "/zkp_auth.Auth/CommitChallenge",
+    "/zkp_auth.Auth/VerifyAuthentication",
+    "/zkp_auth.Auth/Authenticate",
+    "/zkp_auth.Auth/UpdateCredentials",
+];
+
+/// `requests` per `period` per key, all of which may come at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+#[tonic::async_trait]
+pub trait RateLimiter: std::fmt::Debug + Send + Sync + 'static {
+    /// Takes one request from the budget of `key`, false when it is used up.
+    async fn acquire(&self, key: &str) -> Result<bool, StoreError>;
+
+    /// Changes the budget of every key from the next request on.
+    fn set_limit(&self, limit: RateLimit);
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets in process memory, for a single server.
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RwLock<RateLimit>,
+    buckets: DashMap<String, Bucket>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit: RwLock::new(limit),
+            buckets: DashMap::new(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl RateLimiter for TokenBucket {
+    async fn acquire(&self, key: &str) -> Result<bool, StoreError> {
+        let limit = *self.limit.read().unwrap();
+        let capacity = f64::from(limit.requests);
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        let refill = now.duration_since(bucket.updated).as_secs_f64() / limit.period.as_secs_f64()
+            * capacity;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Ok(false);
+        }
+        bucket.tokens -= 1.0;
+        Ok(true)
+    }
+
+    fn set_limit(&self, limit: RateLimit) {
+        *self.limit.write().unwrap() = limit;
+    }
+}
+
+/// Fixed windows counted in Redis, so server replicas share one budget.
+#[cfg(feature = "redis")]
+pub struct RedisRateLimiter {
+    limit: RwLock<RateLimit>,
+    connection: ConnectionManager,
+}
+
+#[cfg(feature = "redis")]
+impl std::fmt::Debug for RedisRateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisRateLimiter")
+            .field("limit", &self.limit)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "redis")]
+impl RedisRateLimiter {
+    pub async fn connect(url: &str, limit: RateLimit) -> Result<Self, StoreError> {
+        let client = redis::Client::open(url).map_err(StoreError::backend)?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(Self {
+            limit: RwLock::new(limit),
+            connection,
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[tonic::async_trait]
+impl RateLimiter for RedisRateLimiter {
+    async fn acquire(&self, key: &str) -> Result<bool, StoreError> {
+        let limit = *self.limit.read().unwrap();
+        let period = limit.period.as_secs().max(1);
+        let window = unix_now() / period;
+        let key = format!("zkp_auth:rate:{key}:{window}");
+
+        let mut connection = self.connection.clone();
+        let (count,): (u32,) = redis::pipe()
+            .atomic()
+            .incr(&key, 1)
+            .expire(&key, period as i64)
+            .ignore()
+            .query_async(&mut connection)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(count <= limit.requests)
+    }
+
+    fn set_limit(&self, limit: RateLimit) {
+        *self.limit.write().unwrap() = limit;
+    }
+}
+
+/// Applies the per-IP budget, passes everything through without a limiter.
+#[derive(Clone, Default)]
+pub struct RateLimitLayer {
+    limiter: Option<Arc<dyn RateLimiter>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Option<Arc<dyn RateLimiter>>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: Option<Arc<dyn RateLimiter>>,
+}
+
+impl<S, B> Service<http::Request<B>> for RateLimitService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // The clone wasn't polled ready, the original that was handles this
+        // request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let limited = self
+            .limiter
+            .clone()
+            .filter(|_| LIMITED_PATHS.contains(&request.uri().path()))
+            .zip(
+                request
+                    .extensions()
+                    .get::<TcpConnectInfo>()
+                    .and_then(TcpConnectInfo::remote_addr),
+            );
+
+        Box::pin(async move {
+            if let Some((limiter, addr)) = limited {
+                match limiter.acquire(&format!("ip:{}", addr.ip())).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::warn!("IP: {} is over its rate limit.", addr.ip());
+                        return Ok(too_many_requests().into_http());
+                    }
+                    Err(err) => return Ok(Status::from(err).into_http()),
+                }
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+pub fn too_many_requests() -> Status {
+    status_with_error_code(
+        Code::ResourceExhausted,
+        "Too many requests, try again later.",
+        ErrorCode::RateLimited,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let limiter = TokenBucket::new(RateLimit {
+            requests: 2,
+            period: Duration::from_secs(60),
+        });
+        assert!(limiter.acquire("a").await.unwrap());
+        assert!(limiter.acquire("a").await.unwrap());
+        assert!(!limiter.acquire("a").await.unwrap());
+        assert!(limiter.acquire("b").await.unwrap());
+
+        // The budget refills at the new rate.
+        limiter.set_limit(RateLimit {
+            requests: 2,
+            period: Duration::from_nanos(1),
+        });
+        assert!(limiter.acquire("a").await.unwrap());
+    }
+}
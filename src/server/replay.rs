@@ -0,0 +1,101 @@
+//! Refuses login requests replayed by a hop between prover and server. A
+//! prover puts a fresh random nonce and the time in each challenge and
+//! answer request; requests made too long ago, or whose nonce was seen
+//! within the window, are refused. Nonces are remembered in process memory,
+//! so in CLUSTER mode each server only knows the ones it was sent.
+
+use std::time::Duration;
+
+use dashmap::DashMap;
+use tonic::{Code, Status};
+
+use crate::{server::store::unix_now, status_with_error_code, zkp_auth::ErrorCode};
+
+/// Fewest bytes of a nonce, few enough to collide by chance aren't taken.
+pub const MIN_NONCE_LEN: usize = 16;
+
+/// Most bytes of a nonce.
+pub const MAX_NONCE_LEN: usize = 64;
+
+/// Nonces remembered before those out of the window are swept out.
+const MAX_TRACKED_NONCES: usize = 100_000;
+
+#[derive(Debug)]
+pub struct NonceCache {
+    /// How far a request's timestamp may be from the server's clock, either
+    /// way.
+    window: Duration,
+    /// Nonce to when its request falls out of the window.
+    seen: DashMap<Vec<u8>, u64>,
+}
+
+impl NonceCache {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            seen: DashMap::new(),
+        }
+    }
+
+    /// Takes the nonce of a request made at `timestamp`, Unix time in
+    /// seconds, refusing it if it is missing, was taken before or the
+    /// request is outside the window.
+    pub fn check(&self, nonce: &[u8], timestamp: u64) -> Result<(), Status> {
+        if !(MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&nonce.len()) {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                format!("Requests need a nonce of {MIN_NONCE_LEN} to {MAX_NONCE_LEN} bytes."),
+            ));
+        }
+        let now = unix_now();
+        let window = self.window.as_secs();
+        if now.abs_diff(timestamp) > window {
+            return Err(status_with_error_code(
+                Code::InvalidArgument,
+                format!("Request timestamp is more than {window} seconds off."),
+                ErrorCode::RequestReplayed,
+            ));
+        }
+
+        if self.seen.len() >= MAX_TRACKED_NONCES {
+            self.seen.retain(|_, until| *until >= now);
+        }
+        let until = timestamp.saturating_add(window);
+        if self.seen.insert(nonce.to_vec(), until).is_some() {
+            log::warn!("Refused a replayed request.");
+            return Err(status_with_error_code(
+                Code::PermissionDenied,
+                "Request replayed.",
+                ErrorCode::RequestReplayed,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error_code;
+
+    #[test]
+    fn test_nonce_cache() {
+        let cache = NonceCache::new(Duration::from_secs(60));
+        let now = unix_now();
+
+        cache.check(&[1; 16], now).unwrap();
+        let status = cache.check(&[1; 16], now).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(error_code(&status), Some(ErrorCode::RequestReplayed));
+        cache.check(&[2; 16], now - 30).unwrap();
+
+        let status = cache.check(&[3; 16], now - 120).unwrap_err();
+        assert_eq!(error_code(&status), Some(ErrorCode::RequestReplayed));
+        cache.check(&[3; 16], now + 30).unwrap();
+
+        for nonce in [&[][..], &[4; 8], &[4; 65]] {
+            let status = cache.check(nonce, now).unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+    }
+}
@@ -0,0 +1,678 @@
+//! JSON over HTTP for clients that can't speak gRPC. Each endpoint is one
+//! `AuthImpl` call on the same store, so users, challenges and sessions are
+//! shared with the gRPC service. Bytes and big integers travel as standard
+//! base64, the way proto3's JSON mapping has them.
+//!
+//! Only the per-user rate limit applies here, the per-IP one is a layer of
+//! the gRPC server.
+//!
+//! The OpenAPI document at /openapi.json is derived from the handlers and
+//! body types, for generating clients. /ws carries one classic login
+//! over a WebSocket, see `ws`.
+//!
+//! Bodies carrying secrets print them redacted, as src/redact.rs does for
+//! the gRPC messages.
+
+mod ws;
+
+use std::{collections::HashMap, fmt, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tonic::{transport::server::TcpConnectInfo, Code, Status};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    server::{
+        grpc_impl::auth::auth_impl::AuthImpl,
+        store::{SessionStore, UserStore},
+        telemetry,
+    },
+    zkp_auth::{self, auth_server::Auth},
+    Secret,
+};
+
+pub fn router<S: UserStore + SessionStore + Clone>(auth: AuthImpl<S>) -> Router {
+    Router::new()
+        .route("/register", post(register::<S>))
+        .route("/challenge", post(challenge::<S>))
+        .route("/verify", post(verify::<S>))
+        .route("/validate", post(validate::<S>))
+        .route("/openapi.json", get(|| async { Json(openapi()) }))
+        .route("/ws", get(ws::upgrade::<S>))
+        .layer(axum::middleware::from_fn(telemetry::http_span))
+        .with_state(auth)
+}
+
+/// OpenAPI description of the endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zkp_auth REST gateway"),
+    paths(register, challenge, verify, validate)
+)]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+/// As `zkp_auth::KdfParams`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+/// As `zkp_auth::RegisterRequest`, in the server's group.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RegisterRequest {
+    pub name: String,
+    #[serde(default)]
+    pub realm: String,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub y1: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub y2: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub salt: Vec<u8>,
+    #[serde(default)]
+    pub kdf_params: Option<KdfParams>,
+    #[serde(default)]
+    pub pow_nonce: u64,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub totp: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RegisterResponse {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub totp_secret: String,
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+}
+
+impl fmt::Debug for RegisterResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisterResponse")
+            .field("totp_secret", &Secret::new(&self.totp_secret))
+            .field("recovery_codes", &Secret::new(&self.recovery_codes))
+            .finish()
+    }
+}
+
+/// As `zkp_auth::AuthenticationChallengeRequest`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChallengeRequest {
+    pub user: String,
+    #[serde(default)]
+    pub realm: String,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub r1: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub r2: Vec<u8>,
+    #[serde(default)]
+    pub challenge_bits: u32,
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub max_protocol_version: u32,
+    #[serde(default, with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub nonce: Vec<u8>,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+impl fmt::Debug for ChallengeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChallengeRequest")
+            .field("user", &self.user)
+            .field("r1", &Secret::new(&self.r1))
+            .field("r2", &Secret::new(&self.r2))
+            .field("challenge_bits", &self.challenge_bits)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+impl From<ChallengeRequest> for zkp_auth::AuthenticationChallengeRequest {
+    fn from(request: ChallengeRequest) -> Self {
+        Self {
+            user: request.user,
+            r1: request.r1,
+            r2: request.r2,
+            challenge_bits: request.challenge_bits,
+            realm: request.realm,
+            protocol_version: request.protocol_version,
+            max_protocol_version: request.max_protocol_version,
+            nonce: request.nonce,
+            timestamp: request.timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChallengeResponse {
+    pub auth_id: String,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub c: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub challenge_seed: Vec<u8>,
+}
+
+impl From<zkp_auth::AuthenticationChallengeResponse> for ChallengeResponse {
+    fn from(challenge: zkp_auth::AuthenticationChallengeResponse) -> Self {
+        Self {
+            auth_id: challenge.auth_id,
+            c: challenge.c,
+            challenge_seed: challenge.challenge_seed,
+        }
+    }
+}
+
+/// As `zkp_auth::AuthenticationAnswerRequest`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VerifyRequest {
+    pub auth_id: String,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub s: Vec<u8>,
+    #[serde(default)]
+    pub totp_code: String,
+    #[serde(default, with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub nonce: Vec<u8>,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+impl fmt::Debug for VerifyRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifyRequest")
+            .field("auth_id", &self.auth_id)
+            .field("s", &Secret::new(&self.s))
+            .field("totp_code", &Secret::new(&self.totp_code))
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VerifyResponse {
+    pub session_id: String,
+    pub expires_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_proof: Option<Proof>,
+}
+
+impl fmt::Debug for VerifyResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifyResponse")
+            .field("session_id", &Secret::new(&self.session_id))
+            .field("expires_at", &self.expires_at)
+            .field("server_proof", &self.server_proof)
+            .finish()
+    }
+}
+
+impl From<zkp_auth::AuthenticationAnswerResponse> for VerifyResponse {
+    fn from(answer: zkp_auth::AuthenticationAnswerResponse) -> Self {
+        Self {
+            session_id: answer.session_id,
+            expires_at: answer.expires_at,
+            server_proof: answer.server_proof.map(|proof| Proof {
+                r1: proof.r1,
+                r2: proof.r2,
+                s: proof.s,
+                expires_at: proof.expires_at,
+            }),
+        }
+    }
+}
+
+/// As `zkp_auth::Proof`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Proof {
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub r1: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub r2: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    #[schema(value_type = String, format = Byte)]
+    pub s: Vec<u8>,
+    #[serde(default)]
+    pub expires_at: u64,
+}
+
+impl fmt::Debug for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proof")
+            .field("r1", &Secret::new(&self.r1))
+            .field("r2", &Secret::new(&self.r2))
+            .field("s", &Secret::new(&self.s))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// As `zkp_auth::ValidateSessionRequest`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ValidateRequest {
+    pub session_id: String,
+}
+
+impl fmt::Debug for ValidateRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValidateRequest")
+            .field("session_id", &Secret::new(&self.session_id))
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ValidateResponse {
+    pub valid: bool,
+    pub user: String,
+    pub realm: String,
+    pub expires_at: u64,
+}
+
+/// Registers the public key (y1, y2) of a user in the server's group.
+#[utoipa::path(
+    post,
+    path = "/register",
+    request_body = RegisterRequest,
+    responses(
+        (status = 200, body = RegisterResponse),
+        (status = "default", description = "The gRPC status of the failed call", body = ErrorBody),
+    )
+)]
+async fn register<S: UserStore + SessionStore + Clone>(
+    State(auth): State<AuthImpl<S>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<RegisterRequest>,
+) -> Result<Json<RegisterResponse>, RestError> {
+    let response = auth
+        .register(grpc_request(
+            client,
+            zkp_auth::RegisterRequest {
+                name: request.name,
+                y1: request.y1,
+                y2: request.y2,
+                salt: request.salt,
+                kdf_params: request.kdf_params.map(|params| zkp_auth::KdfParams {
+                    m_cost: params.m_cost,
+                    t_cost: params.t_cost,
+                    p_cost: params.p_cost,
+                }),
+                pow_nonce: request.pow_nonce,
+                realm: request.realm,
+                group: None,
+                metadata: request.metadata,
+                totp: request.totp,
+            },
+        ))
+        .await?
+        .into_inner();
+    Ok(Json(RegisterResponse {
+        totp_secret: response.totp_secret,
+        recovery_codes: response.recovery_codes,
+    }))
+}
+
+/// Starts a login with the commitment (r1, r2), answers the challenge c.
+#[utoipa::path(
+    post,
+    path = "/challenge",
+    request_body = ChallengeRequest,
+    responses(
+        (status = 200, body = ChallengeResponse),
+        (status = "default", description = "The gRPC status of the failed call", body = ErrorBody),
+    )
+)]
+async fn challenge<S: UserStore + SessionStore + Clone>(
+    State(auth): State<AuthImpl<S>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<ChallengeRequest>,
+) -> Result<Json<ChallengeResponse>, RestError> {
+    let challenge = auth
+        .create_authentication_challenge(grpc_request(client, request.into()))
+        .await?
+        .into_inner();
+    Ok(Json(challenge.into()))
+}
+
+/// Checks s = k - c * x mod q, answers a session.
+#[utoipa::path(
+    post,
+    path = "/verify",
+    request_body = VerifyRequest,
+    responses(
+        (status = 200, body = VerifyResponse),
+        (status = "default", description = "The gRPC status of the failed call", body = ErrorBody),
+    )
+)]
+async fn verify<S: UserStore + SessionStore + Clone>(
+    State(auth): State<AuthImpl<S>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>, RestError> {
+    let answer = auth
+        .verify_authentication(grpc_request(
+            client,
+            zkp_auth::AuthenticationAnswerRequest {
+                auth_id: request.auth_id,
+                s: request.s,
+                totp_code: request.totp_code,
+                nonce: request.nonce,
+                timestamp: request.timestamp,
+            },
+        ))
+        .await?
+        .into_inner();
+    Ok(Json(answer.into()))
+}
+
+/// Says whether a session is live and whose it is.
+#[utoipa::path(
+    post,
+    path = "/validate",
+    request_body = ValidateRequest,
+    responses(
+        (status = 200, body = ValidateResponse),
+        (status = "default", description = "The gRPC status of the failed call", body = ErrorBody),
+    )
+)]
+async fn validate<S: UserStore + SessionStore + Clone>(
+    State(auth): State<AuthImpl<S>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<ValidateRequest>,
+) -> Result<Json<ValidateResponse>, RestError> {
+    let session = auth
+        .validate_session(grpc_request(
+            client,
+            zkp_auth::ValidateSessionRequest {
+                session_id: request.session_id,
+            },
+        ))
+        .await?
+        .into_inner();
+    Ok(Json(ValidateResponse {
+        valid: session.valid,
+        user: session.user,
+        realm: session.realm,
+        expires_at: session.expires_at,
+    }))
+}
+
+/// `message` as a gRPC call from `client`, so `AuthImpl` sees where it
+/// came from.
+fn grpc_request<T>(client: Option<ConnectInfo<SocketAddr>>, message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.extensions_mut().insert(TcpConnectInfo {
+        local_addr: None,
+        remote_addr: client.map(|ConnectInfo(addr)| addr),
+    });
+    request
+}
+
+/// A failed call as JSON, {"code": "NotFound", "message": "..."}, under the
+/// nearest HTTP status.
+#[derive(Debug)]
+pub struct RestError(Status);
+
+impl From<Status> for RestError {
+    fn from(status: Status) -> Self {
+        Self(status)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+impl From<&Status> for ErrorBody {
+    fn from(status: &Status) -> Self {
+        Self {
+            code: format!("{:?}", status.code()),
+            message: status.message().to_string(),
+        }
+    }
+}
+
+impl IntoResponse for RestError {
+    fn into_response(self) -> Response {
+        let status = match self.0.code() {
+            Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition => {
+                StatusCode::BAD_REQUEST
+            }
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+            Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+            Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+            Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+            Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(ErrorBody::from(&self.0))).into_response()
+    }
+}
+
+/// Serde for bytes as a standard base64 string.
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        STANDARD.decode(text).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use http_body_util::BodyExt;
+    use num_bigint::BigUint;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+    use tower::Service;
+
+    use super::*;
+    use crate::{server::store::MemoryStore, ZKP};
+
+    async fn post<T: DeserializeOwned>(
+        router: &mut Router,
+        path: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, T) {
+        let request = axum::http::Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_rest_login() {
+        let zkp = ZKP::default();
+        let server_x = ZKP::generate_random_below(zkp.q());
+        let mut router = router(AuthImpl::new(
+            Arc::<MemoryStore>::default(),
+            zkp.clone(),
+            server_x,
+        ));
+        let b64 = |n: &BigUint| STANDARD.encode(n.to_bytes_be());
+
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        let (status, _): (_, RegisterResponse) = post(
+            &mut router,
+            "/register",
+            json!({
+                "name": "alice",
+                "y1": b64(&y1),
+                "y2": b64(&y2),
+                "salt": STANDARD.encode([0; 16]),
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let k = ZKP::generate_random_below(zkp.q());
+        let (r1, r2) = zkp.compute_pair(&k);
+        let (_, challenge): (_, ChallengeResponse) = post(
+            &mut router,
+            "/challenge",
+            json!({"user": "alice", "r1": b64(&r1), "r2": b64(&r2)}),
+        )
+        .await;
+        let s = zkp.solve(&k, &BigUint::from_bytes_be(&challenge.c), &x);
+
+        let (status, error): (_, ErrorBody) = post(
+            &mut router,
+            "/verify",
+            json!({"auth_id": "0".repeat(32), "s": b64(&s)}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error.code, "NotFound");
+
+        let (_, session): (_, VerifyResponse) = post(
+            &mut router,
+            "/verify",
+            json!({"auth_id": challenge.auth_id, "s": b64(&s)}),
+        )
+        .await;
+        assert!(session.server_proof.is_some());
+
+        let (_, validated): (_, ValidateResponse) = post(
+            &mut router,
+            "/validate",
+            json!({"session_id": session.session_id}),
+        )
+        .await;
+        assert!(validated.valid);
+        assert_eq!(validated.user, "alice");
+    }
+
+    /// A body with only the required fields of `T`'s schema in `spec`, which
+    /// must deserialize.
+    fn check_required<T: ToSchema + DeserializeOwned>(spec: &Value) {
+        let schema = &spec["components"]["schemas"][T::name().as_ref()];
+        let body: serde_json::Map<String, Value> = schema["required"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|name| {
+                let name = name.as_str().unwrap();
+                let value = match schema["properties"][name]["type"].as_str() {
+                    Some("integer") => json!(0),
+                    Some("boolean") => json!(false),
+                    _ => json!(""),
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        if let Err(err) = serde_json::from_value::<T>(Value::Object(body)) {
+            panic!("{} needs more than its required fields: {err}", T::name());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openapi() {
+        let zkp = ZKP::default();
+        let server_x = ZKP::generate_random_below(zkp.q());
+        let mut router = router(AuthImpl::new(Arc::<MemoryStore>::default(), zkp, server_x));
+        let request = axum::http::Request::get("/openapi.json")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let spec: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(spec, serde_json::to_value(openapi()).unwrap());
+
+        let paths: Vec<_> = spec["paths"].as_object().unwrap().keys().collect();
+        assert_eq!(paths, ["/challenge", "/register", "/validate", "/verify"]);
+        for (name, schema) in spec["components"]["schemas"].as_object().unwrap() {
+            for field in schema["required"].as_array().into_iter().flatten() {
+                let field = field.as_str().unwrap();
+                assert!(
+                    schema["properties"].get(field).is_some(),
+                    "{name}.{field} is required but not a property"
+                );
+            }
+        }
+
+        check_required::<RegisterRequest>(&spec);
+        check_required::<ChallengeRequest>(&spec);
+        check_required::<VerifyRequest>(&spec);
+        check_required::<ValidateRequest>(&spec);
+        check_required::<VerifyResponse>(&spec);
+    }
+
+    #[test]
+    fn test_bodies_are_redacted() {
+        let registered = RegisterResponse {
+            totp_secret: "JBSWY3DPEHPK3PXP".to_string(),
+            recovery_codes: vec!["ABCD-EFGH-JKMN-PQRS".to_string()],
+        };
+        let printed = format!("{registered:?}");
+        assert!(!printed.contains("JBSWY3DPEHPK3PXP"));
+        assert!(!printed.contains("ABCD"));
+
+        let verify = VerifyRequest {
+            auth_id: "auth-id".to_string(),
+            s: vec![0xde, 0xad, 0xbe, 0xef],
+            totp_code: "123456".to_string(),
+            nonce: Vec::new(),
+            timestamp: 0,
+        };
+        let printed = format!("{verify:?}");
+        assert!(printed.contains("auth-id"));
+        assert!(!printed.contains("222") && !printed.contains("123456"));
+
+        let verified = VerifyResponse {
+            session_id: "session-id".to_string(),
+            expires_at: 0,
+            server_proof: None,
+        };
+        assert!(!format!("{verified:?}").contains("session-id"));
+        let validate = ValidateRequest {
+            session_id: "session-id".to_string(),
+        };
+        assert!(!format!("{validate:?}").contains("session-id"));
+    }
+}
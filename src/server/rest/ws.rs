@@ -0,0 +1,260 @@
+//! The interactive protocol over one WebSocket, for browser demos. The three
+//! moves travel as JSON text frames tagged by "type":
+//!     client: {"type": "commitment", ...}, as for POST /challenge
+//!     server: {"type": "challenge", ...}, as POST /challenge answers
+//!     client: {"type": "response", "s": ..., "totp_code": ..., "nonce": ...,
+//!              "timestamp": ...}
+//!     server: {"type": "session", ...}, as POST /verify answers
+//! or {"type": "error", ...} in place of any server message, after which the
+//! server closes the socket. Like the Authenticate stream, the attempt lives
+//! with the socket and never reaches the store.
+
+use std::{fmt, net::SocketAddr, time::Duration};
+
+use axum::{
+    extract::{
+        ws::{self, WebSocket, WebSocketUpgrade},
+        ConnectInfo, State,
+    },
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tonic::{Code, Status};
+use tracing::{Instrument, Span};
+
+use super::{ChallengeRequest, ChallengeResponse, ErrorBody, VerifyResponse};
+use crate::{
+    server::{
+        grpc_impl::auth::auth_impl::{AuthImpl, Peer},
+        store::{SessionStore, UserStore},
+    },
+    status_with_error_code,
+    zkp_auth::ErrorCode,
+    AuthId, Secret,
+};
+
+/// Largest message accepted, a commitment in a 4096-bit group is well below
+/// it.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// How long a client gets to send its commitment.
+const COMMITMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A message of the login, see the module docs.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Commitment(ChallengeRequest),
+    Challenge(ChallengeResponse),
+    Response(Answer),
+    Session(VerifyResponse),
+    Error(ErrorBody),
+}
+
+/// Move 3, the auth_id is the socket's.
+#[derive(Serialize, Deserialize)]
+pub struct Answer {
+    #[serde(with = "super::base64_bytes")]
+    pub s: Vec<u8>,
+    /// For users enrolled in TOTP.
+    #[serde(default)]
+    pub totp_code: String,
+    /// For servers that refuse replays, as in POST /verify.
+    #[serde(default, with = "super::base64_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+impl fmt::Debug for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Answer")
+            .field("s", &Secret::new(&self.s))
+            .field("totp_code", &Secret::new(&self.totp_code))
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+pub async fn upgrade<S: UserStore + SessionStore + Clone>(
+    State(auth): State<AuthImpl<S>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let peer = Peer {
+        ip: client.map(|ConnectInfo(addr)| addr.ip()),
+        cert: None,
+    };
+    // The login outlives the upgrade request, but is still part of it.
+    let span = Span::current();
+    upgrade
+        .max_message_size(MAX_MESSAGE_LEN)
+        .on_upgrade(move |socket| serve(auth, socket, peer).instrument(span))
+}
+
+/// One login on the socket, then a close.
+async fn serve<S: UserStore + SessionStore>(auth: AuthImpl<S>, mut socket: WebSocket, peer: Peer) {
+    let last = match login(&auth, &mut socket, &peer).await {
+        Ok(session) => Message::Session(session),
+        Err(status) => {
+            log::warn!("WebSocket login failed: {}", status.message());
+            Message::Error(ErrorBody::from(&status))
+        }
+    };
+    // Nobody to tell when the client already went away.
+    let _ = send(&mut socket, &last).await;
+    let _ = socket.send(ws::Message::Close(None)).await;
+}
+
+async fn login<S: UserStore + SessionStore>(
+    auth: &AuthImpl<S>,
+    socket: &mut WebSocket,
+    peer: &Peer,
+) -> Result<VerifyResponse, Status> {
+    let commitment = tokio::time::timeout(COMMITMENT_TIMEOUT, receive(socket))
+        .await
+        .map_err(|_| Status::new(Code::DeadlineExceeded, "No commitment came."))??;
+    let Some(Message::Commitment(request)) = commitment else {
+        return Err(unexpected("commitment"));
+    };
+    let (attempt, challenge) = auth.bound_challenge(request.into(), peer).await?;
+    let auth_id = AuthId::from(challenge.auth_id.clone());
+    send(socket, &Message::Challenge(challenge.into())).await?;
+
+    let response = tokio::time::timeout(attempt.ttl(), receive(socket))
+        .await
+        .map_err(|_| {
+            status_with_error_code(
+                Code::DeadlineExceeded,
+                format!("Auth ID: {auth_id} expired."),
+                ErrorCode::ChallengeExpired,
+            )
+        })??;
+    let Some(Message::Response(answer)) = response else {
+        return Err(unexpected("response"));
+    };
+    auth.check_replay(&answer.nonce, answer.timestamp)?;
+    let answer = auth
+        .check_answer(&auth_id, attempt, &answer.s, &answer.totp_code, peer)
+        .await?;
+    Ok(answer.into())
+}
+
+/// The next message, None once the client closed the socket. Pings are
+/// answered underneath.
+async fn receive(socket: &mut WebSocket) -> Result<Option<Message>, Status> {
+    loop {
+        match socket.recv().await.transpose().map_err(broken)? {
+            Some(ws::Message::Text(text)) => {
+                return serde_json::from_str(&text)
+                    .map(Some)
+                    .map_err(|err| Status::invalid_argument(format!("Bad message: {err}")));
+            }
+            Some(ws::Message::Binary(_)) => {
+                return Err(Status::invalid_argument("Only text messages are spoken."));
+            }
+            Some(ws::Message::Ping(_) | ws::Message::Pong(_)) => {}
+            Some(ws::Message::Close(_)) | None => return Ok(None),
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &Message) -> Result<(), Status> {
+    let text = serde_json::to_string(message).expect("Messages serialize.");
+    socket.send(ws::Message::Text(text)).await.map_err(broken)
+}
+
+fn unexpected(expected: &str) -> Status {
+    Status::new(
+        Code::FailedPrecondition,
+        format!("Expected the {expected} message of the login."),
+    )
+}
+
+fn broken(err: axum::Error) -> Status {
+    Status::new(Code::Unavailable, format!("The socket broke: {err}"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use futures::{SinkExt, StreamExt};
+    use num_bigint::BigUint;
+    use tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::TcpListener,
+    };
+    use tokio_tungstenite::{tungstenite, WebSocketStream};
+
+    use super::*;
+    use crate::{server::store::MemoryStore, zkp_auth, zkp_auth::auth_server::Auth, SALT_LEN, ZKP};
+
+    async fn receive<T: AsyncRead + AsyncWrite + Unpin>(
+        socket: &mut WebSocketStream<T>,
+    ) -> Message {
+        let text = socket.next().await.unwrap().unwrap().into_text().unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_websocket_login() {
+        let zkp = ZKP::default();
+        let server_x = ZKP::generate_random_below(zkp.q());
+        let auth = AuthImpl::new(Arc::<MemoryStore>::default(), zkp.clone(), server_x);
+        let x = ZKP::generate_random_below(zkp.q());
+        let (y1, y2) = zkp.compute_pair(&x);
+        auth.register(tonic::Request::new(zkp_auth::RegisterRequest {
+            name: "alice".to_string(),
+            y1: y1.to_bytes_be(),
+            y2: y2.to_bytes_be(),
+            salt: vec![0; SALT_LEN],
+            kdf_params: None,
+            pow_nonce: 0,
+            realm: String::new(),
+            group: None,
+            metadata: Default::default(),
+            totp: false,
+        }))
+        .await
+        .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = crate::server::rest::router(auth);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws"))
+            .await
+            .unwrap();
+
+        let b64 = |n: &BigUint| STANDARD.encode(n.to_bytes_be());
+        let k = ZKP::generate_random_below(zkp.q());
+        let (r1, r2) = zkp.compute_pair(&k);
+        let commitment = serde_json::json!({
+            "type": "commitment",
+            "user": "alice",
+            "r1": b64(&r1),
+            "r2": b64(&r2),
+        });
+        socket
+            .send(tungstenite::Message::Text(commitment.to_string()))
+            .await
+            .unwrap();
+        let Message::Challenge(challenge) = receive(&mut socket).await else {
+            panic!("Expected a challenge.");
+        };
+
+        let s = zkp.solve(&k, &BigUint::from_bytes_be(&challenge.c), &x);
+        let response = serde_json::json!({"type": "response", "s": b64(&s)});
+        socket
+            .send(tungstenite::Message::Text(response.to_string()))
+            .await
+            .unwrap();
+        let Message::Session(session) = receive(&mut socket).await else {
+            panic!("Expected a session.");
+        };
+        assert!(!session.session_id.is_empty());
+        assert!(socket.next().await.unwrap().unwrap().is_close());
+    }
+}
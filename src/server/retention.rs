@@ -0,0 +1,133 @@
+//! Retention of users, for tutorial and demo deployments that would pile
+//! up throwaway registrations otherwise. With USER_RETENTION_DAYS set, the
+//! cleanup task deletes users that haven't registered, logged in or
+//! refreshed a session for that many days. AuthAdmin PruneUsers lists them,
+//! or deletes them, on demand.
+
+use std::{net::IpAddr, time::Duration};
+
+use crate::server::{
+    audit::{AuditEvent, AuditLog},
+    store::{unix_now, SessionStore, StoreError, UserStore},
+};
+
+/// Users looked up per store call.
+const PAGE_SIZE: usize = 100;
+
+/// `days` as a retention.
+pub fn days(days: u32) -> Duration {
+    Duration::from_secs(u64::from(days) * 24 * 60 * 60)
+}
+
+/// Users unused for longer than `retention`, deleted with their challenges
+/// and sessions unless `dry_run`. Returns their names.
+pub async fn prune_users<S: UserStore + SessionStore>(
+    store: &S,
+    retention: Duration,
+    dry_run: bool,
+    audit: &AuditLog,
+    source_ip: Option<IpAddr>,
+) -> Result<Vec<String>, StoreError> {
+    let before = unix_now().saturating_sub(retention.as_secs());
+    let mut pruned = Vec::new();
+    let mut after = String::new();
+    loop {
+        let page = store.list_stale_users(before, &after, PAGE_SIZE).await?;
+        let last_page = page.len() < PAGE_SIZE;
+        if let Some(last) = page.last() {
+            after.clone_from(last);
+        }
+        for user_name in page {
+            if !dry_run {
+                // The user goes first, see `AuthImpl::delete_account`.
+                if !store.delete_user(&user_name).await? {
+                    continue;
+                }
+                store.purge_user(&user_name).await?;
+                audit.record(
+                    source_ip,
+                    AuditEvent::UserPruned {
+                        user: user_name.clone(),
+                    },
+                );
+            }
+            pruned.push(user_name);
+        }
+        if last_page {
+            return Ok(pruned);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        server::store::{MemoryStore, UserInfo},
+        KdfParams,
+    };
+    #[cfg(all(feature = "sqlite", feature = "sled"))]
+    use crate::{
+        server::store::{SledStore, SqliteStore},
+        ZKP,
+    };
+
+    async fn check_prune<S: UserStore + SessionStore>(store: S) {
+        let now = unix_now();
+        for (name, last_used) in [
+            ("alice", now - 3 * 86400),
+            ("bob", now),
+            ("carol", now - 10),
+        ] {
+            store
+                .put_user(UserInfo {
+                    user_name: name.to_string(),
+                    y1: BigUint::from(4u32),
+                    y2: BigUint::from(9u32),
+                    salt: vec![0; 16],
+                    kdf_params: KdfParams::default(),
+                    group: None,
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            store.touch_user(name, last_used).await.unwrap();
+        }
+        let audit = AuditLog::default();
+
+        let stale = prune_users(&store, days(2), true, &audit, None)
+            .await
+            .unwrap();
+        assert_eq!(stale, ["alice"]);
+        assert!(store.get_user("alice").await.unwrap().is_some());
+
+        let pruned = prune_users(&store, days(2), false, &audit, None)
+            .await
+            .unwrap();
+        assert_eq!(pruned, ["alice"]);
+        assert!(store.get_user("alice").await.unwrap().is_none());
+        assert!(store.get_user("carol").await.unwrap().is_some());
+        assert!(prune_users(&store, days(2), true, &audit, None)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prune_users() {
+        check_prune(MemoryStore::default()).await;
+
+        #[cfg(all(feature = "sqlite", feature = "sled"))]
+        {
+            let path = std::env::temp_dir().join(format!(
+                "zkp_auth_retention_{}",
+                ZKP::generate_random_string(8)
+            ));
+            let url = format!("sqlite://{}.db", path.display());
+            check_prune(SqliteStore::connect(&url).await.unwrap()).await;
+            check_prune(SledStore::open(&path).unwrap()).await;
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! The Auth service with the defaults the `server` binary starts from,
+//! behind a builder that only asks for what an embedding application is
+//! likely to pick: the store, the group and the server's key.
+//!
+//! ```no_run
+//! use zkp_chaum_pedersen::{
+//!     server::{store::SqliteStore, AuthService},
+//!     ParamSet,
+//! };
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let service = AuthService::builder()
+//!     .with_store(SqliteStore::connect("sqlite://users.db").await?)
+//!     .with_params(ParamSet::Rfc5114_2048_256)
+//!     .build()?;
+//! // Next to the application's own services,
+//! //     Server::builder().add_service(service.into_server())...
+//! // or on its own.
+//! service
+//!     .serve_with_shutdown("127.0.0.1:50051".parse()?, async {
+//!         tokio::signal::ctrl_c().await.ok();
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use num_bigint::BigUint;
+use thiserror::Error;
+use tokio::sync::watch;
+
+use crate::{
+    server::{
+        grpc_impl::auth::auth_impl::{AuthImpl, DEFAULT_CHALLENGE_TTL, DEFAULT_SESSION_TTL},
+        health,
+        panic::CatchPanicLayer,
+        store::{MemoryStore, SessionStore, StoreError, UserStore},
+        telemetry,
+    },
+    zkp_auth::auth_server::AuthServer,
+    Backend, ParamSet, ZkpError, ZKP,
+};
+
+/// Why `AuthService::serve_with_shutdown` failed.
+#[derive(Debug, Error)]
+pub enum ServeError {
+    /// Binding `addr` or serving on it.
+    #[error("transport error: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    /// Shutting the store down once serving stopped.
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+}
+
+/// The Auth service over a store of type `S`.
+#[derive(Debug, Clone)]
+pub struct AuthService<S> {
+    auth: AuthImpl<Arc<S>>,
+}
+
+impl AuthService<MemoryStore> {
+    /// Users kept in memory and the default group until told otherwise.
+    pub fn builder() -> AuthServiceBuilder<MemoryStore> {
+        AuthServiceBuilder::default()
+    }
+}
+
+impl<S: UserStore + SessionStore> AuthService<S> {
+    /// A service set up some other way, e.g. with the rate limiter or
+    /// lockout policy the builder doesn't offer.
+    pub fn new(auth: AuthImpl<Arc<S>>) -> Self {
+        Self { auth }
+    }
+
+    /// The handlers, for a caller that wants to tune them further.
+    pub fn auth_impl(&self) -> &AuthImpl<Arc<S>> {
+        &self.auth
+    }
+
+    /// The server's public key, SERVER_Y1 and SERVER_Y2 of the clients
+    /// that pin it.
+    pub fn server_public_key(&self) -> (BigUint, BigUint) {
+        self.auth.zkp.compute_pair(self.auth.server_x.expose())
+    }
+
+    /// The service to add to an application's own tonic server.
+    pub fn into_server(self) -> AuthServer<AuthImpl<Arc<S>>> {
+        AuthServer::new(self.auth)
+    }
+
+    /// Serves Auth and grpc.health.v1.Health on `addr` until `signal`
+    /// resolves, then finishes the calls in flight and shuts the store
+    /// down.
+    pub async fn serve_with_shutdown<F>(self, addr: SocketAddr, signal: F) -> Result<(), ServeError>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let store = self.auth.store.clone();
+        let (stop, stopping) = watch::channel(());
+        let (reporter, health) = tonic_health::server::health_reporter();
+        tokio::spawn(health::watch_store(store.clone(), reporter, stopping));
+
+        tonic::transport::Server::builder()
+            .trace_fn(telemetry::rpc_span)
+            .layer(CatchPanicLayer)
+            .add_service(health)
+            .add_service(self.into_server())
+            .serve_with_shutdown(addr, signal)
+            .await?;
+        let _ = stop.send(());
+        store.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Named-argument construction of `AuthService`, the group is checked in
+/// `build`.
+#[derive(Debug)]
+pub struct AuthServiceBuilder<S> {
+    store: S,
+    params: ParamSet,
+    server_x: Option<BigUint>,
+    challenge_ttl: Duration,
+    session_ttl: Duration,
+    session_max_lifetime: Option<Duration>,
+}
+
+impl Default for AuthServiceBuilder<MemoryStore> {
+    fn default() -> Self {
+        Self {
+            store: MemoryStore::default(),
+            params: ParamSet::default(),
+            server_x: None,
+            challenge_ttl: DEFAULT_CHALLENGE_TTL,
+            session_ttl: DEFAULT_SESSION_TTL,
+            session_max_lifetime: None,
+        }
+    }
+}
+
+impl<S: UserStore + SessionStore> AuthServiceBuilder<S> {
+    /// Where users, challenges and sessions are kept.
+    pub fn with_store<T: UserStore + SessionStore>(self, store: T) -> AuthServiceBuilder<T> {
+        AuthServiceBuilder {
+            store,
+            params: self.params,
+            server_x: self.server_x,
+            challenge_ttl: self.challenge_ttl,
+            session_ttl: self.session_ttl,
+            session_max_lifetime: self.session_max_lifetime,
+        }
+    }
+
+    /// The group users register their keys in.
+    pub fn with_params(mut self, params: ParamSet) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// The server's secret, SERVER_SECRET_KEY of the binary. A throwaway
+    /// one is generated when not set, which clients can only pin until the
+    /// service is built again.
+    pub fn with_server_secret(mut self, server_x: BigUint) -> Self {
+        self.server_x = Some(server_x);
+        self
+    }
+
+    pub fn with_challenge_ttl(mut self, challenge_ttl: Duration) -> Self {
+        self.challenge_ttl = challenge_ttl;
+        self
+    }
+
+    pub fn with_session_ttl(mut self, session_ttl: Duration) -> Self {
+        self.session_ttl = session_ttl;
+        self
+    }
+
+    /// Turns on sliding session expiry, see `AuthImpl::session_max_lifetime`.
+    pub fn with_session_max_lifetime(mut self, session_max_lifetime: Duration) -> Self {
+        self.session_max_lifetime = Some(session_max_lifetime);
+        self
+    }
+
+    pub fn build(self) -> Result<AuthService<S>, ZkpError> {
+        let zkp = ZKP::builder()
+            .param_set(self.params)
+            .backend(Backend::Fixed2048)
+            .build()?;
+        let server_x = self
+            .server_x
+            .unwrap_or_else(|| ZKP::generate_random_below(zkp.q()));
+        let mut auth = AuthImpl::new(Arc::new(self.store), zkp, server_x)
+            .with_challenge_ttl(self.challenge_ttl)
+            .with_session_ttl(self.session_ttl);
+        if let Some(session_max_lifetime) = self.session_max_lifetime {
+            auth = auth.with_session_max_lifetime(session_max_lifetime);
+        }
+        Ok(AuthService::new(auth))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Channel;
+
+    use super::*;
+    use crate::zkp_auth::{auth_client::AuthClient, ParametersRequest};
+
+    #[tokio::test]
+    async fn test_mounted() {
+        let service = AuthService::builder()
+            .with_store(MemoryStore::with_shards(4))
+            .with_params(ParamSet::Rfc5114_2048_256)
+            .with_server_secret(BigUint::from(7u32))
+            .build()
+            .unwrap();
+        let zkp = &service.auth_impl().zkp;
+        assert_eq!(service.server_public_key(), zkp.compute_pair(&7u32.into()));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = Channel::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let parameters = AuthClient::new(channel)
+            .get_parameters(ParametersRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            parameters.group.unwrap().param_set,
+            ParamSet::Rfc5114_2048_256.name()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_with_shutdown() {
+        let service = AuthService::builder().build().unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        service.serve_with_shutdown(addr, async {}).await.unwrap();
+    }
+}
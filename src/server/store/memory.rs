@@ -0,0 +1,421 @@
+use std::{collections::VecDeque, hash::Hash};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use super::{
+    unix_now, AuthAttempt, Device, LoginAttempt, Session, SessionStore, StoreError, UserInfo,
+    UserStore,
+};
+use crate::{AuthId, SessionId};
+
+/// Everything in process memory, gone on restart.
+///
+/// The maps are sharded, requests for different users rarely wait on each
+/// other, see `benches/store.rs`.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    users: DashMap<String, UserInfo>,
+    /// User to when they last registered or logged in.
+    last_used: DashMap<String, u64>,
+    /// User to their login history, oldest first.
+    attempts: DashMap<String, VecDeque<LoginAttempt>>,
+    /// User to the hashes of their unused recovery codes.
+    recovery_codes: DashMap<String, Vec<[u8; 32]>>,
+    challenges: DashMap<AuthId, AuthAttempt>,
+    sessions: DashMap<SessionId, Session>,
+    /// Revoked session_id to when it expires.
+    revoked: DashMap<SessionId, u64>,
+    failures: DashMap<String, u32>,
+    locked_until: DashMap<String, u64>,
+    pow_difficulty: std::sync::Mutex<Option<u32>>,
+    ip_denylist: std::sync::Mutex<Option<Vec<String>>>,
+}
+
+impl MemoryStore {
+    /// Each map in `shards` shards instead of DashMap's four per CPU, a power
+    /// of two above 1.
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            users: DashMap::with_shard_amount(shards),
+            last_used: DashMap::with_shard_amount(shards),
+            attempts: DashMap::with_shard_amount(shards),
+            recovery_codes: DashMap::with_shard_amount(shards),
+            challenges: DashMap::with_shard_amount(shards),
+            sessions: DashMap::with_shard_amount(shards),
+            revoked: DashMap::with_shard_amount(shards),
+            failures: DashMap::with_shard_amount(shards),
+            locked_until: DashMap::with_shard_amount(shards),
+            pow_difficulty: Default::default(),
+            ip_denylist: Default::default(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl UserStore for MemoryStore {
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserInfo>, StoreError> {
+        Ok(self.users.get(user_name).map(|user| user.clone()))
+    }
+
+    async fn create_user(&self, user: UserInfo) -> Result<bool, StoreError> {
+        match self.users.entry(user.user_name.clone()) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(user);
+                Ok(true)
+            }
+        }
+    }
+
+    async fn put_user(&self, user: UserInfo) -> Result<(), StoreError> {
+        self.users.insert(user.user_name.clone(), user);
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<bool, StoreError> {
+        self.last_used.remove(user_name);
+        self.attempts.remove(user_name);
+        self.recovery_codes.remove(user_name);
+        Ok(self.users.remove(user_name).is_some())
+    }
+
+    async fn list_users(&self, after: &str, limit: usize) -> Result<Vec<UserInfo>, StoreError> {
+        let mut users: Vec<UserInfo> = self
+            .users
+            .iter()
+            .filter(|user| user.key().as_str() > after)
+            .map(|user| user.clone())
+            .collect();
+        users.sort_by(|a, b| a.user_name.cmp(&b.user_name));
+        users.truncate(limit);
+        Ok(users)
+    }
+
+    async fn count_users(&self) -> Result<u64, StoreError> {
+        Ok(self.users.len() as u64)
+    }
+
+    async fn touch_user(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        self.last_used.insert(user_name.to_string(), at);
+        Ok(())
+    }
+
+    async fn record_login(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        if let Some(mut user) = self.users.get_mut(user_name) {
+            user.last_login = Some(at);
+        }
+        Ok(())
+    }
+
+    async fn set_devices(&self, user_name: &str, devices: &[Device]) -> Result<bool, StoreError> {
+        let Some(mut user) = self.users.get_mut(user_name) else {
+            return Ok(false);
+        };
+        user.devices = devices.to_vec();
+        Ok(true)
+    }
+
+    async fn set_admin(&self, user_name: &str, admin: bool) -> Result<bool, StoreError> {
+        let Some(mut user) = self.users.get_mut(user_name) else {
+            return Ok(false);
+        };
+        user.admin = admin;
+        Ok(true)
+    }
+
+    async fn record_attempt(
+        &self,
+        user_name: &str,
+        attempt: LoginAttempt,
+        keep: usize,
+    ) -> Result<(), StoreError> {
+        let mut attempts = self.attempts.entry(user_name.to_string()).or_default();
+        attempts.push_back(attempt);
+        let excess = attempts.len().saturating_sub(keep);
+        attempts.drain(..excess);
+        Ok(())
+    }
+
+    async fn list_attempts(
+        &self,
+        user_name: &str,
+        limit: usize,
+    ) -> Result<Vec<LoginAttempt>, StoreError> {
+        Ok(self
+            .attempts
+            .get(user_name)
+            .map(|attempts| attempts.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn set_recovery_codes(
+        &self,
+        user_name: &str,
+        hashes: Vec<[u8; 32]>,
+    ) -> Result<(), StoreError> {
+        self.recovery_codes.insert(user_name.to_string(), hashes);
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_name: &str,
+        hash: &[u8; 32],
+    ) -> Result<bool, StoreError> {
+        let Some(mut hashes) = self.recovery_codes.get_mut(user_name) else {
+            return Ok(false);
+        };
+        let Some(index) = hashes.iter().position(|stored| stored == hash) else {
+            return Ok(false);
+        };
+        hashes.swap_remove(index);
+        Ok(true)
+    }
+
+    async fn list_recovery_codes(&self, user_name: &str) -> Result<Vec<[u8; 32]>, StoreError> {
+        Ok(self
+            .recovery_codes
+            .get(user_name)
+            .map(|hashes| hashes.clone())
+            .unwrap_or_default())
+    }
+
+    async fn list_stale_users(
+        &self,
+        before: u64,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreError> {
+        let mut names: Vec<String> = self
+            .last_used
+            .iter()
+            .filter(|entry| *entry.value() < before && entry.key().as_str() > after)
+            .filter(|entry| self.users.contains_key(entry.key()))
+            .map(|entry| entry.key().clone())
+            .collect();
+        names.sort();
+        names.truncate(limit);
+        Ok(names)
+    }
+}
+
+#[tonic::async_trait]
+impl SessionStore for MemoryStore {
+    async fn save_challenge(
+        &self,
+        auth_id: &AuthId,
+        challenge: AuthAttempt,
+    ) -> Result<(), StoreError> {
+        self.challenges.insert(auth_id.clone(), challenge);
+        Ok(())
+    }
+
+    async fn consume_auth_id(&self, auth_id: &AuthId) -> Result<Option<AuthAttempt>, StoreError> {
+        Ok(self
+            .challenges
+            .remove(auth_id)
+            .map(|(_, challenge)| challenge))
+    }
+
+    async fn save_session(
+        &self,
+        session_id: &SessionId,
+        session: Session,
+    ) -> Result<(), StoreError> {
+        self.sessions.insert(session_id.clone(), session);
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        Ok(self.sessions.get(session_id).map(|session| session.clone()))
+    }
+
+    async fn remove_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        Ok(self.sessions.remove(session_id).map(|(_, session)| session))
+    }
+
+    async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        if let Some(mut session) = self.sessions.get_mut(session_id) {
+            session.last_seen = session.last_seen.max(at);
+            session.expires_at = session.expires_at.max(expires_at);
+        }
+        Ok(())
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        self.revoked.insert(session_id.clone(), expires_at);
+        Ok(())
+    }
+
+    async fn is_revoked(&self, session_id: &SessionId) -> Result<bool, StoreError> {
+        Ok(self
+            .revoked
+            .get(session_id)
+            .is_some_and(|expires_at| *expires_at > unix_now()))
+    }
+
+    async fn record_failure(&self, user_name: &str) -> Result<u32, StoreError> {
+        let mut failures = self.failures.entry(user_name.to_string()).or_default();
+        *failures += 1;
+        Ok(*failures)
+    }
+
+    async fn clear_failures(&self, user_name: &str) -> Result<(), StoreError> {
+        self.failures.remove(user_name);
+        Ok(())
+    }
+
+    async fn lock_user(&self, user_name: &str, until: u64) -> Result<(), StoreError> {
+        self.locked_until.insert(user_name.to_string(), until);
+        self.failures.remove(user_name);
+        Ok(())
+    }
+
+    async fn locked_until(&self, user_name: &str) -> Result<Option<u64>, StoreError> {
+        Ok(self.locked_until.get(user_name).map(|until| *until))
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<u64, StoreError> {
+        self.challenges
+            .retain(|_, attempt| attempt.user_name != user_name);
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|_, session| session.user_name != user_name);
+        self.failures.remove(user_name);
+        self.locked_until.remove(user_name);
+        Ok(before.saturating_sub(self.sessions.len()) as u64)
+    }
+
+    async fn list_sessions(
+        &self,
+        user_name: &str,
+    ) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        Ok(self
+            .sessions
+            .iter()
+            .filter(|session| session.user_name == user_name)
+            .map(|session| (session.key().clone(), session.clone()))
+            .collect())
+    }
+
+    async fn list_all_sessions(&self) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        Ok(self
+            .sessions
+            .iter()
+            .map(|session| (session.key().clone(), session.clone()))
+            .collect())
+    }
+
+    async fn count_sessions(&self) -> Result<u64, StoreError> {
+        Ok(self
+            .sessions
+            .iter()
+            .filter(|session| !session.is_expired())
+            .count() as u64)
+    }
+
+    async fn purge_expired(&self, now: u64, limit: usize) -> Result<u64, StoreError> {
+        let mut purged = purge(&self.challenges, limit, |_, attempt| {
+            attempt.expires_at <= now
+        });
+        purged += purge(&self.sessions, limit - purged, |_, session| {
+            session.expires_at <= now
+        });
+        purged += purge(&self.revoked, limit - purged, |_, expires_at| {
+            *expires_at <= now
+        });
+        // As the database backends, which keep both in one row, a lockout
+        // stays while failures are counted since.
+        purged += purge(&self.locked_until, limit - purged, |user_name, until| {
+            *until <= now && !self.failures.contains_key(user_name)
+        });
+        Ok(purged as u64)
+    }
+
+    async fn pow_difficulty(&self) -> Result<Option<u32>, StoreError> {
+        Ok(*self.pow_difficulty.lock().unwrap())
+    }
+
+    async fn set_pow_difficulty(&self, difficulty: u32) -> Result<(), StoreError> {
+        *self.pow_difficulty.lock().unwrap() = Some(difficulty);
+        Ok(())
+    }
+
+    async fn ip_denylist(&self) -> Result<Option<Vec<String>>, StoreError> {
+        Ok(self.ip_denylist.lock().unwrap().clone())
+    }
+
+    async fn set_ip_denylist(&self, cidrs: &[String]) -> Result<(), StoreError> {
+        *self.ip_denylist.lock().unwrap() = Some(cidrs.to_vec());
+        Ok(())
+    }
+}
+
+/// Removes up to `limit` entries of `map` that are `expired`, returns how
+/// many went.
+fn purge<K: Eq + Hash + Clone, V>(
+    map: &DashMap<K, V>,
+    limit: usize,
+    expired: impl Fn(&K, &V) -> bool,
+) -> usize {
+    let keys: Vec<K> = map
+        .iter()
+        .filter(|entry| expired(entry.key(), entry.value()))
+        .take(limit)
+        .map(|entry| entry.key().clone())
+        .collect();
+    // Only while still expired, a user may have been locked out again since.
+    keys.iter()
+        .filter(|key| {
+            map.remove_if(*key, |key, value| expired(key, value))
+                .is_some()
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::server::store::ChallengeState;
+
+    #[tokio::test]
+    async fn test_auth_id_is_consumed_once() {
+        let store = MemoryStore::default();
+        let challenge = AuthAttempt {
+            user_name: "alice".to_string(),
+            state: ChallengeState::Opened {
+                r1: BigUint::from(1u32),
+                r2: BigUint::from(2u32),
+                c: BigUint::from(3u32),
+            },
+            expires_at: 1_700_000_000,
+        };
+        store
+            .save_challenge(&AuthId::from("auth"), challenge)
+            .await
+            .unwrap();
+
+        let consumed = store
+            .consume_auth_id(&AuthId::from("auth"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consumed.user_name, "alice");
+        assert!(store
+            .consume_auth_id(&AuthId::from("auth"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
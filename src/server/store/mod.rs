@@ -0,0 +1,1306 @@
+//! Persistence behind `AuthImpl`. Handlers only go through `UserStore` and
+//! `SessionStore`, so where users, challenges and sessions live is up to the
+//! implementation.
+
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "sled")]
+pub mod sled;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use tonic::{Code, Status};
+
+use crate::{
+    AuthId, ChallengeOpening, ChallengeSpec, CommittedChallenge, KdfParams, SessionId,
+    OPENING_NONCE_LEN, ZKP,
+};
+
+pub use memory::MemoryStore;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+#[cfg(feature = "redis")]
+pub use redis::RedisStore;
+#[cfg(feature = "sled")]
+pub use sled::SledStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// A registered user.
+#[derive(Debug, Clone, Default)]
+pub struct UserInfo {
+    pub user_name: String,
+    pub y1: BigUint,
+    pub y2: BigUint,
+    pub salt: Vec<u8>,
+    pub kdf_params: KdfParams,
+    /// The group y1 and y2 live in when the user registered in one other
+    /// than the server's.
+    pub group: Option<ZKP>,
+    /// Unix time in seconds the user registered at, 0 for users from before
+    /// it was noted.
+    pub created_at: u64,
+    /// Unix time in seconds of their last login, see `record_login`.
+    pub last_login: Option<u64>,
+    /// Whatever the client registered the user with, the server doesn't
+    /// look into it.
+    pub metadata: BTreeMap<String, String>,
+    /// Whether the user's sessions may call AuthAdmin, see `AdminAuth`.
+    pub admin: bool,
+    /// The secret of the user's TOTP codes, when they enrolled in TOTP and
+    /// their logins need a code too.
+    pub totp_secret: Option<Vec<u8>>,
+    /// Keys added for the user's devices, any of which logs them in as well
+    /// as y1 and y2 do. In the user's group.
+    pub devices: Vec<Device>,
+}
+
+/// A key of a user's device, see `DeviceKey`. Its x comes from a password
+/// of its own, or wherever the device keeps it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// Names the device among the user's.
+    pub label: String,
+    pub y1: BigUint,
+    pub y2: BigUint,
+    /// What GetSalt answers for the device.
+    pub salt: Vec<u8>,
+    pub kdf_params: KdfParams,
+    /// Unix time in seconds the device was added at.
+    pub added_at: u64,
+}
+
+/// An answer to a login challenge, right or wrong, in the user's history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginAttempt {
+    /// Unix time in seconds the answer came.
+    pub at: u64,
+    pub success: bool,
+    /// None over a Unix socket.
+    pub source_ip: Option<String>,
+    pub auth_id: String,
+}
+
+/// One login attempt, keyed by the auth_id its challenge went out under and
+/// not answered yet. A user can have any number of these at once, one per
+/// device or tab, and each carries its own r1, r2 and c.
+#[derive(Debug, Clone)]
+pub struct AuthAttempt {
+    pub user_name: String,
+    pub state: ChallengeState,
+    /// Unix time in seconds from which the auth_id is refused.
+    pub expires_at: u64,
+}
+
+impl AuthAttempt {
+    pub fn is_expired(&self) -> bool {
+        unix_now() >= self.expires_at
+    }
+
+    /// How much longer the attempt is good for, zero once expired.
+    pub fn ttl(&self) -> Duration {
+        until(self.expires_at)
+    }
+}
+
+/// A logged in user, stored under the session_id handed out for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub user_name: String,
+    /// Unix time in seconds of the login the session goes back to, kept
+    /// across refreshes.
+    pub created_at: u64,
+    /// Unix time in seconds from which the session is refused, pushed out
+    /// by each use with sliding expiry.
+    pub expires_at: u64,
+    /// Label of the device whose key the login proved, none for the
+    /// user's own.
+    pub device: Option<String>,
+    /// Unix time in seconds the session was last used at, to the minute or
+    /// so, see `SessionStore::touch_session`.
+    pub last_seen: u64,
+}
+
+impl Session {
+    pub fn is_expired(&self) -> bool {
+        unix_now() >= self.expires_at
+    }
+
+    /// How much longer the session is good for, zero once expired.
+    pub fn ttl(&self) -> Duration {
+        until(self.expires_at)
+    }
+}
+
+/// Seconds since the Unix epoch, clocks before 1970 count as 0.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn until(expires_at: u64) -> Duration {
+    Duration::from_secs(expires_at.saturating_sub(unix_now()))
+}
+
+/// Where a login stands.
+#[derive(Debug, Clone)]
+pub enum ChallengeState {
+    /// Classic login, c went out bound to the auth_id, see `ChallengeBinding`.
+    Bound {
+        r1: BigUint,
+        r2: BigUint,
+        c: BigUint,
+        seed: Vec<u8>,
+        spec: ChallengeSpec,
+        /// `ChallengeBinding::version` and `max_version`.
+        version: u32,
+        max_version: u32,
+    },
+    /// Four-move login after move 1, c is committed to but not sent.
+    Committed(CommittedChallenge),
+    /// Four-move login after move 3.
+    Opened {
+        r1: BigUint,
+        r2: BigUint,
+        c: BigUint,
+    },
+}
+
+/// `ChallengeState` as stored by the database backends, integers and bytes
+/// in hex.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum StoredState {
+    Bound {
+        r1: String,
+        r2: String,
+        c: String,
+        seed: String,
+        bits: u64,
+        rounds: usize,
+        #[serde(default = "legacy_version")]
+        version: u32,
+        #[serde(default = "legacy_version")]
+        max_version: u32,
+    },
+    Committed {
+        c: String,
+        nonce: String,
+    },
+    Opened {
+        r1: String,
+        r2: String,
+        c: String,
+    },
+}
+
+/// `UserInfo` for backends that keep the whole record in one value.
+#[derive(Serialize, Deserialize)]
+struct StoredUser {
+    user_name: String,
+    y1: String,
+    y2: String,
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    /// `ZKP::to_bytes` in hex, absent for the server's group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(default)]
+    created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_login: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    admin: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    totp_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    devices: Vec<StoredDevice>,
+}
+
+/// `Device` in JSON, inside `StoredUser` and on its own in the database
+/// backends' devices column.
+#[derive(Serialize, Deserialize)]
+struct StoredDevice {
+    label: String,
+    y1: String,
+    y2: String,
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    added_at: u64,
+}
+
+impl From<&Device> for StoredDevice {
+    fn from(device: &Device) -> Self {
+        Self {
+            label: device.label.clone(),
+            y1: to_hex(&device.y1),
+            y2: to_hex(&device.y2),
+            salt: hex::encode(&device.salt),
+            m_cost: device.kdf_params.m_cost,
+            t_cost: device.kdf_params.t_cost,
+            p_cost: device.kdf_params.p_cost,
+            added_at: device.added_at,
+        }
+    }
+}
+
+impl TryFrom<StoredDevice> for Device {
+    type Error = StoreError;
+
+    fn try_from(device: StoredDevice) -> Result<Self, StoreError> {
+        Ok(Self {
+            label: device.label,
+            y1: from_hex(&device.y1)?,
+            y2: from_hex(&device.y2)?,
+            salt: hex::decode(device.salt).map_err(StoreError::corrupt)?,
+            kdf_params: KdfParams {
+                m_cost: device.m_cost,
+                t_cost: device.t_cost,
+                p_cost: device.p_cost,
+            },
+            added_at: device.added_at,
+        })
+    }
+}
+
+impl UserInfo {
+    pub fn to_json(&self) -> String {
+        let user = StoredUser {
+            user_name: self.user_name.clone(),
+            y1: to_hex(&self.y1),
+            y2: to_hex(&self.y2),
+            salt: hex::encode(&self.salt),
+            m_cost: self.kdf_params.m_cost,
+            t_cost: self.kdf_params.t_cost,
+            p_cost: self.kdf_params.p_cost,
+            group: group_to_bytes(&self.group).map(hex::encode),
+            created_at: self.created_at,
+            last_login: self.last_login,
+            metadata: self.metadata.clone(),
+            admin: self.admin,
+            totp_secret: self.totp_secret.as_ref().map(hex::encode),
+            devices: self.devices.iter().map(StoredDevice::from).collect(),
+        };
+        serde_json::to_string(&user).expect("Plain strings and numbers serialize.")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, StoreError> {
+        let user: StoredUser = serde_json::from_str(json).map_err(StoreError::corrupt)?;
+        Ok(Self {
+            user_name: user.user_name,
+            y1: from_hex(&user.y1)?,
+            y2: from_hex(&user.y2)?,
+            salt: hex::decode(user.salt).map_err(StoreError::corrupt)?,
+            kdf_params: KdfParams {
+                m_cost: user.m_cost,
+                t_cost: user.t_cost,
+                p_cost: user.p_cost,
+            },
+            group: group_from_bytes(
+                user.group
+                    .map(hex::decode)
+                    .transpose()
+                    .map_err(StoreError::corrupt)?,
+            )?,
+            created_at: user.created_at,
+            last_login: user.last_login,
+            metadata: user.metadata,
+            admin: user.admin,
+            totp_secret: user
+                .totp_secret
+                .map(hex::decode)
+                .transpose()
+                .map_err(StoreError::corrupt)?,
+            devices: user
+                .devices
+                .into_iter()
+                .map(Device::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// `AuthAttempt` for backends that keep the whole record in one value.
+#[derive(Serialize, Deserialize)]
+struct StoredAttempt {
+    user_name: String,
+    state: StoredState,
+    expires_at: u64,
+}
+
+impl AuthAttempt {
+    pub fn to_json(&self) -> String {
+        let challenge = StoredAttempt {
+            user_name: self.user_name.clone(),
+            state: self.state.to_stored(),
+            expires_at: self.expires_at,
+        };
+        serde_json::to_string(&challenge).expect("Plain strings and numbers serialize.")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, StoreError> {
+        let challenge: StoredAttempt = serde_json::from_str(json).map_err(StoreError::corrupt)?;
+        Ok(Self {
+            user_name: challenge.user_name,
+            state: ChallengeState::from_stored(challenge.state)?,
+            expires_at: challenge.expires_at,
+        })
+    }
+}
+
+/// `Session` for backends that keep the whole record in one value.
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    user_name: String,
+    created_at: u64,
+    expires_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    /// Absent from sessions stored before it was kept, `created_at` then.
+    #[serde(default)]
+    last_seen: Option<u64>,
+}
+
+impl Session {
+    pub fn to_json(&self) -> String {
+        let session = StoredSession {
+            user_name: self.user_name.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            device: self.device.clone(),
+            last_seen: Some(self.last_seen),
+        };
+        serde_json::to_string(&session).expect("Plain strings and numbers serialize.")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, StoreError> {
+        let session: StoredSession = serde_json::from_str(json).map_err(StoreError::corrupt)?;
+        Ok(Self {
+            user_name: session.user_name,
+            created_at: session.created_at,
+            expires_at: session.expires_at,
+            device: session.device,
+            last_seen: session.last_seen.unwrap_or(session.created_at),
+        })
+    }
+}
+
+impl ChallengeState {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.to_stored()).expect("Plain strings and numbers serialize.")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, StoreError> {
+        Self::from_stored(serde_json::from_str(json).map_err(StoreError::corrupt)?)
+    }
+
+    fn to_stored(&self) -> StoredState {
+        match self {
+            ChallengeState::Bound {
+                r1,
+                r2,
+                c,
+                seed,
+                spec,
+                version,
+                max_version,
+            } => StoredState::Bound {
+                r1: to_hex(r1),
+                r2: to_hex(r2),
+                c: to_hex(c),
+                seed: hex::encode(seed),
+                bits: spec.bits,
+                rounds: spec.rounds,
+                version: *version,
+                max_version: *max_version,
+            },
+            ChallengeState::Committed(committed) => {
+                let opening = committed.clone().open();
+                StoredState::Committed {
+                    c: to_hex(&opening.c),
+                    nonce: hex::encode(opening.nonce),
+                }
+            }
+            ChallengeState::Opened { r1, r2, c } => StoredState::Opened {
+                r1: to_hex(r1),
+                r2: to_hex(r2),
+                c: to_hex(c),
+            },
+        }
+    }
+
+    fn from_stored(state: StoredState) -> Result<Self, StoreError> {
+        Ok(match state {
+            StoredState::Bound {
+                r1,
+                r2,
+                c,
+                seed,
+                bits,
+                rounds,
+                version,
+                max_version,
+            } => ChallengeState::Bound {
+                r1: from_hex(&r1)?,
+                r2: from_hex(&r2)?,
+                c: from_hex(&c)?,
+                seed: hex::decode(seed).map_err(StoreError::corrupt)?,
+                spec: ChallengeSpec { bits, rounds },
+                version,
+                max_version,
+            },
+            StoredState::Committed { c, nonce } => {
+                let nonce: [u8; OPENING_NONCE_LEN] = hex::decode(nonce)
+                    .map_err(StoreError::corrupt)?
+                    .try_into()
+                    .map_err(|_| StoreError::Corrupt("nonce length".into()))?;
+                ChallengeState::Committed(CommittedChallenge::from_opening(ChallengeOpening {
+                    c: from_hex(&c)?,
+                    nonce,
+                }))
+            }
+            StoredState::Opened { r1, r2, c } => ChallengeState::Opened {
+                r1: from_hex(&r1)?,
+                r2: from_hex(&r2)?,
+                c: from_hex(&c)?,
+            },
+        })
+    }
+}
+
+/// Challenges stored before protocol versions existed were version 1.
+fn legacy_version() -> u32 {
+    1
+}
+
+fn to_hex(n: &BigUint) -> String {
+    hex::encode(n.to_bytes_be())
+}
+
+fn from_hex(s: &str) -> Result<BigUint, StoreError> {
+    Ok(BigUint::from_bytes_be(
+        &hex::decode(s).map_err(StoreError::corrupt)?,
+    ))
+}
+
+/// `UserInfo::group` as the database backends store it.
+fn group_to_bytes(group: &Option<ZKP>) -> Option<Vec<u8>> {
+    group.as_ref().map(ZKP::to_bytes)
+}
+
+fn group_from_bytes(bytes: Option<Vec<u8>>) -> Result<Option<ZKP>, StoreError> {
+    bytes
+        .map(|bytes| ZKP::from_bytes(&bytes))
+        .transpose()
+        .map_err(StoreError::corrupt)
+}
+
+/// `UserInfo::metadata` as the database backends store it, a JSON object.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn metadata_to_json(metadata: &BTreeMap<String, String>) -> String {
+    serde_json::to_string(metadata).expect("Strings serialize.")
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn metadata_from_json(json: &str) -> Result<BTreeMap<String, String>, StoreError> {
+    serde_json::from_str(json).map_err(StoreError::corrupt)
+}
+
+/// `UserInfo::devices` as the database backends store it, a JSON array.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn devices_to_json(devices: &[Device]) -> String {
+    let devices: Vec<StoredDevice> = devices.iter().map(StoredDevice::from).collect();
+    serde_json::to_string(&devices).expect("Plain strings and numbers serialize.")
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn devices_from_json(json: &str) -> Result<Vec<Device>, StoreError> {
+    let devices: Vec<StoredDevice> = serde_json::from_str(json).map_err(StoreError::corrupt)?;
+    devices.into_iter().map(Device::try_from).collect()
+}
+
+/// A migration of a database backend that failed. A database with a
+/// migration this server doesn't know is from a newer server, and is left
+/// as it is.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn migrate_error(err: sqlx::migrate::MigrateError) -> StoreError {
+    match err {
+        sqlx::migrate::MigrateError::VersionMissing(version) => newer_schema(version),
+        err => StoreError::backend(err),
+    }
+}
+
+/// A database at a schema version past what this server knows.
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "sled"))]
+fn newer_schema(version: impl std::fmt::Display) -> StoreError {
+    StoreError::Backend(format!(
+        "the database is at schema version {version}, from a newer server, upgrade this one"
+    ))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("storage backend failed: {0}")]
+    Backend(String),
+    /// A stored record that doesn't decode.
+    #[error("corrupt record: {0}")]
+    Corrupt(String),
+}
+
+impl StoreError {
+    pub fn backend(err: impl std::fmt::Display) -> Self {
+        Self::Backend(err.to_string())
+    }
+
+    pub fn corrupt(err: impl std::fmt::Display) -> Self {
+        Self::Corrupt(err.to_string())
+    }
+}
+
+impl From<StoreError> for Status {
+    fn from(err: StoreError) -> Self {
+        log::error!("{err}");
+        Status::new(Code::Internal, "Storage failure.")
+    }
+}
+
+#[tonic::async_trait]
+pub trait UserStore: Send + Sync + 'static {
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserInfo>, StoreError>;
+
+    /// Inserts the user unless one of that name exists, returns whether it
+    /// did. Two registrations racing for a name can't both win.
+    async fn create_user(&self, user: UserInfo) -> Result<bool, StoreError>;
+
+    /// Inserts or replaces the user of that name.
+    async fn put_user(&self, user: UserInfo) -> Result<(), StoreError>;
+
+    /// Removes the user, returns whether there was one.
+    async fn delete_user(&self, user_name: &str) -> Result<bool, StoreError>;
+
+    /// Up to `limit` users named after `after`, ordered by name. An empty
+    /// `after` starts at the first user.
+    async fn list_users(&self, after: &str, limit: usize) -> Result<Vec<UserInfo>, StoreError>;
+
+    /// Notes that the user registered or logged in at `at`, Unix time in
+    /// seconds.
+    async fn touch_user(&self, user_name: &str, at: u64) -> Result<(), StoreError>;
+
+    /// Sets `UserInfo::last_login` of the user, if there is one, to `at`.
+    async fn record_login(&self, user_name: &str, at: u64) -> Result<(), StoreError>;
+
+    /// Sets `UserInfo::devices` of the user and leaves the rest of the
+    /// record alone, returns whether there was one. Unlike `put_user`, a
+    /// concurrent UpdateCredentials isn't undone nor a deleted user revived.
+    async fn set_devices(&self, user_name: &str, devices: &[Device]) -> Result<bool, StoreError>;
+
+    /// Sets `UserInfo::admin` of the user, as `set_devices`.
+    async fn set_admin(&self, user_name: &str, admin: bool) -> Result<bool, StoreError>;
+
+    /// Adds the attempt to the user's history, and drops the oldest past the
+    /// `keep` newest. `delete_user` drops all of them.
+    async fn record_attempt(
+        &self,
+        user_name: &str,
+        attempt: LoginAttempt,
+        keep: usize,
+    ) -> Result<(), StoreError>;
+
+    /// Up to `limit` attempts of the user's history, newest first.
+    async fn list_attempts(
+        &self,
+        user_name: &str,
+        limit: usize,
+    ) -> Result<Vec<LoginAttempt>, StoreError>;
+
+    /// Replaces the user's recovery codes by `hashes`, see
+    /// `hash_recovery_code`. `delete_user` drops them.
+    async fn set_recovery_codes(
+        &self,
+        user_name: &str,
+        hashes: Vec<[u8; 32]>,
+    ) -> Result<(), StoreError>;
+
+    /// Removes `hash` from the user's recovery codes, returns whether it was
+    /// one. Two recoveries racing for a code can't both win.
+    async fn use_recovery_code(&self, user_name: &str, hash: &[u8; 32])
+        -> Result<bool, StoreError>;
+
+    /// The hashes of the user's unused recovery codes, in no particular
+    /// order, for backups.
+    async fn list_recovery_codes(&self, user_name: &str) -> Result<Vec<[u8; 32]>, StoreError>;
+
+    /// Up to `limit` names of users last used before `before`, named after
+    /// `after` and ordered by name. Users the store had before uses were
+    /// noted count as used when it was first opened since.
+    async fn list_stale_users(
+        &self,
+        before: u64,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreError>;
+
+    /// How many users are registered.
+    async fn count_users(&self) -> Result<u64, StoreError>;
+
+    /// Fails when the backend can't be reached, for health checks.
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Writes out whatever the backend still buffers and closes its
+    /// connections, once the server is done serving. Challenges and sessions
+    /// are short-lived, so only the user store is shut down.
+    async fn shutdown(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    async fn save_challenge(
+        &self,
+        auth_id: &AuthId,
+        attempt: AuthAttempt,
+    ) -> Result<(), StoreError>;
+
+    /// Removes and returns the challenge, so an auth_id is used at most once
+    /// even when two requests race for it. Expired attempts may still be
+    /// returned, the caller checks `AuthAttempt::is_expired`.
+    async fn consume_auth_id(&self, auth_id: &AuthId) -> Result<Option<AuthAttempt>, StoreError>;
+
+    async fn save_session(
+        &self,
+        session_id: &SessionId,
+        session: Session,
+    ) -> Result<(), StoreError>;
+
+    /// The session, expired or not, the caller checks `Session::is_expired`.
+    async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError>;
+
+    /// Removes and returns the session, so it is refreshed at most once even
+    /// when two requests race for it.
+    async fn remove_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError>;
+
+    /// Notes the session as used at `at` and pushes its expiry out to
+    /// `expires_at`, Unix times in seconds. Neither moves back. A session
+    /// that is gone stays gone.
+    async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError>;
+
+    /// Puts the session_id on the revocation list until `expires_at`, when
+    /// it would be refused anyway.
+    async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        expires_at: u64,
+    ) -> Result<(), StoreError>;
+
+    async fn is_revoked(&self, session_id: &SessionId) -> Result<bool, StoreError>;
+
+    /// Counts a failed verification for the user, returns the failures since
+    /// the last `clear_failures` or `lock_user`.
+    async fn record_failure(&self, user_name: &str) -> Result<u32, StoreError>;
+
+    async fn clear_failures(&self, user_name: &str) -> Result<(), StoreError>;
+
+    /// Locks the user out until `until`, Unix time in seconds, and starts
+    /// counting failures from zero again.
+    async fn lock_user(&self, user_name: &str, until: u64) -> Result<(), StoreError>;
+
+    /// When the user's lockout ends, if they were ever locked out. May be in
+    /// the past.
+    async fn locked_until(&self, user_name: &str) -> Result<Option<u64>, StoreError>;
+
+    /// Removes every pending challenge, session and failure count of the
+    /// user in one go, returns how many sessions there were.
+    async fn purge_user(&self, user_name: &str) -> Result<u64, StoreError>;
+
+    /// Every session of the user by session_id, expired ones included.
+    async fn list_sessions(&self, user_name: &str)
+        -> Result<Vec<(SessionId, Session)>, StoreError>;
+
+    /// Every session of every user by session_id, expired ones included, in
+    /// one pass for backups.
+    async fn list_all_sessions(&self) -> Result<Vec<(SessionId, Session)>, StoreError>;
+
+    /// How many sessions of all users have not expired yet.
+    async fn count_sessions(&self) -> Result<u64, StoreError>;
+
+    /// Removes up to `limit` records that are of no more use at `now`, Unix
+    /// time in seconds: expired challenges, sessions and revocations, and
+    /// lockouts that have ended with no failures counted since. Returns how
+    /// many went, fewer than `limit` once there are none left.
+    async fn purge_expired(&self, now: u64, limit: usize) -> Result<u64, StoreError>;
+
+    /// The registration proof of work difficulty an admin set last, if any,
+    /// which replicas in CLUSTER mode go by.
+    async fn pow_difficulty(&self) -> Result<Option<u32>, StoreError>;
+
+    async fn set_pow_difficulty(&self, difficulty: u32) -> Result<(), StoreError>;
+
+    /// The addresses and CIDR blocks an admin denied last, if ever, which
+    /// replicas in CLUSTER mode go by.
+    async fn ip_denylist(&self) -> Result<Option<Vec<String>>, StoreError>;
+
+    async fn set_ip_denylist(&self, cidrs: &[String]) -> Result<(), StoreError>;
+
+    /// Fails when the backend can't be reached, for health checks.
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// One store shared by the services, e.g. `AuthImpl` and `AdminImpl`. Each
+/// call but health checks and metrics gets a tracing span.
+#[tonic::async_trait]
+impl<T: UserStore> UserStore for Arc<T> {
+    #[tracing::instrument(skip_all)]
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserInfo>, StoreError> {
+        (**self).get_user(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn create_user(&self, user: UserInfo) -> Result<bool, StoreError> {
+        (**self).create_user(user).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn put_user(&self, user: UserInfo) -> Result<(), StoreError> {
+        (**self).put_user(user).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn delete_user(&self, user_name: &str) -> Result<bool, StoreError> {
+        (**self).delete_user(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn list_users(&self, after: &str, limit: usize) -> Result<Vec<UserInfo>, StoreError> {
+        (**self).list_users(after, limit).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn touch_user(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        (**self).touch_user(user_name, at).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn record_login(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        (**self).record_login(user_name, at).await
+    }
+
+    async fn set_devices(&self, user_name: &str, devices: &[Device]) -> Result<bool, StoreError> {
+        (**self).set_devices(user_name, devices).await
+    }
+
+    async fn set_admin(&self, user_name: &str, admin: bool) -> Result<bool, StoreError> {
+        (**self).set_admin(user_name, admin).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn record_attempt(
+        &self,
+        user_name: &str,
+        attempt: LoginAttempt,
+        keep: usize,
+    ) -> Result<(), StoreError> {
+        (**self).record_attempt(user_name, attempt, keep).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn list_attempts(
+        &self,
+        user_name: &str,
+        limit: usize,
+    ) -> Result<Vec<LoginAttempt>, StoreError> {
+        (**self).list_attempts(user_name, limit).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn set_recovery_codes(
+        &self,
+        user_name: &str,
+        hashes: Vec<[u8; 32]>,
+    ) -> Result<(), StoreError> {
+        (**self).set_recovery_codes(user_name, hashes).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn use_recovery_code(
+        &self,
+        user_name: &str,
+        hash: &[u8; 32],
+    ) -> Result<bool, StoreError> {
+        (**self).use_recovery_code(user_name, hash).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn list_recovery_codes(&self, user_name: &str) -> Result<Vec<[u8; 32]>, StoreError> {
+        (**self).list_recovery_codes(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn list_stale_users(
+        &self,
+        before: u64,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreError> {
+        (**self).list_stale_users(before, after, limit).await
+    }
+
+    async fn count_users(&self) -> Result<u64, StoreError> {
+        (**self).count_users().await
+    }
+
+    async fn shutdown(&self) -> Result<(), StoreError> {
+        (**self).shutdown().await
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        UserStore::ping(&**self).await
+    }
+}
+
+#[tonic::async_trait]
+impl<T: SessionStore> SessionStore for Arc<T> {
+    #[tracing::instrument(skip_all)]
+    async fn save_challenge(
+        &self,
+        auth_id: &AuthId,
+        attempt: AuthAttempt,
+    ) -> Result<(), StoreError> {
+        (**self).save_challenge(auth_id, attempt).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn consume_auth_id(&self, auth_id: &AuthId) -> Result<Option<AuthAttempt>, StoreError> {
+        (**self).consume_auth_id(auth_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn save_session(
+        &self,
+        session_id: &SessionId,
+        session: Session,
+    ) -> Result<(), StoreError> {
+        (**self).save_session(session_id, session).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        (**self).get_session(session_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn remove_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        (**self).remove_session(session_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        (**self).touch_session(session_id, at, expires_at).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        (**self).revoke_session(session_id, expires_at).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn is_revoked(&self, session_id: &SessionId) -> Result<bool, StoreError> {
+        (**self).is_revoked(session_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn record_failure(&self, user_name: &str) -> Result<u32, StoreError> {
+        (**self).record_failure(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn clear_failures(&self, user_name: &str) -> Result<(), StoreError> {
+        (**self).clear_failures(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn lock_user(&self, user_name: &str, until: u64) -> Result<(), StoreError> {
+        (**self).lock_user(user_name, until).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn locked_until(&self, user_name: &str) -> Result<Option<u64>, StoreError> {
+        (**self).locked_until(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn purge_user(&self, user_name: &str) -> Result<u64, StoreError> {
+        (**self).purge_user(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn list_sessions(
+        &self,
+        user_name: &str,
+    ) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        (**self).list_sessions(user_name).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn list_all_sessions(&self) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        (**self).list_all_sessions().await
+    }
+
+    async fn count_sessions(&self) -> Result<u64, StoreError> {
+        (**self).count_sessions().await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn purge_expired(&self, now: u64, limit: usize) -> Result<u64, StoreError> {
+        (**self).purge_expired(now, limit).await
+    }
+
+    async fn pow_difficulty(&self) -> Result<Option<u32>, StoreError> {
+        (**self).pow_difficulty().await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn set_pow_difficulty(&self, difficulty: u32) -> Result<(), StoreError> {
+        (**self).set_pow_difficulty(difficulty).await
+    }
+
+    async fn ip_denylist(&self) -> Result<Option<Vec<String>>, StoreError> {
+        (**self).ip_denylist().await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn set_ip_denylist(&self, cidrs: &[String]) -> Result<(), StoreError> {
+        (**self).set_ip_denylist(cidrs).await
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        SessionStore::ping(&**self).await
+    }
+}
+
+/// Users from one store, challenges and sessions from another, e.g. users in
+/// PostgreSQL and the short-lived state in Redis.
+#[derive(Debug)]
+pub struct SplitStore<U, S> {
+    pub users: U,
+    pub sessions: S,
+}
+
+#[tonic::async_trait]
+impl<U: UserStore, S: Send + Sync + 'static> UserStore for SplitStore<U, S> {
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserInfo>, StoreError> {
+        self.users.get_user(user_name).await
+    }
+
+    async fn create_user(&self, user: UserInfo) -> Result<bool, StoreError> {
+        self.users.create_user(user).await
+    }
+
+    async fn put_user(&self, user: UserInfo) -> Result<(), StoreError> {
+        self.users.put_user(user).await
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<bool, StoreError> {
+        self.users.delete_user(user_name).await
+    }
+
+    async fn list_users(&self, after: &str, limit: usize) -> Result<Vec<UserInfo>, StoreError> {
+        self.users.list_users(after, limit).await
+    }
+
+    async fn touch_user(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        self.users.touch_user(user_name, at).await
+    }
+
+    async fn record_login(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        self.users.record_login(user_name, at).await
+    }
+
+    async fn set_devices(&self, user_name: &str, devices: &[Device]) -> Result<bool, StoreError> {
+        self.users.set_devices(user_name, devices).await
+    }
+
+    async fn set_admin(&self, user_name: &str, admin: bool) -> Result<bool, StoreError> {
+        self.users.set_admin(user_name, admin).await
+    }
+
+    async fn record_attempt(
+        &self,
+        user_name: &str,
+        attempt: LoginAttempt,
+        keep: usize,
+    ) -> Result<(), StoreError> {
+        self.users.record_attempt(user_name, attempt, keep).await
+    }
+
+    async fn list_attempts(
+        &self,
+        user_name: &str,
+        limit: usize,
+    ) -> Result<Vec<LoginAttempt>, StoreError> {
+        self.users.list_attempts(user_name, limit).await
+    }
+
+    async fn set_recovery_codes(
+        &self,
+        user_name: &str,
+        hashes: Vec<[u8; 32]>,
+    ) -> Result<(), StoreError> {
+        self.users.set_recovery_codes(user_name, hashes).await
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_name: &str,
+        hash: &[u8; 32],
+    ) -> Result<bool, StoreError> {
+        self.users.use_recovery_code(user_name, hash).await
+    }
+
+    async fn list_recovery_codes(&self, user_name: &str) -> Result<Vec<[u8; 32]>, StoreError> {
+        self.users.list_recovery_codes(user_name).await
+    }
+
+    async fn list_stale_users(
+        &self,
+        before: u64,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreError> {
+        self.users.list_stale_users(before, after, limit).await
+    }
+
+    async fn count_users(&self) -> Result<u64, StoreError> {
+        self.users.count_users().await
+    }
+
+    async fn shutdown(&self) -> Result<(), StoreError> {
+        self.users.shutdown().await
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.users.ping().await
+    }
+}
+
+#[tonic::async_trait]
+impl<U: Send + Sync + 'static, S: SessionStore> SessionStore for SplitStore<U, S> {
+    async fn save_challenge(
+        &self,
+        auth_id: &AuthId,
+        challenge: AuthAttempt,
+    ) -> Result<(), StoreError> {
+        self.sessions.save_challenge(auth_id, challenge).await
+    }
+
+    async fn consume_auth_id(&self, auth_id: &AuthId) -> Result<Option<AuthAttempt>, StoreError> {
+        self.sessions.consume_auth_id(auth_id).await
+    }
+
+    async fn save_session(
+        &self,
+        session_id: &SessionId,
+        session: Session,
+    ) -> Result<(), StoreError> {
+        self.sessions.save_session(session_id, session).await
+    }
+
+    async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        self.sessions.get_session(session_id).await
+    }
+
+    async fn remove_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        self.sessions.remove_session(session_id).await
+    }
+
+    async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        self.sessions
+            .touch_session(session_id, at, expires_at)
+            .await
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        self.sessions.revoke_session(session_id, expires_at).await
+    }
+
+    async fn is_revoked(&self, session_id: &SessionId) -> Result<bool, StoreError> {
+        self.sessions.is_revoked(session_id).await
+    }
+
+    async fn record_failure(&self, user_name: &str) -> Result<u32, StoreError> {
+        self.sessions.record_failure(user_name).await
+    }
+
+    async fn clear_failures(&self, user_name: &str) -> Result<(), StoreError> {
+        self.sessions.clear_failures(user_name).await
+    }
+
+    async fn lock_user(&self, user_name: &str, until: u64) -> Result<(), StoreError> {
+        self.sessions.lock_user(user_name, until).await
+    }
+
+    async fn locked_until(&self, user_name: &str) -> Result<Option<u64>, StoreError> {
+        self.sessions.locked_until(user_name).await
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<u64, StoreError> {
+        self.sessions.purge_user(user_name).await
+    }
+
+    async fn list_sessions(
+        &self,
+        user_name: &str,
+    ) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        self.sessions.list_sessions(user_name).await
+    }
+
+    async fn list_all_sessions(&self) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        self.sessions.list_all_sessions().await
+    }
+
+    async fn count_sessions(&self) -> Result<u64, StoreError> {
+        self.sessions.count_sessions().await
+    }
+
+    async fn purge_expired(&self, now: u64, limit: usize) -> Result<u64, StoreError> {
+        self.sessions.purge_expired(now, limit).await
+    }
+
+    async fn pow_difficulty(&self) -> Result<Option<u32>, StoreError> {
+        self.sessions.pow_difficulty().await
+    }
+
+    async fn set_pow_difficulty(&self, difficulty: u32) -> Result<(), StoreError> {
+        self.sessions.set_pow_difficulty(difficulty).await
+    }
+
+    async fn ip_denylist(&self) -> Result<Option<Vec<String>>, StoreError> {
+        self.sessions.ip_denylist().await
+    }
+
+    async fn set_ip_denylist(&self, cidrs: &[String]) -> Result<(), StoreError> {
+        self.sessions.set_ip_denylist(cidrs).await
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.sessions.ping().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ZKP;
+
+    #[test]
+    fn test_auth_attempt_json_roundtrip() {
+        let zkp = ZKP::default();
+        let spec = ChallengeSpec::full(&zkp);
+        let states = [
+            ChallengeState::Bound {
+                r1: BigUint::from(1u32),
+                r2: BigUint::from(2u32),
+                c: BigUint::from(3u32),
+                seed: vec![4; 32],
+                spec,
+                version: 2,
+                max_version: 2,
+            },
+            ChallengeState::Committed(zkp.commit_challenge(&spec)),
+            ChallengeState::Opened {
+                r1: BigUint::from(5u32),
+                r2: BigUint::from(6u32),
+                c: BigUint::ZERO,
+            },
+        ];
+        for state in states {
+            let challenge = AuthAttempt {
+                user_name: "alice".to_string(),
+                state,
+                expires_at: 1_700_000_000,
+            };
+            let json = challenge.to_json();
+            let restored = AuthAttempt::from_json(&json).unwrap();
+            assert_eq!(restored.user_name, "alice");
+            assert_eq!(restored.to_json(), json);
+        }
+
+        // Challenges stored before versions existed are version 1.
+        let legacy = r#"{"user_name":"alice","expires_at":1,"state":{"kind":"bound",
+            "r1":"01","r2":"02","c":"03","seed":"04","bits":160,"rounds":1}}"#;
+        let ChallengeState::Bound { version, .. } = AuthAttempt::from_json(legacy).unwrap().state
+        else {
+            panic!("state changed in storage");
+        };
+        assert_eq!(version, 1);
+
+        assert!(matches!(
+            AuthAttempt::from_json("{}"),
+            Err(StoreError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_session_json_roundtrip() {
+        let session = Session {
+            user_name: "alice".to_string(),
+            created_at: 1_700_000_000,
+            expires_at: 1_700_086_400,
+            device: Some("phone".to_string()),
+            last_seen: 1_700_000_600,
+        };
+        assert_eq!(Session::from_json(&session.to_json()).unwrap(), session);
+
+        let legacy = Session::from_json(
+            r#"{"user_name":"alice","created_at":1700000000,"expires_at":1700086400}"#,
+        )
+        .unwrap();
+        assert_eq!((legacy.device, legacy.last_seen), (None, legacy.created_at));
+    }
+}
@@ -0,0 +1,353 @@
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use super::{unix_now, AuthAttempt, Session, SessionStore, StoreError};
+use crate::{AuthId, SessionId};
+
+/// Challenges and sessions in Redis, shared by every server replica behind a
+/// load balancer. Redis expires both by itself at their `expires_at`. Users
+/// stay in whatever `UserStore` this is paired with, see `SplitStore`.
+#[derive(Clone)]
+pub struct RedisStore {
+    connection: ConnectionManager,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore").finish_non_exhaustive()
+    }
+}
+
+impl RedisStore {
+    /// `url` as in `redis://localhost:6379`. The connection is re-established
+    /// on its own if it drops.
+    pub async fn connect(url: &str) -> Result<Self, StoreError> {
+        let client = redis::Client::open(url).map_err(StoreError::backend)?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(Self { connection })
+    }
+}
+
+fn challenge_key(auth_id: &str) -> String {
+    format!("zkp_auth:challenge:{auth_id}")
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("zkp_auth:session:{session_id}")
+}
+
+fn revoked_key(session_id: &str) -> String {
+    format!("zkp_auth:revoked:{session_id}")
+}
+
+fn failures_key(user_name: &str) -> String {
+    format!("zkp_auth:failures:{user_name}")
+}
+
+fn locked_key(user_name: &str) -> String {
+    format!("zkp_auth:locked:{user_name}")
+}
+
+const POW_DIFFICULTY_KEY: &str = "zkp_auth:setting:pow_difficulty";
+
+/// The JSON array of the denylist.
+const IP_DENYLIST_KEY: &str = "zkp_auth:setting:ip_denylist";
+
+#[tonic::async_trait]
+impl SessionStore for RedisStore {
+    async fn save_challenge(
+        &self,
+        auth_id: &AuthId,
+        challenge: AuthAttempt,
+    ) -> Result<(), StoreError> {
+        // EX 0 is an error, an attempt that just expired lives one more second.
+        let ttl = challenge.ttl().as_secs().max(1);
+        let mut connection = self.connection.clone();
+        let () = connection
+            .set_ex(challenge_key(auth_id.as_str()), challenge.to_json(), ttl)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn consume_auth_id(&self, auth_id: &AuthId) -> Result<Option<AuthAttempt>, StoreError> {
+        // GETDEL is atomic, only one replica gets the challenge.
+        let mut connection = self.connection.clone();
+        let json: Option<String> = connection
+            .get_del(challenge_key(auth_id.as_str()))
+            .await
+            .map_err(StoreError::backend)?;
+        json.as_deref().map(AuthAttempt::from_json).transpose()
+    }
+
+    async fn save_session(
+        &self,
+        session_id: &SessionId,
+        session: Session,
+    ) -> Result<(), StoreError> {
+        let ttl = session.ttl().as_secs().max(1);
+        let mut connection = self.connection.clone();
+        let () = connection
+            .set_ex(session_key(session_id.as_str()), session.to_json(), ttl)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        let mut connection = self.connection.clone();
+        let json: Option<String> = connection
+            .get(session_key(session_id.as_str()))
+            .await
+            .map_err(StoreError::backend)?;
+        json.as_deref().map(Session::from_json).transpose()
+    }
+
+    async fn remove_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        let mut connection = self.connection.clone();
+        let json: Option<String> = connection
+            .get_del(session_key(session_id.as_str()))
+            .await
+            .map_err(StoreError::backend)?;
+        json.as_deref().map(Session::from_json).transpose()
+    }
+
+    async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        let Some(mut session) = self.get_session(session_id).await? else {
+            return Ok(());
+        };
+        session.last_seen = session.last_seen.max(at);
+        session.expires_at = session.expires_at.max(expires_at);
+        // XX, a session removed in the meantime isn't written back.
+        let mut connection = self.connection.clone();
+        let _: Option<String> = redis::cmd("SET")
+            .arg(session_key(session_id.as_str()))
+            .arg(session.to_json())
+            .arg("XX")
+            .arg("EX")
+            .arg(session.ttl().as_secs().max(1))
+            .query_async(&mut connection)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        let ttl = expires_at.saturating_sub(unix_now()).max(1);
+        let mut connection = self.connection.clone();
+        let () = connection
+            .set_ex(revoked_key(session_id.as_str()), expires_at, ttl)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn is_revoked(&self, session_id: &SessionId) -> Result<bool, StoreError> {
+        let mut connection = self.connection.clone();
+        connection
+            .exists(revoked_key(session_id.as_str()))
+            .await
+            .map_err(StoreError::backend)
+    }
+
+    async fn record_failure(&self, user_name: &str) -> Result<u32, StoreError> {
+        let mut connection = self.connection.clone();
+        connection
+            .incr(failures_key(user_name), 1)
+            .await
+            .map_err(StoreError::backend)
+    }
+
+    async fn clear_failures(&self, user_name: &str) -> Result<(), StoreError> {
+        let mut connection = self.connection.clone();
+        let () = connection
+            .del(failures_key(user_name))
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn lock_user(&self, user_name: &str, until: u64) -> Result<(), StoreError> {
+        let ttl = until.saturating_sub(unix_now()).max(1);
+        let mut connection = self.connection.clone();
+        let () = redis::pipe()
+            .atomic()
+            .set_ex(locked_key(user_name), until, ttl)
+            .ignore()
+            .del(failures_key(user_name))
+            .ignore()
+            .query_async(&mut connection)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn locked_until(&self, user_name: &str) -> Result<Option<u64>, StoreError> {
+        let mut connection = self.connection.clone();
+        connection
+            .get(locked_key(user_name))
+            .await
+            .map_err(StoreError::backend)
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<u64, StoreError> {
+        // Nothing is indexed by user, deleting an account is rare enough to
+        // scan every challenge and session instead.
+        let challenges = self
+            .records(&challenge_key("*"), AuthAttempt::from_json)
+            .await?
+            .into_iter()
+            .filter(|(_, attempt)| attempt.user_name == user_name);
+        let sessions: Vec<String> = self
+            .records(&session_key("*"), Session::from_json)
+            .await?
+            .into_iter()
+            .filter(|(_, session)| session.user_name == user_name)
+            .map(|(key, _)| key)
+            .collect();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, _) in challenges {
+            pipe.del(key).ignore();
+        }
+        for key in &sessions {
+            pipe.del(key).ignore();
+        }
+        pipe.del(failures_key(user_name))
+            .ignore()
+            .del(locked_key(user_name))
+            .ignore();
+        let mut connection = self.connection.clone();
+        let () = pipe
+            .query_async(&mut connection)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(sessions.len() as u64)
+    }
+
+    async fn list_sessions(
+        &self,
+        user_name: &str,
+    ) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        let prefix = session_key("");
+        Ok(self
+            .records(&session_key("*"), Session::from_json)
+            .await?
+            .into_iter()
+            .filter(|(_, session)| session.user_name == user_name)
+            .map(|(key, session)| (SessionId::from(&key[prefix.len()..]), session))
+            .collect())
+    }
+
+    async fn list_all_sessions(&self) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        let prefix = session_key("");
+        Ok(self
+            .records(&session_key("*"), Session::from_json)
+            .await?
+            .into_iter()
+            .map(|(key, session)| (SessionId::from(&key[prefix.len()..]), session))
+            .collect())
+    }
+
+    async fn count_sessions(&self) -> Result<u64, StoreError> {
+        // Redis drops sessions once they expire.
+        Ok(self
+            .records(&session_key("*"), Session::from_json)
+            .await?
+            .len() as u64)
+    }
+
+    async fn purge_expired(&self, _now: u64, _limit: usize) -> Result<u64, StoreError> {
+        // Every record but failure counts has a TTL, Redis purges them.
+        Ok(0)
+    }
+
+    async fn pow_difficulty(&self) -> Result<Option<u32>, StoreError> {
+        let mut connection = self.connection.clone();
+        connection
+            .get(POW_DIFFICULTY_KEY)
+            .await
+            .map_err(StoreError::backend)
+    }
+
+    async fn set_pow_difficulty(&self, difficulty: u32) -> Result<(), StoreError> {
+        let mut connection = self.connection.clone();
+        let () = connection
+            .set(POW_DIFFICULTY_KEY, difficulty)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn ip_denylist(&self) -> Result<Option<Vec<String>>, StoreError> {
+        let mut connection = self.connection.clone();
+        let json: Option<String> = connection
+            .get(IP_DENYLIST_KEY)
+            .await
+            .map_err(StoreError::backend)?;
+        json.map(|json| serde_json::from_str(&json).map_err(StoreError::corrupt))
+            .transpose()
+    }
+
+    async fn set_ip_denylist(&self, cidrs: &[String]) -> Result<(), StoreError> {
+        let json = serde_json::to_string(cidrs).expect("Strings serialize.");
+        let mut connection = self.connection.clone();
+        let () = connection
+            .set(IP_DENYLIST_KEY, json)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        let mut connection = self.connection.clone();
+        let _: String = redis::cmd("PING")
+            .query_async(&mut connection)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+}
+
+impl RedisStore {
+    /// Every record under a key matching `pattern`, decoded from JSON by
+    /// `decode`. Scans the whole keyspace.
+    async fn records<T>(
+        &self,
+        pattern: &str,
+        decode: impl Fn(&str) -> Result<T, StoreError>,
+    ) -> Result<Vec<(String, T)>, StoreError> {
+        let mut connection = self.connection.clone();
+        let mut keys = Vec::new();
+        {
+            let mut iter = connection
+                .scan_match::<_, String>(pattern)
+                .await
+                .map_err(StoreError::backend)?;
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+        }
+
+        let mut records = Vec::new();
+        for key in keys {
+            // Gone already if it expired since the scan.
+            let json: Option<String> = connection.get(&key).await.map_err(StoreError::backend)?;
+            if let Some(json) = json {
+                records.push((key, decode(&json)?));
+            }
+        }
+        Ok(records)
+    }
+}
@@ -0,0 +1,786 @@
+use std::{ops::Bound, path::Path};
+
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError, Transactional},
+    IVec,
+};
+
+use super::{
+    newer_schema, unix_now, AuthAttempt, Device, LoginAttempt, Session, SessionStore, StoreError,
+    UserInfo, UserStore,
+};
+use crate::{AuthId, SessionId};
+
+/// Version of the layout `open` leaves the database in, stored big endian
+/// under `SCHEMA_VERSION_KEY` in the default tree. Databases from before it
+/// was kept are at 0.
+const SCHEMA_VERSION: u64 = 1;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// Users, challenges and sessions in a sled database in a local directory,
+/// for a single binary deployment without a database server.
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    db: sled::Db,
+    users: sled::Tree,
+    /// User to when they last registered or logged in, big endian.
+    last_used: sled::Tree,
+    challenges: sled::Tree,
+    sessions: sled::Tree,
+    /// Revoked session_id to when it expires, big endian.
+    revoked: sled::Tree,
+    /// User to failed verifications, big endian.
+    failures: sled::Tree,
+    /// User to the end of their lockout, big endian.
+    locked_until: sled::Tree,
+    /// Settings admins change at runtime, by name, big endian numbers or
+    /// JSON.
+    settings: sled::Tree,
+    /// User, a zero byte and a `generate_id`, big endian, to the JSON of a
+    /// `LoginAttempt`, so a user's attempts scan oldest first.
+    login_attempts: sled::Tree,
+    /// User, a zero byte and the hash of one of their unused recovery codes,
+    /// to nothing.
+    recovery_codes: sled::Tree,
+}
+
+impl SledStore {
+    /// Opens or creates the database in `path`, and migrates it to
+    /// `SCHEMA_VERSION`. A database past that is from a newer server, and
+    /// isn't opened.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let db = sled::open(path).map_err(StoreError::backend)?;
+        let tree = |name: &str| db.open_tree(name).map_err(StoreError::backend);
+        let store = Self {
+            users: tree("users")?,
+            last_used: tree("last_used")?,
+            challenges: tree("challenges")?,
+            sessions: tree("sessions")?,
+            revoked: tree("revoked_sessions")?,
+            failures: tree("failures")?,
+            locked_until: tree("locked_until")?,
+            settings: tree("settings")?,
+            login_attempts: tree("login_attempts")?,
+            recovery_codes: tree("recovery_codes")?,
+            db,
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    /// Takes the database from the version it is at to `SCHEMA_VERSION`, a
+    /// step at a time.
+    fn migrate(&self) -> Result<(), StoreError> {
+        let stored = self
+            .db
+            .get(SCHEMA_VERSION_KEY)
+            .map_err(StoreError::backend)?;
+        let mut version = stored.map(|bytes| to_u64(&bytes)).transpose()?.unwrap_or(0);
+        if version > SCHEMA_VERSION {
+            return Err(newer_schema(version));
+        }
+        while version < SCHEMA_VERSION {
+            match version {
+                // Users from before uses were noted count as used now.
+                0 => {
+                    let now = unix_now().to_be_bytes();
+                    for user_name in self.users.iter().keys() {
+                        let user_name = user_name.map_err(StoreError::backend)?;
+                        self.last_used
+                            .compare_and_swap(user_name, None::<&[u8]>, Some(&now[..]))
+                            .map_err(StoreError::backend)?
+                            .ok();
+                    }
+                }
+                _ => unreachable!("Every version below SCHEMA_VERSION has a step."),
+            }
+            version += 1;
+            self.db
+                .insert(SCHEMA_VERSION_KEY, &version.to_be_bytes())
+                .map_err(StoreError::backend)?;
+            log::info!("Migrated the sled database to schema version {version}");
+        }
+        self.db.flush().map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    /// Waits until everything written so far is on disk.
+    async fn flush(&self) -> Result<(), StoreError> {
+        self.db.flush_async().await.map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    /// Applies `update` to the user, if there is one, returns whether there
+    /// was. Compare and swap, a concurrent UpdateCredentials isn't undone.
+    fn update_user(
+        &self,
+        user_name: &str,
+        update: impl Fn(&mut UserInfo),
+    ) -> Result<bool, StoreError> {
+        loop {
+            let Some(old) = self.users.get(user_name).map_err(StoreError::backend)? else {
+                return Ok(false);
+            };
+            let mut user = UserInfo::from_json(to_str(&old)?)?;
+            update(&mut user);
+            let swapped = self
+                .users
+                .compare_and_swap(user_name, Some(old), Some(user.to_json().as_bytes()))
+                .map_err(StoreError::backend)?;
+            if swapped.is_ok() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+fn to_str(bytes: &[u8]) -> Result<&str, StoreError> {
+    std::str::from_utf8(bytes).map_err(StoreError::corrupt)
+}
+
+fn to_u64(bytes: &[u8]) -> Result<u64, StoreError> {
+    let bytes: [u8; 8] = bytes
+        .try_into()
+        .map_err(|_| StoreError::Corrupt("integer length".into()))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[tonic::async_trait]
+impl UserStore for SledStore {
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserInfo>, StoreError> {
+        let value = self.users.get(user_name).map_err(StoreError::backend)?;
+        value
+            .map(|value| UserInfo::from_json(to_str(&value)?))
+            .transpose()
+    }
+
+    async fn create_user(&self, user: UserInfo) -> Result<bool, StoreError> {
+        let created = self
+            .users
+            .compare_and_swap(
+                user.user_name.as_bytes(),
+                None::<&[u8]>,
+                Some(user.to_json().as_bytes()),
+            )
+            .map_err(StoreError::backend)?
+            .is_ok();
+        self.flush().await?;
+        Ok(created)
+    }
+
+    async fn put_user(&self, user: UserInfo) -> Result<(), StoreError> {
+        self.users
+            .insert(user.user_name.as_bytes(), user.to_json().as_bytes())
+            .map_err(StoreError::backend)?;
+        self.flush().await
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<bool, StoreError> {
+        let value = self.users.remove(user_name).map_err(StoreError::backend)?;
+        self.last_used
+            .remove(user_name)
+            .map_err(StoreError::backend)?;
+        for tree in [&self.login_attempts, &self.recovery_codes] {
+            for key in tree.scan_prefix(user_prefix(user_name)).keys() {
+                tree.remove(key.map_err(StoreError::backend)?)
+                    .map_err(StoreError::backend)?;
+            }
+        }
+        self.flush().await?;
+        Ok(value.is_some())
+    }
+
+    async fn list_users(&self, after: &str, limit: usize) -> Result<Vec<UserInfo>, StoreError> {
+        self.users
+            .range::<&[u8], _>((Bound::Excluded(after.as_bytes()), Bound::Unbounded))
+            .take(limit)
+            .map(|entry| {
+                let (_, value) = entry.map_err(StoreError::backend)?;
+                UserInfo::from_json(to_str(&value)?)
+            })
+            .collect()
+    }
+
+    async fn count_users(&self) -> Result<u64, StoreError> {
+        Ok(self.users.len() as u64)
+    }
+
+    async fn touch_user(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        self.last_used
+            .insert(user_name, &at.to_be_bytes())
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn record_login(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        self.update_user(user_name, |user| user.last_login = Some(at))?;
+        Ok(())
+    }
+
+    async fn set_devices(&self, user_name: &str, devices: &[Device]) -> Result<bool, StoreError> {
+        let updated = self.update_user(user_name, |user| user.devices = devices.to_vec())?;
+        self.flush().await?;
+        Ok(updated)
+    }
+
+    async fn set_admin(&self, user_name: &str, admin: bool) -> Result<bool, StoreError> {
+        let updated = self.update_user(user_name, |user| user.admin = admin)?;
+        self.flush().await?;
+        Ok(updated)
+    }
+
+    async fn record_attempt(
+        &self,
+        user_name: &str,
+        attempt: LoginAttempt,
+        keep: usize,
+    ) -> Result<(), StoreError> {
+        let prefix = user_prefix(user_name);
+        let id = self.db.generate_id().map_err(StoreError::backend)?;
+        let json = serde_json::to_vec(&attempt).expect("Plain strings and numbers serialize.");
+        self.login_attempts
+            .insert([&prefix[..], &id.to_be_bytes()].concat(), json)
+            .map_err(StoreError::backend)?;
+        let kept = self.login_attempts.scan_prefix(&prefix).count();
+        for entry in self
+            .login_attempts
+            .scan_prefix(&prefix)
+            .take(kept.saturating_sub(keep))
+        {
+            let (key, _) = entry.map_err(StoreError::backend)?;
+            self.login_attempts
+                .remove(key)
+                .map_err(StoreError::backend)?;
+        }
+        Ok(())
+    }
+
+    async fn list_attempts(
+        &self,
+        user_name: &str,
+        limit: usize,
+    ) -> Result<Vec<LoginAttempt>, StoreError> {
+        self.login_attempts
+            .scan_prefix(user_prefix(user_name))
+            .rev()
+            .take(limit)
+            .map(|entry| {
+                let (_, value) = entry.map_err(StoreError::backend)?;
+                serde_json::from_slice(&value).map_err(StoreError::corrupt)
+            })
+            .collect()
+    }
+
+    async fn set_recovery_codes(
+        &self,
+        user_name: &str,
+        hashes: Vec<[u8; 32]>,
+    ) -> Result<(), StoreError> {
+        let prefix = user_prefix(user_name);
+        for key in self.recovery_codes.scan_prefix(&prefix).keys() {
+            self.recovery_codes
+                .remove(key.map_err(StoreError::backend)?)
+                .map_err(StoreError::backend)?;
+        }
+        for hash in hashes {
+            self.recovery_codes
+                .insert([&prefix[..], &hash].concat(), &[])
+                .map_err(StoreError::backend)?;
+        }
+        self.flush().await
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_name: &str,
+        hash: &[u8; 32],
+    ) -> Result<bool, StoreError> {
+        // `remove` hands the old value to exactly one caller.
+        let value = self
+            .recovery_codes
+            .remove([&user_prefix(user_name)[..], hash].concat())
+            .map_err(StoreError::backend)?;
+        self.flush().await?;
+        Ok(value.is_some())
+    }
+
+    async fn list_recovery_codes(&self, user_name: &str) -> Result<Vec<[u8; 32]>, StoreError> {
+        let prefix = user_prefix(user_name);
+        self.recovery_codes
+            .scan_prefix(&prefix)
+            .keys()
+            .map(|key| {
+                let key = key.map_err(StoreError::backend)?;
+                key[prefix.len()..].try_into().map_err(StoreError::corrupt)
+            })
+            .collect()
+    }
+
+    async fn list_stale_users(
+        &self,
+        before: u64,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreError> {
+        let mut names = Vec::new();
+        let entries = self
+            .last_used
+            .range::<&[u8], _>((Bound::Excluded(after.as_bytes()), Bound::Unbounded));
+        for entry in entries {
+            if names.len() == limit {
+                break;
+            }
+            let (user_name, value) = entry.map_err(StoreError::backend)?;
+            if to_u64(&value)? < before
+                && self
+                    .users
+                    .contains_key(&user_name)
+                    .map_err(StoreError::backend)?
+            {
+                names.push(to_str(&user_name)?.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    async fn shutdown(&self) -> Result<(), StoreError> {
+        self.db.flush_async().await.map_err(StoreError::backend)?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl SessionStore for SledStore {
+    async fn save_challenge(
+        &self,
+        auth_id: &AuthId,
+        challenge: AuthAttempt,
+    ) -> Result<(), StoreError> {
+        self.challenges
+            .insert(auth_id.as_str(), challenge.to_json().as_bytes())
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn consume_auth_id(&self, auth_id: &AuthId) -> Result<Option<AuthAttempt>, StoreError> {
+        // `remove` hands the old value to exactly one caller.
+        let value = self
+            .challenges
+            .remove(auth_id.as_str())
+            .map_err(StoreError::backend)?;
+        value
+            .map(|value| AuthAttempt::from_json(to_str(&value)?))
+            .transpose()
+    }
+
+    async fn save_session(
+        &self,
+        session_id: &SessionId,
+        session: Session,
+    ) -> Result<(), StoreError> {
+        self.sessions
+            .insert(session_id.as_str(), session.to_json().as_bytes())
+            .map_err(StoreError::backend)?;
+        self.flush().await
+    }
+
+    async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        let value = self
+            .sessions
+            .get(session_id.as_str())
+            .map_err(StoreError::backend)?;
+        value
+            .map(|value| Session::from_json(to_str(&value)?))
+            .transpose()
+    }
+
+    async fn remove_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        let value = self
+            .sessions
+            .remove(session_id.as_str())
+            .map_err(StoreError::backend)?;
+        self.flush().await?;
+        value
+            .map(|value| Session::from_json(to_str(&value)?))
+            .transpose()
+    }
+
+    async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        // `update_and_fetch` leaves a removed session removed.
+        self.sessions
+            .update_and_fetch(session_id.as_str(), |old| {
+                let old = old?;
+                let touched = std::str::from_utf8(old)
+                    .ok()
+                    .and_then(|json| Session::from_json(json).ok())
+                    .map(|mut session| {
+                        session.last_seen = session.last_seen.max(at);
+                        session.expires_at = session.expires_at.max(expires_at);
+                        session.to_json().into_bytes()
+                    });
+                Some(touched.unwrap_or_else(|| old.to_vec()))
+            })
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        self.revoked
+            .insert(session_id.as_str(), &expires_at.to_be_bytes())
+            .map_err(StoreError::backend)?;
+        self.flush().await
+    }
+
+    async fn is_revoked(&self, session_id: &SessionId) -> Result<bool, StoreError> {
+        let Some(value) = self
+            .revoked
+            .get(session_id.as_str())
+            .map_err(StoreError::backend)?
+        else {
+            return Ok(false);
+        };
+        Ok(to_u64(&value)? > unix_now())
+    }
+
+    async fn record_failure(&self, user_name: &str) -> Result<u32, StoreError> {
+        // `update_and_fetch` retries on a concurrent write, no failure is lost.
+        let value = self
+            .failures
+            .update_and_fetch(user_name, |old| {
+                let failures = old.and_then(|old| to_u64(old).ok()).unwrap_or(0);
+                Some((failures + 1).to_be_bytes().to_vec())
+            })
+            .map_err(StoreError::backend)?
+            .expect("The update always stores a value.");
+        Ok(to_u64(&value)? as u32)
+    }
+
+    async fn clear_failures(&self, user_name: &str) -> Result<(), StoreError> {
+        self.failures
+            .remove(user_name)
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn lock_user(&self, user_name: &str, until: u64) -> Result<(), StoreError> {
+        self.locked_until
+            .insert(user_name, &until.to_be_bytes())
+            .map_err(StoreError::backend)?;
+        self.failures
+            .remove(user_name)
+            .map_err(StoreError::backend)?;
+        self.flush().await
+    }
+
+    async fn locked_until(&self, user_name: &str) -> Result<Option<u64>, StoreError> {
+        let value = self
+            .locked_until
+            .get(user_name)
+            .map_err(StoreError::backend)?;
+        value.map(|value| to_u64(&value)).transpose()
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<u64, StoreError> {
+        let challenge_ids: Vec<IVec> = records(&self.challenges, AuthAttempt::from_json)?
+            .into_iter()
+            .filter(|(_, attempt)| attempt.user_name == user_name)
+            .map(|(auth_id, _)| auth_id)
+            .collect();
+        let session_ids: Vec<IVec> = records(&self.sessions, Session::from_json)?
+            .into_iter()
+            .filter(|(_, session)| session.user_name == user_name)
+            .map(|(session_id, _)| session_id)
+            .collect();
+
+        (
+            &self.challenges,
+            &self.sessions,
+            &self.failures,
+            &self.locked_until,
+        )
+            .transaction(|(challenges, sessions, failures, locked_until)| {
+                for auth_id in &challenge_ids {
+                    challenges.remove(auth_id)?;
+                }
+                for session_id in &session_ids {
+                    sessions.remove(session_id)?;
+                }
+                failures.remove(user_name)?;
+                locked_until.remove(user_name)?;
+                Ok::<_, ConflictableTransactionError<StoreError>>(())
+            })
+            .map_err(|err: TransactionError<StoreError>| StoreError::backend(err))?;
+        self.flush().await?;
+        Ok(session_ids.len() as u64)
+    }
+
+    async fn list_sessions(
+        &self,
+        user_name: &str,
+    ) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        records(&self.sessions, Session::from_json)?
+            .into_iter()
+            .filter(|(_, session)| session.user_name == user_name)
+            .map(|(session_id, session)| Ok((SessionId::from(to_str(&session_id)?), session)))
+            .collect()
+    }
+
+    async fn list_all_sessions(&self) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        records(&self.sessions, Session::from_json)?
+            .into_iter()
+            .map(|(session_id, session)| Ok((SessionId::from(to_str(&session_id)?), session)))
+            .collect()
+    }
+
+    async fn count_sessions(&self) -> Result<u64, StoreError> {
+        Ok(records(&self.sessions, Session::from_json)?
+            .iter()
+            .filter(|(_, session)| !session.is_expired())
+            .count() as u64)
+    }
+
+    async fn purge_expired(&self, now: u64, limit: usize) -> Result<u64, StoreError> {
+        let mut purged = purge(&self.challenges, limit, |_, value| {
+            Ok(AuthAttempt::from_json(to_str(value)?)?.expires_at <= now)
+        })?;
+        purged += purge(&self.sessions, limit - purged, |_, value| {
+            Ok(Session::from_json(to_str(value)?)?.expires_at <= now)
+        })?;
+        purged += purge(&self.revoked, limit - purged, |_, value| {
+            Ok(to_u64(value)? <= now)
+        })?;
+        // As the database backends, which keep both in one row, a lockout
+        // stays while failures are counted since.
+        purged += purge(&self.locked_until, limit - purged, |user_name, value| {
+            Ok(to_u64(value)? <= now
+                && !self
+                    .failures
+                    .contains_key(user_name)
+                    .map_err(StoreError::backend)?)
+        })?;
+        if purged > 0 {
+            self.flush().await?;
+        }
+        Ok(purged as u64)
+    }
+
+    async fn pow_difficulty(&self) -> Result<Option<u32>, StoreError> {
+        let value = self
+            .settings
+            .get("pow_difficulty")
+            .map_err(StoreError::backend)?;
+        value
+            .map(|value| u32::try_from(to_u64(&value)?).map_err(StoreError::corrupt))
+            .transpose()
+    }
+
+    async fn set_pow_difficulty(&self, difficulty: u32) -> Result<(), StoreError> {
+        self.settings
+            .insert("pow_difficulty", &u64::from(difficulty).to_be_bytes())
+            .map_err(StoreError::backend)?;
+        self.flush().await
+    }
+
+    async fn ip_denylist(&self) -> Result<Option<Vec<String>>, StoreError> {
+        let value = self
+            .settings
+            .get("ip_denylist")
+            .map_err(StoreError::backend)?;
+        value
+            .map(|value| serde_json::from_slice(&value).map_err(StoreError::corrupt))
+            .transpose()
+    }
+
+    async fn set_ip_denylist(&self, cidrs: &[String]) -> Result<(), StoreError> {
+        let json = serde_json::to_vec(cidrs).expect("Strings serialize.");
+        self.settings
+            .insert("ip_denylist", json)
+            .map_err(StoreError::backend)?;
+        self.flush().await
+    }
+}
+
+/// Removes up to `limit` records of `tree` that are `expired`, returns how
+/// many went.
+fn purge(
+    tree: &sled::Tree,
+    limit: usize,
+    expired: impl Fn(&[u8], &[u8]) -> Result<bool, StoreError>,
+) -> Result<usize, StoreError> {
+    let mut purged = 0;
+    for entry in tree.iter() {
+        if purged == limit {
+            break;
+        }
+        let (key, value) = entry.map_err(StoreError::backend)?;
+        if !expired(&key, &value)? {
+            continue;
+        }
+        // Only while unchanged, a user may have been locked out again since.
+        let swapped = tree
+            .compare_and_swap(&key, Some(&value), None::<&[u8]>)
+            .map_err(StoreError::backend)?;
+        if swapped.is_ok() {
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Every record in `tree`, decoded from JSON by `decode`.
+fn records<T>(
+    tree: &sled::Tree,
+    decode: impl Fn(&str) -> Result<T, StoreError>,
+) -> Result<Vec<(IVec, T)>, StoreError> {
+    tree.iter()
+        .map(|entry| {
+            let (key, value) = entry.map_err(StoreError::backend)?;
+            Ok((key, decode(to_str(&value)?)?))
+        })
+        .collect()
+}
+
+/// What the keys of `user_name`'s login attempts and recovery codes start
+/// with.
+fn user_prefix(user_name: &str) -> Vec<u8> {
+    [user_name.as_bytes(), &[0]].concat()
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{server::store::ChallengeState, KdfParams, ZKP};
+
+    #[tokio::test]
+    async fn test_sled_survives_reopen() {
+        let path =
+            std::env::temp_dir().join(format!("zkp_auth_sled_{}", ZKP::generate_random_string(8)));
+        let user = UserInfo {
+            user_name: "alice".to_string(),
+            y1: BigUint::from(4u32),
+            y2: BigUint::from(9u32),
+            salt: vec![1; 16],
+            kdf_params: KdfParams::default(),
+            group: None,
+            ..Default::default()
+        };
+        {
+            let store = SledStore::open(&path).unwrap();
+            store.put_user(user.clone()).await.unwrap();
+            let challenge = AuthAttempt {
+                user_name: "alice".to_string(),
+                state: ChallengeState::Opened {
+                    r1: BigUint::from(1u32),
+                    r2: BigUint::from(2u32),
+                    c: BigUint::from(3u32),
+                },
+                expires_at: 1_700_000_000,
+            };
+            store
+                .save_challenge(&AuthId::from("auth"), challenge)
+                .await
+                .unwrap();
+            store
+                .set_recovery_codes("alice", vec![[1; 32], [2; 32]])
+                .await
+                .unwrap();
+            let session = Session {
+                user_name: "alice".to_string(),
+                created_at: 1_700_000_000,
+                expires_at: 1_700_086_400,
+                device: None,
+                last_seen: 1_700_000_000,
+            };
+            store
+                .save_session(&SessionId::from("session"), session)
+                .await
+                .unwrap();
+            store
+                .touch_session(&SessionId::from("session"), 1_700_000_600, 1_700_090_000)
+                .await
+                .unwrap();
+            store
+                .touch_session(&SessionId::from("gone"), 1_700_000_600, 1_700_090_000)
+                .await
+                .unwrap();
+        }
+
+        let store = SledStore::open(&path).unwrap();
+        let stored = store.get_user("alice").await.unwrap().unwrap();
+        assert_eq!(stored.to_json(), user.to_json());
+        assert!(store
+            .consume_auth_id(&AuthId::from("auth"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(store
+            .consume_auth_id(&AuthId::from("auth"))
+            .await
+            .unwrap()
+            .is_none());
+        let session = store
+            .get_session(&SessionId::from("session"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (session.last_seen, session.expires_at),
+            (1_700_000_600, 1_700_090_000)
+        );
+        assert!(store
+            .get_session(&SessionId::from("gone"))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.use_recovery_code("alice", &[1; 32]).await.unwrap());
+        assert!(!store.use_recovery_code("alice", &[1; 32]).await.unwrap());
+        assert_eq!(
+            store.list_recovery_codes("alice").await.unwrap(),
+            vec![[2; 32]]
+        );
+        assert!(store.delete_user("alice").await.unwrap());
+        assert!(!store.use_recovery_code("alice", &[2; 32]).await.unwrap());
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sled_migrations() {
+        let path = std::env::temp_dir().join(format!(
+            "zkp_auth_sled_migrations_{}",
+            ZKP::generate_random_string(8)
+        ));
+        let store = SledStore::open(&path).unwrap();
+        let version = store.db.get(SCHEMA_VERSION_KEY).unwrap().unwrap();
+        assert_eq!(to_u64(&version).unwrap(), SCHEMA_VERSION);
+
+        // A database from before schema versions were kept.
+        store.users.insert("alice", "{}").unwrap();
+        store.db.remove(SCHEMA_VERSION_KEY).unwrap();
+        store.migrate().unwrap();
+        assert!(store.last_used.get("alice").unwrap().is_some());
+
+        store
+            .db
+            .insert(SCHEMA_VERSION_KEY, &(SCHEMA_VERSION + 1).to_be_bytes())
+            .unwrap();
+        let err = store.migrate().unwrap_err();
+        assert!(err.to_string().contains("newer server"), "{err}");
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,956 @@
+use num_bigint::BigUint;
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    Row, SqlitePool,
+};
+
+use super::{
+    devices_from_json, devices_to_json, group_from_bytes, group_to_bytes, metadata_from_json,
+    metadata_to_json, migrate_error, unix_now, AuthAttempt, ChallengeState, Device, LoginAttempt,
+    Session, SessionStore, StoreError, UserInfo, UserStore,
+};
+use crate::KdfParams;
+use crate::{AuthId, SessionId};
+
+/// Steps from an empty database to the schema this server uses, applied
+/// on `connect` to those it hasn't been yet.
+static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// Each table with records of no more use, its key and which records.
+const EXPIRED: [(&str, &str, &str); 4] = [
+    ("challenges", "auth_id", "expires_at <= ?1"),
+    ("sessions", "session_id", "expires_at <= ?1"),
+    ("revoked_sessions", "session_id", "expires_at <= ?1"),
+    (
+        "lockouts",
+        "user_name",
+        "failures = 0 AND (locked_until IS NULL OR locked_until <= ?1)",
+    ),
+];
+
+/// Users, challenges and sessions in a SQLite file, so they survive a
+/// restart. `connect` creates the tables, or migrates them to the schema
+/// this server uses.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// `url` as in `sqlite://users.db`, the file is created if missing.
+    pub async fn connect(url: &str) -> Result<Self, StoreError> {
+        let options: SqliteConnectOptions = url.parse().map_err(StoreError::backend)?;
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options.create_if_missing(true))
+            .await
+            .map_err(StoreError::backend)?;
+        MIGRATOR.run(&pool).await.map_err(migrate_error)?;
+        Ok(Self { pool })
+    }
+}
+
+fn session_from_row(row: &SqliteRow) -> Session {
+    Session {
+        user_name: row.get("user_name"),
+        created_at: row.get::<i64, _>("created_at") as u64,
+        expires_at: row.get::<i64, _>("expires_at") as u64,
+        device: row.get("device"),
+        last_seen: row.get::<i64, _>("last_seen_at") as u64,
+    }
+}
+
+/// A row of `sessions` with its session_id.
+fn session_with_id(row: &SqliteRow) -> (SessionId, Session) {
+    (
+        SessionId::from(row.get::<String, _>("session_id")),
+        session_from_row(row),
+    )
+}
+
+fn user_from_row(row: &SqliteRow) -> Result<UserInfo, StoreError> {
+    Ok(UserInfo {
+        user_name: row.get("user_name"),
+        y1: BigUint::from_bytes_be(row.get("y1")),
+        y2: BigUint::from_bytes_be(row.get("y2")),
+        salt: row.get("salt"),
+        kdf_params: KdfParams {
+            m_cost: row.get("m_cost"),
+            t_cost: row.get("t_cost"),
+            p_cost: row.get("p_cost"),
+        },
+        group: group_from_bytes(row.get("group_params"))?,
+        created_at: row.get::<i64, _>("created_at") as u64,
+        last_login: row
+            .get::<Option<i64>, _>("last_login_at")
+            .map(|at| at as u64),
+        metadata: metadata_from_json(row.get("metadata"))?,
+        admin: row.get("admin"),
+        totp_secret: row.get("totp_secret"),
+        devices: devices_from_json(row.get("devices"))?,
+    })
+}
+
+#[tonic::async_trait]
+impl UserStore for SqliteStore {
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserInfo>, StoreError> {
+        let row = sqlx::query(
+            "SELECT user_name, y1, y2, salt, m_cost, t_cost, p_cost, group_params,
+             created_at, last_login_at, metadata, admin, totp_secret, devices FROM users WHERE user_name = ?",
+        )
+        .bind(user_name)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    async fn create_user(&self, user: UserInfo) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "INSERT OR IGNORE INTO users (user_name, y1, y2, salt, m_cost, t_cost, p_cost, group_params,
+             created_at, last_login_at, metadata, admin, totp_secret, devices)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user.user_name)
+        .bind(user.y1.to_bytes_be())
+        .bind(user.y2.to_bytes_be())
+        .bind(&user.salt)
+        .bind(user.kdf_params.m_cost)
+        .bind(user.kdf_params.t_cost)
+        .bind(user.kdf_params.p_cost)
+        .bind(group_to_bytes(&user.group))
+        .bind(user.created_at as i64)
+        .bind(user.last_login.map(|at| at as i64))
+        .bind(metadata_to_json(&user.metadata))
+        .bind(user.admin)
+        .bind(&user.totp_secret)
+        .bind(devices_to_json(&user.devices))
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn put_user(&self, user: UserInfo) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO users (user_name, y1, y2, salt, m_cost, t_cost, p_cost, group_params,
+             created_at, last_login_at, metadata, admin, totp_secret, devices)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user.user_name)
+        .bind(user.y1.to_bytes_be())
+        .bind(user.y2.to_bytes_be())
+        .bind(&user.salt)
+        .bind(user.kdf_params.m_cost)
+        .bind(user.kdf_params.t_cost)
+        .bind(user.kdf_params.p_cost)
+        .bind(group_to_bytes(&user.group))
+        .bind(user.created_at as i64)
+        .bind(user.last_login.map(|at| at as i64))
+        .bind(metadata_to_json(&user.metadata))
+        .bind(user.admin)
+        .bind(&user.totp_secret)
+        .bind(devices_to_json(&user.devices))
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<bool, StoreError> {
+        let result = sqlx::query("DELETE FROM users WHERE user_name = ?")
+            .bind(user_name)
+            .execute(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        for table in ["user_activity", "login_attempts", "recovery_codes"] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE user_name = ?"))
+                .bind(user_name)
+                .execute(&self.pool)
+                .await
+                .map_err(StoreError::backend)?;
+        }
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn list_users(&self, after: &str, limit: usize) -> Result<Vec<UserInfo>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT user_name, y1, y2, salt, m_cost, t_cost, p_cost, group_params,
+             created_at, last_login_at, metadata, admin, totp_secret, devices FROM users
+             WHERE user_name > ? ORDER BY user_name LIMIT ?",
+        )
+        .bind(after)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        rows.iter().map(user_from_row).collect()
+    }
+
+    async fn count_users(&self) -> Result<u64, StoreError> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM users")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(row.get::<i64, _>("count") as u64)
+    }
+
+    async fn touch_user(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO user_activity (user_name, last_used_at) VALUES (?, ?)
+             ON CONFLICT (user_name) DO UPDATE SET last_used_at = EXCLUDED.last_used_at",
+        )
+        .bind(user_name)
+        .bind(at as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn record_login(&self, user_name: &str, at: u64) -> Result<(), StoreError> {
+        sqlx::query("UPDATE users SET last_login_at = ? WHERE user_name = ?")
+            .bind(at as i64)
+            .bind(user_name)
+            .execute(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn set_devices(&self, user_name: &str, devices: &[Device]) -> Result<bool, StoreError> {
+        let result = sqlx::query("UPDATE users SET devices = ? WHERE user_name = ?")
+            .bind(devices_to_json(devices))
+            .bind(user_name)
+            .execute(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn set_admin(&self, user_name: &str, admin: bool) -> Result<bool, StoreError> {
+        let result = sqlx::query("UPDATE users SET admin = ? WHERE user_name = ?")
+            .bind(admin)
+            .bind(user_name)
+            .execute(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn record_attempt(
+        &self,
+        user_name: &str,
+        attempt: LoginAttempt,
+        keep: usize,
+    ) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await.map_err(StoreError::backend)?;
+        sqlx::query(
+            "INSERT INTO login_attempts (user_name, at, success, source_ip, auth_id)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(user_name)
+        .bind(attempt.at as i64)
+        .bind(attempt.success)
+        .bind(attempt.source_ip)
+        .bind(attempt.auth_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(StoreError::backend)?;
+        sqlx::query(
+            "DELETE FROM login_attempts WHERE user_name = ? AND id NOT IN
+             (SELECT id FROM login_attempts WHERE user_name = ? ORDER BY id DESC LIMIT ?)",
+        )
+        .bind(user_name)
+        .bind(user_name)
+        .bind(keep as i64)
+        .execute(&mut *tx)
+        .await
+        .map_err(StoreError::backend)?;
+        tx.commit().await.map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn list_attempts(
+        &self,
+        user_name: &str,
+        limit: usize,
+    ) -> Result<Vec<LoginAttempt>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT at, success, source_ip, auth_id FROM login_attempts
+             WHERE user_name = ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(user_name)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(rows
+            .iter()
+            .map(|row| LoginAttempt {
+                at: row.get::<i64, _>("at") as u64,
+                success: row.get("success"),
+                source_ip: row.get("source_ip"),
+                auth_id: row.get("auth_id"),
+            })
+            .collect())
+    }
+
+    async fn set_recovery_codes(
+        &self,
+        user_name: &str,
+        hashes: Vec<[u8; 32]>,
+    ) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await.map_err(StoreError::backend)?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_name = ?")
+            .bind(user_name)
+            .execute(&mut *tx)
+            .await
+            .map_err(StoreError::backend)?;
+        for hash in hashes {
+            sqlx::query("INSERT INTO recovery_codes (user_name, code_hash) VALUES (?, ?)")
+                .bind(user_name)
+                .bind(&hash[..])
+                .execute(&mut *tx)
+                .await
+                .map_err(StoreError::backend)?;
+        }
+        tx.commit().await.map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_name: &str,
+        hash: &[u8; 32],
+    ) -> Result<bool, StoreError> {
+        // A single statement, two requests can't both delete the row.
+        let result =
+            sqlx::query("DELETE FROM recovery_codes WHERE user_name = ? AND code_hash = ?")
+                .bind(user_name)
+                .bind(&hash[..])
+                .execute(&self.pool)
+                .await
+                .map_err(StoreError::backend)?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn list_recovery_codes(&self, user_name: &str) -> Result<Vec<[u8; 32]>, StoreError> {
+        let rows = sqlx::query("SELECT code_hash FROM recovery_codes WHERE user_name = ?")
+            .bind(user_name)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        rows.iter()
+            .map(|row| {
+                row.get::<Vec<u8>, _>("code_hash")
+                    .as_slice()
+                    .try_into()
+                    .map_err(StoreError::corrupt)
+            })
+            .collect()
+    }
+
+    async fn list_stale_users(
+        &self,
+        before: u64,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT user_name FROM user_activity JOIN users USING (user_name)
+             WHERE last_used_at < ? AND user_name > ? ORDER BY user_name LIMIT ?",
+        )
+        .bind(before as i64)
+        .bind(after)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(rows.iter().map(|row| row.get("user_name")).collect())
+    }
+
+    async fn shutdown(&self) -> Result<(), StoreError> {
+        self.pool.close().await;
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl SessionStore for SqliteStore {
+    async fn save_challenge(
+        &self,
+        auth_id: &AuthId,
+        challenge: AuthAttempt,
+    ) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO challenges (auth_id, user_name, state, expires_at)
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(auth_id.as_str())
+        .bind(&challenge.user_name)
+        .bind(challenge.state.to_json())
+        .bind(challenge.expires_at as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn consume_auth_id(&self, auth_id: &AuthId) -> Result<Option<AuthAttempt>, StoreError> {
+        // A single statement, two requests can't both get the row.
+        let row = sqlx::query(
+            "DELETE FROM challenges WHERE auth_id = ? RETURNING user_name, state, expires_at",
+        )
+        .bind(auth_id.as_str())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(AuthAttempt {
+            user_name: row.get("user_name"),
+            state: ChallengeState::from_json(row.get("state"))?,
+            expires_at: row.get::<i64, _>("expires_at") as u64,
+        }))
+    }
+
+    async fn save_session(
+        &self,
+        session_id: &SessionId,
+        session: Session,
+    ) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO sessions (session_id, user_name, created_at, expires_at, device,
+             last_seen_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(session_id.as_str())
+        .bind(&session.user_name)
+        .bind(session.created_at as i64)
+        .bind(session.expires_at as i64)
+        .bind(&session.device)
+        .bind(session.last_seen as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        let row = sqlx::query(
+            "SELECT user_name, created_at, expires_at, device, last_seen_at FROM sessions
+             WHERE session_id = ?",
+        )
+        .bind(session_id.as_str())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(row.map(|row| session_from_row(&row)))
+    }
+
+    async fn remove_session(&self, session_id: &SessionId) -> Result<Option<Session>, StoreError> {
+        // A single statement, two requests can't both get the row.
+        let row = sqlx::query(
+            "DELETE FROM sessions WHERE session_id = ?
+             RETURNING user_name, created_at, expires_at, device, last_seen_at",
+        )
+        .bind(session_id.as_str())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(row.map(|row| session_from_row(&row)))
+    }
+
+    async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        sqlx::query(
+            "UPDATE sessions SET last_seen_at = MAX(last_seen_at, ?),
+             expires_at = MAX(expires_at, ?) WHERE session_id = ?",
+        )
+        .bind(at as i64)
+        .bind(expires_at as i64)
+        .bind(session_id.as_str())
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO revoked_sessions (session_id, expires_at) VALUES (?, ?)",
+        )
+        .bind(session_id.as_str())
+        .bind(expires_at as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn is_revoked(&self, session_id: &SessionId) -> Result<bool, StoreError> {
+        let row =
+            sqlx::query("SELECT 1 FROM revoked_sessions WHERE session_id = ? AND expires_at > ?")
+                .bind(session_id.as_str())
+                .bind(unix_now() as i64)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(StoreError::backend)?;
+        Ok(row.is_some())
+    }
+
+    async fn record_failure(&self, user_name: &str) -> Result<u32, StoreError> {
+        let row = sqlx::query(
+            "INSERT INTO lockouts (user_name, failures) VALUES (?, 1)
+             ON CONFLICT (user_name) DO UPDATE SET failures = lockouts.failures + 1
+             RETURNING failures",
+        )
+        .bind(user_name)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(row.get::<i64, _>("failures") as u32)
+    }
+
+    async fn clear_failures(&self, user_name: &str) -> Result<(), StoreError> {
+        sqlx::query("UPDATE lockouts SET failures = 0 WHERE user_name = ?")
+            .bind(user_name)
+            .execute(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn lock_user(&self, user_name: &str, until: u64) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO lockouts (user_name, failures, locked_until) VALUES (?, 0, ?)
+             ON CONFLICT (user_name) DO UPDATE SET
+                 failures = 0, locked_until = EXCLUDED.locked_until",
+        )
+        .bind(user_name)
+        .bind(until as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn locked_until(&self, user_name: &str) -> Result<Option<u64>, StoreError> {
+        let row = sqlx::query("SELECT locked_until FROM lockouts WHERE user_name = ?")
+            .bind(user_name)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(row
+            .and_then(|row| row.get::<Option<i64>, _>("locked_until"))
+            .map(|until| until as u64))
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<u64, StoreError> {
+        let mut tx = self.pool.begin().await.map_err(StoreError::backend)?;
+        let mut sessions = 0;
+        for table in ["challenges", "sessions", "lockouts"] {
+            let result = sqlx::query(&format!("DELETE FROM {table} WHERE user_name = ?"))
+                .bind(user_name)
+                .execute(&mut *tx)
+                .await
+                .map_err(StoreError::backend)?;
+            if table == "sessions" {
+                sessions = result.rows_affected();
+            }
+        }
+        tx.commit().await.map_err(StoreError::backend)?;
+        Ok(sessions)
+    }
+
+    async fn list_sessions(
+        &self,
+        user_name: &str,
+    ) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT session_id, user_name, created_at, expires_at, device, last_seen_at
+             FROM sessions
+             WHERE user_name = ?",
+        )
+        .bind(user_name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(rows.iter().map(session_with_id).collect())
+    }
+
+    async fn list_all_sessions(&self) -> Result<Vec<(SessionId, Session)>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT session_id, user_name, created_at, expires_at, device, last_seen_at
+             FROM sessions",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(rows.iter().map(session_with_id).collect())
+    }
+
+    async fn count_sessions(&self) -> Result<u64, StoreError> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM sessions WHERE expires_at > ?")
+            .bind(unix_now() as i64)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(row.get::<i64, _>("count") as u64)
+    }
+
+    async fn purge_expired(&self, now: u64, limit: usize) -> Result<u64, StoreError> {
+        let mut purged = 0;
+        for (table, key, expired) in EXPIRED {
+            // The condition again outside the subquery, for a row updated
+            // since it was picked: a user locked out again, say.
+            let result = sqlx::query(&format!(
+                "DELETE FROM {table} WHERE {expired} AND {key} IN
+                 (SELECT {key} FROM {table} WHERE {expired} LIMIT ?2)"
+            ))
+            .bind(now as i64)
+            .bind((limit as u64 - purged) as i64)
+            .execute(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+            purged += result.rows_affected();
+        }
+        Ok(purged)
+    }
+
+    async fn pow_difficulty(&self) -> Result<Option<u32>, StoreError> {
+        let row = sqlx::query("SELECT value FROM settings WHERE name = 'pow_difficulty'")
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        row.map(|row| u32::try_from(row.get::<i64, _>("value")).map_err(StoreError::corrupt))
+            .transpose()
+    }
+
+    async fn set_pow_difficulty(&self, difficulty: u32) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO settings (name, value) VALUES ('pow_difficulty', ?1)
+             ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+        )
+        .bind(i64::from(difficulty))
+        .execute(&self.pool)
+        .await
+        .map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn ip_denylist(&self) -> Result<Option<Vec<String>>, StoreError> {
+        let set = sqlx::query("SELECT value FROM settings WHERE name = 'ip_denylist'")
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        if set.is_none() {
+            return Ok(None);
+        }
+        let rows = sqlx::query("SELECT cidr FROM ip_denylist ORDER BY cidr")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(StoreError::backend)?;
+        Ok(Some(rows.iter().map(|row| row.get("cidr")).collect()))
+    }
+
+    async fn set_ip_denylist(&self, cidrs: &[String]) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await.map_err(StoreError::backend)?;
+        sqlx::query("DELETE FROM ip_denylist")
+            .execute(&mut *tx)
+            .await
+            .map_err(StoreError::backend)?;
+        for cidr in cidrs {
+            sqlx::query("INSERT INTO ip_denylist (cidr) VALUES (?1) ON CONFLICT DO NOTHING")
+                .bind(cidr)
+                .execute(&mut *tx)
+                .await
+                .map_err(StoreError::backend)?;
+        }
+        sqlx::query(
+            "INSERT INTO settings (name, value) VALUES ('ip_denylist', ?1)
+             ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+        )
+        .bind(cidrs.len() as i64)
+        .execute(&mut *tx)
+        .await
+        .map_err(StoreError::backend)?;
+        tx.commit().await.map_err(StoreError::backend)?;
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        UserStore::ping(self).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{server::store::Device, ChallengeSpec, ParamSet, ZKP};
+
+    #[tokio::test]
+    async fn test_sqlite_roundtrip() {
+        let path =
+            std::env::temp_dir().join(format!("zkp_auth_{}.db", ZKP::generate_random_string(8)));
+        let store = SqliteStore::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let user = UserInfo {
+            user_name: "alice".to_string(),
+            y1: BigUint::from(4u32),
+            y2: BigUint::from(9u32),
+            salt: vec![1; 16],
+            kdf_params: KdfParams::default(),
+            group: Some(
+                ZKP::builder()
+                    .param_set(ParamSet::Rfc5114_2048_256)
+                    .build()
+                    .unwrap(),
+            ),
+            created_at: 1_700_000_000,
+            last_login: None,
+            metadata: [("plan".to_string(), "pro".to_string())].into(),
+            admin: true,
+            totp_secret: Some(vec![7; 20]),
+            devices: vec![Device {
+                label: "phone".to_string(),
+                y1: BigUint::from(16u32),
+                y2: BigUint::from(25u32),
+                salt: vec![2; 16],
+                kdf_params: KdfParams::default(),
+                added_at: 1_700_000_050,
+            }],
+        };
+        assert!(store.create_user(user.clone()).await.unwrap());
+        assert!(!store.create_user(user.clone()).await.unwrap());
+        store.put_user(user.clone()).await.unwrap();
+        let stored = store.get_user("alice").await.unwrap().unwrap();
+        assert_eq!(
+            (stored.y1, stored.y2, stored.salt),
+            (user.y1, user.y2, user.salt)
+        );
+        assert_eq!(stored.kdf_params, user.kdf_params);
+        assert_eq!(
+            stored.group.map(|group| group.to_bytes()),
+            user.group.map(|group| group.to_bytes())
+        );
+        assert_eq!(
+            (stored.created_at, stored.last_login, stored.metadata),
+            (user.created_at, None, user.metadata)
+        );
+        assert!(stored.admin);
+        assert_eq!(stored.totp_secret, user.totp_secret);
+        assert_eq!(stored.devices, user.devices);
+        store.record_login("alice", 1_700_000_100).await.unwrap();
+        let stored = store.get_user("alice").await.unwrap().unwrap();
+        assert_eq!(stored.last_login, Some(1_700_000_100));
+        assert!(store.set_devices("alice", &[]).await.unwrap());
+        let stored = store.get_user("alice").await.unwrap().unwrap();
+        assert!(stored.devices.is_empty());
+        assert_eq!(stored.salt, vec![1; 16]);
+        assert!(!store.set_devices("bob", &user.devices).await.unwrap());
+        assert!(store.set_admin("alice", false).await.unwrap());
+        assert!(!store.get_user("alice").await.unwrap().unwrap().admin);
+        assert!(!store.set_admin("bob", true).await.unwrap());
+        assert!(store.get_user("bob").await.unwrap().is_none());
+
+        let zkp = ZKP::default();
+        let committed = zkp.commit_challenge(&ChallengeSpec::full(&zkp));
+        let challenge = AuthAttempt {
+            user_name: "alice".to_string(),
+            state: ChallengeState::Committed(committed.clone()),
+            expires_at: 1_700_000_000,
+        };
+        store
+            .save_challenge(&AuthId::from("auth"), challenge)
+            .await
+            .unwrap();
+
+        let consumed = store
+            .consume_auth_id(&AuthId::from("auth"))
+            .await
+            .unwrap()
+            .unwrap();
+        let ChallengeState::Committed(restored) = consumed.state else {
+            panic!("state changed in storage");
+        };
+        assert_eq!(restored, committed);
+        assert_eq!(consumed.expires_at, 1_700_000_000);
+        assert!(store
+            .consume_auth_id(&AuthId::from("auth"))
+            .await
+            .unwrap()
+            .is_none());
+
+        let session = Session {
+            user_name: "alice".to_string(),
+            created_at: 1_700_000_000,
+            expires_at: 1_700_086_400,
+            device: Some("phone".to_string()),
+            last_seen: 1_700_000_000,
+        };
+        store
+            .save_session(&SessionId::from("session"), session.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .get_session(&SessionId::from("session"))
+                .await
+                .unwrap(),
+            Some(session.clone())
+        );
+        store
+            .touch_session(&SessionId::from("session"), 1_700_000_600, 1_700_090_000)
+            .await
+            .unwrap();
+        store
+            .touch_session(&SessionId::from("session"), 1_700_000_300, 1_700_080_000)
+            .await
+            .unwrap();
+        let session = Session {
+            last_seen: 1_700_000_600,
+            expires_at: 1_700_090_000,
+            ..session
+        };
+        assert_eq!(
+            store
+                .remove_session(&SessionId::from("session"))
+                .await
+                .unwrap(),
+            Some(session.clone())
+        );
+        store
+            .touch_session(&SessionId::from("session"), 1_700_000_900, 1_700_090_000)
+            .await
+            .unwrap();
+        assert!(store
+            .get_session(&SessionId::from("session"))
+            .await
+            .unwrap()
+            .is_none());
+
+        store
+            .revoke_session(&SessionId::from("session"), u64::MAX >> 1)
+            .await
+            .unwrap();
+        store
+            .revoke_session(&SessionId::from("stale"), 1)
+            .await
+            .unwrap();
+        assert!(store.is_revoked(&SessionId::from("session")).await.unwrap());
+        assert!(!store.is_revoked(&SessionId::from("stale")).await.unwrap());
+
+        assert_eq!(store.record_failure("alice").await.unwrap(), 1);
+        assert_eq!(store.record_failure("alice").await.unwrap(), 2);
+        store.lock_user("alice", 1_700_000_000).await.unwrap();
+        assert_eq!(store.record_failure("alice").await.unwrap(), 1);
+        store.clear_failures("alice").await.unwrap();
+        assert_eq!(store.record_failure("alice").await.unwrap(), 1);
+        assert_eq!(
+            store.locked_until("alice").await.unwrap(),
+            Some(1_700_000_000)
+        );
+        assert_eq!(store.locked_until("bob").await.unwrap(), None);
+
+        store
+            .save_session(&SessionId::from("a"), session.clone())
+            .await
+            .unwrap();
+        store
+            .save_session(&SessionId::from("b"), session)
+            .await
+            .unwrap();
+        assert_eq!(store.list_sessions("alice").await.unwrap().len(), 2);
+        let users = store.list_users("", 10).await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert!(store.list_users("alice", 10).await.unwrap().is_empty());
+        assert_eq!(store.purge_user("alice").await.unwrap(), 2);
+        assert!(store
+            .get_session(&SessionId::from("a"))
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(store.locked_until("alice").await.unwrap(), None);
+
+        store
+            .set_recovery_codes("alice", vec![[1; 32], [2; 32]])
+            .await
+            .unwrap();
+        assert!(store.use_recovery_code("alice", &[1; 32]).await.unwrap());
+        assert!(!store.use_recovery_code("alice", &[1; 32]).await.unwrap());
+        assert_eq!(
+            store.list_recovery_codes("alice").await.unwrap(),
+            vec![[2; 32]]
+        );
+        assert!(!store.use_recovery_code("bob", &[2; 32]).await.unwrap());
+        assert!(store.delete_user("alice").await.unwrap());
+        assert!(!store.use_recovery_code("alice", &[2; 32]).await.unwrap());
+        assert!(store.get_user("alice").await.unwrap().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_migrations() {
+        let path =
+            std::env::temp_dir().join(format!("zkp_auth_{}.db", ZKP::generate_random_string(8)));
+        let url = format!("sqlite://{}", path.display());
+        // A database from before schema versions were kept.
+        {
+            let options: SqliteConnectOptions = url.parse().unwrap();
+            let pool = SqlitePool::connect_with(options.create_if_missing(true))
+                .await
+                .unwrap();
+            sqlx::raw_sql(
+                "CREATE TABLE users (user_name TEXT PRIMARY KEY, y1 BLOB NOT NULL,
+                    y2 BLOB NOT NULL, salt BLOB NOT NULL, m_cost INTEGER NOT NULL,
+                    t_cost INTEGER NOT NULL, p_cost INTEGER NOT NULL, group_params BLOB);
+                INSERT INTO users VALUES ('alice', X'04', X'09', X'01', 1, 1, 1, NULL);",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            pool.close().await;
+        }
+
+        let store = SqliteStore::connect(&url).await.unwrap();
+        assert!(store.get_user("alice").await.unwrap().is_some());
+        assert!(store
+            .list_stale_users(unix_now() + 1, "", 10)
+            .await
+            .unwrap()
+            .contains(&"alice".to_string()));
+
+        // A newer server applied a migration this one doesn't know.
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (9999, 'future', TRUE, X'00', 0)",
+        )
+        .execute(&store.pool)
+        .await
+        .unwrap();
+        store.pool.close().await;
+        let err = SqliteStore::connect(&url).await.unwrap_err();
+        assert!(err.to_string().contains("schema version 9999"), "{err}");
+        std::fs::remove_file(path).unwrap();
+    }
+}
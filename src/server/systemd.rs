@@ -0,0 +1,122 @@
+//! systemd socket activation and readiness. Under a .socket unit systemd
+//! binds the listeners and hands them over in LISTEN_FDS, so the socket
+//! keeps taking connections while the server restarts, and with
+//! Type=notify it learns from NOTIFY_SOCKET when the server is serving and
+//! when it is shutting down.
+//!
+//! ```ini
+//! # zkp-auth.socket
+//! [Socket]
+//! ListenStream=5051
+//!
+//! # zkp-auth.service
+//! [Service]
+//! Type=notify
+//! ExecStart=/usr/local/bin/server
+//! ```
+
+use std::{
+    io,
+    net::TcpListener,
+    os::{
+        fd::{FromRawFd, IntoRawFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram, UnixListener},
+    },
+};
+
+/// The first descriptor systemd passes, SD_LISTEN_FDS_START.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A listener systemd bound for the server.
+#[derive(Debug)]
+pub enum Activated {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// The listeners passed in LISTEN_FDS, none when the server wasn't started
+/// by socket activation. They are the server's to take once, so the
+/// variables are removed.
+pub fn listeners() -> io::Result<Vec<Activated>> {
+    let for_us = std::env::var("LISTEN_PID").is_ok_and(|pid| pid.parse() == Ok(std::process::id()));
+    let fds = std::env::var("LISTEN_FDS").ok();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    let Some(fds) = fds.filter(|_| for_us) else {
+        return Ok(Vec::new());
+    };
+    let count: RawFd = fds
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "LISTEN_FDS is not a number"))?;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: systemd passes the process `count` open sockets from
+            // fd 3 on, and nothing else in the process owns them.
+            let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+            let listener = match tcp.local_addr() {
+                Ok(_) => Activated::Tcp(tcp),
+                // SAFETY: as above, the descriptor only changes hands.
+                Err(_) => Activated::Unix(unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) }),
+            };
+            match &listener {
+                Activated::Tcp(tcp) => tcp.set_nonblocking(true)?,
+                Activated::Unix(unix) => unix.set_nonblocking(true)?,
+            }
+            Ok(listener)
+        })
+        .collect()
+}
+
+/// Tells systemd the server is serving, READY=1.
+pub fn notify_ready() {
+    notify("READY=1");
+}
+
+/// Tells systemd the server is shutting down, STOPPING=1.
+pub fn notify_stopping() {
+    notify("STOPPING=1");
+}
+
+/// Sends `state` to NOTIFY_SOCKET, if set. systemd carries on without the
+/// message when it doesn't arrive, so failures are only logged.
+fn notify(state: &str) {
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(err) = send(&path, state) {
+        log::warn!("Can't notify systemd at {path}: {err}");
+    }
+}
+
+fn send(path: &str, state: &str) -> io::Result<()> {
+    // A leading '@' names a socket in the abstract namespace.
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ZKP;
+
+    #[test]
+    fn test_send() {
+        let path = std::env::temp_dir().join(format!(
+            "zkp_auth_notify_{}",
+            ZKP::generate_random_string(8)
+        ));
+        let socket = UnixDatagram::bind(&path).unwrap();
+        send(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0; 16];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+//! Logs, and traces of every call sent to an OTLP collector when
+//! OTEL_EXPORTER_OTLP_ENDPOINT is set. Each gRPC and REST call gets a span
+//! with a request ID, continued from the caller's W3C traceparent when it
+//! sent one, and each store call a span inside it.
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use clap::ValueEnum;
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+};
+use serde::Deserialize;
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span, Instrument, Span, Subscriber,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt,
+    layer::{self, Layered, SubscriberExt},
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+use crate::ZKP;
+
+/// service.name of the spans.
+const SERVICE_NAME: &str = "zkp-auth-server";
+
+/// Metadata a caller names its request with, for matching its logs to ours.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request ID taken from a caller, longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// How log lines are written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Which lines get logged, changeable while the server runs.
+#[derive(Clone)]
+pub struct LogFilter(reload::Handle<EnvFilter, Layered<RequestIds, Registry>>);
+
+impl LogFilter {
+    /// Filters by `directives` from now on, RUST_LOG syntax.
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.0.reload(filter)?;
+        Ok(())
+    }
+}
+
+/// Logs to stderr in `format`, filtered by `directives` or RUST_LOG when
+/// there are none, and starts exporting spans to the collector at
+/// `otlp_endpoint` if there is one. Lines logged while handling a call
+/// carry its span, request ID included. The provider must be shut down
+/// before the server exits, or the last batch of spans is lost.
+pub fn init(
+    format: LogFormat,
+    directives: Option<&str>,
+    otlp_endpoint: Option<&str>,
+) -> anyhow::Result<(LogFilter, Option<TracerProvider>)> {
+    let logs = match format {
+        LogFormat::Text => fmt::layer().with_writer(std::io::stderr).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(std::io::stderr).boxed(),
+    };
+    let filter = match directives {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => EnvFilter::from_default_env(),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    let subscriber = tracing_subscriber::registry()
+        .with(RequestIds)
+        .with(logs.with_filter(filter));
+    let log_filter = LogFilter(handle);
+
+    let Some(endpoint) = otlp_endpoint else {
+        subscriber.try_init()?;
+        return Ok((log_filter, None));
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)]))
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = provider.tracer(SERVICE_NAME);
+    // Spans of the server's own, not the debug chatter of its dependencies.
+    let traces = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(LevelFilter::INFO);
+    subscriber.with(traces).try_init()?;
+    log::info!("Sending traces to {endpoint}");
+    Ok((log_filter, Some(provider)))
+}
+
+/// Sends the spans still buffered.
+pub fn shutdown(provider: TracerProvider) {
+    if let Err(err) = provider.shutdown() {
+        log::warn!("Can't send the last traces: {err}");
+    }
+}
+
+/// The span of one gRPC call, for `Server::trace_fn`.
+pub fn rpc_span(request: &http::Request<()>) -> Span {
+    request_span(request.uri().path(), request.headers())
+}
+
+/// Runs a REST call in its span, as `rpc_span` does for gRPC.
+pub async fn http_span(request: Request, next: Next) -> Response {
+    let span = request_span(request.uri().path(), request.headers());
+    next.run(request).instrument(span).await
+}
+
+/// A span for the call to `path`, under the caller's trace if it sent a
+/// traceparent, and named by the caller's x-request-id or a fresh one.
+fn request_span(path: &str, headers: &http::HeaderMap) -> Span {
+    let request_id = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_request_id(id))
+        .map_or_else(|| ZKP::generate_random_string(16), str::to_string);
+    let span = tracing::info_span!("request", otel.name = path, request_id = %request_id);
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&Headers(headers)));
+    span.set_parent(parent);
+    span
+}
+
+/// The request ID of the call being handled, if there is one.
+pub fn request_id() -> Option<String> {
+    Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let span = dispatch.downcast_ref::<Registry>()?.span(id)?;
+            span.scope()
+                .find_map(|span| span.extensions().get::<RequestId>().map(|id| id.0.clone()))
+        })
+        .flatten()
+}
+
+/// Keeps the request ID of each request span where `request_id` finds it,
+/// as a span's fields can't be read back otherwise.
+pub struct RequestIds;
+
+struct RequestId(String);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for RequestIds {
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: layer::Context<'_, S>) {
+        let mut visitor = RequestIdVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(request_id), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(RequestId(request_id));
+        }
+    }
+}
+
+struct RequestIdVisitor(Option<String>);
+
+impl Visit for RequestIdVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "request_id" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// Whether a caller's request ID is fit to log as is.
+fn is_request_id(id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LEN).contains(&id.len())
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+}
+
+/// Reads the trace context out of request headers.
+struct Headers<'a>(&'a http::HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use opentelemetry::{propagation::TextMapPropagator, trace::TraceContextExt};
+
+    use super::*;
+
+    #[test]
+    fn test_traceparent() {
+        let request = http::Request::builder()
+            .header(
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            )
+            .body(())
+            .unwrap();
+        let context = TraceContextPropagator::new().extract(&Headers(request.headers()));
+        assert_eq!(
+            context.span().span_context().trace_id().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+    }
+
+    #[test]
+    fn test_request_id() {
+        assert!(is_request_id("7c9e6679-7425-40de-944b-e07fc1f90ae7"));
+        assert!(!is_request_id(""));
+        assert!(!is_request_id("a b"));
+        assert!(!is_request_id("line\nbreak"));
+        assert!(!is_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+}
@@ -0,0 +1,299 @@
+//! Webhooks: JSON POSTs to operator URLs when a user registers, or answers
+//! wrong so many times in a row. Each body is signed with WEBHOOK_SECRET in
+//! the x-zkp-signature header, `sha256=` and the hex HMAC-SHA256 of the
+//! body, and retried with backoff while the receiver is down or failing.
+
+use std::{net::IpAddr, time::Duration};
+
+use bytes::Bytes;
+use clap::ValueEnum;
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use reqwest::{header::CONTENT_TYPE, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    server::{events::AuthEvents, grpc_impl::auth::auth_impl::Peer, store::unix_now},
+    AuthId,
+};
+
+/// Header the signature of a body is sent in.
+pub const SIGNATURE_HEADER: &str = "x-zkp-signature";
+
+/// Wrong answers in a row that make a failed_logins event, unless
+/// configured otherwise.
+pub const DEFAULT_FAILURE_STREAK: u32 = 3;
+
+/// Tries of one delivery, the first included.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled before each one after.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest a receiver gets to answer one try.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a webhook can be sent for, the "event" of its body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A user registered.
+    Registered,
+    /// A user answered wrong WEBHOOK_FAILURE_STREAK times in a row, and
+    /// again each time as many wrong answers follow.
+    #[value(name = "failed_logins")]
+    FailedLogins,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Every event goes to each of them.
+    pub urls: Vec<Url>,
+    pub secret: Vec<u8>,
+    pub events: Vec<WebhookEvent>,
+    pub failure_streak: u32,
+}
+
+/// The body of a webhook.
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    event: WebhookEvent,
+    /// Unix time in seconds.
+    time: u64,
+    user: &'a str,
+    source_ip: Option<IpAddr>,
+    /// Wrong answers in a row, for failed_logins.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failures: Option<u32>,
+}
+
+/// Sends webhooks as `AuthImpl` reports events. Deliveries run in tasks of
+/// their own, so a slow receiver doesn't hold up logins.
+pub struct Webhooks {
+    config: WebhookConfig,
+    client: reqwest::Client,
+    /// Wrong answers in a row by user. Each replica counts its own.
+    failures: DashMap<String, u32>,
+    first_backoff: Duration,
+}
+
+impl std::fmt::Debug for Webhooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhooks")
+            .field("urls", &self.config.urls)
+            .field("events", &self.config.events)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Webhooks {
+    pub fn new(config: WebhookConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Self {
+            config,
+            client,
+            failures: DashMap::new(),
+            first_backoff: FIRST_BACKOFF,
+        })
+    }
+
+    /// Sends `payload` to every URL, if its event is wanted.
+    fn send(&self, payload: Payload) {
+        if !self.config.events.contains(&payload.event) {
+            return;
+        }
+        let body = Bytes::from(serde_json::to_vec(&payload).expect("The payload serializes."));
+        let signature = sign(&self.config.secret, &body);
+        for url in &self.config.urls {
+            tokio::spawn(deliver(
+                self.client.clone(),
+                url.clone(),
+                body.clone(),
+                signature.clone(),
+                self.first_backoff,
+            ));
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl AuthEvents for Webhooks {
+    async fn on_registered(&self, user: &str, peer: &Peer) {
+        self.send(Payload {
+            event: WebhookEvent::Registered,
+            time: unix_now(),
+            user,
+            source_ip: peer.ip,
+            failures: None,
+        });
+    }
+
+    async fn on_auth_success(&self, user: &str, _auth_id: &AuthId, _peer: &Peer) {
+        self.failures.remove(user);
+    }
+
+    async fn on_auth_failure(&self, user: &str, _auth_id: &AuthId, peer: &Peer) {
+        let failures = {
+            let mut failures = self.failures.entry(user.to_string()).or_insert(0);
+            *failures += 1;
+            *failures
+        };
+        if failures % self.config.failure_streak == 0 {
+            self.send(Payload {
+                event: WebhookEvent::FailedLogins,
+                time: unix_now(),
+                user,
+                source_ip: peer.ip,
+                failures: Some(failures),
+            });
+        }
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `body` under `secret`.
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length.");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs `body` to `url` until it is taken, refused for good or the tries
+/// run out. Failures are retried, but for 4xx other than 429: the receiver
+/// won't take the body any better the next time.
+async fn deliver(
+    client: reqwest::Client,
+    url: Url,
+    body: Bytes,
+    signature: String,
+    mut backoff: Duration,
+) {
+    for attempt in 1..=MAX_ATTEMPTS {
+        let sent = client
+            .post(url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .send()
+            .await;
+        match sent {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response)
+                if response.status().is_client_error()
+                    && response.status() != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                log::warn!("Webhook {url} refused with {}", response.status());
+                return;
+            }
+            Ok(response) => {
+                log::warn!(
+                    "Webhook {url} answered {}, try {attempt} of {MAX_ATTEMPTS}",
+                    response.status()
+                );
+            }
+            Err(err) => {
+                log::warn!("Webhook {url} failed: {err}, try {attempt} of {MAX_ATTEMPTS}");
+            }
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    log::error!("Webhook {url} given up on");
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::State, http::HeaderMap, routing::post, Router};
+
+    use super::*;
+
+    /// Bodies a receiver got, with their signatures.
+    type Received = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    /// A receiver that fails the first call, and takes every one after.
+    async fn receiver() -> (Url, Received) {
+        let received = Received::default();
+        let router = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                        let mut received = received.lock().unwrap();
+                        let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+                        assert_eq!(signature, sign(b"secret", &body));
+                        received.push((signature, serde_json::from_slice(&body).unwrap()));
+                        if received.len() == 1 {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        } else {
+                            StatusCode::OK
+                        }
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (url.parse().unwrap(), received)
+    }
+
+    /// Waits for the receiver to get `count` bodies.
+    async fn wait_for(received: &Received, count: usize) {
+        for _ in 0..100 {
+            if received.lock().unwrap().len() >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Got {:?}", received.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_webhooks() {
+        let (url, received) = receiver().await;
+        let mut webhooks = Webhooks::new(WebhookConfig {
+            urls: vec![url],
+            secret: b"secret".to_vec(),
+            events: vec![WebhookEvent::Registered, WebhookEvent::FailedLogins],
+            failure_streak: 2,
+        })
+        .unwrap();
+        webhooks.first_backoff = Duration::from_millis(10);
+        let peer = Peer {
+            ip: Some("192.0.2.1".parse().unwrap()),
+            cert: None,
+        };
+
+        // The first try fails and is retried.
+        webhooks.on_registered("alice", &peer).await;
+        wait_for(&received, 2).await;
+
+        // A success in between starts the streak over.
+        webhooks
+            .on_auth_failure("alice", &AuthId::from("1"), &peer)
+            .await;
+        webhooks
+            .on_auth_success("alice", &AuthId::from("2"), &peer)
+            .await;
+        webhooks
+            .on_auth_failure("alice", &AuthId::from("3"), &peer)
+            .await;
+        webhooks
+            .on_auth_failure("alice", &AuthId::from("4"), &peer)
+            .await;
+        wait_for(&received, 3).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], received[1]);
+        assert_eq!(received[1].1["event"], "registered");
+        assert_eq!(received[1].1["user"], "alice");
+        assert_eq!(received[1].1["source_ip"], "192.0.2.1");
+        assert_eq!(received[2].1["event"], "failed_logins");
+        assert_eq!(received[2].1["failures"], 2);
+    }
+}
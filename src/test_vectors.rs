@@ -0,0 +1,212 @@
+use num_bigint::{BigUint, RandBigInt};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{check_params, ParamSet, ZkpError, ZKP};
+
+/// Seed of the RNG behind `fixtures`, changing it changes every fixture.
+pub const FIXTURE_SEED: u64 = 0x7a6b_705f_6361_7065;
+
+/// Number of runs `fixtures` produces for each parameter set.
+pub const FIXTURES_PER_SET: usize = 3;
+
+/// One full run of the interactive protocol with every value written out, so
+/// that other implementations can replay it and compare.
+///
+/// Numbers are lowercase hex without a prefix or padding, which is what
+/// Python's `int(v, 16)` and JS's `BigInt("0x" + v)` both read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub name: String,
+
+    // group
+    pub p: String,
+    pub q: String,
+    pub alpha: String,
+    pub beta: String,
+
+    // inputs
+    pub x: String,
+    pub k: String,
+    pub c: String,
+
+    // expected outputs
+    pub y1: String,
+    pub y2: String,
+    pub r1: String,
+    pub r2: String,
+    pub s: String,
+}
+
+impl TestVector {
+    /// Runs the prover with the given secret, nonce and challenge and records
+    /// the result.
+    pub fn generate(name: &str, zkp: &ZKP, x: &BigUint, k: &BigUint, c: &BigUint) -> Self {
+        let (y1, y2) = zkp.compute_pair(x);
+        let (r1, r2) = zkp.compute_pair(k);
+        let s = zkp.solve(k, c, x);
+
+        Self {
+            name: name.to_string(),
+            p: to_hex(zkp.p()),
+            q: to_hex(zkp.q()),
+            alpha: to_hex(zkp.alpha()),
+            beta: to_hex(zkp.beta()),
+            x: to_hex(x),
+            k: to_hex(k),
+            c: to_hex(c),
+            y1: to_hex(&y1),
+            y2: to_hex(&y2),
+            r1: to_hex(&r1),
+            r2: to_hex(&r2),
+            s: to_hex(&s),
+        }
+    }
+
+    /// The group this vector was generated in, validated.
+    pub fn zkp(&self) -> Result<ZKP, ZkpError> {
+        let p = from_hex("p", &self.p)?;
+        let q = from_hex("q", &self.q)?;
+        let alpha = from_hex("alpha", &self.alpha)?;
+        let beta = from_hex("beta", &self.beta)?;
+        check_params(&p, &q, &alpha, &beta)?;
+        Ok(ZKP::new(p, q, alpha, beta))
+    }
+
+    /// Recomputes every output from the inputs and checks that the verifier
+    /// accepts the recorded transcript.
+    pub fn check(&self) -> Result<(), ZkpError> {
+        let zkp = self.zkp()?;
+        let x = from_hex("x", &self.x)?;
+        let k = from_hex("k", &self.k)?;
+        let c = from_hex("c", &self.c)?;
+
+        if Self::generate(&self.name, &zkp, &x, &k, &c) != *self {
+            return Err(ZkpError::Verify);
+        }
+
+        zkp.verify(
+            &from_hex("r1", &self.r1)?,
+            &from_hex("r2", &self.r2)?,
+            &from_hex("y1", &self.y1)?,
+            &from_hex("y2", &self.y2)?,
+            &c,
+            &from_hex("s", &self.s)?,
+        )
+    }
+}
+
+/// Pretty-printed JSON array of `vectors`.
+pub fn to_json(vectors: &[TestVector]) -> Result<String, ZkpError> {
+    serde_json::to_string_pretty(vectors).map_err(|err| ZkpError::Encoding(err.to_string()))
+}
+
+/// Inverse of `to_json`. The vectors are only parsed, call `TestVector::check`
+/// to replay them.
+pub fn from_json(json: &str) -> Result<Vec<TestVector>, ZkpError> {
+    serde_json::from_str(json).map_err(|err| ZkpError::Encoding(err.to_string()))
+}
+
+/// Fixed vectors for the default RFC 5114 1024-bit group, shipped in
+/// `test_vectors/rfc5114_1024_160.json`.
+pub fn golden_vectors() -> Vec<TestVector> {
+    from_json(include_str!("../test_vectors/rfc5114_1024_160.json"))
+        .expect("The bundled test vectors are valid JSON.")
+}
+
+/// Deterministic protocol runs, `FIXTURES_PER_SET` for every `ParamSet`.
+///
+/// x, k and c come from a ChaCha20 stream seeded with `FIXTURE_SEED`, so the
+/// output is the same on every platform and across runs. Names look like
+/// `rfc5114-2048-256/1`.
+pub fn fixtures() -> Vec<TestVector> {
+    let mut rng = ChaCha20Rng::seed_from_u64(FIXTURE_SEED);
+    let mut vectors = Vec::with_capacity(ParamSet::ALL.len() * FIXTURES_PER_SET);
+
+    for set in ParamSet::ALL {
+        let zkp = ZKP::builder()
+            .param_set(set)
+            .build()
+            .expect("The named parameter sets are valid.");
+        for i in 0..FIXTURES_PER_SET {
+            let x = rng.gen_biguint_below(zkp.q());
+            let k = rng.gen_biguint_below(zkp.q());
+            let c = rng.gen_biguint_below(zkp.q());
+            vectors.push(TestVector::generate(
+                &format!("{}/{i}", set.name()),
+                &zkp,
+                &x,
+                &k,
+                &c,
+            ));
+        }
+    }
+
+    vectors
+}
+
+fn to_hex(n: &BigUint) -> String {
+    n.to_str_radix(16)
+}
+
+fn from_hex(field: &str, value: &str) -> Result<BigUint, ZkpError> {
+    BigUint::parse_bytes(value.as_bytes(), 16)
+        .ok_or_else(|| ZkpError::Encoding(format!("{field} is not a hex number")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_golden_vectors() {
+        let vectors = golden_vectors();
+        assert!(!vectors.is_empty());
+        for vector in &vectors {
+            assert!(vector.check().is_ok(), "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn test_fixtures_are_deterministic() {
+        let vectors = fixtures();
+        assert_eq!(vectors.len(), ParamSet::ALL.len() * FIXTURES_PER_SET);
+        assert_eq!(vectors, fixtures());
+
+        for set in ParamSet::ALL {
+            assert!(vectors
+                .iter()
+                .any(|vector| vector.name.starts_with(set.name())));
+        }
+        for vector in &vectors {
+            assert!(vector.check().is_ok(), "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let zkp = ZKP::default();
+        let x = ZKP::generate_random_below(zkp.q());
+        let k = ZKP::generate_random_below(zkp.q());
+        let c = ZKP::generate_random_below(zkp.q());
+        let vector = TestVector::generate("random", &zkp, &x, &k, &c);
+        assert!(vector.check().is_ok());
+
+        let json = to_json(std::slice::from_ref(&vector)).unwrap();
+        assert_eq!(from_json(&json).unwrap(), vec![vector]);
+    }
+
+    #[test]
+    fn test_tampered_vector_fails() {
+        let mut vector = golden_vectors().remove(0);
+        vector.s = "1".into();
+        assert!(matches!(vector.check(), Err(ZkpError::Verify)));
+
+        let mut vector = golden_vectors().remove(0);
+        vector.x = "not hex".into();
+        assert!(matches!(vector.check(), Err(ZkpError::Encoding(_))));
+
+        assert!(matches!(from_json("{"), Err(ZkpError::Encoding(_))));
+    }
+}
@@ -0,0 +1,39 @@
+//! Versions of the login protocol. A prover names the version it speaks and
+//! the newest one it could speak in its challenge request, and both go into
+//! `ChallengeBinding`: a man in the middle who talks either side down to an
+//! older version changes c and is caught by a prover that checks it.
+
+/// Newest protocol version this crate speaks. Version 2 binds the versions
+/// into challenges, version 1 is the protocol from before there were any.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version this crate still speaks.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The version a party speaking `min_version..=PROTOCOL_VERSION` uses with a
+/// peer speaking up to `peer_max_version`: the newest both speak, None when
+/// they have none in common.
+pub fn negotiate_version(peer_max_version: u32, min_version: u32) -> Option<u32> {
+    let version = peer_max_version.min(PROTOCOL_VERSION);
+    (version >= min_version.max(MIN_PROTOCOL_VERSION)).then_some(version)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION, 1),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION + 5, 1),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(negotiate_version(1, 1), Some(1));
+        assert_eq!(negotiate_version(1, 2), None);
+        assert_eq!(negotiate_version(0, 0), None);
+    }
+}
@@ -1,8 +1,106 @@
 // This file is @generated by prost-build.
 ///
+/// Argon2id cost parameters the prover used to derive x from its password.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct KdfParams {
+    #[prost(uint32, tag = "1")]
+    pub m_cost: u32,
+    #[prost(uint32, tag = "2")]
+    pub t_cost: u32,
+    #[prost(uint32, tag = "3")]
+    pub p_cost: u32,
+}
+///
+/// A group other than the server's: one of the named sets in param_set (e.g.
+/// "rfc5114-2048-256") or p, q, alpha, beta, each big-endian. Explicit groups must
+/// pass zkp_chaum_pedersen::validate_group
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GroupParams {
+    #[prost(string, tag = "1")]
+    pub param_set: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub p: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub q: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub alpha: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub beta: ::prost::alloc::vec::Vec<u8>,
+}
+///
+/// Prover sends the newest protocol version it speaks, the server answers with the
+/// newest one both speak, or FAILED_PRECONDITION when they have none in common.
+/// See zkp_chaum_pedersen::version
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct HelloRequest {
+    #[prost(uint32, tag = "1")]
+    pub max_version: u32,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct HelloResponse {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+}
+///
+/// Anyone asks what the server supports, to pick a mode both sides speak before
+/// registering:
+/// group_kinds: kinds of group users can be in, "mod-p" (Schnorr groups mod a
+/// prime p) so far
+/// param_sets: named sets a RegisterRequest may ask for
+/// custom_groups: whether a RegisterRequest may bring its own p, q, alpha, beta
+/// login_flows: "classic" (CreateAuthenticationChallenge) and "four-move"
+/// (CommitChallenge, OpenChallenge)
+/// proof_kinds: "interactive" for logins, "fiat-shamir" for the
+/// non-interactive proofs of UpdateCredentials and DeleteAccount
+/// session_token: "opaque" (random string) or "jwt" (HS256 signed)
+/// min_protocol_version, max_protocol_version: see Hello
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct CapabilitiesRequest {}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CapabilitiesResponse {
+    #[prost(string, repeated, tag = "1")]
+    pub group_kinds: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "2")]
+    pub param_sets: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(bool, tag = "3")]
+    pub custom_groups: bool,
+    #[prost(string, repeated, tag = "4")]
+    pub login_flows: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "5")]
+    pub proof_kinds: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, tag = "6")]
+    pub session_token: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "7")]
+    pub min_protocol_version: u32,
+    #[prost(uint32, tag = "8")]
+    pub max_protocol_version: u32,
+}
+///
+/// Anyone asks which group the server runs on, so clients needn't have it built
+/// in. param_set names it if it is one of the named sets of that version, see
+/// zkp_chaum_pedersen::PARAMS_VERSION; p, q, alpha and beta are always set
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ParametersRequest {}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ParametersResponse {
+    #[prost(message, optional, tag = "1")]
+    pub group: ::core::option::Option<GroupParams>,
+    #[prost(uint32, tag = "2")]
+    pub version: u32,
+}
+///
 /// Prover registers in the server sending:
 /// y1: alpha^x mod p
 /// y2: beta^x mod p
+/// salt, kdf_params: x = Argon2id(password, salt) mod (q - 1) + 1
+/// pow_nonce: solution to the registration puzzle, see GetRegistrationPuzzle
+/// group: the group alpha, beta, p and q are from, the server's when unset.
+/// Every later exchange of the user's happens in it
+/// metadata: anything the client keeps with the user, returned by the admin
+/// API as is. At most 16 entries, keys of at most 64 bytes and values of
+/// at most 1024
+/// totp: enrolls the user in TOTP, every login then needs a code as well
+/// as the proof. The server answers the secret, once
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RegisterRequest {
     #[prost(string, tag = "1")]
@@ -11,15 +109,95 @@ pub struct RegisterRequest {
     pub y1: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "3")]
     pub y2: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub salt: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "5")]
+    pub kdf_params: ::core::option::Option<KdfParams>,
+    #[prost(uint64, tag = "6")]
+    pub pow_nonce: u64,
+    #[prost(string, tag = "7")]
+    pub realm: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "8")]
+    pub group: ::core::option::Option<GroupParams>,
+    #[prost(map = "string, string", tag = "9")]
+    pub metadata: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    #[prost(bool, tag = "10")]
+    pub totp: bool,
 }
+///
+/// Prover asks how much work registering takes right now. With difficulty > 0
+/// the RegisterRequest must carry a pow_nonce such that
+/// SHA-256("zkp_chaum_pedersen/pow", qualified name, y1, y2, salt, pow_nonce)
+/// starts with difficulty zero bits, the fields length prefixed
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RegistrationPuzzleRequest {}
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
-pub struct RegisterResponse {}
+pub struct RegistrationPuzzleResponse {
+    #[prost(uint32, tag = "1")]
+    pub difficulty: u32,
+}
+///
+/// totp_secret: the user's TOTP secret in base32 for an authenticator app, as
+/// RFC 6238 with SHA-1, 30 second steps and 6 digits. Empty unless asked for
+/// recovery_codes: one-time codes for RecoverAccount, should the password be
+/// lost. Only their hashes stay with the server, they are never shown again
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct RegisterResponse {
+    #[prost(string, tag = "1")]
+    pub totp_secret: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub recovery_codes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+///
+/// Prover fetches its salt before login so it can derive x from the password again,
+/// along with the group it registered in as explicit p, q, alpha, beta (unset for
+/// the server's)
+/// device: label of a device added with AddDevice, for the salt and KDF
+/// parameters of its key instead of the user's
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SaltRequest {
+    #[prost(string, tag = "1")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub realm: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub device: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SaltResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub salt: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "2")]
+    pub kdf_params: ::core::option::Option<KdfParams>,
+    #[prost(message, optional, tag = "3")]
+    pub group: ::core::option::Option<GroupParams>,
+}
 ///
 /// Prover ask for challenge in the server sending r1, r2
 /// r1: alpha^k mod p
 /// r2: beta^k mod p
-/// Verifies sends challenge "c" back
+/// challenge_bits: width of c, 0 for all of [0, q). The server refuses widths
+/// that would leave less than 128 bits of soundness.
+/// protocol_version: version of the login, see Hello. 0 is version 1
+/// max_protocol_version: newest version the prover speaks, 0 is version 1.
+/// The server refuses a protocol_version older than
+/// the newest both speak
+/// nonce: 16 to 64 fresh random bytes, and timestamp: Unix time in seconds
+/// the request was made. A server with a replay window refuses
+/// requests without them, from outside the window or with a nonce
+/// it has seen, others ignore them
+/// Verifies sends challenge "c" back, bound to the login:
+/// c = H(challenge_seed, user, auth_id, server public key, versions, r1, r2)
+/// mod bound
+/// so an answer only counts for this auth_id, and a prover that pinned the
+/// server key can tell that c was made for it. Versions are bound from
+/// version 2 on
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
 pub struct AuthenticationChallengeRequest {
     #[prost(string, tag = "1")]
     pub user: ::prost::alloc::string::String,
@@ -27,6 +205,18 @@ pub struct AuthenticationChallengeRequest {
     pub r1: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "3")]
     pub r2: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag = "4")]
+    pub challenge_bits: u32,
+    #[prost(string, tag = "5")]
+    pub realm: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "6")]
+    pub protocol_version: u32,
+    #[prost(uint32, tag = "7")]
+    pub max_protocol_version: u32,
+    #[prost(bytes = "vec", tag = "8")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "9")]
+    pub timestamp: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AuthenticationChallengeResponse {
@@ -34,21 +224,657 @@ pub struct AuthenticationChallengeResponse {
     pub auth_id: ::prost::alloc::string::String,
     #[prost(bytes = "vec", tag = "2")]
     pub c: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub challenge_seed: ::prost::alloc::vec::Vec<u8>,
 }
 ///
 /// Prover sends solution "s = k - c * x mod q" to the challenge
-/// Verifier sends the session ID if the solution is correct
+/// Verifier sends the session ID if the solution is correct, together with a
+/// proof of the server's own secret over (user, auth_id, session_id, r1, r2, c, s)
+/// that the prover checks against the pinned server public key
+/// totp_code: the current TOTP code, for users enrolled in TOTP
+/// nonce, timestamp: as for AuthenticationChallengeRequest, a nonce of its own
+/// expires_at: Unix time in seconds from which the session is refused
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
 pub struct AuthenticationAnswerRequest {
     #[prost(string, tag = "1")]
     pub auth_id: ::prost::alloc::string::String,
     #[prost(bytes = "vec", tag = "2")]
     pub s: ::prost::alloc::vec::Vec<u8>,
+    #[prost(string, tag = "3")]
+    pub totp_code: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "4")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "5")]
+    pub timestamp: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
 pub struct AuthenticationAnswerResponse {
     #[prost(string, tag = "1")]
     pub session_id: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub server_proof: ::core::option::Option<Proof>,
+    #[prost(uint64, tag = "3")]
+    pub expires_at: u64,
+}
+///
+/// The classic flow over one stream instead of two calls, the attempt lives with
+/// the stream and never reaches the server's store:
+/// prover: start, as for CreateAuthenticationChallenge
+/// verifier: challenge, as CreateAuthenticationChallenge answers
+/// prover: answer, as for VerifyAuthentication, auth_id may be left empty
+/// verifier: session, as VerifyAuthentication answers
+/// The stream ends after the session or the first error
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuthenticateRequest {
+    #[prost(oneof = "authenticate_request::Step", tags = "1, 2")]
+    pub step: ::core::option::Option<authenticate_request::Step>,
+}
+/// Nested message and enum types in `AuthenticateRequest`.
+pub mod authenticate_request {
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Step {
+        #[prost(message, tag = "1")]
+        Start(super::AuthenticationChallengeRequest),
+        #[prost(message, tag = "2")]
+        Answer(super::AuthenticationAnswerRequest),
+    }
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuthenticateResponse {
+    #[prost(oneof = "authenticate_response::Step", tags = "1, 2")]
+    pub step: ::core::option::Option<authenticate_response::Step>,
+}
+/// Nested message and enum types in `AuthenticateResponse`.
+pub mod authenticate_response {
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Step {
+        #[prost(message, tag = "1")]
+        Challenge(super::AuthenticationChallengeResponse),
+        #[prost(message, tag = "2")]
+        Session(super::AuthenticationAnswerResponse),
+    }
+}
+///
+/// Four-move variant, the verifier commits to c before it sees r1, r2 so it can't
+/// pick c depending on them:
+/// CommitChallenge: prover sends user, verifier sends auth_id and
+/// commitment = H(user, auth_id, c, nonce), user with
+/// its realm, so the commitment is good for this login only
+/// OpenChallenge: prover sends r1, r2, verifier reveals c and nonce, the
+/// prover checks them against commitment
+/// VerifyAuthentication: as in the classic flow
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChallengeCommitmentRequest {
+    #[prost(string, tag = "1")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub challenge_bits: u32,
+    #[prost(string, tag = "3")]
+    pub realm: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChallengeCommitmentResponse {
+    #[prost(string, tag = "1")]
+    pub auth_id: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub commitment: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct OpenChallengeRequest {
+    #[prost(string, tag = "1")]
+    pub auth_id: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub r1: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub r2: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OpenChallengeResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub c: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+}
+///
+/// Non-interactive proof of x, the challenge is c = H(transcript) mod q
+/// expires_at: Unix time in seconds after which the proof is refused, part of
+/// the transcript. 0 for a proof that doesn't expire.
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct Proof {
+    #[prost(bytes = "vec", tag = "1")]
+    pub r1: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub r2: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub s: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "4")]
+    pub expires_at: u64,
+}
+///
+/// Prover replaces its registered y1, y2, salt and KDF parameters by new ones,
+/// proving in the same message that it knows both the old and the new secret
+/// old_proof, new_proof: see ZKP::prove_rotation, expires_at set and no later
+/// than a challenge would expire
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct UpdateCredentialsRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub new_y1: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub new_y2: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "4")]
+    pub old_proof: ::core::option::Option<Proof>,
+    #[prost(message, optional, tag = "5")]
+    pub new_proof: ::core::option::Option<Proof>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub new_salt: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "7")]
+    pub new_kdf_params: ::core::option::Option<KdfParams>,
+    #[prost(string, tag = "8")]
+    pub realm: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct UpdateCredentialsResponse {}
+///
+/// Prover that lost its password replaces its registered y1, y2, salt and KDF
+/// parameters by new ones, authorized by one of the recovery codes Register
+/// answered, which is then used up. new_proof proves the new secret, see
+/// ZKP::prove_recovery. The user's challenges, sessions and device keys end, a
+/// TOTP enrollment stays
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct RecoverAccountRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub realm: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub recovery_code: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "4")]
+    pub new_y1: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub new_y2: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "6")]
+    pub new_proof: ::core::option::Option<Proof>,
+    #[prost(bytes = "vec", tag = "7")]
+    pub new_salt: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "8")]
+    pub new_kdf_params: ::core::option::Option<KdfParams>,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RecoverAccountResponse {}
+///
+/// Prover deletes its registration together with its pending challenges and
+/// sessions. Authorized by one of
+/// proof: fresh proof of x, expires_at set, see ZKP::prove_deletion
+/// session_id: a live session of the user
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct DeleteAccountRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub proof: ::core::option::Option<Proof>,
+    #[prost(string, tag = "3")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub realm: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct DeleteAccountResponse {}
+///
+/// Prover trades a live session for a new one with a fresh expiry, the old
+/// session_id stops working
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct RefreshSessionRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct RefreshSessionResponse {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub expires_at: u64,
+}
+///
+/// Prover ends its session, the session_id stops working right away. Logging out
+/// of a session that is already gone succeeds as well
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct LogoutRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct LogoutResponse {}
+///
+/// Resource servers check a session_id a client presented to them. On a server
+/// with sliding sessions every valid check pushes expires_at out, up to the
+/// session's maximum lifetime
+/// valid: false for a session that is unknown, logged out or expired, user
+/// and expires_at are left empty then
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct ValidateSessionRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValidateSessionResponse {
+    #[prost(bool, tag = "1")]
+    pub valid: bool,
+    #[prost(string, tag = "2")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub expires_at: u64,
+    #[prost(string, tag = "4")]
+    pub realm: ::prost::alloc::string::String,
+}
+///
+/// A user adds a key for one of their devices with a live session of theirs,
+/// proving in the same message that they know its x, see ZKP::prove_device.
+/// Logins may then prove the device's x as well as the user's. The key is in the
+/// user's group
+/// label: names the device among the user's, 1 to 64 bytes. At most 16
+/// devices a user
+/// salt, kdf_params: what GetSalt answers for the device
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct AddDeviceRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub label: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub y1: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub y2: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "5")]
+    pub proof: ::core::option::Option<Proof>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub salt: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "7")]
+    pub kdf_params: ::core::option::Option<KdfParams>,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct AddDeviceResponse {}
+///
+/// A device of the user's
+/// added_at: Unix time in seconds the device was added at
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Device {
+    #[prost(string, tag = "1")]
+    pub label: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub added_at: u64,
+}
+///
+/// A user lists the devices they added, with a live session of theirs
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct ListDevicesRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListDevicesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub devices: ::prost::alloc::vec::Vec<Device>,
+}
+///
+/// A user takes the key of one of their devices away, with a live session of
+/// theirs. Sessions the device already has end with it
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct RevokeDeviceRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub label: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RevokeDeviceResponse {}
+///
+/// A user's live sessions, the one asking included, with a live session of
+/// theirs. Sessions go by a handle rather than their session_id, which would log
+/// in whoever reads it
+/// handle: names the session to RevokeOwnSession
+/// device: label of the device whose key the login proved, empty for the
+/// user's own key
+/// last_seen: Unix time in seconds the session was last used at, to the
+/// minute
+/// current: the session of the request
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct ListOwnSessionsRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OwnSession {
+    #[prost(string, tag = "1")]
+    pub handle: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub device: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub created_at: u64,
+    #[prost(uint64, tag = "4")]
+    pub last_seen: u64,
+    #[prost(uint64, tag = "5")]
+    pub expires_at: u64,
+    #[prost(bool, tag = "6")]
+    pub current: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListOwnSessionsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub sessions: ::prost::alloc::vec::Vec<OwnSession>,
+}
+///
+/// A user ends one of their sessions, by the handle ListOwnSessions gave for it,
+/// with a live session of theirs. That may be the session of the request
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct RevokeOwnSessionRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub handle: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RevokeOwnSessionResponse {}
+///
+/// A registered user as the admin sees it
+/// created_at: Unix time in seconds the user registered at, 0 if they did
+/// before it was noted
+/// last_login: Unix time in seconds of their last login, 0 if none
+/// metadata: what they registered with, see RegisterRequest
+/// admin: whether their sessions may call AuthAdmin, see SetAdmin
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct User {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub y1: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub y2: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "4")]
+    pub kdf_params: ::core::option::Option<KdfParams>,
+    #[prost(string, tag = "5")]
+    pub realm: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "6")]
+    pub created_at: u64,
+    #[prost(uint64, tag = "7")]
+    pub last_login: u64,
+    #[prost(map = "string, string", tag = "8")]
+    pub metadata: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    #[prost(bool, tag = "9")]
+    pub admin: bool,
+    #[prost(bool, tag = "10")]
+    pub totp: bool,
+}
+///
+/// Users of every realm ordered by qualified name, page_size of them at most (100 when 0). Pass the
+/// next_page_token of a response as page_token for the page after it, it is
+/// empty on the last page
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListUsersRequest {
+    #[prost(uint32, tag = "1")]
+    pub page_size: u32,
+    #[prost(string, tag = "2")]
+    pub page_token: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListUsersResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub users: ::prost::alloc::vec::Vec<User>,
+    #[prost(string, tag = "2")]
+    pub next_page_token: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetUserRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub realm: ::prost::alloc::string::String,
+}
+///
+/// locked_until: Unix time in seconds the user's lockout ends, 0 if not
+/// locked out
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetUserResponse {
+    #[prost(message, optional, tag = "1")]
+    pub user: ::core::option::Option<User>,
+    #[prost(uint64, tag = "2")]
+    pub locked_until: u64,
+}
+///
+/// Removes the user with its pending challenges and sessions, as DeleteAccount
+/// does without a proof
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteUserRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub realm: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct DeleteUserResponse {}
+///
+/// Grants or takes away the admin role of a user. With ADMIN_SESSIONS set, a
+/// live session of an admin, as the bearer token, lets its holder call
+/// AuthAdmin as ADMIN_TOKEN does
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetAdminRequest {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub realm: ::prost::alloc::string::String,
+    #[prost(bool, tag = "3")]
+    pub admin: bool,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct SetAdminResponse {}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListSessionsRequest {
+    #[prost(string, tag = "1")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub realm: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct SessionInfo {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub created_at: u64,
+    #[prost(uint64, tag = "3")]
+    pub expires_at: u64,
+    #[prost(string, tag = "4")]
+    pub device: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "5")]
+    pub last_seen: u64,
+}
+///
+/// Live sessions of the user, pass a session_id to RevokeSession to end it
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListSessionsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub sessions: ::prost::alloc::vec::Vec<SessionInfo>,
+}
+///
+/// Puts a session on the revocation list, ValidateSession and RefreshSession
+/// refuse it from then on, JWT sessions that haven't expired yet included
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct RevokeSessionRequest {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RevokeSessionResponse {}
+///
+/// Changes the registration puzzle difficulty at runtime, 0 turns it off
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct SetPowDifficultyRequest {
+    #[prost(uint32, tag = "1")]
+    pub difficulty: u32,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct SetPowDifficultyResponse {}
+///
+/// Users that haven't registered, logged in or refreshed a session for
+/// older_than_days days (USER_RETENTION_DAYS when 0), deleted with their
+/// challenges and sessions unless dry_run
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct PruneUsersRequest {
+    #[prost(uint32, tag = "1")]
+    pub older_than_days: u32,
+    #[prost(bool, tag = "2")]
+    pub dry_run: bool,
+}
+///
+/// users: qualified names of the users deleted, or that would be
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PruneUsersResponse {
+    #[prost(string, repeated, tag = "1")]
+    pub users: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+///
+/// Answers to the user's login challenges, newest first, limit of them at most
+/// (every one kept when 0). The server keeps the last LOGIN_HISTORY_SIZE of
+/// each user.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListLoginAttemptsRequest {
+    #[prost(string, tag = "1")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub realm: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub limit: u32,
+}
+///
+/// at: Unix time in seconds the answer came
+/// source_ip: empty over a Unix socket
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LoginAttempt {
+    #[prost(uint64, tag = "1")]
+    pub at: u64,
+    #[prost(bool, tag = "2")]
+    pub success: bool,
+    #[prost(string, tag = "3")]
+    pub source_ip: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub auth_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListLoginAttemptsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub attempts: ::prost::alloc::vec::Vec<LoginAttempt>,
+}
+///
+/// Adds to and removes from the IP denylist at runtime, IP addresses or CIDR
+/// blocks. Leave both empty to just read it.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateIpDenylistRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub add: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "2")]
+    pub remove: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+///
+/// denylist: every CIDR block denied after the update
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateIpDenylistResponse {
+    #[prost(string, repeated, tag = "1")]
+    pub denylist: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+///
+/// The metrics served at /metrics as they are now, for deployments without a
+/// Prometheus server to scrape them
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct GetMetricsRequest {}
+///
+/// json: an object mapping each metric name to a list of its series, each
+/// with its labels and value, or count and sum for histograms. Login
+/// counters are labeled by tenant (the realm, "(other)" past the
+/// first 100), param_set ("custom" for groups that aren't a named
+/// set) and, for verifications, result.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetMetricsResponse {
+    #[prost(string, tag = "1")]
+    pub json: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ErrorDetail {
+    #[prost(enumeration = "ErrorCode", tag = "1")]
+    pub code: i32,
+}
+///
+/// Why a call failed, for clients that react to it rather than parse status
+/// messages. Failed calls carry it as an ErrorDetail among the details of a
+/// google.rpc.Status, the grpc-status-details-bin trailer. Failures without a
+/// code of their own carry none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Unspecified = 0,
+    /// No user of that name, in that realm.
+    UserNotFound = 1,
+    /// The auth_id expired or is unknown, start the login over.
+    ChallengeExpired = 2,
+    /// A proof or answer that doesn't check out, e.g. a wrong password.
+    ProofInvalid = 3,
+    /// Too many wrong answers, the user is locked out for a while.
+    LockedOut = 4,
+    /// Too many requests, try again later.
+    RateLimited = 5,
+    /// A nonce seen before, or a timestamp outside the server's replay window.
+    RequestReplayed = 6,
+}
+impl ErrorCode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "ERROR_CODE_UNSPECIFIED",
+            Self::UserNotFound => "USER_NOT_FOUND",
+            Self::ChallengeExpired => "CHALLENGE_EXPIRED",
+            Self::ProofInvalid => "PROOF_INVALID",
+            Self::LockedOut => "LOCKED_OUT",
+            Self::RateLimited => "RATE_LIMITED",
+            Self::RequestReplayed => "REQUEST_REPLAYED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ERROR_CODE_UNSPECIFIED" => Some(Self::Unspecified),
+            "USER_NOT_FOUND" => Some(Self::UserNotFound),
+            "CHALLENGE_EXPIRED" => Some(Self::ChallengeExpired),
+            "PROOF_INVALID" => Some(Self::ProofInvalid),
+            "LOCKED_OUT" => Some(Self::LockedOut),
+            "RATE_LIMITED" => Some(Self::RateLimited),
+            "REQUEST_REPLAYED" => Some(Self::RequestReplayed),
+            _ => None,
+        }
+    }
 }
 /// Generated client implementations.
 pub mod auth_client {
@@ -65,17 +891,6 @@ pub mod auth_client {
     pub struct AuthClient<T> {
         inner: tonic::client::Grpc<T>,
     }
-    impl AuthClient<tonic::transport::Channel> {
-        /// Attempt to create a new client by connecting to a given endpoint.
-        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
-        where
-            D: TryInto<tonic::transport::Endpoint>,
-            D::Error: Into<StdError>,
-        {
-            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
-            Ok(Self::new(conn))
-        }
-    }
     impl<T> AuthClient<T>
     where
         T: tonic::client::GrpcService<tonic::body::BoxBody>,
@@ -141,6 +956,96 @@ pub mod auth_client {
             self.inner = self.inner.max_encoding_message_size(limit);
             self
         }
+        pub async fn hello(
+            &mut self,
+            request: impl tonic::IntoRequest<super::HelloRequest>,
+        ) -> std::result::Result<tonic::Response<super::HelloResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/zkp_auth.Auth/Hello");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("zkp_auth.Auth", "Hello"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_capabilities(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CapabilitiesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CapabilitiesResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/GetCapabilities",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "GetCapabilities"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_parameters(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ParametersRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ParametersResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/GetParameters",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "GetParameters"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_registration_puzzle(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RegistrationPuzzleRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RegistrationPuzzleResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/GetRegistrationPuzzle",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "GetRegistrationPuzzle"));
+            self.inner.unary(req, path, codec).await
+        }
         pub async fn register(
             &mut self,
             request: impl tonic::IntoRequest<super::RegisterRequest>,
@@ -162,6 +1067,24 @@ pub mod auth_client {
             req.extensions_mut().insert(GrpcMethod::new("zkp_auth.Auth", "Register"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn get_salt(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SaltRequest>,
+        ) -> std::result::Result<tonic::Response<super::SaltResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/zkp_auth.Auth/GetSalt");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("zkp_auth.Auth", "GetSalt"));
+            self.inner.unary(req, path, codec).await
+        }
         pub async fn create_authentication_challenge(
             &mut self,
             request: impl tonic::IntoRequest<super::AuthenticationChallengeRequest>,
@@ -188,34 +1111,1968 @@ pub mod auth_client {
                 );
             self.inner.unary(req, path, codec).await
         }
-        pub async fn verify_authentication(
-            &mut self,
-            request: impl tonic::IntoRequest<super::AuthenticationAnswerRequest>,
-        ) -> std::result::Result<
-            tonic::Response<super::AuthenticationAnswerResponse>,
-            tonic::Status,
-        > {
-            self.inner
-                .ready()
-                .await
-                .map_err(|e| {
-                    tonic::Status::unknown(
-                        format!("Service was not ready: {}", e.into()),
-                    )
-                })?;
-            let codec = tonic::codec::ProstCodec::default();
-            let path = http::uri::PathAndQuery::from_static(
-                "/zkp_auth.Auth/VerifyAuthentication",
-            );
-            let mut req = request.into_request();
-            req.extensions_mut()
-                .insert(GrpcMethod::new("zkp_auth.Auth", "VerifyAuthentication"));
-            self.inner.unary(req, path, codec).await
+        pub async fn commit_challenge(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ChallengeCommitmentRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ChallengeCommitmentResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/CommitChallenge",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "CommitChallenge"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn open_challenge(
+            &mut self,
+            request: impl tonic::IntoRequest<super::OpenChallengeRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::OpenChallengeResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/OpenChallenge",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "OpenChallenge"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn verify_authentication(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AuthenticationAnswerRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::AuthenticationAnswerResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/VerifyAuthentication",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "VerifyAuthentication"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn authenticate(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<
+                Message = super::AuthenticateRequest,
+            >,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::AuthenticateResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/Authenticate",
+            );
+            let mut req = request.into_streaming_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "Authenticate"));
+            self.inner.streaming(req, path, codec).await
+        }
+        pub async fn update_credentials(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdateCredentialsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::UpdateCredentialsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/UpdateCredentials",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "UpdateCredentials"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn recover_account(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RecoverAccountRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RecoverAccountResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/RecoverAccount",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "RecoverAccount"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn delete_account(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DeleteAccountRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::DeleteAccountResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/DeleteAccount",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "DeleteAccount"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn refresh_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RefreshSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RefreshSessionResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/RefreshSession",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "RefreshSession"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn logout(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LogoutRequest>,
+        ) -> std::result::Result<tonic::Response<super::LogoutResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/zkp_auth.Auth/Logout");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("zkp_auth.Auth", "Logout"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn validate_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ValidateSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ValidateSessionResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/ValidateSession",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "ValidateSession"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn add_device(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AddDeviceRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::AddDeviceResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/zkp_auth.Auth/AddDevice");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("zkp_auth.Auth", "AddDevice"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn list_devices(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListDevicesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListDevicesResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/ListDevices",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("zkp_auth.Auth", "ListDevices"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn revoke_device(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RevokeDeviceRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RevokeDeviceResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/RevokeDevice",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "RevokeDevice"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn list_own_sessions(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListOwnSessionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListOwnSessionsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/ListOwnSessions",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "ListOwnSessions"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn revoke_own_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RevokeOwnSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RevokeOwnSessionResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.Auth/RevokeOwnSession",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.Auth", "RevokeOwnSession"));
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated client implementations.
+pub mod auth_admin_client {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
+    #[derive(Debug, Clone)]
+    pub struct AuthAdminClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl<T> AuthAdminClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + std::marker::Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + std::marker::Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> AuthAdminClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + std::marker::Send + std::marker::Sync,
+        {
+            AuthAdminClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        pub async fn list_users(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListUsersRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListUsersResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/ListUsers",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "ListUsers"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_user(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetUserRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetUserResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/GetUser",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "GetUser"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn delete_user(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DeleteUserRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::DeleteUserResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/DeleteUser",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "DeleteUser"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn set_admin(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SetAdminRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::SetAdminResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/SetAdmin",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "SetAdmin"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn list_sessions(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListSessionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListSessionsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/ListSessions",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "ListSessions"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn revoke_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RevokeSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RevokeSessionResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/RevokeSession",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "RevokeSession"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn set_pow_difficulty(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SetPowDifficultyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::SetPowDifficultyResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/SetPowDifficulty",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "SetPowDifficulty"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn prune_users(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PruneUsersRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::PruneUsersResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/PruneUsers",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "PruneUsers"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn list_login_attempts(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListLoginAttemptsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListLoginAttemptsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/ListLoginAttempts",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "ListLoginAttempts"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn update_ip_denylist(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdateIpDenylistRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::UpdateIpDenylistResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/UpdateIpDenylist",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "UpdateIpDenylist"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_metrics(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetMetricsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetMetricsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/zkp_auth.AuthAdmin/GetMetrics",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("zkp_auth.AuthAdmin", "GetMetrics"));
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod auth_server {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with AuthServer.
+    #[async_trait]
+    pub trait Auth: std::marker::Send + std::marker::Sync + 'static {
+        async fn hello(
+            &self,
+            request: tonic::Request<super::HelloRequest>,
+        ) -> std::result::Result<tonic::Response<super::HelloResponse>, tonic::Status>;
+        async fn get_capabilities(
+            &self,
+            request: tonic::Request<super::CapabilitiesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CapabilitiesResponse>,
+            tonic::Status,
+        >;
+        async fn get_parameters(
+            &self,
+            request: tonic::Request<super::ParametersRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ParametersResponse>,
+            tonic::Status,
+        >;
+        async fn get_registration_puzzle(
+            &self,
+            request: tonic::Request<super::RegistrationPuzzleRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RegistrationPuzzleResponse>,
+            tonic::Status,
+        >;
+        async fn register(
+            &self,
+            request: tonic::Request<super::RegisterRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RegisterResponse>,
+            tonic::Status,
+        >;
+        async fn get_salt(
+            &self,
+            request: tonic::Request<super::SaltRequest>,
+        ) -> std::result::Result<tonic::Response<super::SaltResponse>, tonic::Status>;
+        async fn create_authentication_challenge(
+            &self,
+            request: tonic::Request<super::AuthenticationChallengeRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::AuthenticationChallengeResponse>,
+            tonic::Status,
+        >;
+        async fn commit_challenge(
+            &self,
+            request: tonic::Request<super::ChallengeCommitmentRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ChallengeCommitmentResponse>,
+            tonic::Status,
+        >;
+        async fn open_challenge(
+            &self,
+            request: tonic::Request<super::OpenChallengeRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::OpenChallengeResponse>,
+            tonic::Status,
+        >;
+        async fn verify_authentication(
+            &self,
+            request: tonic::Request<super::AuthenticationAnswerRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::AuthenticationAnswerResponse>,
+            tonic::Status,
+        >;
+        /// Server streaming response type for the Authenticate method.
+        type AuthenticateStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::AuthenticateResponse, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        async fn authenticate(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::AuthenticateRequest>>,
+        ) -> std::result::Result<
+            tonic::Response<Self::AuthenticateStream>,
+            tonic::Status,
+        >;
+        async fn update_credentials(
+            &self,
+            request: tonic::Request<super::UpdateCredentialsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::UpdateCredentialsResponse>,
+            tonic::Status,
+        >;
+        async fn recover_account(
+            &self,
+            request: tonic::Request<super::RecoverAccountRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RecoverAccountResponse>,
+            tonic::Status,
+        >;
+        async fn delete_account(
+            &self,
+            request: tonic::Request<super::DeleteAccountRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::DeleteAccountResponse>,
+            tonic::Status,
+        >;
+        async fn refresh_session(
+            &self,
+            request: tonic::Request<super::RefreshSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RefreshSessionResponse>,
+            tonic::Status,
+        >;
+        async fn logout(
+            &self,
+            request: tonic::Request<super::LogoutRequest>,
+        ) -> std::result::Result<tonic::Response<super::LogoutResponse>, tonic::Status>;
+        async fn validate_session(
+            &self,
+            request: tonic::Request<super::ValidateSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ValidateSessionResponse>,
+            tonic::Status,
+        >;
+        async fn add_device(
+            &self,
+            request: tonic::Request<super::AddDeviceRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::AddDeviceResponse>,
+            tonic::Status,
+        >;
+        async fn list_devices(
+            &self,
+            request: tonic::Request<super::ListDevicesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListDevicesResponse>,
+            tonic::Status,
+        >;
+        async fn revoke_device(
+            &self,
+            request: tonic::Request<super::RevokeDeviceRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RevokeDeviceResponse>,
+            tonic::Status,
+        >;
+        async fn list_own_sessions(
+            &self,
+            request: tonic::Request<super::ListOwnSessionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListOwnSessionsResponse>,
+            tonic::Status,
+        >;
+        async fn revoke_own_session(
+            &self,
+            request: tonic::Request<super::RevokeOwnSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RevokeOwnSessionResponse>,
+            tonic::Status,
+        >;
+    }
+    #[derive(Debug)]
+    pub struct AuthServer<T> {
+        inner: Arc<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    impl<T> AuthServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for AuthServer<T>
+    where
+        T: Auth,
+        B: Body + std::marker::Send + 'static,
+        B::Error: Into<StdError> + std::marker::Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            match req.uri().path() {
+                "/zkp_auth.Auth/Hello" => {
+                    #[allow(non_camel_case_types)]
+                    struct HelloSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::HelloRequest>
+                    for HelloSvc<T> {
+                        type Response = super::HelloResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::HelloRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::hello(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = HelloSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/GetCapabilities" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetCapabilitiesSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::CapabilitiesRequest>
+                    for GetCapabilitiesSvc<T> {
+                        type Response = super::CapabilitiesResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CapabilitiesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::get_capabilities(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetCapabilitiesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/GetParameters" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetParametersSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::ParametersRequest>
+                    for GetParametersSvc<T> {
+                        type Response = super::ParametersResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ParametersRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::get_parameters(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetParametersSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/GetRegistrationPuzzle" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetRegistrationPuzzleSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::RegistrationPuzzleRequest>
+                    for GetRegistrationPuzzleSvc<T> {
+                        type Response = super::RegistrationPuzzleResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RegistrationPuzzleRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::get_registration_puzzle(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetRegistrationPuzzleSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/Register" => {
+                    #[allow(non_camel_case_types)]
+                    struct RegisterSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::RegisterRequest>
+                    for RegisterSvc<T> {
+                        type Response = super::RegisterResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RegisterRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::register(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RegisterSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/GetSalt" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetSaltSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::SaltRequest>
+                    for GetSaltSvc<T> {
+                        type Response = super::SaltResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SaltRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::get_salt(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetSaltSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/CreateAuthenticationChallenge" => {
+                    #[allow(non_camel_case_types)]
+                    struct CreateAuthenticationChallengeSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::AuthenticationChallengeRequest>
+                    for CreateAuthenticationChallengeSvc<T> {
+                        type Response = super::AuthenticationChallengeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                super::AuthenticationChallengeRequest,
+                            >,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::create_authentication_challenge(
+                                        &inner,
+                                        request,
+                                    )
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = CreateAuthenticationChallengeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/CommitChallenge" => {
+                    #[allow(non_camel_case_types)]
+                    struct CommitChallengeSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::ChallengeCommitmentRequest>
+                    for CommitChallengeSvc<T> {
+                        type Response = super::ChallengeCommitmentResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ChallengeCommitmentRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::commit_challenge(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = CommitChallengeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/OpenChallenge" => {
+                    #[allow(non_camel_case_types)]
+                    struct OpenChallengeSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::OpenChallengeRequest>
+                    for OpenChallengeSvc<T> {
+                        type Response = super::OpenChallengeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::OpenChallengeRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::open_challenge(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = OpenChallengeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/VerifyAuthentication" => {
+                    #[allow(non_camel_case_types)]
+                    struct VerifyAuthenticationSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::AuthenticationAnswerRequest>
+                    for VerifyAuthenticationSvc<T> {
+                        type Response = super::AuthenticationAnswerResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AuthenticationAnswerRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::verify_authentication(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = VerifyAuthenticationSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/Authenticate" => {
+                    #[allow(non_camel_case_types)]
+                    struct AuthenticateSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::StreamingService<super::AuthenticateRequest>
+                    for AuthenticateSvc<T> {
+                        type Response = super::AuthenticateResponse;
+                        type ResponseStream = T::AuthenticateStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                tonic::Streaming<super::AuthenticateRequest>,
+                            >,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::authenticate(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = AuthenticateSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/UpdateCredentials" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdateCredentialsSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::UpdateCredentialsRequest>
+                    for UpdateCredentialsSvc<T> {
+                        type Response = super::UpdateCredentialsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdateCredentialsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::update_credentials(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = UpdateCredentialsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/RecoverAccount" => {
+                    #[allow(non_camel_case_types)]
+                    struct RecoverAccountSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::RecoverAccountRequest>
+                    for RecoverAccountSvc<T> {
+                        type Response = super::RecoverAccountResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RecoverAccountRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::recover_account(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RecoverAccountSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/DeleteAccount" => {
+                    #[allow(non_camel_case_types)]
+                    struct DeleteAccountSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::DeleteAccountRequest>
+                    for DeleteAccountSvc<T> {
+                        type Response = super::DeleteAccountResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DeleteAccountRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::delete_account(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = DeleteAccountSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/RefreshSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct RefreshSessionSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::RefreshSessionRequest>
+                    for RefreshSessionSvc<T> {
+                        type Response = super::RefreshSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RefreshSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::refresh_session(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RefreshSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/Logout" => {
+                    #[allow(non_camel_case_types)]
+                    struct LogoutSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::LogoutRequest>
+                    for LogoutSvc<T> {
+                        type Response = super::LogoutResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::LogoutRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::logout(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = LogoutSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/ValidateSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct ValidateSessionSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::ValidateSessionRequest>
+                    for ValidateSessionSvc<T> {
+                        type Response = super::ValidateSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ValidateSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::validate_session(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ValidateSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/AddDevice" => {
+                    #[allow(non_camel_case_types)]
+                    struct AddDeviceSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::AddDeviceRequest>
+                    for AddDeviceSvc<T> {
+                        type Response = super::AddDeviceResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AddDeviceRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::add_device(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = AddDeviceSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/ListDevices" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListDevicesSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::ListDevicesRequest>
+                    for ListDevicesSvc<T> {
+                        type Response = super::ListDevicesResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListDevicesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::list_devices(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ListDevicesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/RevokeDevice" => {
+                    #[allow(non_camel_case_types)]
+                    struct RevokeDeviceSvc<T: Auth>(pub Arc<T>);
+                    impl<T: Auth> tonic::server::UnaryService<super::RevokeDeviceRequest>
+                    for RevokeDeviceSvc<T> {
+                        type Response = super::RevokeDeviceResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RevokeDeviceRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::revoke_device(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RevokeDeviceSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/ListOwnSessions" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListOwnSessionsSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::ListOwnSessionsRequest>
+                    for ListOwnSessionsSvc<T> {
+                        type Response = super::ListOwnSessionsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListOwnSessionsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::list_own_sessions(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ListOwnSessionsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.Auth/RevokeOwnSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct RevokeOwnSessionSvc<T: Auth>(pub Arc<T>);
+                    impl<
+                        T: Auth,
+                    > tonic::server::UnaryService<super::RevokeOwnSessionRequest>
+                    for RevokeOwnSessionSvc<T> {
+                        type Response = super::RevokeOwnSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RevokeOwnSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Auth>::revoke_own_session(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RevokeOwnSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        let mut response = http::Response::new(empty_body());
+                        let headers = response.headers_mut();
+                        headers
+                            .insert(
+                                tonic::Status::GRPC_STATUS,
+                                (tonic::Code::Unimplemented as i32).into(),
+                            );
+                        headers
+                            .insert(
+                                http::header::CONTENT_TYPE,
+                                tonic::metadata::GRPC_CONTENT_TYPE,
+                            );
+                        Ok(response)
+                    })
+                }
+            }
+        }
+    }
+    impl<T> Clone for AuthServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
         }
     }
+    /// Generated gRPC service name
+    pub const SERVICE_NAME: &str = "zkp_auth.Auth";
+    impl<T> tonic::server::NamedService for AuthServer<T> {
+        const NAME: &'static str = SERVICE_NAME;
+    }
 }
 /// Generated server implementations.
-pub mod auth_server {
+pub mod auth_admin_server {
     #![allow(
         unused_variables,
         dead_code,
@@ -224,40 +3081,93 @@ pub mod auth_server {
         clippy::let_unit_value,
     )]
     use tonic::codegen::*;
-    /// Generated trait containing gRPC methods that should be implemented for use with AuthServer.
+    /// Generated trait containing gRPC methods that should be implemented for use with AuthAdminServer.
     #[async_trait]
-    pub trait Auth: std::marker::Send + std::marker::Sync + 'static {
-        async fn register(
+    pub trait AuthAdmin: std::marker::Send + std::marker::Sync + 'static {
+        async fn list_users(
             &self,
-            request: tonic::Request<super::RegisterRequest>,
+            request: tonic::Request<super::ListUsersRequest>,
         ) -> std::result::Result<
-            tonic::Response<super::RegisterResponse>,
+            tonic::Response<super::ListUsersResponse>,
             tonic::Status,
         >;
-        async fn create_authentication_challenge(
+        async fn get_user(
             &self,
-            request: tonic::Request<super::AuthenticationChallengeRequest>,
+            request: tonic::Request<super::GetUserRequest>,
+        ) -> std::result::Result<tonic::Response<super::GetUserResponse>, tonic::Status>;
+        async fn delete_user(
+            &self,
+            request: tonic::Request<super::DeleteUserRequest>,
         ) -> std::result::Result<
-            tonic::Response<super::AuthenticationChallengeResponse>,
+            tonic::Response<super::DeleteUserResponse>,
             tonic::Status,
         >;
-        async fn verify_authentication(
+        async fn set_admin(
             &self,
-            request: tonic::Request<super::AuthenticationAnswerRequest>,
+            request: tonic::Request<super::SetAdminRequest>,
         ) -> std::result::Result<
-            tonic::Response<super::AuthenticationAnswerResponse>,
+            tonic::Response<super::SetAdminResponse>,
+            tonic::Status,
+        >;
+        async fn list_sessions(
+            &self,
+            request: tonic::Request<super::ListSessionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListSessionsResponse>,
+            tonic::Status,
+        >;
+        async fn revoke_session(
+            &self,
+            request: tonic::Request<super::RevokeSessionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RevokeSessionResponse>,
+            tonic::Status,
+        >;
+        async fn set_pow_difficulty(
+            &self,
+            request: tonic::Request<super::SetPowDifficultyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::SetPowDifficultyResponse>,
+            tonic::Status,
+        >;
+        async fn prune_users(
+            &self,
+            request: tonic::Request<super::PruneUsersRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::PruneUsersResponse>,
+            tonic::Status,
+        >;
+        async fn list_login_attempts(
+            &self,
+            request: tonic::Request<super::ListLoginAttemptsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListLoginAttemptsResponse>,
+            tonic::Status,
+        >;
+        async fn update_ip_denylist(
+            &self,
+            request: tonic::Request<super::UpdateIpDenylistRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::UpdateIpDenylistResponse>,
+            tonic::Status,
+        >;
+        async fn get_metrics(
+            &self,
+            request: tonic::Request<super::GetMetricsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetMetricsResponse>,
             tonic::Status,
         >;
     }
     #[derive(Debug)]
-    pub struct AuthServer<T> {
+    pub struct AuthAdminServer<T> {
         inner: Arc<T>,
         accept_compression_encodings: EnabledCompressionEncodings,
         send_compression_encodings: EnabledCompressionEncodings,
         max_decoding_message_size: Option<usize>,
         max_encoding_message_size: Option<usize>,
     }
-    impl<T> AuthServer<T> {
+    impl<T> AuthAdminServer<T> {
         pub fn new(inner: T) -> Self {
             Self::from_arc(Arc::new(inner))
         }
@@ -308,9 +3218,9 @@ pub mod auth_server {
             self
         }
     }
-    impl<T, B> tonic::codegen::Service<http::Request<B>> for AuthServer<T>
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for AuthAdminServer<T>
     where
-        T: Auth,
+        T: AuthAdmin,
         B: Body + std::marker::Send + 'static,
         B::Error: Into<StdError> + std::marker::Send + 'static,
     {
@@ -325,23 +3235,113 @@ pub mod auth_server {
         }
         fn call(&mut self, req: http::Request<B>) -> Self::Future {
             match req.uri().path() {
-                "/zkp_auth.Auth/Register" => {
+                "/zkp_auth.AuthAdmin/ListUsers" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListUsersSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::ListUsersRequest>
+                    for ListUsersSvc<T> {
+                        type Response = super::ListUsersResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListUsersRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::list_users(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ListUsersSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/GetUser" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetUserSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<T: AuthAdmin> tonic::server::UnaryService<super::GetUserRequest>
+                    for GetUserSvc<T> {
+                        type Response = super::GetUserResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetUserRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::get_user(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetUserSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/DeleteUser" => {
                     #[allow(non_camel_case_types)]
-                    struct RegisterSvc<T: Auth>(pub Arc<T>);
-                    impl<T: Auth> tonic::server::UnaryService<super::RegisterRequest>
-                    for RegisterSvc<T> {
-                        type Response = super::RegisterResponse;
+                    struct DeleteUserSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::DeleteUserRequest>
+                    for DeleteUserSvc<T> {
+                        type Response = super::DeleteUserResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::RegisterRequest>,
+                            request: tonic::Request<super::DeleteUserRequest>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
                             let fut = async move {
-                                <T as Auth>::register(&inner, request).await
+                                <T as AuthAdmin>::delete_user(&inner, request).await
                             };
                             Box::pin(fut)
                         }
@@ -352,7 +3352,7 @@ pub mod auth_server {
                     let max_encoding_message_size = self.max_encoding_message_size;
                     let inner = self.inner.clone();
                     let fut = async move {
-                        let method = RegisterSvc(inner);
+                        let method = DeleteUserSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
@@ -368,31 +3368,25 @@ pub mod auth_server {
                     };
                     Box::pin(fut)
                 }
-                "/zkp_auth.Auth/CreateAuthenticationChallenge" => {
+                "/zkp_auth.AuthAdmin/SetAdmin" => {
                     #[allow(non_camel_case_types)]
-                    struct CreateAuthenticationChallengeSvc<T: Auth>(pub Arc<T>);
+                    struct SetAdminSvc<T: AuthAdmin>(pub Arc<T>);
                     impl<
-                        T: Auth,
-                    > tonic::server::UnaryService<super::AuthenticationChallengeRequest>
-                    for CreateAuthenticationChallengeSvc<T> {
-                        type Response = super::AuthenticationChallengeResponse;
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::SetAdminRequest>
+                    for SetAdminSvc<T> {
+                        type Response = super::SetAdminResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<
-                                super::AuthenticationChallengeRequest,
-                            >,
+                            request: tonic::Request<super::SetAdminRequest>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
                             let fut = async move {
-                                <T as Auth>::create_authentication_challenge(
-                                        &inner,
-                                        request,
-                                    )
-                                    .await
+                                <T as AuthAdmin>::set_admin(&inner, request).await
                             };
                             Box::pin(fut)
                         }
@@ -403,7 +3397,7 @@ pub mod auth_server {
                     let max_encoding_message_size = self.max_encoding_message_size;
                     let inner = self.inner.clone();
                     let fut = async move {
-                        let method = CreateAuthenticationChallengeSvc(inner);
+                        let method = SetAdminSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
@@ -419,25 +3413,25 @@ pub mod auth_server {
                     };
                     Box::pin(fut)
                 }
-                "/zkp_auth.Auth/VerifyAuthentication" => {
+                "/zkp_auth.AuthAdmin/ListSessions" => {
                     #[allow(non_camel_case_types)]
-                    struct VerifyAuthenticationSvc<T: Auth>(pub Arc<T>);
+                    struct ListSessionsSvc<T: AuthAdmin>(pub Arc<T>);
                     impl<
-                        T: Auth,
-                    > tonic::server::UnaryService<super::AuthenticationAnswerRequest>
-                    for VerifyAuthenticationSvc<T> {
-                        type Response = super::AuthenticationAnswerResponse;
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::ListSessionsRequest>
+                    for ListSessionsSvc<T> {
+                        type Response = super::ListSessionsResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::AuthenticationAnswerRequest>,
+                            request: tonic::Request<super::ListSessionsRequest>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
                             let fut = async move {
-                                <T as Auth>::verify_authentication(&inner, request).await
+                                <T as AuthAdmin>::list_sessions(&inner, request).await
                             };
                             Box::pin(fut)
                         }
@@ -448,7 +3442,277 @@ pub mod auth_server {
                     let max_encoding_message_size = self.max_encoding_message_size;
                     let inner = self.inner.clone();
                     let fut = async move {
-                        let method = VerifyAuthenticationSvc(inner);
+                        let method = ListSessionsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/RevokeSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct RevokeSessionSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::RevokeSessionRequest>
+                    for RevokeSessionSvc<T> {
+                        type Response = super::RevokeSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RevokeSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::revoke_session(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RevokeSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/SetPowDifficulty" => {
+                    #[allow(non_camel_case_types)]
+                    struct SetPowDifficultySvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::SetPowDifficultyRequest>
+                    for SetPowDifficultySvc<T> {
+                        type Response = super::SetPowDifficultyResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SetPowDifficultyRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::set_pow_difficulty(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = SetPowDifficultySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/PruneUsers" => {
+                    #[allow(non_camel_case_types)]
+                    struct PruneUsersSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::PruneUsersRequest>
+                    for PruneUsersSvc<T> {
+                        type Response = super::PruneUsersResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PruneUsersRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::prune_users(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = PruneUsersSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/ListLoginAttempts" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListLoginAttemptsSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::ListLoginAttemptsRequest>
+                    for ListLoginAttemptsSvc<T> {
+                        type Response = super::ListLoginAttemptsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListLoginAttemptsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::list_login_attempts(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ListLoginAttemptsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/UpdateIpDenylist" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdateIpDenylistSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::UpdateIpDenylistRequest>
+                    for UpdateIpDenylistSvc<T> {
+                        type Response = super::UpdateIpDenylistResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdateIpDenylistRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::update_ip_denylist(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = UpdateIpDenylistSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/zkp_auth.AuthAdmin/GetMetrics" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetMetricsSvc<T: AuthAdmin>(pub Arc<T>);
+                    impl<
+                        T: AuthAdmin,
+                    > tonic::server::UnaryService<super::GetMetricsRequest>
+                    for GetMetricsSvc<T> {
+                        type Response = super::GetMetricsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetMetricsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AuthAdmin>::get_metrics(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetMetricsSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
@@ -484,7 +3748,7 @@ pub mod auth_server {
             }
         }
     }
-    impl<T> Clone for AuthServer<T> {
+    impl<T> Clone for AuthAdminServer<T> {
         fn clone(&self) -> Self {
             let inner = self.inner.clone();
             Self {
@@ -497,8 +3761,8 @@ pub mod auth_server {
         }
     }
     /// Generated gRPC service name
-    pub const SERVICE_NAME: &str = "zkp_auth.Auth";
-    impl<T> tonic::server::NamedService for AuthServer<T> {
+    pub const SERVICE_NAME: &str = "zkp_auth.AuthAdmin";
+    impl<T> tonic::server::NamedService for AuthAdminServer<T> {
         const NAME: &'static str = SERVICE_NAME;
     }
 }